@@ -20,7 +20,7 @@ pub extern "C" fn main() -> i32 {
 
     std::println!("Starting FPS Test (1000 frames @ 640x400)...");
 
-    let start_ticks = std::os::get_system_ticks();
+    let start = std::time::Instant::now();
 
     let mut root = Widget::frame(1)
         .width(Size::Relative(100))
@@ -55,17 +55,17 @@ pub extern "C" fn main() -> i32 {
         win.update();
     }
 
-    let end_ticks = std::os::get_system_ticks();
-    let duration_ms = end_ticks - start_ticks;
+    let elapsed = start.elapsed();
+    let duration_ms = elapsed.as_secs_f64() * 1000.0;
 
-    let fps = if duration_ms > 0 {
-        (1000.0 / duration_ms as f64) * 1000.0
+    let fps = if elapsed.as_nanos() > 0 {
+        1000.0 / elapsed.as_secs_f64()
     } else {
         9999.0
     };
 
     std::println!("Test Complete.");
-    std::println!("Time: {} ms", duration_ms);
+    std::println!("Time: {:.3} ms", duration_ms);
     std::println!("Average FPS: {:.2}", fps);
 
     loop {
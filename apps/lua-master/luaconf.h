@@ -239,7 +239,7 @@
 
 #else			/* }{ */
 
-#define LUA_ROOT	"@0xE0/"
+#define LUA_ROOT	"/"
 #define LUA_LDIR	LUA_ROOT "share/lua/" LUA_VDIR "/"
 #define LUA_CDIR	LUA_ROOT "lib/lua/" LUA_VDIR "/"
 
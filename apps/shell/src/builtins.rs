@@ -3,11 +3,11 @@ use alloc::string::String;
 use alloc::string::ToString;
 use alloc::vec::Vec;
 use std::io::{Read, Write};
-use crate::utils::resolve_path;
+use crate::utils::{format_date, mode_string, resolve_path};
 
 pub fn execute_builtin(cmd: &str, args: &[String], cwd: &mut String, path_env: &mut String, in_fd: usize, out_fd: usize) -> i32 {
     if cmd == "help" {
-        std::os::file_write(out_fd, b"Available commands: help, clear, ls, cd, pwd, touch, mkdir, rm, mv, cp, cat, sleep, osfetch, echo, export\n");
+        std::os::file_write(out_fd, b"Available commands: help, clear, ls [-l], cd, pwd, touch, mkdir, rm, mv, cp, cat, sleep, osfetch, echo, export, mount, umount, sync, ln\n");
         return 0;
     } else if cmd == "export" {
         if !args.is_empty() {
@@ -217,13 +217,25 @@ pub fn execute_builtin(cmd: &str, args: &[String], cwd: &mut String, path_env: &
         std::os::file_write(out_fd, b"\n");
         return 0;
     } else if cmd == "ls" {
-        let target = if args.is_empty() { cwd.as_str() } else { &args[0] };
+        let long = args.first().map_or(false, |a| a == "-l");
+        let rest = if long { &args[1..] } else { &args[..] };
+        let target = if rest.is_empty() { cwd.as_str() } else { &rest[0] };
         let full_path = resolve_path(cwd, target);
         match std::fs::read_dir(&full_path) {
             Ok(entries) => {
                 for entry in entries {
                     let mut line = String::new();
-                    line.push_str("  ");
+                    if long {
+                        match std::fs::symlink_metadata(&resolve_path(&full_path, &entry.name)) {
+                            Ok(meta) => line.push_str(&format!(
+                                "{} {:>2} {:>4} {:>4} {:>8} {} ",
+                                mode_string(meta.mode), meta.nlink, meta.uid, meta.gid, meta.size, format_date(meta.mtime)
+                            )),
+                            Err(_) => line.push_str("?????????? "),
+                        }
+                    } else {
+                        line.push_str("  ");
+                    }
                     if entry.file_type == std::fs::FileType::Directory {
                         line.push_str("\x1B[1m\x1B[94m");
                         line.push(core::char::from_u32(0xF07B).unwrap());
@@ -231,6 +243,15 @@ pub fn execute_builtin(cmd: &str, args: &[String], cwd: &mut String, path_env: &
                         line.push_str(&entry.name);
                         line.push_str("/");
                         line.push_str("\x1B[0m\n");
+                    } else if entry.file_type == std::fs::FileType::Symlink {
+                        let link_target = std::fs::read_link(&resolve_path(&full_path, &entry.name)).unwrap_or_default();
+                        line.push_str("\x1B[96m");
+                        line.push(core::char::from_u32(0xF0C1).unwrap());
+                        line.push_str(" ");
+                        line.push_str(&entry.name);
+                        line.push_str("\x1B[0m -> ");
+                        line.push_str(&link_target);
+                        line.push_str("\n");
                     } else {
                         line.push_str("\x1B[37m");
                         line.push(core::char::from_u32(0xF016).unwrap());
@@ -250,7 +271,7 @@ pub fn execute_builtin(cmd: &str, args: &[String], cwd: &mut String, path_env: &
         }
     } else if cmd == "cd" {
         if args.is_empty() {
-            *cwd = String::from("@0xE0");
+            *cwd = String::from("/");
             return 0;
         } else {
             let new_path = resolve_path(cwd, &args[0]);
@@ -283,6 +304,55 @@ pub fn execute_builtin(cmd: &str, args: &[String], cwd: &mut String, path_env: &
             }
         }
         return 0;
+    } else if cmd == "mount" {
+        if args.len() < 2 {
+            std::os::file_write(out_fd, b"usage: mount @disk[:lba] dir\n");
+            return 1;
+        }
+        let (disk, lba) = args[0].trim_start_matches('@').split_once(':').unwrap_or((args[0].trim_start_matches('@'), "0"));
+        let disk_id = match disk.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16).ok(),
+            None => disk.parse::<u8>().ok(),
+        };
+        let path = resolve_path(cwd, &args[1]);
+        match (disk_id, lba.parse::<u64>()) {
+            (Some(disk_id), Ok(lba)) if std::fs::mount(disk_id, lba, &path, "ext2").is_ok() => return 0,
+            _ => {
+                let err = format!("mount: cannot mount {} on \"{}\"\n", args[0], path);
+                std::os::file_write(out_fd, err.as_bytes());
+                return 1;
+            }
+        }
+    } else if cmd == "umount" {
+        if !args.is_empty() {
+            let path = resolve_path(cwd, &args[0]);
+            if let Err(_) = std::fs::umount(&path) {
+                let err = format!("umount: cannot unmount \"{}\"\n", path);
+                std::os::file_write(out_fd, err.as_bytes());
+                return 1;
+            }
+        }
+        return 0;
+    } else if cmd == "ln" {
+        let symbolic = args.first().map_or(false, |a| a == "-s");
+        let rest = if symbolic { &args[1..] } else { &args[..] };
+        if rest.len() == 2 {
+            let link = resolve_path(cwd, &rest[1]);
+            let result = if symbolic {
+                std::fs::symlink(&rest[0], &link)
+            } else {
+                std::fs::hard_link(&resolve_path(cwd, &rest[0]), &link)
+            };
+            if let Err(_) = result {
+                let err = format!("ln: cannot create link \"{}\"\n", link);
+                std::os::file_write(out_fd, err.as_bytes());
+                return 1;
+            }
+        }
+        return 0;
+    } else if cmd == "sync" {
+        std::fs::sync();
+        return 0;
     } else if cmd == "rm" {
         if !args.is_empty() {
             let path = resolve_path(cwd, &args[0]);
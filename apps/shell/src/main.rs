@@ -25,7 +25,7 @@ pub extern "C" fn main() -> i32 {
     let welcome_msg = format!("\nWelcome to KrakeOS Shell {} \n> ", welcome_icon);
     std::os::file_write(STDOUT_FD, welcome_msg.as_bytes());
 
-    let mut cwd = String::from("@0xE0");
+    let mut cwd = String::from("/");
     let mut path_env = String::from("/sys/bin;/apps");
     let mut cmd_buffer = String::new();
 
@@ -115,7 +115,7 @@ pub extern "C" fn main() -> i32 {
                             }
 
                             let is_builtin = match parsed.cmd.as_str() {
-                                "cd" | "ls" | "pwd" | "help" | "clear" | "touch" | "mkdir" | "rm" | "mv" | "cp" | "sleep" | "osfetch" | "echo" | "cat" | "export" => true,
+                                "cd" | "ls" | "pwd" | "help" | "clear" | "touch" | "mkdir" | "rm" | "mv" | "cp" | "sleep" | "osfetch" | "echo" | "cat" | "export" | "mount" | "umount" | "sync" | "ln" => true,
                                 _ => false
                             };
 
@@ -144,7 +144,7 @@ pub extern "C" fn main() -> i32 {
                                             break;
                                         }
 
-                                        if !found && (path_dir.ends_with("/apps") || path_dir == "@0xE0/apps") {
+                                        if !found && path_dir.ends_with("/apps") {
                                             let apps_dir = format!("{}/{}", path_dir, parsed.cmd);
                                             if let Ok(entries) = std::fs::read_dir(&apps_dir) {
                                                 for entry in entries {
@@ -212,7 +212,8 @@ pub extern "C" fn main() -> i32 {
                 std::os::file_write(STDOUT_FD, &[b]);
             }
         } else {
-            std::os::yield_task();
+            // End of input, or a stdin that can no longer be read.
+            return 0;
         }
     }
 }
\ No newline at end of file
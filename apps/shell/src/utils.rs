@@ -5,46 +5,74 @@ pub fn resolve_path(cwd: &str, path: &str) -> String {
     let trimmed_path = path.trim();
     if trimmed_path.is_empty() { return String::from(cwd); }
 
-    let mut parts = Vec::new();
+    let full_path = if trimmed_path.starts_with('@') || trimmed_path.starts_with('/') {
+        String::from(trimmed_path)
+    } else {
+        alloc::format!("{}/{}", cwd, trimmed_path)
+    };
 
-    if !trimmed_path.starts_with('@') {
-        if trimmed_path.starts_with('/') {
-            
-            if let Some(idx) = cwd.find('/') {
-                parts.push(&cwd[..idx]);
-            } else {
-                parts.push(cwd);
-            }
-        } else {
-            
-            for part in cwd.split('/') {
-                if !part.is_empty() {
-                    parts.push(part);
-                }
-            }
+    // `@disk/...` paths keep the disk as their root.
+    let (mut res, rest) = match full_path.strip_prefix('@') {
+        Some(alias) => {
+            let (disk, rest) = alias.split_once('/').unwrap_or((alias, ""));
+            (alloc::format!("@{}", disk), rest)
         }
-    }
+        None => (String::new(), full_path.as_str()),
+    };
 
-    for part in trimmed_path.split('/') {
+    let mut parts = Vec::new();
+    for part in rest.split('/') {
         if part.is_empty() || part == "." {
             continue;
         } else if part == ".." {
-            if parts.len() > 1 {
-                parts.pop();
-            }
+            parts.pop();
         } else {
             parts.push(part);
         }
     }
 
-    if parts.is_empty() {
-        return String::from("@0xE0");
-    }
-
-    let mut res = String::new();
-    for (i, p) in parts.iter().enumerate() {
-        if i > 0 { res.push('/'); }
+    for p in parts {
+        res.push('/');
         res.push_str(p);
     }
+    if res.is_empty() {
+        res.push('/');
+    }
     res
 }
+
+/// `ls -l` style type and permission column, e.g. `drwxr-xr-x`.
+pub fn mode_string(mode: u32) -> String {
+    let mut out = String::new();
+    out.push(match mode & 0o170000 {
+        0o040000 => 'd',
+        0o120000 => 'l',
+        0o020000 => 'c',
+        0o060000 => 'b',
+        0o010000 => 'p',
+        _ => '-',
+    });
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 7;
+        out.push(if bits & 4 != 0 { 'r' } else { '-' });
+        out.push(if bits & 2 != 0 { 'w' } else { '-' });
+        out.push(if bits & 1 != 0 { 'x' } else { '-' });
+    }
+    out
+}
+
+/// `YYYY-MM-DD HH:MM` in UTC for `secs` since the Unix epoch.
+pub fn format_date(secs: u64) -> String {
+    // Days to civil date, after Howard Hinnant's `civil_from_days`.
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    let rem = secs % 86400;
+    alloc::format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, rem / 3600, rem % 3600 / 60)
+}
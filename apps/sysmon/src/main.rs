@@ -9,9 +9,16 @@ use std::os::ProcessInfo;
 
 const STDIN_FD: usize = 0;
 const STDOUT_FD: usize = 1;
+const REFRESH_TICKS: u64 = 1000;
 
 struct AppState {
     processes: Vec<ProcessInfo>,
+    /// CPU% per entry of `processes`, from the ticks used since the last refresh.
+    cpu: Vec<u64>,
+    /// Resident and swapped bytes per entry of `processes`.
+    mem: Vec<(usize, usize)>,
+    last_ticks: u64,
+    heap: std::os::HeapStats,
     selected_index: usize,
     scroll_offset: usize,
     screen_height: usize,
@@ -21,6 +28,10 @@ impl AppState {
     fn new() -> Self {
         let mut app = AppState {
             processes: Vec::new(),
+            cpu: Vec::new(),
+            mem: Vec::new(),
+            last_ticks: 0,
+            heap: std::os::HeapStats::default(),
             selected_index: 0,
             scroll_offset: 0,
             screen_height: 20,
@@ -30,7 +41,23 @@ impl AppState {
     }
 
     fn refresh(&mut self) {
-        self.processes = std::os::get_process_list();
+        let now = std::os::get_system_ticks();
+        let elapsed = now.saturating_sub(self.last_ticks).max(1);
+        let list = std::os::get_process_list();
+
+        self.cpu = list.iter().map(|p| {
+            match self.processes.iter().find(|old| old.pid == p.pid && old.name == p.name) {
+                Some(old) if self.last_ticks != 0 => {
+                    let used = (p.user_ticks + p.kernel_ticks).saturating_sub(old.user_ticks + old.kernel_ticks);
+                    core::cmp::min(used * 100 / elapsed, 100)
+                }
+                _ => 0,
+            }
+        }).collect();
+        self.mem = list.iter().map(|p| (std::os::get_process_memory(p.pid), std::os::get_process_swap(p.pid))).collect();
+        self.processes = list;
+        self.last_ticks = now;
+        self.heap = std::os::get_heap_stats();
         if self.selected_index >= self.processes.len() {
             self.selected_index = self.processes.len().saturating_sub(1);
         }
@@ -39,7 +66,7 @@ impl AppState {
     fn draw(&self) {
         std::os::file_write(STDOUT_FD, b"\x1B[2J\x1B[H");
         std::os::file_write(STDOUT_FD, b"\x1B[1;37;42m SYSMON - System Monitor \x1B[0m\n\n");
-        std::os::file_write(STDOUT_FD, b"\x1B[1m  PID   STATE   NAME\x1B[0m\n");
+        std::os::file_write(STDOUT_FD, b"\x1B[1m  PID   STATE   CPU%  CLASS  NI     RES KiB  SWAP KiB  NAME\x1B[0m\n");
 
         for (i, proc) in self.processes.iter().enumerate().skip(self.scroll_offset).take(self.screen_height) {
             if i == self.selected_index {
@@ -47,16 +74,25 @@ impl AppState {
             }
 
             let state_str = match proc.state {
-                0 => "RUN  ",
-                1 => "SLEEP",
-                2 => "ZOMB ",
+                std::os::PROC_STATE_READY => "RUN  ",
+                std::os::PROC_STATE_SLEEPING => "SLEEP",
+                std::os::PROC_STATE_ZOMBIE => "ZOMB ",
+                std::os::PROC_STATE_STOPPED => "STOP ",
                 _ => "UNKN ",
             };
+            let class_str = match proc.sched_class {
+                std::os::SCHED_IDLE => "idle ",
+                std::os::SCHED_INTERACTIVE => "inter",
+                _ => "norm ",
+            };
+            let cpu = self.cpu.get(i).copied().unwrap_or(0);
+            let (res, swap) = self.mem.get(i).copied().unwrap_or((0, 0));
 
             let name = String::from_utf8_lossy(&proc.name);
             let name_trimmed = name.trim_matches('\0');
 
-            let line = format!("  {:<5} {:<7} {}\n", proc.pid, state_str, name_trimmed);
+            let line = format!("  {:<5} {:<7} {:>3}%  {:<6} {:<4} {:>8} {:>9}  {}\n",
+                proc.pid, state_str, cpu, class_str, proc.nice, res / 1024, swap / 1024, name_trimmed);
             std::os::file_write(STDOUT_FD, line.as_bytes());
 
             if i == self.selected_index {
@@ -64,6 +100,15 @@ impl AppState {
             }
         }
 
+        let mut heap = format!("\n\x1B[90mKernel heap: {} / {} KiB, {} allocations; slabs:",
+            self.heap.heap_used / 1024, self.heap.heap_size / 1024, self.heap.allocations);
+        for cache in self.heap.caches.iter() {
+            let name = String::from_utf8_lossy(&cache.name);
+            heap.push_str(&format!(" {} {}", name.trim_matches('\0'), cache.objects));
+        }
+        heap.push_str("\x1B[0m");
+        std::os::file_write(STDOUT_FD, heap.as_bytes());
+
         let footer = format!("\n\x1B[90mTotal Processes: {}  [W/S] Move  [K] Kill  [Q] Quit\x1B[0m", self.processes.len());
         std::os::file_write(STDOUT_FD, footer.as_bytes());
     }
@@ -111,8 +156,12 @@ pub extern "C" fn main() -> i32 {
             needs_redraw = false;
         }
 
+        let elapsed = std::os::get_system_ticks().saturating_sub(app.last_ticks);
+        let mut fds = [std::os::PollFd { fd: STDIN_FD as i32, events: std::os::POLLIN, revents: 0 }];
+        let input = std::os::poll(&mut fds, REFRESH_TICKS.saturating_sub(elapsed).max(1) as i32) > 0;
+
         let mut buf = [0u8; 1];
-        if std::os::file_read(STDIN_FD, &mut buf) > 0 {
+        if input && std::os::file_read(STDIN_FD, &mut buf) > 0 {
             let c = buf[0] as char;
             match c {
                 'w' | 'W' => {
@@ -139,7 +188,10 @@ pub extern "C" fn main() -> i32 {
                 _ => {}
             }
         } else {
-            std::os::yield_task();
+            if std::os::get_system_ticks().saturating_sub(app.last_ticks) >= REFRESH_TICKS {
+                app.refresh();
+                needs_redraw = true;
+            }
             
             
         }
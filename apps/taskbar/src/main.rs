@@ -22,6 +22,8 @@ fn wifi_status(_win: &mut Window, _id: usize) {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn main() -> i32 {
+    std::os::sched_setscheduler(0, std::os::SCHED_INTERACTIVE);
+
     let screen_w = std::graphics::get_screen_width();
     let screen_total_h = std::graphics::get_screen_height();
     let screen_h = (screen_total_h * 4) / 100;
@@ -32,7 +34,7 @@ pub extern "C" fn main() -> i32 {
     win.y = 0;
 
     {
-        if let Ok(mut file) = File::open("@0xE0/sys/fonts/CaskaydiaNerd.ttf") {
+        if let Ok(mut file) = File::open("/sys/fonts/CaskaydiaNerd.ttf") {
             let size = file.size();
             let buffer_addr = std::memory::malloc(size);
             let buffer = unsafe { core::slice::from_raw_parts_mut(buffer_addr as *mut u8, size) };
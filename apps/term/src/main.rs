@@ -50,6 +50,8 @@ fn update_term_size(win: &Window) {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn main() -> i32 {
+    std::os::sched_setscheduler(0, std::os::SCHED_INTERACTIVE);
+
     let width = 800;
     let height = 400;
 
@@ -86,7 +88,7 @@ pub extern "C" fn main() -> i32 {
     win.y = y as isize;
 
     {
-        if let Ok(mut file) = File::open("@0xE0/sys/fonts/CaskaydiaNerd.ttf") {
+        if let Ok(mut file) = File::open("/sys/fonts/CaskaydiaNerd.ttf") {
             let size = file.size();
             let buffer_addr = std::memory::malloc(size);
             let buffer = unsafe { core::slice::from_raw_parts_mut(buffer_addr as *mut u8, size) };
@@ -101,6 +103,7 @@ pub extern "C" fn main() -> i32 {
     let mut fds_out = [0i32; 2];
     std::os::pipe(&mut fds_out);
     unsafe { TERM_READ_FD = fds_out[0] as usize; }
+    std::os::fcntl(fds_out[0] as usize, std::os::F_SETFL, std::os::O_NONBLOCK);
 
     let mut fds_in = [0i32; 2];
     std::os::pipe(&mut fds_in);
@@ -112,7 +115,7 @@ pub extern "C" fn main() -> i32 {
         (2, fds_out[1] as u8),
     ];
 
-    std::os::spawn_with_fds("@0xE0/sys/bin/shell.elf", &[], &fds_map);
+    std::os::spawn_with_fds("/sys/bin/shell.elf", &[], &fds_map);
 
 
     std::os::file_close(fds_in[0] as usize);
@@ -143,6 +146,7 @@ pub extern "C" fn main() -> i32 {
 
     let mut term_buffer = TerminalBuffer::new();
     let mut pipe_buf = [0u8; 4096];
+    let mut shell_gone = false;
 
     loop {
         let mut did_work = false;
@@ -428,7 +432,15 @@ pub extern "C" fn main() -> i32 {
         }
 
         if !did_work {
-            std::os::yield_task();
+            let mut fds = [
+                std::os::PollFd { fd: win.id as i32, events: std::os::POLLWINDOW, revents: 0 },
+                std::os::PollFd { fd: unsafe { TERM_READ_FD } as i32, events: std::os::POLLIN, revents: 0 },
+            ];
+            let count = if shell_gone { 1 } else { 2 };
+            std::os::poll(&mut fds[..count], -1);
+            if (fds[1].revents & std::os::POLLHUP) != 0 && (fds[1].revents & std::os::POLLIN) == 0 {
+                shell_gone = true;
+            }
         }
     }
 }
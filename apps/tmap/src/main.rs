@@ -32,7 +32,7 @@ impl AppState {
         self.selected_index = 0;
 
 
-        if self.current_path != "@0xE0" && self.current_path.len() > 5 {
+        if self.current_path != "/" {
             self.entries.push(fs::DirEntry {
                 name: String::from(".."),
                 file_type: fs::FileType::Directory,
@@ -91,10 +91,9 @@ impl AppState {
         if self.selected_index < self.entries.len() {
             let entry = &self.entries[self.selected_index];
             if entry.name == ".." {
-                if let Some(last_slash) = self.current_path.rfind('/') {
-                    self.current_path.truncate(last_slash);
-                } else {
-                    self.current_path = String::from("@0xE0");
+                match self.current_path.rfind('/') {
+                    Some(last_slash) if last_slash > 0 => self.current_path.truncate(last_slash),
+                    _ => self.current_path = String::from("/"),
                 }
                 self.refresh();
             } else if entry.file_type == fs::FileType::Directory {
@@ -110,7 +109,7 @@ impl AppState {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn main() -> i32 {
-    let mut app = AppState::new("@0xE0");
+    let mut app = AppState::new("/");
     let mut needs_redraw = true;
 
 
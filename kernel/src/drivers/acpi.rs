@@ -0,0 +1,114 @@
+use alloc::vec::Vec;
+use crate::boot::BOOT_INFO;
+use crate::memory::paging::HHDM_OFFSET;
+
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+const HPET_SIGNATURE: &[u8; 4] = b"HPET";
+
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_SOURCE_OVERRIDE: u8 = 2;
+const MADT_LAPIC_OVERRIDE: u8 = 5;
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub addr: u64,
+    /// First global system interrupt this I/O APIC serves.
+    pub gsi_base: u32,
+}
+
+/// An ISA IRQ wired to a different GSI, or with non-ISA polarity or trigger.
+#[derive(Debug, Clone, Copy)]
+pub struct IrqOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    /// MPS INTI flags: polarity in bits 0-1, trigger mode in bits 2-3.
+    pub flags: u16,
+}
+
+/// What the kernel needs from the MADT to run more than one CPU and route IRQs.
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub lapic_addr: u64,
+    /// APIC IDs of usable processors, the BSP included.
+    pub cpus: Vec<u8>,
+    pub io_apics: Vec<IoApicInfo>,
+    pub overrides: Vec<IrqOverride>,
+}
+
+unsafe fn read<T: Copy>(phys: u64) -> T {
+    unsafe { core::ptr::read_unaligned((phys + HHDM_OFFSET) as *const T) }
+}
+
+fn checksum_ok(phys: u64, len: usize) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts((phys + HHDM_OFFSET) as *const u8, len) };
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+/// Walks the RSDT handed over by the bootloader for a table with `signature`.
+fn find_table(signature: &[u8; 4]) -> Option<u64> {
+    let rsdp = unsafe { BOOT_INFO.rsdp };
+    if &rsdp.signature != b"RSD PTR " || rsdp.rsdt_address == 0 {
+        return None;
+    }
+    let rsdt = rsdp.rsdt_address as u64;
+    let len = unsafe { read::<u32>(rsdt + 4) } as u64;
+    if unsafe { read::<[u8; 4]>(rsdt) } != *b"RSDT" || len < 36 {
+        return None;
+    }
+    (0..(len - 36) / 4)
+        .map(|i| unsafe { read::<u32>(rsdt + 36 + i * 4) } as u64)
+        .find(|&table| unsafe { read::<[u8; 4]>(table) } == *signature
+            && checksum_ok(table, unsafe { read::<u32>(table + 4) } as usize))
+}
+
+pub fn madt() -> Option<Madt> {
+    let table = find_table(MADT_SIGNATURE)?;
+    let len = unsafe { read::<u32>(table + 4) } as u64;
+    let mut madt = Madt {
+        lapic_addr: unsafe { read::<u32>(table + 36) } as u64,
+        cpus: Vec::new(),
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+    };
+
+    let mut off = 44;
+    while off + 2 <= len {
+        let kind: u8 = unsafe { read(table + off) };
+        let entry_len: u8 = unsafe { read(table + off + 1) };
+        if entry_len < 2 {
+            break;
+        }
+        match kind {
+            MADT_LOCAL_APIC => {
+                let apic_id: u8 = unsafe { read(table + off + 3) };
+                let flags: u32 = unsafe { read(table + off + 4) };
+                if flags & 0b11 != 0 {
+                    madt.cpus.push(apic_id);
+                }
+            }
+            MADT_IO_APIC => madt.io_apics.push(IoApicInfo {
+                id: unsafe { read(table + off + 2) },
+                addr: unsafe { read::<u32>(table + off + 4) } as u64,
+                gsi_base: unsafe { read(table + off + 8) },
+            }),
+            MADT_SOURCE_OVERRIDE => madt.overrides.push(IrqOverride {
+                irq: unsafe { read(table + off + 3) },
+                gsi: unsafe { read(table + off + 4) },
+                flags: unsafe { read(table + off + 8) },
+            }),
+            MADT_LAPIC_OVERRIDE => madt.lapic_addr = unsafe { read(table + off + 4) },
+            _ => {}
+        }
+        off += entry_len as u64;
+    }
+    Some(madt)
+}
+
+/// Physical address of the HPET registers, from the HPET table.
+pub fn hpet_address() -> Option<u64> {
+    let table = find_table(HPET_SIGNATURE)?;
+    let addr = unsafe { read::<u64>(table + 44) };
+    if addr == 0 { None } else { Some(addr) }
+}
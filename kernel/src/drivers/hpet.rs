@@ -0,0 +1,52 @@
+use core::ptr::{read_volatile, write_volatile};
+
+const HPET_CAPABILITIES: u64 = 0x00;
+const HPET_CONFIG: u64 = 0x10;
+const HPET_COUNTER: u64 = 0xF0;
+
+const CAP_COUNTER_64: u64 = 1 << 13;
+const CONFIG_ENABLE: u64 = 1;
+/// Longest counter period the spec allows, 100 ns in femtoseconds.
+const MAX_PERIOD_FS: u64 = 100_000_000;
+
+static mut HPET_BASE: u64 = 0;
+/// Length of one counter tick in femtoseconds.
+static mut PERIOD_FS: u64 = 0;
+
+fn read(reg: u64) -> u64 {
+    unsafe { read_volatile((HPET_BASE + reg) as *const u64) }
+}
+
+fn write(reg: u64, value: u64) {
+    unsafe { write_volatile((HPET_BASE + reg) as *mut u64, value) }
+}
+
+/// Maps the HPET listed in ACPI and starts its main counter. Only 64-bit
+/// counters are used, so readings never wrap. Returns false if there is none.
+pub fn init() -> bool {
+    let phys = match crate::drivers::acpi::hpet_address() {
+        Some(a) => a,
+        None => return false,
+    };
+    unsafe { HPET_BASE = crate::memory::vmm::map_mmio(phys, 4096); }
+
+    let caps = read(HPET_CAPABILITIES);
+    let period = caps >> 32;
+    if caps & CAP_COUNTER_64 == 0 || period == 0 || period > MAX_PERIOD_FS {
+        unsafe { HPET_BASE = 0; }
+        return false;
+    }
+    unsafe { PERIOD_FS = period; }
+    write(HPET_CONFIG, read(HPET_CONFIG) | CONFIG_ENABLE);
+    crate::debugln!("HPET: counter at {} kHz", 1_000_000_000_000 / period);
+    true
+}
+
+pub fn is_present() -> bool {
+    unsafe { HPET_BASE != 0 }
+}
+
+/// Main counter reading in nanoseconds.
+pub fn nanos() -> u64 {
+    (read(HPET_COUNTER) as u128 * unsafe { PERIOD_FS } as u128 / 1_000_000) as u64
+}
@@ -2,4 +2,6 @@ pub mod video;
 pub mod periferics;
 pub mod port;
 pub mod pci;
-pub mod rtc;
\ No newline at end of file
+pub mod rtc;
+pub mod acpi;
+pub mod hpet;
\ No newline at end of file
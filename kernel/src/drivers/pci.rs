@@ -128,6 +128,32 @@ pub fn find_device_by_class(class_id: u32, subclass_id: u32) -> Option<PciDevice
     None
 }
 
+/// Every function present on the bus.
+pub fn devices() -> Vec<PciDevice> {
+    let mut found = Vec::new();
+    for bus in 0..=255 {
+        for device in 0..32 {
+            for function in 0..8 {
+                let id = pci_read(bus, device, function, 0);
+                if id & 0xFFFF == 0xFFFF {
+                    continue;
+                }
+                let class_subclass = pci_read(bus, device, function, 8);
+                found.push(PciDevice {
+                    class: (class_subclass >> 24) & 0xFF,
+                    subclass: (class_subclass >> 16) & 0xFF,
+                    vendor_id: id & 0xFFFF,
+                    device_id: id >> 16,
+                    bus,
+                    device,
+                    function,
+                });
+            }
+        }
+    }
+    found
+}
+
 pub fn list_devices() {
     for bus in 0..=255 {
         for device in 0..32 {
@@ -155,6 +181,13 @@ impl PciDevice {
         (value & 0xFF) as u8
     }
 
+    /// Legacy IRQ the firmware wired the device's INTx pin to, if it uses one.
+    pub fn interrupt_line(&self) -> Option<u8> {
+        let value = self.read_u32(0x3C);
+        let (line, pin) = ((value & 0xFF) as u8, ((value >> 8) & 0xFF) as u8);
+        if pin == 0 || line >= 16 { None } else { Some(line) }
+    }
+
     pub fn get_bar(&self, bar_index: u8) -> Option<u32> {
         if bar_index > 5 {
             return None;
@@ -4,6 +4,8 @@ use crate::sync::Mutex;
 
 #[allow(dead_code)]
 pub static KEYBOARD_BUFFER: Mutex<VecDeque<u32>> = Mutex::new(VecDeque::new());
+/// Readers of `KEYBOARD_BUFFER` sleep here.
+pub static KEYBOARD_WAIT: crate::interrupts::wait::WaitQueue = crate::interrupts::wait::WaitQueue::new();
 
 
 pub const KEY_LEFT: u32 = 0x110001;
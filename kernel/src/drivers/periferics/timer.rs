@@ -1,10 +1,14 @@
-use crate::drivers::port::outb;
+use crate::drivers::port::{inb, outb};
 
 const PIT_CHANNEL_0: u16 = 0x40;
+const PIT_CHANNEL_2: u16 = 0x42;
 const PIT_COMMAND: u16 = 0x43;
+/// Gate of channel 2 in bit 0, its output in bit 5; bit 1 drives the speaker.
+const PIT_GATE: u16 = 0x61;
+const PIT_FREQUENCY: u32 = 1193182;
 
 pub fn init_pit(frequency: u32) {
-    let divisor = 1193182 / frequency;
+    let divisor = PIT_FREQUENCY / frequency;
 
     unsafe {
         outb(PIT_COMMAND, 0x36);
@@ -14,3 +18,19 @@ pub fn init_pit(frequency: u32) {
         outb(PIT_CHANNEL_0, ((divisor >> 8) & 0xFF) as u8);
     }
 }
+
+/// Starts channel 2 counting down `ms` milliseconds, with the speaker off.
+/// Poll `oneshot_done` for the end; used to calibrate other timers.
+pub fn start_oneshot(ms: u32) {
+    let count = PIT_FREQUENCY * ms / 1000;
+    let gate = inb(PIT_GATE) & !0b11;
+    outb(PIT_GATE, gate);
+    outb(PIT_COMMAND, 0xB0);
+    outb(PIT_CHANNEL_2, (count & 0xFF) as u8);
+    outb(PIT_CHANNEL_2, ((count >> 8) & 0xFF) as u8);
+    outb(PIT_GATE, gate | 1);
+}
+
+pub fn oneshot_done() -> bool {
+    (inb(PIT_GATE) & 0x20) != 0
+}
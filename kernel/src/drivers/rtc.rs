@@ -2,6 +2,8 @@ use crate::drivers::port::Port;
 
 const CMOS_ADDR: u16 = 0x70;
 const CMOS_DATA: u16 = 0x71;
+const CMOS_STATUS_A: u8 = 0x0A;
+const UPDATE_IN_PROGRESS: u8 = 0x80;
 
 pub fn read_rtc(reg: u8) -> u8 {
     unsafe {
@@ -10,6 +12,14 @@ pub fn read_rtc(reg: u8) -> u8 {
     }
 }
 
+fn from_bcd(value: u8, register_b: u8) -> u8 {
+    if (register_b & 0x04) == 0 {
+        (value & 0x0F) + ((value / 16) * 10)
+    } else {
+        value
+    }
+}
+
 pub fn get_time() -> (u8, u8, u8) {
     let mut second = read_rtc(0x00);
     let mut minute = read_rtc(0x02);
@@ -30,3 +40,20 @@ pub fn get_time() -> (u8, u8, u8) {
 
     (hour, minute, second)
 }
+
+/// Seconds since the Unix epoch, taking the RTC to run on UTC.
+pub fn unix_time() -> u64 {
+    while read_rtc(CMOS_STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    let (hour, minute, second) = get_time();
+    let register_b = read_rtc(0x0B);
+    let day = from_bcd(read_rtc(0x07), register_b) as u64;
+    let month = from_bcd(read_rtc(0x08), register_b) as u64;
+    let year = 2000 + from_bcd(read_rtc(0x09), register_b) as u64;
+
+    // Days from civil date, with March as the first month of the year.
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let days = y * 365 + y / 4 - y / 100 + y / 400 + (153 * m + 2) / 5 + day - 1 - 719_468;
+    days * 86_400 + hour as u64 * 3600 + minute as u64 * 60 + second as u64
+}
@@ -0,0 +1,199 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use crate::fs::disk;
+use crate::sync::Mutex;
+
+/// Bytes per cached block: eight sectors, aligned on the disk.
+const BLOCK_SIZE: usize = 4096;
+const SECTORS: u64 = (BLOCK_SIZE / 512) as u64;
+/// Blocks kept before the least recently used one is evicted.
+const CAPACITY: usize = 1024;
+/// Blocks merged into one transfer on write-back.
+const MAX_RUN: usize = 32;
+/// How long a block may stay dirty before the flusher writes it back.
+const WRITEBACK_AGE_NS: u64 = 5_000_000_000;
+const FLUSH_INTERVAL_NS: u64 = 1_000_000_000;
+
+/// Disk and block number, in `BLOCK_SIZE` units from the start of the disk.
+type Key = (u8, u64);
+
+struct Buffer {
+    data: Vec<u8>,
+    /// When it was dirtied since its last write-back, in ns since boot.
+    dirty_since: Option<u64>,
+    /// Its entry in the LRU order.
+    stamp: u64,
+}
+
+struct BlockCache {
+    blocks: BTreeMap<Key, Buffer>,
+    /// Cached blocks by last use, oldest first.
+    lru: BTreeMap<u64, Key>,
+    clock: u64,
+}
+
+static CACHE: Mutex<BlockCache> = Mutex::new(BlockCache {
+    blocks: BTreeMap::new(),
+    lru: BTreeMap::new(),
+    clock: 0,
+});
+
+impl BlockCache {
+    fn touch(&mut self, key: Key) {
+        if let Some(buf) = self.blocks.get_mut(&key) {
+            self.lru.remove(&buf.stamp);
+            self.clock += 1;
+            buf.stamp = self.clock;
+            self.lru.insert(self.clock, key);
+        }
+    }
+
+    /// Drops the least recently used block, writing it back if dirty, and
+    /// returns its memory.
+    fn evict(&mut self) -> Option<Vec<u8>> {
+        let (_, key) = self.lru.pop_first()?;
+        let buf = self.blocks.remove(&key)?;
+        if buf.dirty_since.is_some() {
+            disk::write(key.1 * SECTORS, key.0, &buf.data);
+        }
+        Some(buf.data)
+    }
+
+    /// Block `key`, brought in from disk if it is not cached. None when
+    /// there is no memory for it and nothing to evict.
+    fn get(&mut self, key: Key) -> Option<&mut Buffer> {
+        if self.blocks.contains_key(&key) {
+            self.touch(key);
+        } else {
+            let mut data = Vec::new();
+            if self.blocks.len() >= CAPACITY || data.try_reserve_exact(BLOCK_SIZE).is_err() {
+                data = self.evict()?;
+            }
+            data.resize(BLOCK_SIZE, 0);
+            disk::read(key.1 * SECTORS, key.0, &mut data);
+            self.clock += 1;
+            self.lru.insert(self.clock, key);
+            self.blocks.insert(key, Buffer { data, dirty_since: None, stamp: self.clock });
+        }
+        self.blocks.get_mut(&key)
+    }
+
+    /// Whole blocks from `key` on, at most `max`, that are not cached.
+    fn uncached_run(&self, key: Key, max: usize) -> usize {
+        (0..max).take_while(|&i| !self.blocks.contains_key(&(key.0, key.1 + i as u64))).count()
+    }
+
+    /// Writes back the dirty blocks `pick` selects, merging neighbours into
+    /// one transfer.
+    fn write_back(&mut self, pick: impl Fn(&Key, &Buffer) -> bool) {
+        let keys: Vec<Key> = self.blocks.iter()
+            .filter(|(key, buf)| buf.dirty_since.is_some() && pick(key, buf))
+            .map(|(key, _)| *key)
+            .collect();
+        let mut run = Vec::new();
+        let mut start = 0;
+        for (i, &key) in keys.iter().enumerate() {
+            if run.is_empty() {
+                start = i;
+            }
+            let buf = self.blocks.get_mut(&key).unwrap();
+            buf.dirty_since = None;
+            run.extend_from_slice(&buf.data);
+            let joined = keys.get(i + 1).map_or(false, |&(d, b)| d == key.0 && b == key.1 + 1);
+            if !joined || i + 1 - start >= MAX_RUN {
+                disk::write(keys[start].1 * SECTORS, key.0, &run);
+                run.clear();
+            }
+        }
+    }
+}
+
+/// Reads `buf.len()` bytes at byte `offset` of disk `disk`. Whole blocks
+/// nobody has cached go straight to the caller, so bulk file data and swap
+/// do not push metadata out of the cache.
+pub fn read(disk: u8, offset: u64, buf: &mut [u8]) {
+    let mut cache = CACHE.lock();
+    let mut done = 0;
+    while done < buf.len() {
+        let at = offset + done as u64;
+        let key = (disk, at / BLOCK_SIZE as u64);
+        let within = (at % BLOCK_SIZE as u64) as usize;
+        let len = (buf.len() - done).min(BLOCK_SIZE - within);
+        let whole = if within == 0 { cache.uncached_run(key, (buf.len() - done) / BLOCK_SIZE) } else { 0 };
+        if whole > 0 {
+            let len = whole * BLOCK_SIZE;
+            disk::read(key.1 * SECTORS, disk, &mut buf[done..done + len]);
+            done += len;
+            continue;
+        }
+        match cache.get(key) {
+            Some(block) => buf[done..done + len].copy_from_slice(&block.data[within..within + len]),
+            None => {
+                let mut block = [0u8; BLOCK_SIZE];
+                disk::read(key.1 * SECTORS, disk, &mut block);
+                buf[done..done + len].copy_from_slice(&block[within..within + len]);
+            }
+        }
+        done += len;
+    }
+}
+
+/// Writes `buf` at byte `offset` of disk `disk`. Cached and partial blocks
+/// are only marked dirty; uncached whole blocks are written through.
+pub fn write(disk: u8, offset: u64, buf: &[u8]) {
+    let mut cache = CACHE.lock();
+    let now = crate::time::now_ns();
+    let mut done = 0;
+    while done < buf.len() {
+        let at = offset + done as u64;
+        let key = (disk, at / BLOCK_SIZE as u64);
+        let within = (at % BLOCK_SIZE as u64) as usize;
+        let len = (buf.len() - done).min(BLOCK_SIZE - within);
+        let whole = if within == 0 { cache.uncached_run(key, (buf.len() - done) / BLOCK_SIZE) } else { 0 };
+        if whole > 0 {
+            let len = whole * BLOCK_SIZE;
+            disk::write(key.1 * SECTORS, disk, &buf[done..done + len]);
+            done += len;
+            continue;
+        }
+        match cache.get(key) {
+            Some(block) => {
+                block.data[within..within + len].copy_from_slice(&buf[done..done + len]);
+                block.dirty_since.get_or_insert(now);
+            }
+            None => {
+                let mut block = [0u8; BLOCK_SIZE];
+                disk::read(key.1 * SECTORS, disk, &mut block);
+                block[within..within + len].copy_from_slice(&buf[done..done + len]);
+                disk::write(key.1 * SECTORS, disk, &block);
+            }
+        }
+        done += len;
+    }
+}
+
+/// Writes back every dirty block of `disk`, or of every disk for None.
+pub fn sync(disk: Option<u8>) {
+    CACHE.lock().write_back(|key, _| disk.map_or(true, |d| key.0 == d));
+}
+
+/// Writes back and drops every block of `disk`, once nothing has it mounted.
+pub fn invalidate(disk: u8) {
+    let mut cache = CACHE.lock();
+    cache.write_back(|key, _| key.0 == disk);
+    cache.blocks.retain(|key, _| key.0 != disk);
+    cache.lru.retain(|_, key| key.0 != disk);
+}
+
+/// Body of the flusher kernel thread: writes back blocks dirty for longer
+/// than `WRITEBACK_AGE_NS`, waking every `FLUSH_INTERVAL_NS`.
+pub fn flusher() -> ! {
+    loop {
+        crate::interrupts::task::TASK_MANAGER.int_lock().sleep_current(crate::time::deadline_after(FLUSH_INTERVAL_NS));
+        crate::interrupts::task::yield_now();
+
+        let _kernel = crate::sync::KERNEL_LOCK.lock();
+        let cutoff = crate::time::now_ns().saturating_sub(WRITEBACK_AGE_NS);
+        CACHE.lock().write_back(|_, buf| buf.dirty_since.map_or(false, |t| t <= cutoff));
+    }
+}
@@ -138,6 +138,41 @@ pub fn is_busy() -> bool {
     (status & 0b10000000) != 0
 }
 
+/// Sectors the ATA disk `disk` (0xE0 or 0xF0) can address, from IDENTIFY.
+/// None if no ATA disk answers. Capped to LBA28, which is all this driver issues.
+pub fn sector_count(disk: u8) -> Option<u64> {
+    while is_busy() {}
+    outb(0x1F6, disk & 0xF0);
+    outb(0x1F2, 0);
+    outb(0x1F3, 0);
+    outb(0x1F4, 0);
+    outb(0x1F5, 0);
+    outb(0x1F7, 0xEC);
+    if inb(0x1F7) == 0 {
+        return None;
+    }
+    while is_busy() {}
+    // ATAPI and SATA devices set the LBA mid/high signature instead.
+    if inb(0x1F4) != 0 || inb(0x1F5) != 0 {
+        return None;
+    }
+    loop {
+        let status = inb(0x1F7);
+        if (status & 0x01) != 0 {
+            return None;
+        }
+        if (status & 0x08) != 0 {
+            break;
+        }
+    }
+    let mut id = [0u16; 256];
+    for word in id.iter_mut() {
+        *word = inw(0x1F0);
+    }
+    let lba28 = id[60] as u64 | (id[61] as u64) << 16;
+    Some(lba28.min(1 << 28))
+}
+
 fn delay() {
     for _ in 0..10000 {
         unsafe { asm!("nop") };
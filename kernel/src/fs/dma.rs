@@ -17,55 +17,51 @@ fn test_pci_detection() {
     }
 }
 
-#[allow(dead_code)]
+#[repr(C)]
 struct PrdtEntry {
     buffer_phys: u32,
     transfer_size: u16,
     flags: u16,
 }
 
-#[allow(dead_code)]
-static mut PRDT: PrdtEntry = PrdtEntry {
-    buffer_phys: 0,
-    transfer_size: 0,
-    flags: 0,
-};
+/// One PRD entry moves at most 64 KiB.
+const MAX_SECTORS: usize = 128;
+const BOUNCE_PAGES: usize = MAX_SECTORS * 512 / 4096;
+
+/// PRD table and bounce buffer, in the DMA zone: the bus master only takes
+/// 32-bit addresses, and a 64 KiB block there never crosses a 64 KiB boundary.
+static mut PRDT_PHYS: u64 = 0;
+static mut BOUNCE_PHYS: u64 = 0;
 
 static mut BM_BASE: u16 = 0;
 static mut BMR_COMMAND: u16 = unsafe { BM_BASE + 0 };
 static mut BMR_STATUS: u16 = unsafe { BM_BASE + 2 };
 static mut BMR_PRDT: u16 = unsafe { BM_BASE + 4 };
 
-#[allow(dead_code)]
 const ATA: u16 = 0x1F0;
-#[allow(dead_code)]
 const ATA_DISK: u16 = ATA + 6;
-#[allow(dead_code)]
 const ATA_SECTOR: u16 = ATA + 2;
-#[allow(dead_code)]
 const ATA_LBA_LOW: u16 = ATA + 3;
-#[allow(dead_code)]
 const ATA_LBA_MID: u16 = ATA + 4;
-#[allow(dead_code)]
 const ATA_LBA_HIG: u16 = ATA + 5;
-#[allow(dead_code)]
 const ATA_COMMAND: u16 = ATA + 7;
 
-#[allow(dead_code)]
 const ATA_READ_DMA: u8 = 0xC8;
-#[allow(dead_code)]
 const ATA_WRITE_DMA: u8 = 0xCA;
 
-#[allow(dead_code)]
-pub fn read(lba: u64, disk: u8, target: &mut [u8]) {
-    let sectors = (target.len() / 512) as u8;
+/// Runs one bus-master transfer of `sectors` through the bounce buffer.
+unsafe fn transfer(lba: u64, disk: u8, sectors: usize, command: u8, to_memory: bool) {
     unsafe {
-        PRDT.buffer_phys = target.as_mut_ptr() as u32;
-        PRDT.transfer_size = 512 * sectors as u16;
-        PRDT.flags = 0x8000;
+        outb(BMR_COMMAND, 0);
 
-        outl(BMR_PRDT, core::ptr::addr_of!(PRDT) as u32);
+        let prdt = (PRDT_PHYS + crate::memory::paging::HHDM_OFFSET) as *mut PrdtEntry;
+        prdt.write(PrdtEntry {
+            buffer_phys: BOUNCE_PHYS as u32,
+            transfer_size: (512 * sectors) as u16,
+            flags: 0x8000,
+        });
 
+        outl(BMR_PRDT, PRDT_PHYS as u32);
 
         let drive_select = 0xE0 | ((disk & 1) << 4);
         outb(ATA_DISK, (drive_select as u64 | ((lba >> 24) & 0x0F)) as u8);
@@ -75,9 +71,9 @@ pub fn read(lba: u64, disk: u8, target: &mut [u8]) {
         outb(ATA_LBA_MID, (lba >> 8) as u8);
         outb(ATA_LBA_HIG, (lba >> 16) as u8);
 
-        outb(ATA_COMMAND, ATA_READ_DMA);
+        outb(ATA_COMMAND, command);
 
-        outb(BMR_COMMAND, 0x8 | 0x1);
+        outb(BMR_COMMAND, if to_memory { 0x8 | 0x1 } else { 0x1 });
 
         loop {
             let status = inb(BMR_STATUS);
@@ -100,52 +96,50 @@ pub fn read(lba: u64, disk: u8, target: &mut [u8]) {
     }
 }
 
-#[allow(dead_code)]
-pub fn write(lba: u64, disk: u8, buffer: &[u8]) {
-    let sectors = (buffer.len() / 512) as u8;
-    unsafe {
-        outb(BMR_COMMAND, 0);
-
-        PRDT.buffer_phys = buffer.as_ptr() as u32;
-        PRDT.transfer_size = 512 * sectors as u16;
-        PRDT.flags = 0x8000;
-
-        outl(BMR_PRDT, core::ptr::addr_of!(PRDT) as u32);
-
-        let drive_select = 0xE0 | ((disk & 1) << 4);
-        outb(ATA_DISK, (drive_select as u64 | ((lba >> 24) & 0x0F)) as u8);
-
-        outb(ATA_SECTOR, sectors as u8);
-        outb(ATA_LBA_LOW, lba as u8);
-        outb(ATA_LBA_MID, (lba >> 8) as u8);
-        outb(ATA_LBA_HIG, (lba >> 16) as u8);
-
-        outb(ATA_COMMAND, ATA_WRITE_DMA);
-
-        outb(BMR_COMMAND, 0x1);
-
-        loop {
-            let status = inb(BMR_STATUS);
-
-            if status & 0x02 != 0 {
-                panic!("DMA error occurred");
-            }
-
-            if (status & 0x04) == 0 {
-                break;
-            }
+pub fn read(lba: u64, disk: u8, target: &mut [u8]) {
+    let bounce = unsafe { (BOUNCE_PHYS + crate::memory::paging::HHDM_OFFSET) as *const u8 };
+    let sectors = target.len() / 512;
+    let mut done = 0;
+    while done < sectors {
+        let count = (sectors - done).min(MAX_SECTORS);
+        unsafe {
+            transfer(lba + done as u64, disk, count, ATA_READ_DMA, true);
+            core::ptr::copy_nonoverlapping(bounce, target[done * 512..].as_mut_ptr(), count * 512);
         }
+        done += count;
+    }
+}
 
-        let mut command = inb(BMR_COMMAND);
-        command &= !0x01;
-
-
-        outb(BMR_COMMAND, command);
+pub fn write(lba: u64, disk: u8, buffer: &[u8]) {
+    let bounce = unsafe { (BOUNCE_PHYS + crate::memory::paging::HHDM_OFFSET) as *mut u8 };
+    let sectors = buffer.len() / 512;
+    let mut done = 0;
+    while done < sectors {
+        let count = (sectors - done).min(MAX_SECTORS);
+        unsafe {
+            core::ptr::copy_nonoverlapping(buffer[done * 512..].as_ptr(), bounce, count * 512);
+            transfer(lba + done as u64, disk, count, ATA_WRITE_DMA, false);
+        }
+        done += count;
     }
 }
 
 #[allow(dead_code)]
 pub fn init() {
+    use crate::memory::pmm::{self, Zone};
+
+    let prdt = pmm::allocate_frames_in(Zone::Dma, 1, 0);
+    let bounce = pmm::allocate_frames_in(Zone::Dma, BOUNCE_PAGES, 0);
+    match (prdt, bounce) {
+        (Some(prdt), Some(bounce)) => unsafe {
+            PRDT_PHYS = prdt;
+            BOUNCE_PHYS = bounce;
+        },
+        _ => {
+            crate::debugln!("DMA: no memory below 16 MiB, staying on PIO");
+            return;
+        }
+    }
     test_pci_detection();
 }
 
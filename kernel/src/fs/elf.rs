@@ -1,11 +1,38 @@
-use crate::memory::{paging, pmm, vmm};
+use crate::memory::{paging, vmm};
 use crate::memory::address::PhysAddr;
+use crate::memory::vma::{self, Vma, VmaKind, VmaList};
 use alloc::string::String;
 use alloc::format;
 #[allow(unused_imports)]
 use elfic::{Elf64, Elf64Phdr, Elf64Rela, Elf64Sym, ProgramFlags, ProgramType};
 
-pub fn load_elf(data: &[u8], target_pml4_phys: u64, pid: u64) -> Result<u64, String> {
+/// Initial contents of every thread's TLS block, from the `PT_TLS` segment.
+#[derive(Debug, Clone, Copy)]
+pub struct TlsTemplate {
+    /// User address of the `.tdata` image; the rest up to `mem_size` is `.tbss`.
+    pub image: u64,
+    pub file_size: u64,
+    pub mem_size: u64,
+    pub align: u64,
+}
+
+impl TlsTemplate {
+    /// Distance from the start of the block to the thread pointer, which
+    /// the block ends at (x86-64 variant II).
+    pub fn block_size(&self) -> u64 {
+        let align = self.align.max(1);
+        (self.mem_size + align - 1) / align * align
+    }
+}
+
+pub struct ElfImage {
+    pub entry: u64,
+    pub tls: Option<TlsTemplate>,
+}
+
+/// Maps the loadable segments at `load_base` into `target_pml4_phys` and records them in `vmas`.
+/// Pages holding file data are copied in now; pure `.bss` pages are left to demand paging.
+pub fn load_elf(data: &[u8], target_pml4_phys: u64, pid: u64, load_base: u64, vmas: &mut VmaList) -> Result<ElfImage, String> {
     crate::debugln!("load_elf: START pid={}", pid);
 
     let elf = Elf64::new(data).map_err(|e| format!("ELF Parse Error: {:?}", e))?;
@@ -14,11 +41,17 @@ pub fn load_elf(data: &[u8], target_pml4_phys: u64, pid: u64) -> Result<u64, Str
         return Err(format!("Security Violation: Non-PIE executable (Type {})", elf.header.e_type + 0));
     }
 
-    
-    let load_base = 0x400000;
     crate::debugln!("load_elf: Base address: {:#x}", load_base);
 
     let mut max_end: u64 = 0;
+    let tls = elf.program_headers().iter()
+        .find(|phdr| ProgramType::from(phdr.p_type) == ProgramType::Tls && phdr.p_memsz != 0)
+        .map(|phdr| TlsTemplate {
+            image: phdr.p_vaddr + load_base,
+            file_size: phdr.p_filesz,
+            mem_size: phdr.p_memsz,
+            align: phdr.p_align,
+        });
 
     for phdr in elf.program_headers() {
         if ProgramType::from(phdr.p_type) == ProgramType::Load {
@@ -34,18 +67,23 @@ pub fn load_elf(data: &[u8], target_pml4_phys: u64, pid: u64) -> Result<u64, Str
 
             let page_start = virt_start & !(paging::PAGE_SIZE - 1);
             let page_end = (virt_end + paging::PAGE_SIZE - 1) & !(paging::PAGE_SIZE - 1);
-            
+            let file_page_end = (virt_start + phdr.p_filesz + paging::PAGE_SIZE - 1) & !(paging::PAGE_SIZE - 1);
+
+            let mut flags = paging::PAGE_PRESENT | paging::PAGE_USER;
+            if (phdr.p_flags & ProgramFlags::WRITE) != 0 {
+                flags |= paging::PAGE_WRITABLE;
+            }
+            vmas.insert(Vma::new(page_start, page_end, flags, VmaKind::Image));
+
             let mut current_page = page_start;
-            while current_page < page_end {
-                let frame = pmm::allocate_frame(pid).ok_or("OOM during ELF loading")?;
-                
-                let mut flags = paging::PAGE_PRESENT | paging::PAGE_USER;
-                if (phdr.p_flags & ProgramFlags::WRITE) != 0 {
-                    flags |= paging::PAGE_WRITABLE;
-                }
+            while current_page < file_page_end {
+                let frame = crate::memory::oom::allocate_frame(pid).ok_or("OOM during ELF loading")?;
                 
                 
-                vmm::map_page(current_page, PhysAddr::new(frame), flags, Some(target_pml4_phys));
+                if vmm::map_page(current_page, PhysAddr::new(frame), flags, Some(target_pml4_phys)).is_err() {
+                    crate::memory::pmm::free_frame(frame);
+                    return Err(String::from("OOM during ELF loading"));
+                }
 
                 
                 let dest_ptr = (frame + paging::HHDM_OFFSET) as *mut u8;
@@ -102,6 +140,18 @@ pub fn load_elf(data: &[u8], target_pml4_phys: u64, pid: u64) -> Result<u64, Str
                         val = load_base.wrapping_add(rela.r_addend as u64);
                         found_val = true;
                     }
+                    // R_X86_64_TPOFF64: offset of a TLS variable from the thread pointer.
+                    18 => {
+                        if let (Some(sym_tab), Some(tls)) = (dynsym_shdr, tls) {
+                            let sym_offset = sym_tab.sh_offset as usize + r_sym as usize * core::mem::size_of::<Elf64Sym>();
+                            if sym_offset < data.len() {
+                                let sym = unsafe { &*(data.as_ptr().add(sym_offset) as *const Elf64Sym) };
+                                let value = if r_sym != 0 { sym.st_value } else { 0 };
+                                val = value.wrapping_add(rela.r_addend as u64).wrapping_sub(tls.block_size());
+                                found_val = true;
+                            }
+                        }
+                    }
                     1 | 6 | 7 => {
                         if let Some(sym_tab) = dynsym_shdr {
                             let sym_offset = (sym_tab.sh_offset as usize + 0) + (r_sym as usize * core::mem::size_of::<Elf64Sym>());
@@ -118,6 +168,11 @@ pub fn load_elf(data: &[u8], target_pml4_phys: u64, pid: u64) -> Result<u64, Str
 
                 if found_val {
                     unsafe {
+                        if vmm::get_phys(target_virt, target_pml4_phys).is_none() {
+                            if let Some(area) = vmas.find(target_virt) {
+                                vma::populate(&area, target_virt, target_pml4_phys, pid);
+                            }
+                        }
                         if let Some(phys) = vmm::get_phys(target_virt, target_pml4_phys) {
                             let patch_ptr = (phys + paging::HHDM_OFFSET) as *mut u64;
                             *patch_ptr = val;
@@ -130,5 +185,5 @@ pub fn load_elf(data: &[u8], target_pml4_phys: u64, pid: u64) -> Result<u64, Str
 
     let entry_point = (elf.header.e_entry + 0) + load_base;
     crate::debugln!("load_elf: END entry_point={:#x}", entry_point);
-    Ok(entry_point)
+    Ok(ElfImage { entry: entry_point, tls })
 }
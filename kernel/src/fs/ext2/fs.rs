@@ -17,8 +17,6 @@ pub struct Ext2 {
     block_size: u64,
     inodes_per_group: u32,
     inode_size: u16,
-    cache_lba: Option<u64>,
-    cache_data: [u8; 512],
     pub lock: Mutex<()>,
 }
 
@@ -54,8 +52,6 @@ impl Ext2 {
             block_size: block_size as u64,
             inodes_per_group: superblock.inodes_per_group,
             inode_size,
-            cache_lba: None,
-            cache_data: [0; 512],
             lock: Mutex::new(()),
         }))
     }
@@ -66,71 +62,25 @@ unsafe impl Sync for Ext2 {}
 
 impl Ext2 {
     fn read_disk_data(&mut self, offset: u64, buffer: &mut [u8]) {
-        let abs_offset = offset + (self.base_lba * 512);
-        let start_lba = abs_offset / 512;
-        let offset_in_sector = (abs_offset % 512) as usize;
-
-
-        if offset_in_sector == 0 && (buffer.len() % 512) == 0 && buffer.len() >= 512 {
-            disk::read(start_lba, self.disk_id, buffer);
-            return;
-        }
-
-        let mut current_lba = start_lba;
-        let mut bytes_read = 0;
-        let total_bytes = buffer.len();
-
-        while bytes_read < total_bytes {
-            if self.cache_lba != Some(current_lba) {
-                disk::read(current_lba, self.disk_id, &mut self.cache_data);
-                self.cache_lba = Some(current_lba);
-            }
-
-            let start_index = if current_lba == start_lba { offset_in_sector } else { 0 };
-            let remaining_in_sector = 512 - start_index;
-            let to_copy = core::cmp::min(total_bytes - bytes_read, remaining_in_sector);
-
-            buffer[bytes_read..bytes_read + to_copy].copy_from_slice(&self.cache_data[start_index..start_index + to_copy]);
-
-            bytes_read += to_copy;
-            current_lba += 1;
-        }
+        crate::fs::bcache::read(self.disk_id, offset + self.base_lba * 512, buffer);
     }
 
     fn write_disk_data(&mut self, offset: u64, buffer: &[u8]) {
-        let abs_offset = offset + (self.base_lba * 512);
-        let start_lba = abs_offset / 512;
-        let offset_in_sector = (abs_offset % 512) as usize;
-
+        crate::fs::bcache::write(self.disk_id, offset + self.base_lba * 512, buffer);
+    }
 
-        if offset_in_sector == 0 && (buffer.len() % 512) == 0 && buffer.len() >= 512 {
-            disk::write(start_lba, self.disk_id, buffer);
-            return;
+    /// Directory entry file type for an inode of `mode`.
+    fn dirent_type(mode: u16) -> u8 {
+        match mode & 0xF000 {
+            0x4000 => 2,
+            0xA000 => 7,
+            _ => 1,
         }
+    }
 
-        let mut current_lba = start_lba;
-        let mut bytes_written = 0;
-        let total_bytes = buffer.len();
-
-        while bytes_written < total_bytes {
-            if self.cache_lba != Some(current_lba) {
-                disk::read(current_lba, self.disk_id, &mut self.cache_data);
-                self.cache_lba = Some(current_lba);
-            }
-
-            let start_index = if current_lba == start_lba { offset_in_sector } else { 0 };
-            let remaining_in_sector = 512 - start_index;
-            let to_copy = core::cmp::min(total_bytes - bytes_written, remaining_in_sector);
-
-
-            self.cache_data[start_index..start_index + to_copy].copy_from_slice(&buffer[bytes_written..bytes_written + to_copy]);
-
-
-            disk::write(current_lba, self.disk_id, &self.cache_data);
-
-            bytes_written += to_copy;
-            current_lba += 1;
-        }
+    /// Whether `inode` is a symlink keeping its target in `block` itself.
+    fn is_fast_symlink(inode: &Inode) -> bool {
+        (inode.mode & 0xF000) == 0xA000 && inode.blocks == 0
     }
 
     pub fn read_block_group_descriptor(&mut self, group_idx: u32) -> BlockGroupDescriptor {
@@ -444,6 +394,42 @@ impl Ext2 {
         }
     }
 
+    /// Frees `block` and, through `depth` levels of indirection, every block
+    /// it points to.
+    fn free_block_tree(&mut self, block: u32, depth: u32) {
+        if block == 0 { return; }
+        if depth > 0 {
+            let mut ptrs = alloc::vec![0u8; self.block_size as usize];
+            self.read_disk_data(block as u64 * self.block_size, &mut ptrs);
+            for ptr in ptrs.chunks_exact(4) {
+                self.free_block_tree(u32::from_le_bytes([ptr[0], ptr[1], ptr[2], ptr[3]]), depth - 1);
+            }
+        }
+        self.free_block(block);
+    }
+
+    /// Frees every data and indirect block of `inode` and empties its block array.
+    fn free_inode_blocks(&mut self, inode: &mut Inode) {
+        for i in 0..15 {
+            let depth = if i < 12 { 0 } else { i as u32 - 11 };
+            self.free_block_tree(inode.block[i], depth);
+            inode.block[i] = 0;
+        }
+        inode.blocks = 0;
+    }
+
+    /// Frees the blocks and the number of unlinked inode `ino`.
+    fn release_inode(&mut self, ino: u32, inode: &mut Inode) {
+        // A fast symlink's block array holds its target, not blocks.
+        if Ext2::is_fast_symlink(inode) {
+            inode.block = [0; 15];
+        }
+        self.free_inode_blocks(inode);
+        inode.dtime = unix_now();
+        self.write_inode(ino, inode);
+        self.free_inode(ino);
+    }
+
     fn free_inode(&mut self, inode_id: u32) {
         if inode_id == 0 { return; }
 
@@ -474,7 +460,19 @@ impl Ext2 {
 }
 
 use crate::fs::ext2::structs::DirectoryEntry;
-use crate::fs::vfs::{FileSystem, FileType, VfsNode};
+use crate::fs::vfs::{FileSystem, FileType, Metadata, SetAttr, VfsNode, S_IFMT};
+
+/// Bytes of `Inode::block`, which holds symlink targets shorter than this.
+const FAST_SYMLINK_MAX: usize = 60;
+/// Age after which a read updates the access time even if nothing changed since.
+const ATIME_REFRESH_SECS: u32 = 24 * 60 * 60;
+/// Most hard links an inode may have, as Linux allows.
+const EXT2_LINK_MAX: u16 = 65000;
+
+/// Wall clock in seconds since the Unix epoch, as ext2 keeps times.
+fn unix_now() -> u32 {
+    (crate::time::realtime_ns() / 1_000_000_000) as u32
+}
 
 pub struct Ext2Node {
     fs: *mut Ext2,
@@ -507,6 +505,10 @@ impl VfsNode for Ext2Node {
         self.name.clone()
     }
 
+    fn ino(&self) -> u64 {
+        self.inode_idx as u64
+    }
+
     fn size(&self) -> u64 {
         self.inode.size as u64
     }
@@ -516,6 +518,8 @@ impl VfsNode for Ext2Node {
             FileType::Directory
         } else if (self.inode.mode & 0xF000) == 0x8000 {
             FileType::File
+        } else if (self.inode.mode & 0xF000) == 0xA000 {
+            FileType::Symlink
         } else {
             FileType::Unknown
         }
@@ -630,6 +634,16 @@ impl VfsNode for Ext2Node {
             bytes_read += to_copy;
         }
 
+        // As with relatime: only when the last access predates the last
+        // change, or is a day old, so reads rarely write the inode.
+        let now = unix_now();
+        let atime = self.inode.atime;
+        if bytes_read > 0 && (atime < self.inode.mtime || atime < self.inode.ctime || now.saturating_sub(atime) >= ATIME_REFRESH_SECS) {
+            self.inode.atime = now;
+            let _lock = fs.lock.lock();
+            unsafe { (*fs_ptr).write_inode(self.inode_idx, &self.inode) };
+        }
+
         Ok(bytes_read)
     }
 
@@ -707,10 +721,9 @@ impl VfsNode for Ext2Node {
 
 
         if current_offset > self.inode.size as u64 {
-            let _lock = fs.lock.lock();
             self.inode.size = current_offset as u32;
-            unsafe { (*fs_ptr).write_inode(self.inode_idx, &self.inode) };
         }
+        self.touch_modified();
 
         Ok(bytes_written)
     }
@@ -904,11 +917,11 @@ impl VfsNode for Ext2Node {
 
 
     fn create_file(&mut self, name: &str) -> Result<Box<dyn VfsNode>, String> {
-        self.create_node(name, 0x81B4)
+        Ok(Box::new(self.create_node(name, 0x81B4)?))
     }
 
     fn create_dir(&mut self, name: &str) -> Result<Box<dyn VfsNode>, String> {
-        self.create_node(name, 0x41ED)
+        Ok(Box::new(self.create_node(name, 0x41ED)?))
     }
 
     fn remove(&mut self, name: &str) -> Result<(), String> {
@@ -999,28 +1012,14 @@ impl VfsNode for Ext2Node {
 
                     if target_inode.links_count > 0 {
                         target_inode.links_count -= 1;
-                        if target_inode.links_count == 0 {
-                            {
-                                let _lock = fs.lock.lock();
-                                unsafe {
-                                    for i in 0..12 {
-                                        if target_inode.block[i] != 0 {
-                                            (*fs_ptr).free_block(target_inode.block[i]);
-                                            target_inode.block[i] = 0;
-                                        }
-                                    }
-
-
-                                    (*fs_ptr).write_inode(inode_to_free, &target_inode);
-                                    (*fs_ptr).free_inode(inode_to_free);
-                                }
-                            }
-                        } else {
-                            let _lock = fs.lock.lock();
-                            unsafe { (*fs_ptr).write_inode(inode_to_free, &target_inode) };
-                        }
+                        target_inode.ctime = unix_now();
+                        // At 0 links the inode is an orphan; `evict` frees it
+                        // once nothing has it open.
+                        let _lock = fs.lock.lock();
+                        unsafe { (*fs_ptr).write_inode(inode_to_free, &target_inode) };
                     }
 
+                    self.touch_modified();
                     return Ok(());
                 }
 
@@ -1086,6 +1085,8 @@ impl VfsNode for Ext2Node {
                             } else if (child_inode.mode & 0xF000) == 0x8000 {
                                 1 
 
+                            } else if (child_inode.mode & 0xF000) == 0xA000 {
+                                4
                             } else {
                                 0 
 
@@ -1191,24 +1192,183 @@ impl VfsNode for Ext2Node {
 
         if size == 0 {
             // Support simple truncation to 0 for now
-            for i in 0..15 {
-                let block = self.inode.block[i];
-                if block != 0 {
-                    unsafe { (*fs_ptr).free_block(block) };
-                    self.inode.block[i] = 0;
-                }
-            }
+            unsafe { (*fs_ptr).free_inode_blocks(&mut self.inode) };
             self.inode.size = 0;
-            self.inode.blocks = 0;
+            let now = unix_now();
+            self.inode.mtime = now;
+            self.inode.ctime = now;
             unsafe { (*fs_ptr).write_inode(self.inode_idx, &self.inode) };
             Ok(())
         } else {
             Err(String::from("Partial truncate not yet supported"))
         }
     }
+
+    fn sync(&mut self) -> Result<(), String> {
+        // Blocks are not tracked per inode, so this writes back the whole disk.
+        crate::fs::bcache::sync(Some(unsafe { (*self.fs).disk_id }));
+        Ok(())
+    }
+
+    fn link(&mut self, name: &str, ino: u64) -> Result<(), String> {
+        if self.kind() != FileType::Directory {
+            return Err(String::from("Not a directory"));
+        }
+        if self.find_internal(name).is_ok() {
+            return Err(String::from("File already exists"));
+        }
+        let fs = unsafe { &mut *self.fs };
+        let fs_ptr = fs as *mut Ext2;
+        let ino = ino as u32;
+
+        let mut inode = {
+            let _lock = fs.lock.lock();
+            unsafe { (*fs_ptr).read_inode(ino) }
+        };
+        if (inode.mode & 0xF000) == 0x4000 {
+            return Err(String::from("Cannot link a directory"));
+        }
+        if inode.links_count >= EXT2_LINK_MAX {
+            return Err(String::from("Too many links"));
+        }
+        self.add_directory_entry(ino, name, Ext2::dirent_type(inode.mode))?;
+        inode.links_count += 1;
+        inode.ctime = unix_now();
+        {
+            let _lock = fs.lock.lock();
+            unsafe { (*fs_ptr).write_inode(ino, &inode) };
+        }
+        self.touch_modified();
+        Ok(())
+    }
+
+    fn symlink(&mut self, name: &str, target: &str) -> Result<(), String> {
+        if target.is_empty() {
+            return Err(String::from("Empty symlink target"));
+        }
+        let mut node = self.create_node(name, 0xA1FF)?;
+        if target.len() < FAST_SYMLINK_MAX {
+            let mut raw = [0u8; FAST_SYMLINK_MAX];
+            raw[..target.len()].copy_from_slice(target.as_bytes());
+            let mut block = [0u32; 15];
+            for (word, bytes) in block.iter_mut().zip(raw.chunks_exact(4)) {
+                *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+            node.inode.block = block;
+            node.inode.size = target.len() as u32;
+            let fs = unsafe { &mut *node.fs };
+            let fs_ptr = fs as *mut Ext2;
+            let _lock = fs.lock.lock();
+            unsafe { (*fs_ptr).write_inode(node.inode_idx, &node.inode) };
+        } else if let Err(e) = node.write(0, target.as_bytes()) {
+            let _ = self.remove_internal(name);
+            node.evict();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn readlink(&mut self) -> Result<String, String> {
+        if self.kind() != FileType::Symlink {
+            return Err(String::from("Not a symlink"));
+        }
+        let len = self.inode.size as usize;
+        let target = if Ext2::is_fast_symlink(&self.inode) {
+            let block = self.inode.block;
+            let mut raw: Vec<u8> = block.iter().flat_map(|w| w.to_le_bytes()).collect();
+            raw.truncate(len);
+            raw
+        } else {
+            let mut raw = alloc::vec![0u8; len];
+            let read = self.read(0, &mut raw)?;
+            raw.truncate(read);
+            raw
+        };
+        String::from_utf8(target).map_err(|_| String::from("Invalid symlink target"))
+    }
+
+    fn nlink(&self) -> u64 {
+        self.inode.links_count as u64
+    }
+
+    fn reload(&mut self) {
+        let fs = unsafe { &mut *self.fs };
+        let fs_ptr = fs as *mut Ext2;
+        let _lock = fs.lock.lock();
+        self.inode = unsafe { (*fs_ptr).read_inode(self.inode_idx) };
+    }
+
+    fn evict(&mut self) {
+        let fs = unsafe { &mut *self.fs };
+        let fs_ptr = fs as *mut Ext2;
+        let _lock = fs.lock.lock();
+        self.inode = unsafe { (*fs_ptr).read_inode(self.inode_idx) };
+        if self.inode.links_count == 0 {
+            unsafe { (*fs_ptr).release_inode(self.inode_idx, &mut self.inode) };
+        }
+    }
+
+    fn metadata(&self) -> Metadata {
+        let inode = self.inode;
+        // The high halves of the owner ids live in the Linux part of osd2.
+        let ids_high = inode.osd2[1];
+        Metadata {
+            dev: 0,
+            ino: self.inode_idx as u64,
+            mode: inode.mode as u32,
+            nlink: inode.links_count as u64,
+            uid: inode.uid as u32 | ((ids_high & 0xFFFF) << 16),
+            gid: inode.gid as u32 | (ids_high & 0xFFFF_0000),
+            size: inode.size as u64,
+            block_size: unsafe { (*self.fs).block_size },
+            blocks: inode.blocks as u64,
+            atime: inode.atime as u64,
+            mtime: inode.mtime as u64,
+            ctime: inode.ctime as u64,
+        }
+    }
+
+    fn setattr(&mut self, attr: &SetAttr) -> Result<(), String> {
+        let mut osd2 = self.inode.osd2;
+        if let Some(mode) = attr.mode {
+            self.inode.mode = (self.inode.mode & S_IFMT as u16) | (mode & 0o7777) as u16;
+        }
+        if let Some(uid) = attr.uid {
+            self.inode.uid = uid as u16;
+            osd2[1] = (osd2[1] & 0xFFFF_0000) | (uid >> 16);
+        }
+        if let Some(gid) = attr.gid {
+            self.inode.gid = gid as u16;
+            osd2[1] = (osd2[1] & 0xFFFF) | (gid & 0xFFFF_0000);
+        }
+        self.inode.osd2 = osd2;
+        if let Some(atime) = attr.atime {
+            self.inode.atime = atime as u32;
+        }
+        if let Some(mtime) = attr.mtime {
+            self.inode.mtime = mtime as u32;
+        }
+        self.inode.ctime = unix_now();
+        let fs = unsafe { &mut *self.fs };
+        let fs_ptr = fs as *mut Ext2;
+        let _lock = fs.lock.lock();
+        unsafe { (*fs_ptr).write_inode(self.inode_idx, &self.inode) };
+        Ok(())
+    }
 }
 
 impl Ext2Node {
+    /// Stamps the modification and change times and writes the inode back.
+    fn touch_modified(&mut self) {
+        let now = unix_now();
+        self.inode.mtime = now;
+        self.inode.ctime = now;
+        let fs = unsafe { &mut *self.fs };
+        let fs_ptr = fs as *mut Ext2;
+        let _lock = fs.lock.lock();
+        unsafe { (*fs_ptr).write_inode(self.inode_idx, &self.inode) };
+    }
+
     fn find_internal(&mut self, name: &str) -> Result<Box<dyn VfsNode>, String> {
         if self.kind() != FileType::Directory {
             return Err(String::from("Not a directory"));
@@ -1342,28 +1502,14 @@ impl Ext2Node {
 
                     if target_inode.links_count > 0 {
                         target_inode.links_count -= 1;
-                        if target_inode.links_count == 0 {
-                            {
-                                let _lock = fs.lock.lock();
-                                unsafe {
-                                    for i in 0..12 {
-                                        if target_inode.block[i] != 0 {
-                                            (*fs_ptr).free_block(target_inode.block[i]);
-                                            target_inode.block[i] = 0;
-                                        }
-                                    }
-
-
-                                    (*fs_ptr).write_inode(inode_to_free, &target_inode);
-                                    (*fs_ptr).free_inode(inode_to_free);
-                                }
-                            }
-                        } else {
-                            let _lock = fs.lock.lock();
-                            unsafe { (*fs_ptr).write_inode(inode_to_free, &target_inode) };
-                        }
+                        target_inode.ctime = unix_now();
+                        // At 0 links the inode is an orphan; `evict` frees it
+                        // once nothing has it open.
+                        let _lock = fs.lock.lock();
+                        unsafe { (*fs_ptr).write_inode(inode_to_free, &target_inode) };
                     }
 
+                    self.touch_modified();
                     return Ok(());
                 }
 
@@ -1376,7 +1522,7 @@ impl Ext2Node {
         Err(String::from("File not found"))
     }
 
-    fn create_node(&mut self, name: &str, mode: u16) -> Result<Box<dyn VfsNode>, String> {
+    fn create_node(&mut self, name: &str, mode: u16) -> Result<Ext2Node, String> {
         if let Ok(_) = self.find_internal(name) {
             return Err(String::from("File already exists"));
         }
@@ -1391,7 +1537,7 @@ impl Ext2Node {
         };
         if inode_id == 0 { return Err(String::from("No free inodes")); }
 
-        let current_time = 0;
+        let current_time = unix_now();
 
         let new_inode = Inode {
             mode,
@@ -1420,20 +1566,21 @@ impl Ext2Node {
         }
 
 
-        if let Err(e) = self.add_directory_entry(inode_id, name, if (mode & 0xF000) == 0x4000 { 2 } else { 1 }) {
+        if let Err(e) = self.add_directory_entry(inode_id, name, Ext2::dirent_type(mode)) {
             {
                 let _lock = fs.lock.lock();
                 unsafe { (*fs_ptr).free_inode(inode_id) };
             }
             return Err(e);
         }
+        self.touch_modified();
 
-        Ok(Box::new(Ext2Node {
+        Ok(Ext2Node {
             fs: self.fs,
             inode_idx: inode_id,
             inode: new_inode,
             name: String::from(name),
-        }))
+        })
     }
 
     fn add_directory_entry(&mut self, inode_id: u32, name: &str, file_type: u8) -> Result<(), String> {
@@ -1,3 +1,4 @@
+pub mod bcache;
 pub mod disk;
 pub mod ext2;
 pub mod vfs;
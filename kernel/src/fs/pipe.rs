@@ -73,13 +73,26 @@ impl Pipe {
     }
 
     pub fn write(&self, buf: &[u8]) -> usize {
-        let mut inner = self.inner.lock();
-        inner.write(buf)
+        let written = self.inner.lock().write(buf);
+        if written > 0 {
+            self.wake_readers();
+        }
+        written
     }
 
     pub fn close(&self) {
-        let mut inner = self.inner.lock();
-        inner.closed = true;
+        self.inner.lock().closed = true;
+        self.wake_readers();
+    }
+
+    /// Key readers sleep on until data arrives or the pipe closes.
+    pub fn wait_key(&self) -> u64 {
+        Arc::as_ptr(&self.inner) as u64
+    }
+
+    fn wake_readers(&self) {
+        crate::interrupts::wait::wake(self.wait_key(), usize::MAX);
+        crate::interrupts::wait::POLL_WAIT.wake_all();
     }
 
     pub fn available(&self) -> usize {
@@ -1,15 +1,109 @@
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::string::String;
-use alloc::string::ToString;
+use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
+use crate::sync::Mutex;
 
+struct Mount {
+    id: u32,
+    /// Absolute directory the filesystem is mounted on, `/` for the root.
+    path: String,
+    /// Disk the filesystem lives on, for `@disk/` paths.
+    disk_id: u8,
+    /// First sector of the filesystem on that disk.
+    start_lba: u64,
+    fs: Box<dyn FileSystem>,
+    /// One clone per node handed out, so `umount` can tell the mount is busy.
+    pins: Arc<()>,
+}
+
+/// The mount table, ordered by path so a mount sorts after the one it sits on.
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+static NEXT_MOUNT_ID: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(1);
+
+/// A cached inode. Every lookup and open of one file shares it, so they all
+/// see the same size and metadata.
+pub type InodeRef = Arc<Mutex<Box<dyn VfsNode>>>;
 
-pub static mut FILESYSTEMS: [Option<Box<dyn FileSystem>>; 256] = [const { None }; 256];
-pub static mut OPEN_FILES: [Option<FileHandle>; 256] = [const { None }; 256];
-pub static mut GLOBAL_FILE_REFCOUNT: [u16; 256] = [0; 256];
+/// Dentries kept before unused ones are dropped.
+const DENTRY_LIMIT: usize = 512;
+/// Symlinks one lookup follows before giving up.
+const MAX_SYMLINKS: usize = 16;
+
+struct Cache {
+    /// Resolved absolute paths. Holds its inodes alive, so `umount` drops
+    /// the ones below a mount before checking whether it is busy.
+    dentries: BTreeMap<String, InodeRef>,
+    /// Live inodes by mount and inode number.
+    inodes: BTreeMap<(u32, u64), Weak<Mutex<Box<dyn VfsNode>>>>,
+    /// Unlinked inodes that were still open. Their storage is freed once
+    /// nothing else holds them.
+    orphans: Vec<InodeRef>,
+}
+
+impl Cache {
+    /// The shared inode for `node`, which is dropped if one is cached already.
+    fn intern(&mut self, mount_id: u32, node: Box<dyn VfsNode>) -> InodeRef {
+        let key = (mount_id, node.ino());
+        if let Some(inode) = self.inodes.get(&key).and_then(Weak::upgrade) {
+            return inode;
+        }
+        if self.inodes.len() >= DENTRY_LIMIT {
+            self.inodes.retain(|_, w| w.strong_count() > 0);
+        }
+        let inode = Arc::new(Mutex::new(node));
+        self.inodes.insert(key, Arc::downgrade(&inode));
+        inode
+    }
+
+    fn add_dentry(&mut self, path: &str, inode: &InodeRef) {
+        if self.dentries.len() >= DENTRY_LIMIT {
+            self.dentries.retain(|_, i| Arc::strong_count(i) > 1);
+            if self.dentries.len() >= DENTRY_LIMIT {
+                self.dentries.clear();
+            }
+        }
+        self.dentries.insert(String::from(path), inode.clone());
+    }
+
+    /// Drops the dentries for `path` and everything below it.
+    fn forget(&mut self, path: &str) {
+        self.dentries.retain(|p, _| !is_under(p, path));
+    }
+
+    /// Frees the orphans nobody holds any more.
+    fn reap(&mut self) {
+        self.orphans.retain(|inode| {
+            if Arc::strong_count(inode) > 1 {
+                return true;
+            }
+            inode.lock().evict();
+            false
+        });
+    }
+}
+
+static CACHE: Mutex<Cache> = Mutex::new(Cache { dentries: BTreeMap::new(), inodes: BTreeMap::new(), orphans: Vec::new() });
+
+/// An open file description, made by `open` or `pipe` and shared by every
+/// descriptor duplicated or inherited from it.
+pub struct OpenFile {
+    pub handle: FileHandle,
+    /// `O_*` status flags from `open`, changed with `F_SETFL`.
+    pub flags: u64,
+    refs: usize,
+}
+
+/// Every open file description, indexed by global fd. Grows as needed; the
+/// descriptions are boxed so references to them survive the table moving.
+pub static mut OPEN_FILES: Vec<Option<Box<OpenFile>>> = Vec::new();
+
+/// Global fds below this are never handed out.
+const FIRST_GLOBAL_FD: usize = 3;
 
 pub enum FileHandle {
-    File { node: Box<dyn VfsNode>, offset: u64 },
+    File { node: InodeRef, offset: u64 },
     Pipe { pipe: crate::fs::pipe::Pipe },
 }
 
@@ -17,101 +111,474 @@ pub fn init() {
     
 }
 
-pub fn mount(disk_id: u8, fs: Box<dyn FileSystem>) {
-    crate::debugln!("Mounting at index {}, fs box: {:p}", disk_id, fs);
-    unsafe {
-        FILESYSTEMS[disk_id as usize] = Some(fs);
+/// Parses the disk part of an `@disk/` path: `0xE0`, `224` or `E0`.
+pub fn parse_disk_id(disk: &str) -> Option<u8> {
+    if disk.starts_with("0x") || disk.starts_with("0X") {
+        u8::from_str_radix(&disk[2..], 16).ok()
+    } else {
+        disk.parse::<u8>().ok().or_else(|| u8::from_str_radix(disk, 16).ok())
     }
 }
 
-pub fn open_file(disk_id: u8, path_str: &str) -> Result<usize, String> {
-    let mut actual_path = String::from(path_str);
-    if !path_str.starts_with('@') && !path_str.starts_with('/') {
-        
+/// Whether `path` is `prefix` or lies below it. Both are absolute and normalised.
+fn is_under(path: &str, prefix: &str) -> bool {
+    prefix == "/" || path == prefix || (path.starts_with(prefix) && path.as_bytes().get(prefix.len()) == Some(&b'/'))
+}
+
+/// Splits an absolute, normalised path into parent directory and name. None for `/`.
+pub fn split_parent(path: &str) -> Option<(&str, &str)> {
+    match path.rsplit_once('/')? {
+        (_, "") => None,
+        ("", name) => Some(("/", name)),
+        (parent, name) => Some((parent, name)),
     }
-    
-    let node = open(disk_id, path_str)?;
-    unsafe {
-        for i in 3..256 {
-            if OPEN_FILES[i].is_none() {
-                OPEN_FILES[i] = Some(FileHandle::File { node, offset: 0 });
-                GLOBAL_FILE_REFCOUNT[i] = 1;
-                return Ok(i);
+}
+
+/// Joins `rel` onto absolute `base`, resolving `.` and `..`. An absolute
+/// `rel` replaces `base`.
+fn join(base: &str, rel: &str) -> String {
+    let base = if rel.starts_with('/') { "" } else { base };
+    let mut parts: Vec<&str> = Vec::new();
+    for part in base.split('/').chain(rel.split('/')) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
             }
+            _ => parts.push(part),
         }
-        Err(String::from("No free file descriptors"))
     }
+    alloc::format!("/{}", parts.join("/"))
 }
 
-pub fn get_file(fd: usize) -> Option<&'static mut FileHandle> {
-    unsafe {
-        if fd < 256 {
-            OPEN_FILES[fd].as_mut()
-        } else {
-            None
+/// Mount id of the filesystem `path` lies on.
+fn mount_id(mounts: &[Mount], path: &str) -> u32 {
+    mounts.iter().rev().find(|m| is_under(path, &m.path)).map_or(0, |m| m.id)
+}
+
+/// Mounts `fs`, found on disk `disk_id` at `start_lba`, on directory `path`.
+/// The first mount must be `/`; later ones need an existing directory that is
+/// not a mount point yet. A filesystem can only be mounted once.
+pub fn mount(path: &str, disk_id: u8, start_lba: u64, fs: Box<dyn FileSystem>) -> Result<(), String> {
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|m| m.disk_id == disk_id && m.start_lba == start_lba) {
+        return Err(String::from("Filesystem already mounted"));
+    }
+    let path = if mounts.is_empty() {
+        if path != "/" {
+            return Err(String::from("Root must be mounted first"));
         }
+        String::from(path)
+    } else {
+        let path = canonical(&mut mounts, path, true)?;
+        if mounts.iter().any(|m| m.path == path) {
+            return Err(String::from("Already a mount point"));
+        }
+        if lookup(&mut mounts, &path)?.lock().kind() != FileType::Directory {
+            return Err(String::from("Mount point is not a directory"));
+        }
+        path
+    };
+    crate::debugln!("[VFS] Mounting disk {:#x} on {}", disk_id, path);
+    let id = NEXT_MOUNT_ID.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+    let at = mounts.iter().position(|m| m.path > path).unwrap_or(mounts.len());
+    CACHE.lock().forget(&path);
+    mounts.insert(at, Mount { id, path, disk_id, start_lba, fs, pins: Arc::new(()) });
+    Ok(())
+}
+
+/// Detaches the filesystem mounted on `path`. Fails while anything holds one
+/// of its nodes or another filesystem is mounted below it.
+pub fn umount(path: &str) -> Result<(), String> {
+    if path == "/" {
+        return Err(String::from("Cannot unmount the root"));
+    }
+    let mut mounts = MOUNTS.lock();
+    let path = &canonical(&mut mounts, path, true)?;
+    let idx = mounts.iter().position(|m| m.path == *path).ok_or_else(|| String::from("Not a mount point"))?;
+    {
+        let mut cache = CACHE.lock();
+        cache.forget(path);
+        cache.reap();
+    }
+    let busy = Arc::strong_count(&mounts[idx].pins) > 1
+        || mounts.iter().enumerate().any(|(i, m)| i != idx && is_under(&m.path, path));
+    if busy {
+        return Err(String::from("Mount point busy"));
+    }
+    crate::debugln!("[VFS] Unmounting {}", path);
+    let disk_id = mounts.remove(idx).disk_id;
+    if mounts.iter().any(|m| m.disk_id == disk_id) {
+        crate::fs::bcache::sync(Some(disk_id));
+    } else {
+        crate::fs::bcache::invalidate(disk_id);
+    }
+    Ok(())
+}
+
+/// Where disk `disk_id` is mounted, if it is.
+pub fn mount_point(disk_id: u8) -> Option<String> {
+    MOUNTS.lock().iter().find(|m| m.disk_id == disk_id).map(|m| m.path.clone())
+}
+
+/// Puts a new description with one reference in the open file table.
+pub fn install(handle: FileHandle, flags: u64) -> Result<usize, String> {
+    let files = unsafe { &mut *(&raw mut OPEN_FILES) };
+    if files.len() < FIRST_GLOBAL_FD {
+        files.resize_with(FIRST_GLOBAL_FD, || None);
     }
+    let fd = files.iter().skip(FIRST_GLOBAL_FD).position(|f| f.is_none()).map_or(files.len(), |i| i + FIRST_GLOBAL_FD);
+    if fd == files.len() {
+        files.push(None);
+    }
+    files[fd] = Some(Box::new(OpenFile { handle, flags, refs: 1 }));
+    Ok(fd)
+}
+
+pub fn open_file(path: &str, flags: u64) -> Result<usize, String> {
+    let node = open(path)?;
+    install(FileHandle::File { node, offset: 0 }, flags)
+}
+
+pub fn get_open_file(fd: usize) -> Option<&'static mut OpenFile> {
+    unsafe { (&mut *(&raw mut OPEN_FILES)).get_mut(fd)?.as_deref_mut() }
+}
+
+pub fn get_file(fd: usize) -> Option<&'static mut FileHandle> {
+    get_open_file(fd).map(|f| &mut f.handle)
 }
 
 pub fn close_file(fd: usize) {
-    unsafe {
-        if fd < 256 {
-            if GLOBAL_FILE_REFCOUNT[fd] > 0 {
-                GLOBAL_FILE_REFCOUNT[fd] -= 1;
-                if GLOBAL_FILE_REFCOUNT[fd] == 0 {
-                    if let Some(FileHandle::Pipe { pipe }) = &OPEN_FILES[fd] {
-                        pipe.close();
-                    }
-                    OPEN_FILES[fd] = None;
-                }
-            }
+    let files = unsafe { &mut *(&raw mut OPEN_FILES) };
+    let file = match files.get_mut(fd) {
+        Some(Some(file)) => file,
+        _ => return,
+    };
+    file.refs -= 1;
+    if file.refs == 0 {
+        if let FileHandle::Pipe { pipe } = &file.handle {
+            pipe.close();
         }
+        files[fd] = None;
+        while files.len() > FIRST_GLOBAL_FD && matches!(files.last(), Some(None)) {
+            files.pop();
+        }
+        CACHE.lock().reap();
     }
 }
 
 pub fn increment_ref(fd: usize) {
-    unsafe {
-        if fd < 256 && OPEN_FILES[fd].is_some() {
-            GLOBAL_FILE_REFCOUNT[fd] += 1;
-        }
+    if let Some(file) = get_open_file(fd) {
+        file.refs += 1;
     }
 }
 
-pub fn open(disk_id: u8, path_str: &str) -> Result<Box<dyn VfsNode>, String> {
-    let components: Vec<String> = path_str.split('/').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect();
+/// Looks up absolute, normalised `path`, starting from the deepest mount
+/// that contains it.
+pub fn open(path: &str) -> Result<InodeRef, String> {
+    let mut mounts = MOUNTS.lock();
+    let path = canonical(&mut mounts, path, true)?;
+    lookup(&mut mounts, &path)
+}
+
+/// `path` with every symlink along it followed, and `..` applied to the
+/// directory a link leads to rather than to the link's name.
+pub fn realpath(path: &str) -> Result<String, String> {
+    canonical(&mut MOUNTS.lock(), path, true)
+}
 
-    unsafe {
-        if let Some(fs) = &mut FILESYSTEMS[disk_id as usize] {
-            let mut node = fs.root()?;
-            for component in components.iter() {
-                node = node.find(&component)?;
+/// `path` with the symlinks along it replaced by their targets, so that it
+/// only passes through directories. A symlink as the last component is
+/// followed only if `follow`. `..` is applied component by component to what
+/// has been resolved so far, so link targets are spliced in unnormalised.
+fn canonical(mounts: &mut [Mount], path: &str, follow: bool) -> Result<String, String> {
+    let mut path = String::from(path);
+    let mut links = 0;
+    'walk: loop {
+        let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+        let mut resolved = String::from("/");
+        for (i, part) in parts.iter().enumerate() {
+            let at = join(&resolved, part);
+            let last = i + 1 == parts.len();
+            if last && !follow {
+                return Ok(at);
+            }
+            let node = match lookup(mounts, &at) {
+                Ok(node) => node,
+                // A missing last component is for the caller to report or create.
+                Err(_) if last => return Ok(at),
+                Err(e) => return Err(e),
+            };
+            let target = {
+                let mut node = node.lock();
+                if node.kind() == FileType::Symlink { Some(node.readlink()?) } else { None }
+            };
+            let target = match target {
+                Some(t) => t,
+                None => {
+                    resolved = at;
+                    continue;
+                }
+            };
+            links += 1;
+            if links > MAX_SYMLINKS {
+                return Err(String::from("Too many levels of symbolic links"));
             }
-            Ok(node)
-        } else {
-            Err(String::from("Disk ID not mounted"))
+            let target = match target.strip_prefix('@') {
+                Some(alias) => {
+                    let (disk, rest) = alias.split_once('/').unwrap_or((alias, ""));
+                    let root = parse_disk_id(disk)
+                        .and_then(|id| mounts.iter().find(|m| m.disk_id == id))
+                        .ok_or_else(|| String::from("Disk not mounted"))?;
+                    alloc::format!("{}/{}", root.path, rest)
+                }
+                None if target.starts_with('/') => target,
+                None => alloc::format!("{}/{}", resolved, target),
+            };
+            path = alloc::format!("{}/{}", target, parts[i + 1..].join("/"));
+            continue 'walk;
         }
+        return Ok(resolved);
     }
 }
 
-pub fn read(disk_id: u8, path_str: &str, offset: u64, size: u64, buffer: *mut u8) -> Result<usize, String> {
-    let components: Vec<String> = path_str
-        .split('/')
-        .filter(|s| !s.is_empty())
-        .map(|s| s.to_string())
-        .collect();
+fn lookup(mounts: &mut [Mount], path: &str) -> Result<InodeRef, String> {
+    if let Some(inode) = CACHE.lock().dentries.get(path) {
+        return Ok(inode.clone());
+    }
+    let idx = mounts.iter().rposition(|m| is_under(path, &m.path)).ok_or_else(|| String::from("Nothing mounted"))?;
+    let node = if mounts[idx].path == path {
+        let mount = &mut mounts[idx];
+        Box::new(MountedNode { inner: mount.fs.root()?, pins: mount.pins.clone(), dev: mount.id })
+    } else {
+        // The parent lies on the same mount, or is its root.
+        let (parent, name) = split_parent(path).ok_or_else(|| String::from("Invalid path"))?;
+        let parent = lookup(mounts, parent)?;
+        let child = parent.lock().find(name)?;
+        child
+    };
+    let mut cache = CACHE.lock();
+    let inode = cache.intern(mounts[idx].id, node);
+    cache.add_dentry(path, &inode);
+    Ok(inode)
+}
 
-    unsafe {
-        if let Some(fs) = &mut FILESYSTEMS[disk_id as usize] {
-            let mut node = fs.root()?;
-            for component in components {
-                node = node.find(&component)?;
-            }
-            let slice = core::slice::from_raw_parts_mut(buffer, size as usize);
-            node.read(offset, slice)
-        } else {
-            Err(String::from("Disk ID not mounted"))
+/// Creates file or directory `path` in its existing parent directory.
+pub fn create(path: &str, dir: bool) -> Result<InodeRef, String> {
+    let mut mounts = MOUNTS.lock();
+    let path = &canonical(&mut mounts, path, false)?;
+    let (parent, name) = split_parent(path).ok_or_else(|| String::from("Invalid path"))?;
+    let parent = lookup(&mut mounts, parent)?;
+    let node = {
+        let mut parent = parent.lock();
+        if dir { parent.create_dir(name)? } else { parent.create_file(name)? }
+    };
+    let mut cache = CACHE.lock();
+    let inode = cache.intern(mount_id(&mounts, path), node);
+    cache.add_dentry(path, &inode);
+    Ok(inode)
+}
+
+/// Unlinks `path`; a symlink is removed, not what it points to. Removing the
+/// last name of an open file leaves it usable through its open descriptions,
+/// and its storage is freed when the last of them closes.
+pub fn remove(path: &str) -> Result<(), String> {
+    let mut mounts = MOUNTS.lock();
+    let path = &canonical(&mut mounts, path, false)?;
+    if mounts.iter().any(|m| m.path == *path) {
+        return Err(String::from("Mount point busy"));
+    }
+    let (parent, name) = split_parent(path).ok_or_else(|| String::from("Invalid path"))?;
+    let victim = lookup(&mut mounts, path)?;
+    lookup(&mut mounts, parent)?.lock().remove(name)?;
+    let (ino, nlink) = {
+        let mut victim = victim.lock();
+        victim.reload();
+        (victim.ino(), victim.nlink())
+    };
+    let mut cache = CACHE.lock();
+    cache.forget(path);
+    if nlink == 0 {
+        // The number may be reused; the next file under it gets a fresh inode.
+        cache.inodes.remove(&(mount_id(&mounts, path), ino));
+        cache.orphans.push(victim);
+        cache.reap();
+    }
+    Ok(())
+}
+
+/// Makes `new` another name for the file at `old`, on the same filesystem.
+/// A symlink at `old` is linked itself, not followed.
+pub fn link(old: &str, new: &str) -> Result<(), String> {
+    let mut mounts = MOUNTS.lock();
+    let old = &canonical(&mut mounts, old, false)?;
+    let new = &canonical(&mut mounts, new, false)?;
+    let (parent, name) = split_parent(new).ok_or_else(|| String::from("Invalid path"))?;
+    if mount_id(&mounts, old) != mount_id(&mounts, new) {
+        return Err(String::from("Cross-device link"));
+    }
+    let target = lookup(&mut mounts, old)?;
+    let ino = {
+        let target = target.lock();
+        if target.kind() == FileType::Directory {
+            return Err(String::from("Cannot link a directory"));
         }
+        target.ino()
+    };
+    lookup(&mut mounts, parent)?.lock().link(name, ino)?;
+    target.lock().reload();
+    Ok(())
+}
+
+/// Creates symlink `path` holding `target`, which is stored as given and
+/// resolved relative to the symlink's directory when followed.
+pub fn symlink(target: &str, path: &str) -> Result<(), String> {
+    let mut mounts = MOUNTS.lock();
+    let path = &canonical(&mut mounts, path, false)?;
+    let (parent, name) = split_parent(path).ok_or_else(|| String::from("Invalid path"))?;
+    lookup(&mut mounts, parent)?.lock().symlink(name, target)
+}
+
+/// Target of symlink `path`.
+pub fn readlink(path: &str) -> Result<String, String> {
+    let target = open_link(path)?.lock().readlink();
+    target
+}
+
+/// Like `open`, but a symlink as the last component is returned itself.
+pub fn open_link(path: &str) -> Result<InodeRef, String> {
+    let mut mounts = MOUNTS.lock();
+    let path = canonical(&mut mounts, path, false)?;
+    lookup(&mut mounts, &path)
+}
+
+/// Renames `old` to `new` within one directory. Open descriptions follow the
+/// inode, not the name.
+pub fn rename(old: &str, new: &str) -> Result<(), String> {
+    let mut mounts = MOUNTS.lock();
+    let old = &canonical(&mut mounts, old, false)?;
+    let new = &canonical(&mut mounts, new, false)?;
+    if mounts.iter().any(|m| m.path == *old || m.path == *new) {
+        return Err(String::from("Mount point busy"));
+    }
+    let ((old_parent, old_name), (new_parent, new_name)) = match (split_parent(old), split_parent(new)) {
+        (Some(o), Some(n)) => (o, n),
+        _ => return Err(String::from("Invalid path")),
+    };
+    if old_parent != new_parent {
+        return Err(String::from("Moving between directories not supported yet"));
+    }
+    lookup(&mut mounts, old_parent)?.lock().rename(old_name, new_name)?;
+    let mut cache = CACHE.lock();
+    cache.forget(old);
+    cache.forget(new);
+    Ok(())
+}
+
+pub fn read(path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize, String> {
+    open(path)?.lock().read(offset, buffer)
+}
+
+/// A node of a mounted filesystem, keeping the mount pinned while it lives.
+struct MountedNode {
+    inner: Box<dyn VfsNode>,
+    pins: Arc<()>,
+    /// Id of the mount, reported as the device.
+    dev: u32,
+}
+
+impl MountedNode {
+    fn wrap(&self, node: Box<dyn VfsNode>) -> Box<dyn VfsNode> {
+        Box::new(MountedNode { inner: node, pins: self.pins.clone(), dev: self.dev })
+    }
+}
+
+impl VfsNode for MountedNode {
+    fn name(&self) -> String { self.inner.name() }
+    fn ino(&self) -> u64 { self.inner.ino() }
+    fn size(&self) -> u64 { self.inner.size() }
+    fn kind(&self) -> FileType { self.inner.kind() }
+    fn read(&mut self, offset: u64, buffer: &mut [u8]) -> Result<usize, String> { self.inner.read(offset, buffer) }
+    fn write(&mut self, offset: u64, buffer: &[u8]) -> Result<usize, String> { self.inner.write(offset, buffer) }
+
+    fn children(&mut self) -> Result<Vec<Box<dyn VfsNode>>, String> {
+        let children = self.inner.children()?;
+        Ok(children.into_iter().map(|c| self.wrap(c)).collect())
+    }
+
+    fn find(&mut self, name: &str) -> Result<Box<dyn VfsNode>, String> {
+        let node = self.inner.find(name)?;
+        Ok(self.wrap(node))
+    }
+
+    fn read_dir(&mut self, start_index: u64, buffer: &mut [u8]) -> Result<(usize, usize), String> {
+        self.inner.read_dir(start_index, buffer)
     }
+
+    fn create_file(&mut self, name: &str) -> Result<Box<dyn VfsNode>, String> {
+        let node = self.inner.create_file(name)?;
+        Ok(self.wrap(node))
+    }
+
+    fn create_dir(&mut self, name: &str) -> Result<Box<dyn VfsNode>, String> {
+        let node = self.inner.create_dir(name)?;
+        Ok(self.wrap(node))
+    }
+
+    fn remove(&mut self, name: &str) -> Result<(), String> { self.inner.remove(name) }
+    fn rename(&mut self, old_name: &str, new_name: &str) -> Result<(), String> { self.inner.rename(old_name, new_name) }
+    fn truncate(&mut self, size: u64) -> Result<(), String> { self.inner.truncate(size) }
+    fn sync(&mut self) -> Result<(), String> { self.inner.sync() }
+    fn link(&mut self, name: &str, ino: u64) -> Result<(), String> { self.inner.link(name, ino) }
+    fn symlink(&mut self, name: &str, target: &str) -> Result<(), String> { self.inner.symlink(name, target) }
+    fn readlink(&mut self) -> Result<String, String> { self.inner.readlink() }
+    fn nlink(&self) -> u64 { self.inner.nlink() }
+    fn reload(&mut self) { self.inner.reload() }
+    fn evict(&mut self) { self.inner.evict() }
+
+    fn metadata(&self) -> Metadata {
+        Metadata { dev: self.dev as u64, ..self.inner.metadata() }
+    }
+
+    fn setattr(&mut self, attr: &SetAttr) -> Result<(), String> { self.inner.setattr(attr) }
+}
+
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFIFO: u32 = 0o010000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
+/// What `stat` reports about a node. Times are seconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Metadata {
+    pub dev: u64,
+    pub ino: u64,
+    /// File type and permission bits, as in `st_mode`.
+    pub mode: u32,
+    pub nlink: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    /// Preferred I/O size.
+    pub block_size: u64,
+    /// 512-byte sectors allocated.
+    pub blocks: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+/// Changes for `VfsNode::setattr`; None leaves a field as it is.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SetAttr {
+    /// Permission bits; the file type is kept.
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub atime: Option<u64>,
+    pub mtime: Option<u64>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -119,6 +586,7 @@ pub enum FileType {
     File,
     Directory,
     Device,
+    Symlink,
     Unknown,
 }
 
@@ -129,6 +597,8 @@ pub trait FileSystem: Send + Sync {
 
 pub trait VfsNode: Send + Sync {
     fn name(&self) -> String;
+    /// Inode number, unique within the filesystem.
+    fn ino(&self) -> u64;
     fn size(&self) -> u64;
     fn kind(&self) -> FileType;
     fn read(&mut self, offset: u64, buffer: &mut [u8]) -> Result<usize, String>;
@@ -161,4 +631,51 @@ pub trait VfsNode: Send + Sync {
     fn truncate(&mut self, _size: u64) -> Result<(), String> {
         Err(String::from("Not supported"))
     }
+
+    /// Writes the node's cached data and metadata back to its device.
+    fn sync(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Adds entry `name` for inode `ino` of the same filesystem.
+    fn link(&mut self, _name: &str, _ino: u64) -> Result<(), String> {
+        Err(String::from("Not supported"))
+    }
+
+    fn symlink(&mut self, _name: &str, _target: &str) -> Result<(), String> {
+        Err(String::from("Not supported"))
+    }
+
+    fn readlink(&mut self) -> Result<String, String> {
+        Err(String::from("Not a symlink"))
+    }
+
+    /// Directory entries naming the node; 0 once the last is removed.
+    fn nlink(&self) -> u64 {
+        1
+    }
+
+    /// Re-reads metadata that operations through other nodes, such as
+    /// linking and unlinking, may have changed.
+    fn reload(&mut self) {}
+
+    /// Frees the node's storage if it has no names left. Called once the
+    /// last reference to an unlinked node is gone.
+    fn evict(&mut self) {}
+
+    fn metadata(&self) -> Metadata {
+        let mode = match self.kind() {
+            FileType::File => S_IFREG | 0o644,
+            FileType::Directory => S_IFDIR | 0o755,
+            FileType::Device => S_IFCHR | 0o666,
+            FileType::Symlink => S_IFLNK | 0o777,
+            FileType::Unknown => 0,
+        };
+        Metadata { ino: self.ino(), mode, nlink: self.nlink(), size: self.size(), block_size: 4096, ..Metadata::default() }
+    }
+
+    /// Changes permissions, owner or times, and stamps the change time.
+    fn setattr(&mut self, _attr: &SetAttr) -> Result<(), String> {
+        Err(String::from("Not supported"))
+    }
 }
@@ -0,0 +1,134 @@
+use core::ptr::{read_volatile, write_volatile};
+
+const LAPIC_ID: u64 = 0x20;
+const LAPIC_EOI: u64 = 0xB0;
+const LAPIC_SVR: u64 = 0xF0;
+const LAPIC_ICR_LOW: u64 = 0x300;
+const LAPIC_ICR_HIGH: u64 = 0x310;
+const LAPIC_LVT_TIMER: u64 = 0x320;
+const LAPIC_TIMER_INIT: u64 = 0x380;
+const LAPIC_TIMER_CURRENT: u64 = 0x390;
+const LAPIC_TIMER_DIVIDE: u64 = 0x3E0;
+
+const SVR_ENABLE: u32 = 1 << 8;
+const ICR_PENDING: u32 = 1 << 12;
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+const ICR_ASSERT: u32 = 1 << 14;
+const ICR_ALL_BUT_SELF: u32 = 0b11 << 18;
+const TIMER_MASKED: u32 = 1 << 16;
+const TIMER_DIVIDE_16: u32 = 0b0011;
+/// Length of the window the timer is calibrated over.
+const CALIBRATION_MS: u32 = 10;
+
+pub const SPURIOUS_INT: u8 = 0xFF;
+
+/// Virtual address of the local APIC registers, the same on every CPU.
+static mut LAPIC_BASE: u64 = 0;
+/// Timer counts per millisecond, measured once on the BSP.
+static mut TIMER_PER_MS: u32 = 0;
+
+fn read(reg: u64) -> u32 {
+    unsafe { read_volatile((LAPIC_BASE + reg) as *const u32) }
+}
+
+fn write(reg: u64, value: u32) {
+    unsafe { write_volatile((LAPIC_BASE + reg) as *mut u32, value) }
+}
+
+/// Maps the local APIC. Called once on the BSP.
+pub fn init(phys: u64) {
+    unsafe { LAPIC_BASE = crate::memory::vmm::map_mmio(phys, 4096); }
+}
+
+/// Software-enables the calling CPU's local APIC.
+pub fn enable() {
+    write(LAPIC_SVR, SVR_ENABLE | SPURIOUS_INT as u32);
+}
+
+pub fn id() -> u32 {
+    read(LAPIC_ID) >> 24
+}
+
+pub fn eoi() {
+    write(LAPIC_EOI, 0);
+}
+
+/// Whether IRQs come through the I/O APIC and the local APICs rather than the 8259s.
+pub fn is_enabled() -> bool {
+    unsafe { LAPIC_BASE != 0 }
+}
+
+/// Acknowledges IRQ `vector` at whichever controller delivered it.
+pub fn end_interrupt(vector: u8) {
+    if is_enabled() {
+        eoi();
+    } else {
+        unsafe { (*(&raw const crate::interrupts::pic::PICS)).end_interrupt(vector); }
+    }
+}
+
+/// Measures the timer against the system clock. The bus clock behind the
+/// timer is shared, so the BSP does it for everyone.
+pub fn calibrate_timer() {
+    write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+    write(LAPIC_LVT_TIMER, TIMER_MASKED);
+    let end = crate::time::deadline_after(CALIBRATION_MS as u64 * 1_000_000);
+    write(LAPIC_TIMER_INIT, u32::MAX);
+    while crate::time::now_ns() < end {
+        core::hint::spin_loop();
+    }
+    let elapsed = u32::MAX - read(LAPIC_TIMER_CURRENT);
+    write(LAPIC_TIMER_INIT, 0);
+
+    unsafe { TIMER_PER_MS = elapsed / CALIBRATION_MS; }
+    crate::debugln!("APIC: timer runs at {} kHz", elapsed / CALIBRATION_MS * 16);
+}
+
+/// Points the calling CPU's one-shot timer at `vector` and fires it once
+/// a millisecond from now, to enter the scheduler.
+pub fn start_timer(vector: u8) {
+    write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+    write(LAPIC_LVT_TIMER, vector as u32);
+    arm_timer(Some(1_000_000));
+}
+
+/// Fires the calling CPU's timer once, `ns` from now, or stops it for `None`.
+pub fn arm_timer(ns: Option<u64>) {
+    if !is_enabled() {
+        return;
+    }
+    let count = match ns {
+        Some(ns) => (ns as u128 * unsafe { TIMER_PER_MS } as u128 / 1_000_000).clamp(1, u32::MAX as u128) as u32,
+        None => 0,
+    };
+    write(LAPIC_TIMER_INIT, count);
+}
+
+fn send(apic_id: u32, command: u32) {
+    write(LAPIC_ICR_HIGH, apic_id << 24);
+    write(LAPIC_ICR_LOW, command);
+    while read(LAPIC_ICR_LOW) & ICR_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+pub fn send_ipi(apic_id: u32, vector: u8) {
+    send(apic_id, ICR_ASSERT | vector as u32);
+}
+
+/// Sends `vector` to every CPU except the caller.
+pub fn broadcast_ipi(vector: u8) {
+    send(0, ICR_ALL_BUT_SELF | ICR_ASSERT | vector as u32);
+}
+
+pub fn send_init(apic_id: u32) {
+    send(apic_id, ICR_INIT | ICR_ASSERT);
+}
+
+/// Starts `apic_id` in real mode at physical address `page << 12`.
+pub fn send_startup(apic_id: u32, page: u8) {
+    send(apic_id, ICR_STARTUP | ICR_ASSERT | page as u32);
+}
+
+pub extern "x86-interrupt" fn spurious_handler(_info: &mut crate::interrupts::exceptions::StackFrame) {}
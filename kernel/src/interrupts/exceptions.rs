@@ -1,6 +1,10 @@
 use crate::drivers::periferics::keyboard::KEYBOARD_BUFFER;
 use crate::drivers::port::{inb, outb};
+use crate::interrupts::signal::{self, SigInfo};
+use crate::interrupts::task::CPUState;
+use crate::memory::vma;
 use crate::window_manager::input::MOUSE;
+use core::arch::naked_asm;
 
 #[derive(Clone, Copy, Debug)]
 #[repr(C)]
@@ -46,148 +50,156 @@ fn print_hex(n: u64) {
     }
 }
 
-fn kill_current_task() {
-    let mut pid_to_kill = -1;
-    {
-        let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        if let Some(current) = tm.current_task_idx() {
-            if let Some(thread) = tm.tasks[current].as_ref() {
-                pid_to_kill = thread.process.as_ref().expect("Thread has no process").pid as i32;
-            }
-        }
+/// Common entry for user-recoverable exceptions. The vector stubs leave
+/// `[vector, error_code, iret frame]` on the stack; swapping those two slots
+/// with rax/rbp turns it into a `CPUState` so a signal frame can be built.
+#[unsafe(naked)]
+extern "C" fn exception_entry() {
+    unsafe {
+        naked_asm!(
+            "xchg rbp, [rsp + 8]", "xchg rax, [rsp]",
+            "push rbx", "push rcx", "push rdx", "push rsi", "push rdi",
+            "push r8", "push r9", "push r10", "push r11", "push r12", "push r13", "push r14", "push r15",
+            "mov rdi, rsp", "mov rsi, rax", "mov rdx, rbp", "cld", "call exception_dispatch",
+            "pop r15", "pop r14", "pop r13", "pop r12", "pop r11", "pop r10", "pop r9", "pop r8",
+            "pop rdi", "pop rsi", "pop rdx", "pop rcx", "pop rbx", "pop rax", "pop rbp",
+            "iretq",
+        );
     }
+}
 
-    if pid_to_kill != -1 {
-        crate::interrupts::task::TASK_MANAGER.int_lock().kill_process(pid_to_kill as u64);
-
-        unsafe {
-            core::arch::asm!("sti");
-            loop { core::arch::asm!("hlt"); }
+macro_rules! exception_stub {
+    ($name:ident, $vector:expr) => {
+        #[unsafe(naked)]
+        pub extern "C" fn $name() {
+            unsafe {
+                naked_asm!("push 0", "push {v}", "jmp {entry}", v = const $vector, entry = sym exception_entry);
+            }
         }
-    } else {
-        serial_println("Kernel Panic: Exception in Kernel Mode with no valid task.");
-        unsafe {
-            core::arch::asm!("cli");
-            loop { core::arch::asm!("hlt"); }
+    };
+    ($name:ident, $vector:expr, error_code) => {
+        #[unsafe(naked)]
+        pub extern "C" fn $name() {
+            unsafe {
+                naked_asm!("push {v}", "jmp {entry}", v = const $vector, entry = sym exception_entry);
+            }
         }
-    }
-}
-
-pub extern "x86-interrupt" fn div_error(info: &mut StackFrame) {
-    serial_println("EXCEPTION: DIV ERROR");
-    if (info.code_segment & 3) == 3 {
-        serial_println("User mode exception. Terminating task.");
-        kill_current_task();
-    } else {
-        loop {}
-    }
+    };
 }
 
-pub extern "x86-interrupt" fn bounds(info: &mut StackFrame) {
-    serial_println("EXCEPTION: BOUNDS");
-    if (info.code_segment & 3) == 3 {
-        serial_println("User mode exception. Terminating task.");
-        kill_current_task();
-    } else {
-        loop {}
-    }
-}
-
-pub extern "x86-interrupt" fn invalid_opcode(info: &mut StackFrame) {
-    serial_println("EXCEPTION: INVALID OPCODE");
-    serial_print("RIP: ");
-    print_hex(info.instruction_pointer);
-    serial_print("\r\n");
+exception_stub!(div_error, 0);
+exception_stub!(bounds, 5);
+exception_stub!(invalid_opcode, 6);
+exception_stub!(device_not_available, 7);
+exception_stub!(general_protection_fault, 13, error_code);
+exception_stub!(page_fault, 14, error_code);
+exception_stub!(fpu_error, 16);
+exception_stub!(simd_error, 19);
+
+#[unsafe(no_mangle)]
+extern "C" fn exception_dispatch(state: &mut CPUState, vector: u64, error_code: u64) {
+    let user = (state.cs & 3) == 3;
+    // Kernel-mode faults come from code that already holds it.
+    let _kernel = if user { Some(crate::sync::KERNEL_LOCK.lock()) } else { None };
+
+    let mut cr2 = 0;
+    if vector == 14 {
+        unsafe {
+            core::arch::asm!("mov {}, cr2", out(reg) cr2);
+        }
 
-    if (info.code_segment & 3) == 3 {
-        serial_println("User mode exception. Terminating task.");
-        kill_current_task();
-    } else {
-        loop {}
+        let is_write = (error_code & 0x2) != 0;
+        let is_present = (error_code & 0x1) != 0;
+        if cr2 < vma::USER_LIMIT {
+            if !is_present && vma::handle_demand_fault(cr2, is_write) {
+                return;
+            }
+            let pid = crate::interrupts::task::current_pid();
+            if is_present && is_write && crate::memory::vmm::handle_cow_fault(cr2, pid) {
+                return;
+            }
+        }
+        if !user && crate::memory::uaccess::fixup(state) {
+            return;
+        }
     }
-}
-
-pub extern "x86-interrupt" fn double_fault(_info: &mut StackFrame, _error_code: u64) -> ! {
-    serial_println("EXCEPTION: DOUBLE FAULT");
-    loop {}
-}
-
-pub extern "x86-interrupt" fn general_protection_fault(info: &mut StackFrame, error_code: u64) {
-    serial_print("\r\n=== GENERAL PROTECTION FAULT ===\r\n");
-    serial_print("Error Code: ");
-    print_hex(error_code);
-    serial_print("\r\nRIP: ");
-    print_hex(info.instruction_pointer);
-    serial_print("\r\nRSP: ");
-    print_hex(info.stack_pointer);
-    serial_print("\r\n");
 
-    if (info.code_segment & 3) == 3 {
-        serial_println("User mode GPF. Terminating task.");
-        kill_current_task();
-    } else {
-        unsafe {
-            core::arch::asm!("cli");
-            loop { core::arch::asm!("hlt"); }
+    let (sig, info) = match vector {
+        0 => {
+            serial_println("EXCEPTION: DIV ERROR");
+            (signal::SIGFPE, SigInfo::new(signal::SIGFPE, signal::FPE_INTDIV, state.rip))
         }
-    }
-}
+        5 => {
+            serial_println("EXCEPTION: BOUNDS");
+            (signal::SIGSEGV, SigInfo::new(signal::SIGSEGV, 0, state.rip))
+        }
+        6 => {
+            serial_println("EXCEPTION: INVALID OPCODE");
+            serial_print("RIP: ");
+            print_hex(state.rip);
+            serial_print("\r\n");
+            (signal::SIGILL, SigInfo::new(signal::SIGILL, signal::ILL_ILLOPC, state.rip))
+        }
+        7 => {
+            serial_println("EXCEPTION: DEVICE NOT AVAILABLE (#NM)");
+            (signal::SIGILL, SigInfo::new(signal::SIGILL, 0, state.rip))
+        }
+        13 => {
+            serial_print("\r\n=== GENERAL PROTECTION FAULT ===\r\n");
+            serial_print("Error Code: ");
+            print_hex(error_code);
+            serial_print("\r\nRIP: ");
+            print_hex(state.rip);
+            serial_print("\r\nRSP: ");
+            print_hex(state.rsp);
+            serial_print("\r\n");
+            (signal::SIGSEGV, SigInfo::new(signal::SIGSEGV, 0, 0))
+        }
+        14 => {
+            serial_println("\n=== PAGE FAULT ===");
+            serial_print("Address (CR2): ");
+            print_hex(cr2);
+            serial_print("\r\nError Code: ");
+            print_hex(error_code);
+            serial_print("\r\nRIP: ");
+            print_hex(state.rip);
+            serial_println("");
+            let code = if (error_code & 1) != 0 { signal::SEGV_ACCERR } else { signal::SEGV_MAPERR };
+            (signal::SIGSEGV, SigInfo::new(signal::SIGSEGV, code, cr2))
+        }
+        16 => {
+            serial_println("EXCEPTION: x87 FPU ERROR (#MF)");
+            (signal::SIGFPE, SigInfo::new(signal::SIGFPE, 0, state.rip))
+        }
+        _ => {
+            serial_println("EXCEPTION: SIMD FP ERROR (#XM)");
+            (signal::SIGFPE, SigInfo::new(signal::SIGFPE, signal::FPE_FLTINV, state.rip))
+        }
+    };
 
-pub extern "x86-interrupt" fn page_fault(info: &mut StackFrame, error_code: u64) {
-    let cr2: u64;
-    unsafe {
-        core::arch::asm!("mov {}, cr2", out(reg) cr2);
+    if !user && vector == 14 && cr2 < vma::USER_LIMIT && !crate::interrupts::task::TASK_MANAGER.held_by_this_cpu() {
+        serial_println("Bad user pointer passed to the kernel. Terminating task.");
+        signal::terminate_current(signal::SIGSEGV);
     }
 
-    serial_println("\n=== PAGE FAULT ===");
-    serial_print("Address (CR2): ");
-    print_hex(cr2);
-    serial_print("\r\nError Code: ");
-    print_hex(error_code);
-    serial_print("\r\nRIP: ");
-    print_hex(info.instruction_pointer);
-    serial_println("");
-
-    if (info.code_segment & 3) == 3 {
-        serial_println("User mode Page Fault. Terminating task.");
-        kill_current_task();
-    } else {
+    if !user {
+        serial_println("Kernel Panic: Exception in Kernel Mode.");
         unsafe {
             core::arch::asm!("cli");
             loop { core::arch::asm!("hlt"); }
         }
     }
-}
 
-pub extern "x86-interrupt" fn generic_handler(_info: &mut StackFrame) {
-    serial_println("EXCEPTION: GENERIC");
-}
-
-pub extern "x86-interrupt" fn device_not_available(info: &mut StackFrame) {
-    serial_println("EXCEPTION: DEVICE NOT AVAILABLE (#NM)");
-    if (info.code_segment & 3) == 3 {
-        kill_current_task();
-    } else {
-        loop {}
-    }
+    signal::force_signal(state, sig, info);
 }
 
-pub extern "x86-interrupt" fn fpu_error(info: &mut StackFrame) {
-    serial_println("EXCEPTION: x87 FPU ERROR (#MF)");
-    if (info.code_segment & 3) == 3 {
-        kill_current_task();
-    } else {
-        loop {}
-    }
+pub extern "x86-interrupt" fn double_fault(_info: &mut StackFrame, _error_code: u64) -> ! {
+    serial_println("EXCEPTION: DOUBLE FAULT");
+    loop {}
 }
 
-pub extern "x86-interrupt" fn simd_error(info: &mut StackFrame) {
-    serial_println("EXCEPTION: SIMD FP ERROR (#XM)");
-    if (info.code_segment & 3) == 3 {
-        kill_current_task();
-    } else {
-        loop {}
-    }
+pub extern "x86-interrupt" fn generic_handler(_info: &mut StackFrame) {
+    serial_println("EXCEPTION: GENERIC");
 }
 
 
@@ -199,6 +211,7 @@ pub const TIMER_INT: u8 = 32;
 pub const KEYBOARD_INT: u8 = 33;
 
 pub extern "x86-interrupt" fn keyboard_handler(_info: &mut StackFrame) {
+    let _kernel = crate::sync::KERNEL_LOCK.lock();
     let scancode: u8 = inb(0x60);
 
     if let Some((key, pressed)) = crate::drivers::periferics::keyboard::handle_scancode(scancode) {
@@ -212,7 +225,7 @@ pub extern "x86-interrupt" fn keyboard_handler(_info: &mut StackFrame) {
 
                 if key == 't' as u32 {
                     crate::debugln!("Spawning terminal...");
-                    match crate::interrupts::syscalls::spawn_process("@0xE0/sys/bin/term.elf", None, None) {
+                    match crate::interrupts::syscalls::spawn_process("/sys/bin/term.elf", None, None) {
                         Ok(pid) => crate::debugln!("Terminal spawned with PID: {}", pid),
                         Err(e) => crate::debugln!("Failed to spawn terminal: {}", e),
                     }
@@ -246,6 +259,8 @@ pub extern "x86-interrupt" fn keyboard_handler(_info: &mut StackFrame) {
         } else {
             if pressed {
                 KEYBOARD_BUFFER.lock().push_back(key);
+                crate::drivers::periferics::keyboard::KEYBOARD_WAIT.wake_all();
+                crate::interrupts::wait::POLL_WAIT.wake_all();
             } else {
             }
 
@@ -285,7 +300,7 @@ pub extern "x86-interrupt" fn keyboard_handler(_info: &mut StackFrame) {
     }
 
     unsafe {
-        (*(&raw const crate::interrupts::pic::PICS)).end_interrupt(KEYBOARD_INT);
+        crate::interrupts::apic::end_interrupt(KEYBOARD_INT);
     }
 }
 
@@ -298,11 +313,12 @@ pub static mut MOUSE_IDX: usize = 0;
 pub extern "x86-interrupt" fn mouse_handler(_info: &mut StackFrame) {
     use crate::drivers::periferics::mouse::{MOUSE_IDX, MOUSE_PACKET, MOUSE_PACKET_SIZE};
 
+    let _kernel = crate::sync::KERNEL_LOCK.lock();
     let data = inb(0x60);
 
     unsafe {
         if MOUSE_IDX == 0 && ((data & 0x08) == 0 || data == 0xFF) {
-            (*(&raw const crate::interrupts::pic::PICS)).end_interrupt(MOUSE_INT);
+            crate::interrupts::apic::end_interrupt(MOUSE_INT);
             return;
         }
 
@@ -322,8 +338,22 @@ pub extern "x86-interrupt" fn mouse_handler(_info: &mut StackFrame) {
             MOUSE_IDX = 0;
         }
 
-        (*(&raw const crate::interrupts::pic::PICS)).end_interrupt(MOUSE_INT);
+        crate::interrupts::apic::end_interrupt(MOUSE_INT);
     }
 }
 
+pub const IDE_PRIMARY_INT: u8 = 46;
+pub const IDE_SECONDARY_INT: u8 = 47;
+
+/// The ATA driver polls, so these only acknowledge the drive by reading its status.
+pub extern "x86-interrupt" fn ide_primary_handler(_info: &mut StackFrame) {
+    let _ = inb(0x1F7);
+    crate::interrupts::apic::end_interrupt(IDE_PRIMARY_INT);
+}
+
+pub extern "x86-interrupt" fn ide_secondary_handler(_info: &mut StackFrame) {
+    let _ = inb(0x177);
+    crate::interrupts::apic::end_interrupt(IDE_SECONDARY_INT);
+}
+
 pub const YIELD_INT: u8 = 129;
\ No newline at end of file
@@ -68,3 +68,53 @@ pub fn reload_gdt_high_half() {
         asm!("ltr {:x}", in(reg) tr, options(nostack, preserves_flags));
     }
 }
+
+/// Descriptors in the bootloader's GDT, the two-slot TSS descriptor included.
+pub const GDT_ENTRIES: usize = 9;
+const TSS_SELECTOR: u16 = 0x38;
+
+/// Builds the GDT of another CPU: a copy of the calling CPU's, with the TSS
+/// descriptor pointing at `tss`.
+pub fn clone_for_cpu(gdt: &mut [u64; GDT_ENTRIES], tss: u64) {
+    unsafe {
+        let mut gdtr = GdtDescriptor { size: 0, offset: 0 };
+        asm!("sgdt [{}]", in(reg) &mut gdtr, options(nostack, preserves_flags));
+        let count = core::cmp::min((gdtr.size as usize + 1) / 8, GDT_ENTRIES);
+        core::ptr::copy_nonoverlapping(gdtr.offset as *const u64, gdt.as_mut_ptr(), count);
+    }
+
+    let limit = (core::mem::size_of::<crate::boot::TaskStateSegment>() - 1) as u64;
+    let idx = (TSS_SELECTOR >> 3) as usize;
+    gdt[idx] = (limit & 0xFFFF)
+        | ((tss & 0xFFFF) << 16)
+        | (((tss >> 16) & 0xFF) << 32)
+        | (0x89 << 40)
+        | (((limit >> 16) & 0xF) << 48)
+        | (((tss >> 24) & 0xFF) << 56);
+    gdt[idx + 1] = tss >> 32;
+}
+
+/// Loads a GDT built by `clone_for_cpu`, reloads the kernel segments and its TSS.
+pub fn load(gdt: &[u64; GDT_ENTRIES]) {
+    unsafe {
+        let gdtr = GdtDescriptor {
+            size: (core::mem::size_of_val(gdt) - 1) as u16,
+            offset: gdt.as_ptr() as u64,
+        };
+        asm!("lgdt [{}]", in(reg) &gdtr, options(nostack, preserves_flags));
+        asm!(
+            "push 0x28",
+            "lea {tmp}, [rip + 2f]",
+            "push {tmp}",
+            "retfq",
+            "2:",
+            "mov {sel:x}, 0x10",
+            "mov ds, {sel:x}",
+            "mov es, {sel:x}",
+            "mov ss, {sel:x}",
+            tmp = out(reg) _,
+            sel = out(reg) _,
+        );
+        asm!("ltr {:x}", in(reg) TSS_SELECTOR, options(nostack, preserves_flags));
+    }
+}
@@ -105,6 +105,11 @@ impl Idt {
         self.add_ring_3(exceptions::YIELD_INT as usize, task::yield_handler as u64);
         self.add(exceptions::KEYBOARD_INT as usize, exceptions::keyboard_handler as u64);
         self.add(exceptions::MOUSE_INT as usize, exceptions::mouse_handler as u64);
+        self.add(exceptions::IDE_PRIMARY_INT as usize, exceptions::ide_primary_handler as u64);
+        self.add(exceptions::IDE_SECONDARY_INT as usize, exceptions::ide_secondary_handler as u64);
+        self.add(crate::smp::RESCHED_IPI as usize, task::timer_handler as u64);
+        self.add(crate::smp::TLB_IPI as usize, crate::smp::tlb_flush_handler as u64);
+        self.add(crate::interrupts::apic::SPURIOUS_INT as usize, crate::interrupts::apic::spurious_handler as u64);
     }
 }
 
@@ -0,0 +1,175 @@
+use alloc::vec::Vec;
+use crate::drivers::acpi::{IoApicInfo, IrqOverride, Madt};
+use core::ptr::{read_volatile, write_volatile};
+
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDTBL: u32 = 0x10;
+
+const REDIR_ACTIVE_LOW: u64 = 1 << 13;
+const REDIR_LEVEL: u64 = 1 << 15;
+const REDIR_MASKED: u64 = 1 << 16;
+
+/// ISA IRQs land on the vectors the remapped 8259s used, so handlers keep their numbers.
+pub const IRQ_BASE: u8 = 32;
+
+struct IoApic {
+    base: u64,
+    gsi_base: u32,
+    entries: u32,
+}
+
+static mut IO_APICS: Vec<IoApic> = Vec::new();
+static mut OVERRIDES: Vec<IrqOverride> = Vec::new();
+/// APIC ID every routed IRQ is delivered to.
+static mut DESTINATION: u32 = 0;
+
+impl IoApic {
+    fn new(info: &IoApicInfo) -> Self {
+        let mut apic = Self {
+            base: crate::memory::vmm::map_mmio(info.addr, 4096),
+            gsi_base: info.gsi_base,
+            entries: 0,
+        };
+        apic.entries = ((apic.read(IOAPIC_VERSION) >> 16) & 0xFF) + 1;
+        apic
+    }
+
+    fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            write_volatile((self.base + IOREGSEL) as *mut u32, reg);
+            read_volatile((self.base + IOWIN) as *const u32)
+        }
+    }
+
+    fn write(&self, reg: u32, value: u32) {
+        unsafe {
+            write_volatile((self.base + IOREGSEL) as *mut u32, reg);
+            write_volatile((self.base + IOWIN) as *mut u32, value);
+        }
+    }
+
+    fn read_entry(&self, index: u32) -> u64 {
+        let low = self.read(IOAPIC_REDTBL + index * 2) as u64;
+        let high = self.read(IOAPIC_REDTBL + index * 2 + 1) as u64;
+        (high << 32) | low
+    }
+
+    fn write_entry(&self, index: u32, entry: u64) {
+        self.write(IOAPIC_REDTBL + index * 2, REDIR_MASKED as u32);
+        self.write(IOAPIC_REDTBL + index * 2 + 1, (entry >> 32) as u32);
+        self.write(IOAPIC_REDTBL + index * 2, entry as u32);
+    }
+}
+
+/// I/O APIC and pin serving `gsi`.
+fn pin_of(gsi: u32) -> Option<(&'static IoApic, u32)> {
+    let apics = unsafe { &*(&raw const IO_APICS) };
+    apics.iter()
+        .find(|a| gsi >= a.gsi_base && gsi < a.gsi_base + a.entries)
+        .map(|a| (a, gsi - a.gsi_base))
+}
+
+/// GSI and redirection flags of legacy IRQ `irq`. Lines without an override
+/// keep their bus defaults: edge/high for ISA, level/low for PCI.
+fn resolve(irq: u8, pci: bool) -> (u32, u64) {
+    let default = if pci { REDIR_LEVEL | REDIR_ACTIVE_LOW } else { 0 };
+    let overrides = unsafe { &*(&raw const OVERRIDES) };
+    let iso = match overrides.iter().find(|o| o.irq == irq) {
+        Some(o) => o,
+        None => return (irq as u32, default),
+    };
+
+    let mut flags = default;
+    match iso.flags & 0b11 {
+        0b01 => flags &= !REDIR_ACTIVE_LOW,
+        0b11 => flags |= REDIR_ACTIVE_LOW,
+        _ => {}
+    }
+    match (iso.flags >> 2) & 0b11 {
+        0b01 => flags &= !REDIR_LEVEL,
+        0b11 => flags |= REDIR_LEVEL,
+        _ => {}
+    }
+    (iso.gsi, flags)
+}
+
+/// Takes over IRQ delivery from the 8259s: maps every I/O APIC in the MADT with
+/// all pins masked. Returns false if there is none.
+pub fn init(madt: &Madt, bsp_apic_id: u32) -> bool {
+    if madt.io_apics.is_empty() {
+        return false;
+    }
+    unsafe {
+        DESTINATION = bsp_apic_id;
+        *(&raw mut OVERRIDES) = madt.overrides.clone();
+        let apics = &mut *(&raw mut IO_APICS);
+        for info in madt.io_apics.iter() {
+            let apic = IoApic::new(info);
+            for pin in 0..apic.entries {
+                apic.write_entry(pin, REDIR_MASKED);
+            }
+            crate::debugln!("IOAPIC: id {} serving GSIs {}-{}", info.id, apic.gsi_base, apic.gsi_base + apic.entries - 1);
+            apics.push(apic);
+        }
+    }
+    true
+}
+
+/// Points legacy IRQ `irq` at `IRQ_BASE + irq` on the BSP. `pci` selects the
+/// bus defaults for lines shared by PCI INTx pins. Stays masked if `masked`.
+pub fn route(irq: u8, pci: bool, masked: bool) -> bool {
+    let (gsi, flags) = resolve(irq, pci);
+    let (apic, pin) = match pin_of(gsi) {
+        Some(p) => p,
+        None => return false,
+    };
+    let mut entry = flags | (IRQ_BASE + irq) as u64 | ((unsafe { DESTINATION } as u64) << 56);
+    if masked {
+        entry |= REDIR_MASKED;
+    }
+    apic.write_entry(pin, entry);
+    true
+}
+
+/// Routes the PS/2 and IDE lines, plus the INTx lines of PCI devices, which
+/// stay masked until their driver unmasks them.
+pub fn route_legacy_devices() {
+    use crate::interrupts::exceptions::{IDE_PRIMARY_INT, IDE_SECONDARY_INT, KEYBOARD_INT, MOUSE_INT};
+
+    for vector in [KEYBOARD_INT, MOUSE_INT, IDE_PRIMARY_INT, IDE_SECONDARY_INT] {
+        route(vector - IRQ_BASE, false, false);
+    }
+    for dev in crate::drivers::pci::devices() {
+        if let Some(line) = dev.interrupt_line() {
+            if !is_routed(line) {
+                route(line, true, true);
+            }
+        }
+    }
+}
+
+/// Whether `irq` already has a vector.
+pub fn is_routed(irq: u8) -> bool {
+    let (gsi, _) = resolve(irq, false);
+    pin_of(gsi).map_or(false, |(apic, pin)| (apic.read_entry(pin) & 0xFF) != 0)
+}
+
+fn set_masked(irq: u8, masked: bool) {
+    let (gsi, _) = resolve(irq, false);
+    if let Some((apic, pin)) = pin_of(gsi) {
+        let entry = apic.read_entry(pin);
+        let entry = if masked { entry | REDIR_MASKED } else { entry & !REDIR_MASKED };
+        apic.write_entry(pin, entry);
+    }
+}
+
+pub fn mask(irq: u8) {
+    set_masked(irq, true);
+}
+
+/// Lets a routed IRQ through, once its driver has a handler installed.
+pub fn unmask(irq: u8) {
+    set_masked(irq, false);
+}
@@ -2,5 +2,11 @@ pub mod exceptions;
 pub mod idt;
 pub mod pic;
 pub mod task;
+pub mod signal;
+pub mod wait;
+pub mod rlimit;
+pub mod tls;
 pub mod syscalls;
-pub mod gdt;
\ No newline at end of file
+pub mod gdt;
+pub mod apic;
+pub mod ioapic;
\ No newline at end of file
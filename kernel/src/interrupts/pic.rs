@@ -85,7 +85,10 @@ impl Pics {
 
         self.master.write_data(0xFF);
         self.slave.write_data(0xFF);
+    }
 
+    /// Lets the timer, keyboard and mouse through, for machines without an I/O APIC.
+    pub fn unmask_legacy(&self) {
         self.master.unmask_irq(0);
         self.master.unmask_irq(1);
         self.master.unmask_irq(2);
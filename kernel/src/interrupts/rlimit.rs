@@ -0,0 +1,63 @@
+pub const RLIMIT_NPROC: usize = 6;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIMIT_AS: usize = 9;
+pub const RLIM_NLIMITS: usize = 16;
+
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+/// Descriptors a process may hold unless it raises its own limit.
+const DEFAULT_NOFILE: u64 = 1024;
+const DEFAULT_NOFILE_MAX: u64 = 4096;
+
+/// Soft and hard limit, laid out like C `struct rlimit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct RLimit {
+    pub cur: u64,
+    pub max: u64,
+}
+
+unsafe impl crate::memory::uaccess::Pod for RLimit {}
+
+impl RLimit {
+    pub const INFINITE: Self = Self { cur: RLIM_INFINITY, max: RLIM_INFINITY };
+}
+
+/// Per-process limits, inherited across `fork` and `spawn`.
+/// `RLIMIT_NPROC` caps the threads of one process, `RLIMIT_AS` the bytes
+/// covered by its VMAs.
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceLimits {
+    limits: [RLimit; RLIM_NLIMITS],
+}
+
+impl ResourceLimits {
+    pub const fn new() -> Self {
+        let mut limits = [RLimit::INFINITE; RLIM_NLIMITS];
+        limits[RLIMIT_NOFILE] = RLimit { cur: DEFAULT_NOFILE, max: DEFAULT_NOFILE_MAX };
+        Self { limits }
+    }
+
+    pub fn get(&self, resource: usize) -> Option<RLimit> {
+        self.limits.get(resource).copied()
+    }
+
+    /// Soft limit of `resource`.
+    pub fn cur(&self, resource: usize) -> u64 {
+        self.limits[resource].cur
+    }
+
+    /// Installs `new`. The soft limit may not exceed the hard one, and the
+    /// hard limit can only be lowered.
+    pub fn set(&mut self, resource: usize, new: RLimit) -> bool {
+        let old = match self.limits.get_mut(resource) {
+            Some(l) => l,
+            None => return false,
+        };
+        if new.cur > new.max || new.max > old.max {
+            return false;
+        }
+        *old = new;
+        true
+    }
+}
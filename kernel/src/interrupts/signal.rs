@@ -0,0 +1,508 @@
+use crate::interrupts::task::{CPUState, Process, TaskManager, TaskState, TASK_MANAGER};
+use crate::debugln;
+use crate::memory::uaccess;
+use alloc::sync::Arc;
+use core::arch::asm;
+use core::mem::{offset_of, size_of};
+
+pub const NSIG: usize = 32;
+
+pub const SIGHUP: u32 = 1;
+pub const SIGINT: u32 = 2;
+pub const SIGQUIT: u32 = 3;
+pub const SIGILL: u32 = 4;
+pub const SIGTRAP: u32 = 5;
+pub const SIGABRT: u32 = 6;
+pub const SIGBUS: u32 = 7;
+pub const SIGFPE: u32 = 8;
+pub const SIGKILL: u32 = 9;
+pub const SIGUSR1: u32 = 10;
+pub const SIGSEGV: u32 = 11;
+pub const SIGUSR2: u32 = 12;
+pub const SIGPIPE: u32 = 13;
+pub const SIGALRM: u32 = 14;
+pub const SIGTERM: u32 = 15;
+pub const SIGCHLD: u32 = 17;
+pub const SIGCONT: u32 = 18;
+pub const SIGSTOP: u32 = 19;
+pub const SIGTSTP: u32 = 20;
+pub const SIGTTIN: u32 = 21;
+pub const SIGTTOU: u32 = 22;
+pub const SIGURG: u32 = 23;
+pub const SIGWINCH: u32 = 28;
+
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+pub const SA_NODEFER: u32 = 0x4000_0000;
+pub const SA_RESETHAND: u32 = 0x8000_0000;
+
+pub const SIG_BLOCK: u64 = 0;
+pub const SIG_UNBLOCK: u64 = 1;
+pub const SIG_SETMASK: u64 = 2;
+
+/// Fault codes reported in `SigInfo::code` for synchronous signals.
+pub const SEGV_MAPERR: i32 = 1;
+pub const SEGV_ACCERR: i32 = 2;
+pub const ILL_ILLOPC: i32 = 1;
+pub const FPE_INTDIV: i32 = 1;
+pub const FPE_FLTINV: i32 = 7;
+
+const USER_LIMIT: u64 = 0x0000_8000_0000_0000;
+const RED_ZONE: u64 = 128;
+const UNBLOCKABLE: u32 = sig_bit(SIGKILL) | sig_bit(SIGSTOP);
+
+/// Userspace view of a signal disposition, as passed to `SYS_SIGACTION`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigAction {
+    pub handler: u64,
+    pub flags: u32,
+    pub mask: u32,
+    pub restorer: u64,
+}
+
+unsafe impl crate::memory::uaccess::Pod for SigAction {}
+
+impl SigAction {
+    pub const fn default() -> Self {
+        Self { handler: SIG_DFL, flags: 0, mask: 0, restorer: 0 }
+    }
+}
+
+/// Layout-compatible with the leading fields of a POSIX `siginfo_t`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigInfo {
+    pub signo: i32,
+    pub errno: i32,
+    pub code: i32,
+    pub pid: i32,
+    pub addr: u64,
+}
+
+impl SigInfo {
+    pub const fn new(signo: u32, code: i32, addr: u64) -> Self {
+        Self { signo: signo as i32, errno: 0, code, pid: 0, addr }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SignalState {
+    pub pending: u32,
+    pub blocked: u32,
+    pub actions: [SigAction; NSIG],
+}
+
+impl SignalState {
+    pub const fn new() -> Self {
+        Self {
+            pending: 0,
+            blocked: 0,
+            actions: [SigAction::default(); NSIG],
+        }
+    }
+
+    /// State inherited across `fork`: dispositions and mask, but nothing pending.
+    pub fn forked(&self) -> Self {
+        Self { pending: 0, blocked: self.blocked, actions: self.actions }
+    }
+
+    /// Caught signals revert to the default action when the image is replaced.
+    pub fn reset_for_exec(&mut self) {
+        for action in self.actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
+}
+
+/// Pushed onto the user stack before a handler runs and consumed by `sigreturn`.
+/// `restorer` sits at the handler's rsp so a plain `ret` lands in the trampoline.
+#[repr(C, align(16))]
+struct SignalFrame {
+    restorer: u64,
+    info: SigInfo,
+    context: CPUState,
+    blocked: u64,
+    _pad: u64,
+    fpu_state: [u8; 512],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+pub const fn sig_bit(sig: u32) -> u32 {
+    1 << (sig - 1)
+}
+
+fn default_action(sig: u32) -> DefaultAction {
+    match sig {
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+pub fn signal_name(sig: u32) -> &'static str {
+    match sig {
+        SIGHUP => "SIGHUP",
+        SIGINT => "SIGINT",
+        SIGQUIT => "SIGQUIT",
+        SIGILL => "SIGILL",
+        SIGTRAP => "SIGTRAP",
+        SIGABRT => "SIGABRT",
+        SIGBUS => "SIGBUS",
+        SIGFPE => "SIGFPE",
+        SIGKILL => "SIGKILL",
+        SIGUSR1 => "SIGUSR1",
+        SIGSEGV => "SIGSEGV",
+        SIGUSR2 => "SIGUSR2",
+        SIGPIPE => "SIGPIPE",
+        SIGALRM => "SIGALRM",
+        SIGTERM => "SIGTERM",
+        _ => "signal",
+    }
+}
+
+pub fn is_valid(sig: u64) -> bool {
+    sig >= 1 && sig < NSIG as u64
+}
+
+/// Builds a signal frame for `sig` below the interrupted user stack and
+/// redirects `context` into the handler. The live FPU state belongs to the
+/// thread being resumed, so it is saved into the frame and reset.
+fn setup_frame(state: &mut SignalState, context: &mut CPUState, sig: u32, info: SigInfo) -> bool {
+    let action = state.actions[sig as usize];
+
+    let mut frame = SignalFrame {
+        restorer: action.restorer,
+        info,
+        context: *context,
+        blocked: state.blocked as u64,
+        _pad: 0,
+        fpu_state: [0; 512],
+    };
+    unsafe {
+        asm!("fxsave [{}]", in(reg) frame.fpu_state.as_mut_ptr());
+    }
+
+    let user_rsp = context.rsp;
+    if user_rsp >= USER_LIMIT || user_rsp < RED_ZONE + size_of::<SignalFrame>() as u64 {
+        return false;
+    }
+    let frame_addr = ((user_rsp - RED_ZONE - size_of::<SignalFrame>() as u64) & !15) - 8;
+
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&frame as *const SignalFrame as *const u8, size_of::<SignalFrame>())
+    };
+    // Page faults run on an IST stack, so the frame must not fault as it is written.
+    if !uaccess::fault_in(frame_addr, bytes.len(), true) || !uaccess::copy_to_user(frame_addr, bytes) {
+        return false;
+    }
+
+    state.blocked |= action.mask & !UNBLOCKABLE;
+    if (action.flags & SA_NODEFER) == 0 {
+        state.blocked |= sig_bit(sig);
+    }
+    if (action.flags & SA_RESETHAND) != 0 {
+        state.actions[sig as usize] = SigAction::default();
+    }
+
+    context.rip = action.handler;
+    context.rsp = frame_addr;
+    context.rdi = sig as u64;
+    context.rsi = frame_addr + offset_of!(SignalFrame, info) as u64;
+    context.rdx = frame_addr + offset_of!(SignalFrame, context) as u64;
+    context.rax = 0;
+    context.cs = 0x33;
+    context.ss = 0x23;
+    context.rflags &= !0x500;
+
+    unsafe {
+        let mxcsr: u32 = 0x1F80;
+        asm!("fninit", "ldmxcsr [{}]", in(reg) &mxcsr);
+    }
+    true
+}
+
+fn process_of(tm: &TaskManager, pid: u64) -> Option<Arc<Process>> {
+    tm.tasks.iter().flatten()
+        .filter(|t| t.state != TaskState::Zombie && t.state != TaskState::Null)
+        .filter_map(|t| t.process.as_ref())
+        .find(|p| p.pid == pid)
+        .cloned()
+}
+
+fn set_process_state(tm: &mut TaskManager, pid: u64, from: &[TaskState], to: TaskState) {
+    for thread in tm.tasks.iter_mut().flatten() {
+        if thread.process.as_ref().map_or(false, |p| p.pid == pid) && from.contains(&thread.state) {
+            thread.state = to;
+        }
+    }
+    if to == TaskState::Ready {
+        tm.kick_idle_cpus();
+    }
+}
+
+/// Kills every thread of `pid` and closes its descriptors, leaving `128 + sig`
+/// as the exit status for `wait_pid`.
+fn terminate(tm: &mut TaskManager, pid: u64, sig: u32) {
+    let mut name = [0u8; 32];
+    for thread in tm.tasks.iter_mut().flatten() {
+        if let Some(proc) = thread.process.as_ref() {
+            if proc.pid == pid {
+                thread.exit_code = 128 + sig as u64;
+                if name[0] == 0 {
+                    name = thread.name;
+                }
+            }
+        }
+    }
+    let name_len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    debugln!("[Signal] {} (PID {}) terminated by {}", core::str::from_utf8(&name[..name_len]).unwrap_or("?"), pid, signal_name(sig));
+
+    if let Some(proc) = process_of(tm, pid) {
+        proc.close_all_fds();
+    }
+    tm.kill_process(pid);
+}
+
+/// Parks a thread that will never run again until the scheduler drops it.
+pub(crate) fn halt_current() -> ! {
+    crate::sync::KERNEL_LOCK.release_all();
+    unsafe {
+        asm!("sti");
+        loop { asm!("hlt"); }
+    }
+}
+
+/// Generates `sig` for process `pid`. Returns false if no such process exists.
+pub fn send_signal(tm: &mut TaskManager, pid: u64, sig: u32) -> bool {
+    let proc = match process_of(tm, pid) {
+        Some(p) => p,
+        None => return false,
+    };
+    if sig == 0 {
+        return true;
+    }
+
+    match sig {
+        SIGKILL => {
+            terminate(tm, pid, sig);
+            return true;
+        }
+        SIGSTOP => {
+            set_process_state(tm, pid, &[TaskState::Ready, TaskState::Sleeping], TaskState::Stopped);
+            return true;
+        }
+        SIGCONT => set_process_state(tm, pid, &[TaskState::Stopped], TaskState::Ready),
+        _ => {}
+    }
+
+    let mut state = proc.signals.int_lock();
+    let handler = state.actions[sig as usize].handler;
+    let blocked = (state.blocked & sig_bit(sig)) != 0;
+
+    if !blocked && handler == SIG_DFL {
+        match default_action(sig) {
+            DefaultAction::Terminate => {
+                drop(state);
+                terminate(tm, pid, sig);
+            }
+            DefaultAction::Stop => {
+                drop(state);
+                set_process_state(tm, pid, &[TaskState::Ready, TaskState::Sleeping], TaskState::Stopped);
+            }
+            DefaultAction::Ignore | DefaultAction::Continue => {}
+        }
+        return true;
+    }
+    if !blocked && handler == SIG_IGN {
+        return true;
+    }
+
+    state.pending |= sig_bit(sig);
+    drop(state);
+    if !blocked {
+        set_process_state(tm, pid, &[TaskState::Sleeping, TaskState::Blocked], TaskState::Ready);
+    }
+    true
+}
+
+/// Delivers a synchronous fault signal to the current thread. Blocked or
+/// ignored faults cannot be deferred, so those terminate the process.
+pub fn force_signal(context: &mut CPUState, sig: u32, info: SigInfo) {
+    let proc = {
+        let tm = TASK_MANAGER.int_lock();
+        tm.current_task_idx().and_then(|idx| tm.tasks[idx].as_ref()).and_then(|t| t.process.clone())
+    };
+    let proc = match proc {
+        Some(p) => p,
+        None => halt_current(),
+    };
+
+    {
+        let mut state = proc.signals.int_lock();
+        let handler = state.actions[sig as usize].handler;
+        if handler != SIG_DFL && handler != SIG_IGN && (state.blocked & sig_bit(sig)) == 0 {
+            if setup_frame(&mut state, context, sig, info) {
+                return;
+            }
+        }
+    }
+
+    terminate(&mut TASK_MANAGER.int_lock(), proc.pid, sig);
+    halt_current();
+}
+
+/// Terminates the running process with `sig` without consulting its handlers,
+/// for faults the kernel itself takes on the process' behalf.
+pub fn terminate_current(sig: u32) -> ! {
+    let mut tm = TASK_MANAGER.int_lock();
+    let pid = tm.current_task_idx().and_then(|idx| tm.tasks[idx].as_ref()).and_then(|t| t.process.as_ref()).map(|p| p.pid);
+    if let Some(pid) = pid {
+        terminate(&mut tm, pid, sig);
+    }
+    drop(tm);
+    halt_current();
+}
+
+/// Runs on every return from a syscall. Handles pending signals whose action
+/// is not a user handler, then redirects into the first caught one.
+pub fn deliver_on_syscall_return(context: &mut CPUState) {
+    let (proc, zombie) = {
+        let tm = TASK_MANAGER.int_lock();
+        let thread = match tm.current_task_idx().and_then(|idx| tm.tasks[idx].as_ref()) {
+            Some(t) => t,
+            None => return,
+        };
+        (thread.process.clone(), thread.state == TaskState::Zombie)
+    };
+    if zombie {
+        halt_current();
+    }
+    let proc = match proc {
+        Some(p) => p,
+        None => return,
+    };
+
+    loop {
+        let mut state = proc.signals.int_lock();
+        let ready = state.pending & !state.blocked;
+        if ready == 0 {
+            return;
+        }
+        let sig = ready.trailing_zeros() + 1;
+        state.pending &= !sig_bit(sig);
+
+        match state.actions[sig as usize].handler {
+            SIG_IGN => {}
+            SIG_DFL => match default_action(sig) {
+                DefaultAction::Terminate => {
+                    drop(state);
+                    terminate(&mut TASK_MANAGER.int_lock(), proc.pid, sig);
+                    halt_current();
+                }
+                DefaultAction::Stop => {
+                    drop(state);
+                    stop_current(proc.pid);
+                }
+                DefaultAction::Ignore | DefaultAction::Continue => {}
+            },
+            _ => {
+                if !setup_frame(&mut state, context, sig, SigInfo::new(sig, 0, 0)) {
+                    drop(state);
+                    terminate(&mut TASK_MANAGER.int_lock(), proc.pid, SIGSEGV);
+                    halt_current();
+                }
+                return;
+            }
+        }
+    }
+}
+
+fn stop_current(pid: u64) {
+    set_process_state(&mut TASK_MANAGER.int_lock(), pid, &[TaskState::Ready, TaskState::Sleeping], TaskState::Stopped);
+    loop {
+        let stopped = {
+            let tm = TASK_MANAGER.int_lock();
+            tm.current_task_idx().and_then(|idx| tm.tasks[idx].as_ref()).map_or(false, |t| t.state == TaskState::Stopped)
+        };
+        if !stopped {
+            break;
+        }
+        crate::interrupts::task::yield_now();
+    }
+}
+
+/// Called from the scheduler with the task manager held, just before resuming
+/// a thread in user mode. Only caught signals are handled here; anything that
+/// needs a default action is left for the next syscall return.
+pub fn deliver_on_resume(proc: &Process, context: &mut CPUState) {
+    let mut state = proc.signals.int_lock();
+    let mut ready = state.pending & !state.blocked;
+    while ready != 0 {
+        let sig = ready.trailing_zeros() + 1;
+        ready &= !sig_bit(sig);
+
+        let handler = state.actions[sig as usize].handler;
+        if handler == SIG_IGN {
+            state.pending &= !sig_bit(sig);
+        } else if handler != SIG_DFL {
+            if setup_frame(&mut state, context, sig, SigInfo::new(sig, 0, 0)) {
+                state.pending &= !sig_bit(sig);
+            }
+            return;
+        }
+    }
+}
+
+/// Restores the context saved by `setup_frame`. The frame sits just below the
+/// rsp the trampoline was entered with, since the handler's `ret` popped `restorer`.
+pub fn sigreturn(context: &mut CPUState) {
+    let proc = {
+        let tm = TASK_MANAGER.int_lock();
+        tm.current_task_idx().and_then(|idx| tm.tasks[idx].as_ref()).and_then(|t| t.process.clone())
+    };
+    let proc = match proc {
+        Some(p) => p,
+        None => return,
+    };
+
+    let mut frame: SignalFrame = unsafe { core::mem::zeroed() };
+    let frame_addr = context.rsp.wrapping_sub(8);
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(&mut frame as *mut SignalFrame as *mut u8, size_of::<SignalFrame>())
+    };
+    let valid = uaccess::copy_from_user(bytes, frame_addr)
+        && frame.context.rip < USER_LIMIT && frame.context.rsp < USER_LIMIT;
+
+    if !valid {
+        terminate(&mut TASK_MANAGER.int_lock(), proc.pid, SIGSEGV);
+        halt_current();
+    }
+
+    let user_flags = 0xCD5;
+    let rflags = (frame.context.rflags & user_flags) | 0x202;
+    *context = frame.context;
+    context.cs = 0x33;
+    context.ss = 0x23;
+    context.rflags = rflags;
+
+    // MXCSR reserved bits make fxrstor fault, so only keep the defined ones.
+    let mxcsr = u32::from_le_bytes([frame.fpu_state[24], frame.fpu_state[25], frame.fpu_state[26], frame.fpu_state[27]]) & 0xFFBF;
+    frame.fpu_state[24..28].copy_from_slice(&mxcsr.to_le_bytes());
+    unsafe {
+        asm!("fxrstor [{}]", in(reg) frame.fpu_state.as_ptr());
+    }
+
+    proc.signals.int_lock().blocked = (frame.blocked as u32) & !UNBLOCKABLE;
+}
@@ -1,226 +1,224 @@
-use crate::drivers::periferics::keyboard::KEYBOARD_BUFFER;
+use crate::drivers::periferics::keyboard::{KEYBOARD_BUFFER, KEYBOARD_WAIT};
 use crate::interrupts::task::CPUState;
+use crate::interrupts::wait::POLL_WAIT;
 use alloc::string::String;
 use alloc::string::ToString;
+use alloc::vec;
 use alloc::vec::Vec;
 
-use super::{PollFd, POLLERR, POLLIN, POLLNVAL, POLLOUT};
+use super::{PollFd, POLLERR, POLLHUP, POLLIN, POLLNVAL, POLLOUT, POLLWINDOW};
 
 
 
-pub fn copy_string_from_user(ptr: *const u8, len: usize) -> String {
+/// Longest string a syscall takes by pointer and length.
+const MAX_USER_STRING: usize = 4096;
+
+/// Bounce buffer size for moving file and pipe data to and from user memory.
+const IO_CHUNK: usize = 64 * 1024;
+
+/// None if the range is not readable user memory.
+pub fn copy_string_from_user(ptr: *const u8, len: usize) -> Option<String> {
     if ptr.is_null() || len == 0 {
-        return String::new();
+        return Some(String::new());
     }
-    
-    unsafe {
-        let slice = core::slice::from_raw_parts(ptr, len);
-        let s = String::from_utf8_lossy(slice).into_owned();
-        s.trim_matches('\0').to_string()
+    let mut buf = vec![0u8; len.min(MAX_USER_STRING)];
+    if !crate::memory::uaccess::copy_from_user(&mut buf, ptr as u64) {
+        return None;
     }
+    let s = String::from_utf8_lossy(&buf).into_owned();
+    Some(s.trim_matches('\0').to_string())
 }
 
-pub fn resolve_path(cwd: &str, path: &str) -> String {
-    let mut full_path = String::new();
-
-    if path.starts_with('@') {
-        full_path = String::from(path);
+/// Turns `path` into an absolute path. Relative paths start at `cwd` and
+/// `@disk/rest` starts where that disk is mounted. `..` is kept for the VFS
+/// to apply once the symlinks before it are followed. None for an `@disk`
+/// that is not mounted.
+pub fn resolve_path(cwd: &str, path: &str) -> Option<String> {
+    let full_path = if let Some(alias) = path.strip_prefix('@') {
+        let (disk, rest) = alias.split_once('/').unwrap_or((alias, ""));
+        let root = crate::fs::vfs::mount_point(crate::fs::vfs::parse_disk_id(disk)?)?;
+        alloc::format!("{}/{}", root, rest)
     } else if path.starts_with('/') {
-        
-        full_path = alloc::format!("@0xE0{}", path);
+        String::from(path)
     } else {
-        
-        full_path = alloc::format!("{}{}", cwd, path);
-    }
+        alloc::format!("{}/{}", cwd, path)
+    };
 
-    let mut parts: Vec<&str> = Vec::new();
-    for part in full_path.split('/') {
-        if part.is_empty() || part == "." {
-            continue;
-        } else if part == ".." {
-            if parts.len() > 1 {
-                parts.pop();
-            }
-        } else {
-            parts.push(part);
-        }
-    }
+    let parts: Vec<&str> = full_path.split('/').filter(|p| !p.is_empty() && *p != ".").collect();
+    Some(alloc::format!("/{}", parts.join("/")))
+}
 
-    let mut res = String::new();
-    for (i, p) in parts.iter().enumerate() {
-        if i > 0 { res.push('/'); }
-        res.push_str(p);
+/// The caller's `RLIMIT_NOFILE`. A call never needs to name more descriptors
+/// than it may hold, so this bounds arrays of them copied in from user space.
+pub fn nofile_limit() -> usize {
+    let proc = crate::interrupts::task::current_process_ptr();
+    if proc.is_null() {
+        return 0;
     }
-    
-    res
+    unsafe { &*proc }.limits.lock().cur(crate::interrupts::rlimit::RLIMIT_NOFILE) as usize
+}
+
+/// Copies the path at `ptr`/`len` from the caller and resolves it against its cwd.
+fn user_path(ptr: u64, len: usize) -> Option<String> {
+    let path = copy_string_from_user(ptr as *const u8, len)?;
+    resolve_path(&crate::interrupts::syscalls::process::current_cwd(), &path)
 }
 
 pub fn handle_read(context: &mut CPUState) {
     let _fd = context.rdi;
-    let user_ptr = context.rsi as *mut u8;
+    let user_ptr = context.rsi;
     let user_len = context.rdx as usize;
-    let mut bytes_written_to_user = 0;
 
-    if user_ptr.is_null() {
+    if user_ptr == 0 {
         context.rax = 0;
         return;
     }
+    if !crate::memory::uaccess::access_ok(user_ptr, user_len, true) {
+        context.rax = u64::MAX;
+        return;
+    }
 
-    loop {
-        {
-            let mut keyboard_buffer = KEYBOARD_BUFFER.lock();
-            while bytes_written_to_user < user_len {
-                if let Some(keycode) = keyboard_buffer.pop_front() {
-                    unsafe {
-                        *user_ptr.add(bytes_written_to_user) = keycode as u8;
-                    }
-                    bytes_written_to_user += 1;
-                } else {
-                    break;
-                }
-            }
-        }
-
-        if bytes_written_to_user > 0 {
-            break;
-        }
+    let ready = KEYBOARD_WAIT.wait_until(0, |_| !KEYBOARD_BUFFER.lock().is_empty());
+    if !ready {
+        context.rax = u64::MAX;
+        return;
+    }
 
-        
-        unsafe {
-            core::arch::asm!("int 0x81");
+    let mut keys = Vec::new();
+    {
+        let mut keyboard_buffer = KEYBOARD_BUFFER.lock();
+        while keys.len() < user_len {
+            match keyboard_buffer.pop_front() {
+                Some(keycode) => keys.push(keycode as u8),
+                None => break,
+            }
         }
     }
-    
-    context.rax = bytes_written_to_user as u64;
-}
 
-pub fn handle_poll(context: &mut CPUState) {
-    let fds_ptr = context.rdi as *const PollFd;
-    let nfds = context.rsi as usize;
-    let _timeout = context.rdx as i32;
-
-    if fds_ptr.is_null() || nfds == 0 {
-        context.rax = 0;
-        return;
-    }
+    context.rax = if crate::memory::uaccess::copy_to_user(user_ptr, &keys) { keys.len() as u64 } else { u64::MAX };
+}
 
+/// Fills in `revents` for every entry and returns how many are ready.
+/// Entries with `POLLWINDOW` set name a window id instead of a file descriptor.
+fn poll_scan(proc: &crate::interrupts::task::Process, fds: &mut [PollFd]) -> usize {
+    let files = proc.files.lock();
     let mut ready_count = 0;
 
-    {
-        let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        let current = tm.current_task;
+    for pfd in fds.iter_mut() {
+        pfd.revents = 0;
 
-        if current >= 0 {
-            if let Some(thread) = tm.tasks[current as usize].as_ref() {
-                let proc = thread.process.as_ref().expect("Thread has no process");
-                let fd_table = proc.fd_table.lock();
-
-                for i in 0..nfds {
-                    unsafe {
-                        let pfd = &mut *(fds_ptr.add(i) as *mut PollFd);
-                        pfd.revents = 0;
-
-                        let fd = pfd.fd;
-                        if fd >= 0 && (fd as usize) < 16 {
-                            let global_fd = fd_table[fd as usize];
-                            if global_fd != -1 {
-                                if let Some(handle) = crate::fs::vfs::get_file(global_fd as usize) {
-                                    use crate::fs::vfs::FileHandle;
-                                    match handle {
-                                        FileHandle::Pipe { pipe } => {
-                                            if (pfd.events & POLLIN) != 0 {
-                                                if pipe.available() > 0 {
-                                                    pfd.revents |= POLLIN;
-                                                }
-                                            }
-                                            if (pfd.events & POLLOUT) != 0 {
-                                                pfd.revents |= POLLOUT;
-                                            }
-                                        }
-                                        FileHandle::File { .. } => {
-                                            if (pfd.events & POLLIN) != 0 { pfd.revents |= POLLIN; }
-                                            if (pfd.events & POLLOUT) != 0 { pfd.revents |= POLLOUT; }
-                                        }
-                                    }
-                                } else {
-                                    pfd.revents = POLLERR;
+        let fd = pfd.fd;
+        if (pfd.events & POLLWINDOW) != 0 {
+            if crate::window_manager::events::GLOBAL_EVENT_QUEUE.lock().has_events(fd as u32) {
+                pfd.revents |= POLLWINDOW;
+            }
+        } else if fd >= 0 {
+            if let Some(global_fd) = files.get(fd as usize) {
+                if let Some(handle) = crate::fs::vfs::get_file(global_fd) {
+                    use crate::fs::vfs::FileHandle;
+                    match handle {
+                        FileHandle::Pipe { pipe } => {
+                            if (pfd.events & POLLIN) != 0 {
+                                if pipe.available() > 0 {
+                                    pfd.revents |= POLLIN;
                                 }
-                            } else {
-                                pfd.revents = POLLNVAL;
                             }
-                        } else {
-                            pfd.revents = POLLNVAL;
+                            if (pfd.events & POLLOUT) != 0 {
+                                pfd.revents |= POLLOUT;
+                            }
+                            if pipe.is_closed() {
+                                pfd.revents |= POLLHUP;
+                            }
                         }
-
-                        if pfd.revents != 0 {
-                            ready_count += 1;
+                        FileHandle::File { .. } => {
+                            if (pfd.events & POLLIN) != 0 { pfd.revents |= POLLIN; }
+                            if (pfd.events & POLLOUT) != 0 { pfd.revents |= POLLOUT; }
                         }
                     }
+                } else {
+                    pfd.revents = POLLERR;
+                }
+            } else if fd == 0 {
+                if (pfd.events & POLLIN) != 0 && !KEYBOARD_BUFFER.lock().is_empty() {
+                    pfd.revents |= POLLIN;
                 }
+            } else {
+                pfd.revents = POLLNVAL;
             }
+        } else {
+            pfd.revents = POLLNVAL;
         }
-    }
 
-    context.rax = ready_count as u64;
+        if pfd.revents != 0 {
+            ready_count += 1;
+        }
+    }
+    ready_count
 }
 
-pub fn handle_chdir(context: &mut CPUState) {
-    let ptr = context.rdi as *const u8;
-    let len = context.rsi as usize;
-    
-    let path_str_full = copy_string_from_user(ptr, len);
+/// rdx = timeout in ms; negative waits forever, 0 only checks.
+pub fn handle_poll(context: &mut CPUState) {
+    let fds_ptr = context.rdi;
+    let nfds = context.rsi as usize;
+    let timeout = context.rdx as i32;
 
-    let cwd_str = {
-        let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        if tm.current_task >= 0 {
-            if let Some(thread) = tm.tasks[tm.current_task as usize].as_ref() {
-                let proc = thread.process.as_ref().expect("Thread has no process");
-                let cwd = proc.cwd.lock();
-                let cwd_len = cwd.iter().position(|&c| c == 0).unwrap_or(cwd.len());
-                String::from_utf8_lossy(&cwd[..cwd_len]).into_owned()
-            } else {
-                String::from("@0xE0/")
-            }
-        } else {
-            String::from("@0xE0/")
+    if fds_ptr == 0 || nfds == 0 {
+        context.rax = 0;
+        return;
+    }
+    if nfds > nofile_limit() {
+        context.rax = u64::MAX;
+        return;
+    }
+    let mut fds = match crate::memory::uaccess::read_user_slice::<PollFd>(fds_ptr, nfds) {
+        Some(f) => f,
+        None => {
+            context.rax = u64::MAX;
+            return;
         }
     };
 
-    let resolved = resolve_path(&cwd_str, &path_str_full);
-
-    let path_parts: Vec<&str> = resolved.split('/').collect();
-    if path_parts.len() < 1 || !path_parts[0].starts_with('@') {
+    let proc = crate::interrupts::task::current_process_ptr();
+    if proc.is_null() {
         context.rax = u64::MAX;
         return;
     }
+    let proc = unsafe { &*proc };
 
-    let disk_part = &path_parts[0][1..];
-    let disk_id = if disk_part.starts_with("0x") || disk_part.starts_with("0X") {
-        u8::from_str_radix(&disk_part[2..], 16).unwrap_or(0xFF)
+    let mut ready_count = 0;
+    if timeout == 0 {
+        ready_count = poll_scan(proc, &mut fds);
     } else {
-        disk_part.parse::<u8>().unwrap_or_else(|_| u8::from_str_radix(disk_part, 16).unwrap_or(0xFF))
-    };
+        let timeout_ms = if timeout < 0 { 0 } else { timeout as u64 };
+        POLL_WAIT.wait_until(timeout_ms, |_| {
+            ready_count = poll_scan(proc, &mut fds);
+            ready_count > 0
+        });
+    }
 
-    let actual_path = if path_parts.len() > 1 { path_parts[1..].join("/") } else { String::from("") };
+    context.rax = if crate::memory::uaccess::write_user_slice(fds_ptr, &fds) { ready_count as u64 } else { u64::MAX };
+}
 
-    if let Ok(node) = crate::fs::vfs::open(disk_id, &actual_path) {
+pub fn handle_chdir(context: &mut CPUState) {
+    let resolved = match user_path(context.rdi, context.rsi as usize).map(|p| crate::fs::vfs::realpath(&p)) {
+        Some(Ok(p)) if p.len() < 128 => p,
+        _ => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+
+    if let Ok(node) = crate::fs::vfs::open(&resolved) {
         use crate::fs::vfs::FileType;
-        if node.kind() == FileType::Directory {
+        if node.lock().kind() == FileType::Directory {
             let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-            let current_idx = tm.current_task as usize;
-            if tm.current_task >= 0 {
+            let current_idx = tm.current_task() as usize;
+            if tm.current_task() >= 0 {
                 if let Some(thread) = tm.tasks[current_idx].as_mut() {
                     let proc = thread.process.as_ref().expect("Thread has no process");
                     let mut cwd = proc.cwd.lock();
                     cwd.fill(0);
-                    let bytes = resolved.as_bytes();
-                    let len = core::cmp::min(bytes.len(), 127);
-                    cwd[..len].copy_from_slice(&bytes[..len]);
-                    if !resolved.ends_with('/') {
-                        if len < 127 {
-                            cwd[len] = b'/';
-                        }
-                    }
+                    cwd[..resolved.len()].copy_from_slice(resolved.as_bytes());
                     context.rax = 0;
                 } else {
                     context.rax = u64::MAX;
@@ -237,273 +235,190 @@ pub fn handle_chdir(context: &mut CPUState) {
 }
 
 pub fn handle_create(context: &mut CPUState, syscall_num: u64) {
-    let ptr = context.rdi as *const u8;
-    let len = context.rsi as usize;
-    
-    let path_str_full = copy_string_from_user(ptr, len);
-
-    let cwd_str = {
-        let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        if tm.current_task >= 0 {
-            if let Some(thread) = tm.tasks[tm.current_task as usize].as_ref() {
-                let proc = thread.process.as_ref().expect("Thread has no process");
-                let cwd = proc.cwd.lock();
-                let cwd_len = cwd.iter().position(|&c| c == 0).unwrap_or(cwd.len());
-                String::from_utf8_lossy(&cwd[..cwd_len]).into_owned()
-            } else {
-                String::from("@0xE0/")
-            }
-        } else {
-            String::from("@0xE0/")
+    let resolved = match user_path(context.rdi, context.rsi as usize) {
+        Some(p) => p,
+        None => {
+            context.rax = u64::MAX;
+            return;
         }
     };
 
-    let resolved = resolve_path(&cwd_str, &path_str_full);
-
-    let path_parts: Vec<&str> = resolved.split('/').collect();
-    if path_parts.len() < 1 || !path_parts[0].starts_with('@') {
-        context.rax = u64::MAX;
-        return;
+    match crate::fs::vfs::create(&resolved, syscall_num == 83) {
+        Ok(_) => context.rax = 0,
+        Err(_) => context.rax = u64::MAX,
     }
+}
 
-    let disk_part = &path_parts[0][1..];
-    let disk_id = if disk_part.starts_with("0x") || disk_part.starts_with("0X") {
-        u8::from_str_radix(&disk_part[2..], 16).unwrap_or(0xFF)
-    } else {
-        disk_part.parse::<u8>().unwrap_or_else(|_| u8::from_str_radix(disk_part, 16).unwrap_or(0xFF))
+pub fn handle_remove(context: &mut CPUState) {
+    let resolved = match user_path(context.rdi, context.rsi as usize) {
+        Some(p) => p,
+        None => {
+            context.rax = u64::MAX;
+            return;
+        }
     };
 
-    if disk_id == 0xFF {
-        context.rax = u64::MAX;
-        return;
+    match crate::fs::vfs::remove(&resolved) {
+        Ok(_) => context.rax = 0,
+        Err(_) => context.rax = u64::MAX,
     }
+}
 
-    let actual_path = if path_parts.len() > 1 { path_parts[1..].join("/") } else { String::from("") };
-
-    if let Some(last_slash) = actual_path.rfind('/') {
-        let parent_path = &actual_path[..last_slash];
-        let new_name = &actual_path[last_slash + 1..];
-
-        if let Ok(mut parent) = crate::fs::vfs::open(disk_id, parent_path) {
-            let res = if syscall_num == 83 {
-                parent.create_dir(new_name)
-            } else {
-                parent.create_file(new_name)
-            };
-
-            match res {
-                Ok(_) => context.rax = 0,
-                Err(_) => context.rax = u64::MAX,
-            }
-        } else {
+pub fn handle_rename(context: &mut CPUState) {
+    let resolved_old = user_path(context.rdi, context.rsi as usize);
+    let resolved_new = user_path(context.rdx, context.r10 as usize);
+    let (resolved_old, resolved_new) = match (resolved_old, resolved_new) {
+        (Some(old), Some(new)) => (old, new),
+        _ => {
             context.rax = u64::MAX;
+            return;
         }
-    } else {
-        if let Ok(mut root) = crate::fs::vfs::open(disk_id, "") {
-            let res = if syscall_num == 83 {
-                root.create_dir(&actual_path)
-            } else {
-                root.create_file(&actual_path)
-            };
+    };
 
-            match res {
-                Ok(_) => context.rax = 0,
-                Err(_) => context.rax = u64::MAX,
-            }
-        } else {
+    match crate::fs::vfs::rename(&resolved_old, &resolved_new) {
+        Ok(_) => context.rax = 0,
+        Err(e) => {
+            crate::debugln!("SYS_RENAME: {}", e);
             context.rax = u64::MAX;
         }
     }
 }
 
-pub fn handle_remove(context: &mut CPUState) {
-    let ptr = context.rdi as *const u8;
-    let len = context.rsi as usize;
-    
-    let path_str_full = copy_string_from_user(ptr, len);
-
-    let cwd_str = {
-        let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        if tm.current_task >= 0 {
-            if let Some(thread) = tm.tasks[tm.current_task as usize].as_ref() {
-                let proc = thread.process.as_ref().expect("Thread has no process");
-                let cwd = proc.cwd.lock();
-                let cwd_len = cwd.iter().position(|&c| c == 0).unwrap_or(cwd.len());
-                String::from_utf8_lossy(&cwd[..cwd_len]).into_owned()
-            } else {
-                String::from("@0xE0/")
-            }
-        } else {
-            String::from("@0xE0/")
-        }
+/// rdi/rsi = existing file, rdx/r10 = new name for it.
+pub fn handle_link(context: &mut CPUState) {
+    let old = user_path(context.rdi, context.rsi as usize);
+    let new = user_path(context.rdx, context.r10 as usize);
+    context.rax = match (old, new) {
+        (Some(old), Some(new)) => match crate::fs::vfs::link(&old, &new) {
+            Ok(()) => 0,
+            Err(_) => u64::MAX,
+        },
+        _ => u64::MAX,
     };
+}
 
-    let resolved = resolve_path(&cwd_str, &path_str_full);
-
-    let path_parts: Vec<&str> = resolved.split('/').collect();
-    if path_parts.len() < 1 || !path_parts[0].starts_with('@') {
-        context.rax = u64::MAX;
-        return;
-    }
-
-    let disk_part = &path_parts[0][1..];
-    let disk_id = if disk_part.starts_with("0x") || disk_part.starts_with("0X") {
-        u8::from_str_radix(&disk_part[2..], 16).unwrap_or(0xFF)
-    } else {
-        disk_part.parse::<u8>().unwrap_or_else(|_| u8::from_str_radix(disk_part, 16).unwrap_or(0xFF))
+/// rdi/rsi = target, stored as given; rdx/r10 = path of the new symlink.
+pub fn handle_symlink(context: &mut CPUState) {
+    let target = copy_string_from_user(context.rdi as *const u8, context.rsi as usize);
+    let path = user_path(context.rdx, context.r10 as usize);
+    context.rax = match (target, path) {
+        (Some(target), Some(path)) => match crate::fs::vfs::symlink(&target, &path) {
+            Ok(()) => 0,
+            Err(_) => u64::MAX,
+        },
+        _ => u64::MAX,
     };
+}
 
-    let actual_path = if path_parts.len() > 1 { path_parts[1..].join("/") } else { String::from("") };
+/// rdi/rsi = symlink, rdx = buffer, r10 = its size. Returns the bytes of
+/// the target copied, which is not NUL-terminated.
+pub fn handle_readlink(context: &mut CPUState) {
+    let target = match user_path(context.rdi, context.rsi as usize).map(|p| crate::fs::vfs::readlink(&p)) {
+        Some(Ok(t)) => t,
+        _ => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+    let len = target.len().min(context.r10 as usize);
+    context.rax = if crate::memory::uaccess::copy_to_user(context.rdx, &target.as_bytes()[..len]) { len as u64 } else { u64::MAX };
+}
 
-    if let Some(last_slash) = actual_path.rfind('/') {
-        let parent_path = &actual_path[..last_slash];
-        let name = &actual_path[last_slash + 1..];
+pub fn handle_mount(context: &mut CPUState) {
+    let disk_id = context.rdi as u8;
+    let target = user_path(context.rdx, context.r10 as usize);
+    let fs_type = copy_string_from_user(context.r8 as *const u8, context.r9 as usize);
+    let target = match (target, fs_type.as_deref()) {
+        (Some(target), Some("ext2")) => target,
+        _ => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
 
-        if let Ok(mut parent) = crate::fs::vfs::open(disk_id, parent_path) {
-            match parent.remove(name) {
-                Ok(_) => context.rax = 0,
-                Err(_) => context.rax = u64::MAX,
-            }
-        } else { context.rax = u64::MAX; }
-    } else {
-        if let Ok(mut root) = crate::fs::vfs::open(disk_id, "") {
-            match root.remove(&actual_path) {
-                Ok(_) => context.rax = 0,
-                Err(_) => context.rax = u64::MAX,
-            }
-        } else { context.rax = u64::MAX; }
+    let result = crate::fs::ext2::fs::Ext2::new(disk_id, context.rsi)
+        .and_then(|fs| crate::fs::vfs::mount(&target, disk_id, context.rsi, fs));
+    match result {
+        Ok(()) => context.rax = 0,
+        Err(e) => {
+            crate::debugln!("[VFS] mount {} failed: {}", target, e);
+            context.rax = u64::MAX;
+        }
     }
 }
 
-pub fn handle_rename(context: &mut CPUState) {
-    let old_ptr = context.rdi as *const u8;
-    let old_len = context.rsi as usize;
-    let new_ptr = context.rdx as *const u8;
-    let new_len = context.r10 as usize;
-
-    let path_old = copy_string_from_user(old_ptr, old_len);
-    let path_new = copy_string_from_user(new_ptr, new_len);
-
-    let cwd_str = {
-        let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        if tm.current_task >= 0 {
-            if let Some(thread) = tm.tasks[tm.current_task as usize].as_ref() {
-                let proc = thread.process.as_ref().expect("Thread has no process");
-                let cwd = proc.cwd.lock();
-                let cwd_len = cwd.iter().position(|&c| c == 0).unwrap_or(cwd.len());
-                String::from_utf8_lossy(&cwd[..cwd_len]).into_owned()
-            } else {
-                String::from("@0xE0/")
-            }
-        } else {
-            String::from("@0xE0/")
+/// rdi/rsi = mount point. Fails while files on it are open or it is anyone's cwd.
+pub fn handle_umount(context: &mut CPUState) {
+    let target = match user_path(context.rdi, context.rsi as usize) {
+        Some(p) => p,
+        None => {
+            context.rax = u64::MAX;
+            return;
         }
     };
-
-    let resolved_old = resolve_path(&cwd_str, &path_old);
-    let resolved_new = resolve_path(&cwd_str, &path_new);
-
-    let parts_old: Vec<&str> = resolved_old.split('/').collect();
-    if parts_old.len() < 1 || !parts_old[0].starts_with('@') {
+    if cwd_under(&target) {
         context.rax = u64::MAX;
         return;
     }
-
-    let disk_part = &parts_old[0][1..];
-    let disk_id = if disk_part.starts_with("0x") || disk_part.starts_with("0X") {
-        u8::from_str_radix(&disk_part[2..], 16).unwrap_or(0xFF)
-    } else {
-        disk_part.parse::<u8>().unwrap_or_else(|_| u8::from_str_radix(disk_part, 16).unwrap_or(0xFF))
-    };
-    let actual_old = if parts_old.len() > 1 { parts_old[1..].join("/") } else { String::from("") };
-
-    let parts_new: Vec<&str> = resolved_new.split('/').collect();
-    let actual_new = if parts_new.len() > 1 { parts_new[1..].join("/") } else { String::from("") };
-
-    let (parent_old, name_old) = if let Some(idx) = actual_old.rfind('/') {
-        (&actual_old[..idx], &actual_old[idx + 1..])
-    } else {
-        ("", actual_old.as_str())
+    context.rax = match crate::fs::vfs::umount(&target) {
+        Ok(()) => 0,
+        Err(_) => u64::MAX,
     };
+}
 
-    let (parent_new, name_new) = if let Some(idx) = actual_new.rfind('/') {
-        (&actual_new[..idx], &actual_new[idx + 1..])
-    } else {
-        ("", actual_new.as_str())
-    };
+/// Whether a live process has its cwd at or below `path`.
+fn cwd_under(path: &str) -> bool {
+    use crate::interrupts::task::ThreadState;
+    let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    tm.tasks.iter().flatten()
+        .filter(|t| !matches!(t.state, ThreadState::Null | ThreadState::Zombie))
+        .filter_map(|t| t.process.as_ref())
+        .any(|proc| {
+            let cwd = proc.cwd.lock();
+            let len = cwd.iter().position(|&c| c == 0).unwrap_or(cwd.len());
+            cwd[..len].starts_with(path.as_bytes()) && (len == path.len() || cwd[path.len()] == b'/')
+        })
+}
 
-    if parent_old != parent_new {
-        crate::debugln!("SYS_RENAME: Moving between directories not supported yet");
-        context.rax = u64::MAX;
-        return;
-    }
+pub const O_ACCMODE: u64 = 3;
+pub const O_APPEND: u64 = 0o2000;
+pub const O_NONBLOCK: u64 = 0o4000;
+pub const O_CLOEXEC: u64 = 0o2000000;
 
-    if let Ok(mut parent) = crate::fs::vfs::open(disk_id, parent_old) {
-        match parent.rename(name_old, name_new) {
-            Ok(_) => context.rax = 0,
-            Err(_) => context.rax = u64::MAX,
-        }
-    } else {
-        context.rax = u64::MAX;
-    }
-}
+/// Status flags `F_SETFL` may change; the access mode is fixed at open.
+const SETFL_MASK: u64 = O_APPEND | O_NONBLOCK;
 
 pub fn handle_open(context: &mut CPUState) {
     let ptr = context.rdi as *const u8;
     let len = context.rsi as usize;
+    let open_flags = context.rdx;
     
-    let path_str_full = copy_string_from_user(ptr, len);
-
-    let cwd_str = {
-        let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        if tm.current_task >= 0 {
-            if let Some(thread) = tm.tasks[tm.current_task as usize].as_ref() {
-                let proc = thread.process.as_ref().expect("Thread has no process");
-                let cwd = proc.cwd.lock();
-                let cwd_len = cwd.iter().position(|&c| c == 0).unwrap_or(cwd.len());
-                String::from_utf8_lossy(&cwd[..cwd_len]).into_owned()
-            } else {
-                String::from("@0xE0/")
-            }
-        } else {
-            String::from("@0xE0/")
+    let resolved = match user_path(ptr as u64, len) {
+        Some(p) => p,
+        None => {
+            context.rax = u64::MAX;
+            return;
         }
     };
 
-    let resolved = resolve_path(&cwd_str, &path_str_full);
-
-    
-    let path_parts: Vec<&str> = resolved.split('/').collect();
-    let disk_part = &path_parts[0][1..];
-    let disk_id = if disk_part.starts_with("0x") || disk_part.starts_with("0X") {
-        u8::from_str_radix(&disk_part[2..], 16).unwrap_or(0xFF)
-    } else {
-        disk_part.parse::<u8>().unwrap_or_else(|_| u8::from_str_radix(disk_part, 16).unwrap_or(0xFF))
-    };
-    let actual_path_str = if path_parts.len() > 1 { path_parts[1..].join("/") } else { String::from("") };
-
-    match crate::fs::vfs::open_file(disk_id, &actual_path_str) {
+    match crate::fs::vfs::open_file(&resolved, open_flags & (O_ACCMODE | SETFL_MASK)) {
         Ok(global_fd) => {
             let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-            let current = tm.current_task;
+            let current = tm.current_task();
             if current >= 0 {
                 if let Some(thread) = tm.tasks[current as usize].as_mut() {
                     let proc = thread.process.as_ref().expect("Thread has no process");
-                    let mut fd_table = proc.fd_table.lock();
 
-                    let mut local_fd = -1;
-                    for i in 0..16 {
-                        if fd_table[i] == -1 {
-                            local_fd = i as i32;
-                            break;
-                        }
+                    let mut flags = 0;
+                    if (open_flags & O_CLOEXEC) != 0 {
+                        flags |= crate::interrupts::task::FD_CLOEXEC;
                     }
-
-                    if local_fd != -1 {
-                        fd_table[local_fd as usize] = global_fd as i16;
-                        context.rax = local_fd as u64;
-                    } else {
-                        context.rax = u64::MAX;
+                    match proc.alloc_fd(global_fd, flags) {
+                        Some(local_fd) => context.rax = local_fd as u64,
+                        None => {
+                            crate::fs::vfs::close_file(global_fd);
+                            context.rax = u64::MAX;
+                        }
                     }
                 } else {
                     context.rax = u64::MAX;
@@ -518,18 +433,63 @@ pub fn handle_open(context: &mut CPUState) {
     }
 }
 
+/// Fills user buffer `buf` from `source` through a bounce buffer, stopping at
+/// the first short read. Returns the bytes copied, or `u64::MAX` if none were.
+fn read_to_user(buf: u64, len: usize, mut source: impl FnMut(&mut [u8]) -> Result<usize, String>) -> u64 {
+    let mut chunk = vec![0u8; len.min(IO_CHUNK)];
+    let mut done = 0;
+    while done < len {
+        let want = (len - done).min(chunk.len());
+        let n = match source(&mut chunk[..want]) {
+            Ok(n) if crate::memory::uaccess::copy_to_user(buf + done as u64, &chunk[..n]) => n,
+            _ if done == 0 => return u64::MAX,
+            _ => break,
+        };
+        done += n;
+        if n < want {
+            break;
+        }
+    }
+    done as u64
+}
+
+/// Feeds user buffer `buf` to `sink` through a bounce buffer, stopping at the
+/// first short write. Returns the bytes taken, or `u64::MAX` if none were.
+fn write_from_user(buf: u64, len: usize, mut sink: impl FnMut(&[u8]) -> Result<usize, String>) -> u64 {
+    let mut chunk = vec![0u8; len.min(IO_CHUNK)];
+    let mut done = 0;
+    while done < len {
+        let want = (len - done).min(chunk.len());
+        let written = if crate::memory::uaccess::copy_from_user(&mut chunk[..want], buf + done as u64) {
+            sink(&chunk[..want]).ok()
+        } else {
+            None
+        };
+        let n = match written {
+            Some(n) => n,
+            None if done == 0 => return u64::MAX,
+            None => break,
+        };
+        done += n;
+        if n < want {
+            break;
+        }
+    }
+    done as u64
+}
+
 pub fn handle_read_file(context: &mut CPUState) {
     let local_fd = context.rdi as usize;
-    let buf_ptr = context.rsi as *mut u8;
+    let buf_ptr = context.rsi;
     let len = context.rdx as usize;
 
     let global_fd_opt = {
         let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        let current = tm.current_task;
-        if current >= 0 && local_fd < 16 {
+        let current = tm.current_task();
+        if current >= 0 {
             if let Some(thread) = tm.tasks[current as usize].as_ref() {
                 let proc = thread.process.as_ref().expect("Thread has no process");
-                Some(proc.fd_table.lock()[local_fd])
+                Some(proc.fd(local_fd))
             } else { None }
         } else {
             None
@@ -537,32 +497,41 @@ pub fn handle_read_file(context: &mut CPUState) {
     };
 
     if let Some(fd_val) = global_fd_opt {
-        if fd_val == -1 {
-            if local_fd == 0 {
-                handle_read(context);
+        let fd = match fd_val {
+            Some(fd) => fd,
+            None => {
+                if local_fd == 0 {
+                    handle_read(context);
+                    return;
+                }
+                context.rax = u64::MAX;
                 return;
             }
+        };
+        if !crate::memory::uaccess::access_ok(buf_ptr, len, true) {
             context.rax = u64::MAX;
             return;
         }
-        let fd = fd_val as usize;
-        let buf = unsafe { core::slice::from_raw_parts_mut(buf_ptr, len) };
 
-        if let Some(handle) = crate::fs::vfs::get_file(fd) {
+        if let Some(file) = crate::fs::vfs::get_open_file(fd) {
             use crate::fs::vfs::FileHandle;
-            match handle {
+            let nonblock = (file.flags & O_NONBLOCK) != 0;
+            match &mut file.handle {
                 FileHandle::File { node, offset } => {
-                    match node.read(*offset, buf) {
-                        Ok(n) => {
-                            *offset += n as u64;
-                            context.rax = n as u64;
-                        }
-                        Err(_) => context.rax = u64::MAX,
-                    }
+                    context.rax = read_to_user(buf_ptr, len, |chunk| {
+                        let n = node.lock().read(*offset, chunk)?;
+                        *offset += n as u64;
+                        Ok(n)
+                    });
                 }
                 FileHandle::Pipe { pipe } => {
-                    let n = pipe.read(buf);
-                    context.rax = n as u64;
+                    let pipe = pipe.clone();
+                    if !nonblock && len != 0
+                        && !crate::interrupts::wait::wait_until(pipe.wait_key(), 0, |_| pipe.available() > 0 || pipe.is_closed()) {
+                        context.rax = u64::MAX;
+                        return;
+                    }
+                    context.rax = read_to_user(buf_ptr, len, |chunk| Ok(pipe.read(chunk)));
                 }
             }
         } else {
@@ -576,16 +545,16 @@ pub fn handle_read_file(context: &mut CPUState) {
 
 pub fn handle_write_file(context: &mut CPUState) {
     let local_fd = context.rdi as usize;
-    let buf_ptr = context.rsi as *const u8;
+    let buf_ptr = context.rsi;
     let len = context.rdx as usize;
 
     let global_fd_opt = {
         let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        let current = tm.current_task;
-        if current >= 0 && local_fd < 16 {
+        let current = tm.current_task();
+        if current >= 0 {
             if let Some(thread) = tm.tasks[current as usize].as_ref() {
                 let proc = thread.process.as_ref().expect("Thread has no process");
-                Some(proc.fd_table.lock()[local_fd])
+                Some(proc.fd(local_fd))
             } else { None }
         } else {
             None
@@ -593,32 +562,35 @@ pub fn handle_write_file(context: &mut CPUState) {
     };
 
     if let Some(fd_val) = global_fd_opt {
-        if fd_val == -1 {
-            if local_fd == 1 || local_fd == 2 {
-                context.rax = len as u64;
+        let fd = match fd_val {
+            Some(fd) => fd,
+            None => {
+                if local_fd == 1 || local_fd == 2 {
+                    context.rax = len as u64;
+                    return;
+                }
+                context.rax = u64::MAX;
                 return;
             }
-            context.rax = u64::MAX;
-            return;
-        }
-        let fd = fd_val as usize;
-        let buf = unsafe { core::slice::from_raw_parts(buf_ptr, len) };
+        };
 
-        if let Some(handle) = crate::fs::vfs::get_file(fd) {
+        if let Some(file) = crate::fs::vfs::get_open_file(fd) {
             use crate::fs::vfs::FileHandle;
-            match handle {
+            let append = (file.flags & O_APPEND) != 0;
+            match &mut file.handle {
                 FileHandle::File { node, offset } => {
-                    match node.write(*offset, buf) {
-                        Ok(n) => {
-                            *offset += n as u64;
-                            context.rax = n as u64;
+                    context.rax = write_from_user(buf_ptr, len, |chunk| {
+                        let mut node = node.lock();
+                        if append {
+                            *offset = node.size();
                         }
-                        Err(_) => context.rax = u64::MAX,
-                    }
+                        let n = node.write(*offset, chunk)?;
+                        *offset += n as u64;
+                        Ok(n)
+                    });
                 }
                 FileHandle::Pipe { pipe } => {
-                    let n = pipe.write(buf);
-                    context.rax = n as u64;
+                    context.rax = write_from_user(buf_ptr, len, |chunk| Ok(pipe.write(chunk)));
                 }
             }
         } else {
@@ -632,21 +604,21 @@ pub fn handle_write_file(context: &mut CPUState) {
 
 pub fn handle_read_dir(context: &mut CPUState) {
     let local_fd = context.rdi as usize;
-    let buf_ptr = context.rsi as *mut u8;
+    let buf_ptr = context.rsi;
     let len = context.rdx as usize;
 
-    if buf_ptr.is_null() {
+    if buf_ptr == 0 {
         context.rax = u64::MAX;
         return;
     }
 
     let global_fd_opt = {
         let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        let current = tm.current_task;
-        if current >= 0 && local_fd < 16 {
+        let current = tm.current_task();
+        if current >= 0 {
             if let Some(thread) = tm.tasks[current as usize].as_ref() {
                 let proc = thread.process.as_ref().expect("Thread has no process");
-                Some(proc.fd_table.lock()[local_fd])
+                Some(proc.fd(local_fd))
             } else { None }
         } else {
             None
@@ -654,26 +626,31 @@ pub fn handle_read_dir(context: &mut CPUState) {
     };
 
     if let Some(fd_val) = global_fd_opt {
-        if fd_val == -1 {
-            context.rax = u64::MAX;
-            return;
-        }
-        let fd = fd_val as usize;
+        let fd = match fd_val {
+            Some(fd) => fd,
+            None => {
+                context.rax = u64::MAX;
+                return;
+            }
+        };
         if let Some(handle) = crate::fs::vfs::get_file(fd) {
             use crate::fs::vfs::FileHandle;
             match handle {
                 FileHandle::File { node, offset } => {
+                    let mut node = node.lock();
                     if node.kind() != crate::fs::vfs::FileType::Directory {
                         context.rax = u64::MAX;
                         return;
                     }
-                    let buf = unsafe { core::slice::from_raw_parts_mut(buf_ptr, len) };
-                    match node.read_dir(*offset, buf) {
-                        Ok((bytes_written, count_read)) => {
+                    let mut buf = vec![0u8; len.min(IO_CHUNK)];
+                    let listed = node.read_dir(*offset, &mut buf);
+                    drop(node);
+                    match listed {
+                        Ok((bytes_written, count_read)) if crate::memory::uaccess::copy_to_user(buf_ptr, &buf[..bytes_written]) => {
                             *offset += count_read as u64;
                             context.rax = bytes_written as u64;
                         }
-                        Err(_) => context.rax = u64::MAX,
+                        _ => context.rax = u64::MAX,
                     }
                 }
                 FileHandle::Pipe { .. } => context.rax = u64::MAX,
@@ -686,78 +663,148 @@ pub fn handle_read_dir(context: &mut CPUState) {
     }
 }
 
-pub fn handle_file_size(context: &mut CPUState) {
-    let syscall_num = context.rax;
-    
-    if syscall_num == 4 { // SYS_STAT (path)
-        let ptr = context.rdi as *const u8;
-        let len = context.rsi as usize;
-        let path_str_full = copy_string_from_user(ptr, len);
+/// `struct stat` as user space lays it out.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Stat {
+    dev: u64,
+    ino: u64,
+    mode: u32,
+    _pad: u32,
+    nlink: u64,
+    uid: u32,
+    gid: u32,
+    rdev: u64,
+    size: u64,
+    blksize: u64,
+    blocks: u64,
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+}
 
-        let cwd_str = {
-            let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-            if tm.current_task >= 0 {
-                if let Some(thread) = tm.tasks[tm.current_task as usize].as_ref() {
-                    let proc = thread.process.as_ref().expect("Thread has no process");
-                    let cwd = proc.cwd.lock();
-                    let cwd_len = cwd.iter().position(|&c| c == 0).unwrap_or(cwd.len());
-                    String::from_utf8_lossy(&cwd[..cwd_len]).into_owned()
-                } else { String::from("@0xE0/") }
-            } else { String::from("@0xE0/") }
-        };
+/// Copies `meta` out as a `struct stat` at user address `buf`.
+fn put_stat(buf: u64, meta: crate::fs::vfs::Metadata) -> u64 {
+    let stat = Stat {
+        dev: meta.dev,
+        ino: meta.ino,
+        mode: meta.mode,
+        _pad: 0,
+        nlink: meta.nlink,
+        uid: meta.uid,
+        gid: meta.gid,
+        rdev: 0,
+        size: meta.size,
+        blksize: meta.block_size as u64,
+        blocks: meta.blocks,
+        atime: meta.atime,
+        mtime: meta.mtime,
+        ctime: meta.ctime,
+    };
+    if crate::memory::uaccess::write_user(buf, &stat) { 0 } else { u64::MAX }
+}
 
-        let resolved = resolve_path(&cwd_str, &path_str_full);
-        let path_parts: Vec<&str> = resolved.split('/').collect();
-        let disk_part = &path_parts[0][1..];
-        let disk_id = if disk_part.starts_with("0x") || disk_part.starts_with("0X") {
-            u8::from_str_radix(&disk_part[2..], 16).unwrap_or(0xFF)
-        } else {
-            disk_part.parse::<u8>().unwrap_or_else(|_| u8::from_str_radix(disk_part, 16).unwrap_or(0xFF))
-        };
-        let actual_path = if path_parts.len() > 1 { path_parts[1..].join("/") } else { String::from("") };
+/// Open file description behind the caller's descriptor `fd`.
+fn fd_handle(fd: u64) -> Option<&'static mut crate::fs::vfs::FileHandle> {
+    let proc = crate::interrupts::task::current_process_ptr();
+    if proc.is_null() {
+        return None;
+    }
+    unsafe { &*proc }.fd(fd as usize).and_then(crate::fs::vfs::get_file)
+}
 
-        if let Ok(node) = crate::fs::vfs::open(disk_id, &actual_path) {
-            context.rax = node.size();
-        } else {
-            context.rax = u64::MAX;
-        }
-        return;
+/// Inode of the regular file or directory open as `fd`.
+fn fd_node(fd: u64) -> Option<crate::fs::vfs::InodeRef> {
+    match fd_handle(fd)? {
+        crate::fs::vfs::FileHandle::File { node, .. } => Some(node.clone()),
+        crate::fs::vfs::FileHandle::Pipe { .. } => None,
     }
+}
 
-    // SYS_FSTAT (FD)
-    let local_fd = context.rdi as usize;
+fn path_node(ptr: u64, len: u64) -> Option<crate::fs::vfs::InodeRef> {
+    user_path(ptr, len as usize).and_then(|p| crate::fs::vfs::open(&p).ok())
+}
 
-    let global_fd_opt = {
-        let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        let current = tm.current_task;
-        if current >= 0 && local_fd < 16 {
-            if let Some(thread) = tm.tasks[current as usize].as_ref() {
-                let proc = thread.process.as_ref().expect("Thread has no process");
-                Some(proc.fd_table.lock()[local_fd])
-            } else { None }
-        } else {
-            None
+fn set_attr(node: Option<crate::fs::vfs::InodeRef>, attr: &crate::fs::vfs::SetAttr) -> u64 {
+    match node.map(|n| n.lock().setattr(attr)) {
+        Some(Ok(())) => 0,
+        _ => u64::MAX,
+    }
+}
+
+/// rdi/rsi = path, rdx = `struct stat` to fill. SYS_LSTAT describes a
+/// symlink itself rather than what it points to.
+pub fn handle_stat(context: &mut CPUState) {
+    let follow = context.rax == super::SYS_STAT;
+    let node = user_path(context.rdi, context.rsi as usize).and_then(|p| {
+        if follow { crate::fs::vfs::open(&p).ok() } else { crate::fs::vfs::open_link(&p).ok() }
+    });
+    context.rax = match node {
+        Some(node) => {
+            let meta = node.lock().metadata();
+            put_stat(context.rdx, meta)
         }
+        None => u64::MAX,
     };
+}
 
-    if let Some(fd_val) = global_fd_opt {
-        if fd_val == -1 {
+/// rdi = fd, rsi = `struct stat` to fill.
+pub fn handle_fstat(context: &mut CPUState) {
+    use crate::fs::vfs::{FileHandle, Metadata, S_IFIFO};
+    let meta = match fd_handle(context.rdi) {
+        Some(FileHandle::File { node, .. }) => node.lock().metadata(),
+        Some(FileHandle::Pipe { .. }) => Metadata { mode: S_IFIFO | 0o600, nlink: 1, block_size: 4096, ..Default::default() },
+        None => {
             context.rax = u64::MAX;
             return;
         }
-        let fd = fd_val as usize;
-        if let Some(handle) = crate::fs::vfs::get_file(fd) {
-            use crate::fs::vfs::FileHandle;
-            match handle {
-                FileHandle::File { node, .. } => context.rax = node.size(),
-                FileHandle::Pipe { .. } => context.rax = 0,
-            }
-        } else {
-            context.rax = u64::MAX;
-        }
+    };
+    context.rax = put_stat(context.rsi, meta);
+}
+
+/// SYS_CHMOD: rdi/rsi = path, rdx = mode. SYS_FCHMOD: rdi = fd, rsi = mode.
+/// Only the permission bits change.
+pub fn handle_chmod(context: &mut CPUState) {
+    let (node, mode) = if context.rax == super::SYS_CHMOD {
+        (path_node(context.rdi, context.rsi), context.rdx)
     } else {
-        context.rax = u64::MAX;
-    }
+        (fd_node(context.rdi), context.rsi)
+    };
+    let attr = crate::fs::vfs::SetAttr { mode: Some(mode as u32 & 0o7777), ..Default::default() };
+    context.rax = set_attr(node, &attr);
+}
+
+/// SYS_CHOWN: rdi/rsi = path, rdx = uid, r10 = gid. SYS_FCHOWN: rdi = fd,
+/// rsi = uid, rdx = gid. An id of -1 is left as it is.
+pub fn handle_chown(context: &mut CPUState) {
+    let (node, uid, gid) = if context.rax == super::SYS_CHOWN {
+        (path_node(context.rdi, context.rsi), context.rdx as u32, context.r10 as u32)
+    } else {
+        (fd_node(context.rdi), context.rsi as u32, context.rdx as u32)
+    };
+    let id = |v: u32| if v == u32::MAX { None } else { Some(v) };
+    let attr = crate::fs::vfs::SetAttr { uid: id(uid), gid: id(gid), ..Default::default() };
+    context.rax = set_attr(node, &attr);
+}
+
+/// rdi/rsi = path, rdx = access and modification `Timespec`s, or 0 to set
+/// both to now.
+pub fn handle_utimes(context: &mut CPUState) {
+    let times = if context.rdx == 0 {
+        let now = crate::time::realtime_ns() / 1_000_000_000;
+        Some((now, now))
+    } else {
+        crate::memory::uaccess::read_user::<[crate::time::Timespec; 2]>(context.rdx)
+            .filter(|t| t[0].tv_sec >= 0 && t[1].tv_sec >= 0)
+            .map(|t| (t[0].tv_sec as u64, t[1].tv_sec as u64))
+    };
+    context.rax = match times {
+        Some((atime, mtime)) => {
+            let attr = crate::fs::vfs::SetAttr { atime: Some(atime), mtime: Some(mtime), ..Default::default() };
+            set_attr(path_node(context.rdi, context.rsi), &attr)
+        }
+        None => u64::MAX,
+    };
 }
 
 pub fn handle_ftruncate(context: &mut CPUState) {
@@ -766,11 +813,11 @@ pub fn handle_ftruncate(context: &mut CPUState) {
 
     let global_fd_opt = {
         let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        let current = tm.current_task;
-        if current >= 0 && local_fd < 16 {
+        let current = tm.current_task();
+        if current >= 0 {
             if let Some(thread) = tm.tasks[current as usize].as_ref() {
                 let proc = thread.process.as_ref().expect("Thread has no process");
-                Some(proc.fd_table.lock()[local_fd])
+                Some(proc.fd(local_fd))
             } else { None }
         } else {
             None
@@ -778,16 +825,18 @@ pub fn handle_ftruncate(context: &mut CPUState) {
     };
 
     if let Some(fd_val) = global_fd_opt {
-        if fd_val == -1 {
-            context.rax = u64::MAX;
-            return;
-        }
-        let fd = fd_val as usize;
+        let fd = match fd_val {
+            Some(fd) => fd,
+            None => {
+                context.rax = u64::MAX;
+                return;
+            }
+        };
         if let Some(handle) = crate::fs::vfs::get_file(fd) {
             use crate::fs::vfs::FileHandle;
             match handle {
                 FileHandle::File { node, .. } => {
-                    match node.truncate(length) {
+                    match node.lock().truncate(length) {
                         Ok(_) => context.rax = 0,
                         Err(_) => context.rax = u64::MAX,
                     }
@@ -802,101 +851,96 @@ pub fn handle_ftruncate(context: &mut CPUState) {
     }
 }
 
+/// rdi = fd. Writes what the file's filesystem has cached back to its disk.
+pub fn handle_fsync(context: &mut CPUState) {
+    context.rax = match fd_handle(context.rdi) {
+        Some(crate::fs::vfs::FileHandle::File { node, .. }) => match node.lock().sync() {
+            Ok(()) => 0,
+            Err(_) => u64::MAX,
+        },
+        Some(_) => 0,
+        None => u64::MAX,
+    };
+}
+
+/// Writes every dirty cached block back to disk.
+pub fn handle_sync(context: &mut CPUState) {
+    crate::fs::bcache::sync(None);
+    context.rax = 0;
+}
+
 pub fn handle_pipe(context: &mut CPUState) {
-    let fds_ptr = context.rdi as *mut i32;
-    if fds_ptr.is_null() {
+    let fds_ptr = context.rdi;
+    if fds_ptr == 0 || !crate::memory::uaccess::access_ok(fds_ptr, 2 * size_of::<i32>(), true) {
         context.rax = u64::MAX;
         return;
     }
 
-    use crate::fs::vfs::{FileHandle, GLOBAL_FILE_REFCOUNT, OPEN_FILES};
+    use crate::fs::vfs::FileHandle;
     use crate::fs::pipe::Pipe;
 
-    let mut g1 = -1;
-    let mut g2 = -1;
-
-    {
-        for i in 3..256 {
-            unsafe {
-                if OPEN_FILES[i].is_none() {
-                    if g1 == -1 {
-                        g1 = i as i32;
-                    } else {
-                        g2 = i as i32;
-                        break;
-                    }
-                }
-            }
+    let pipe = Pipe::new();
+    let g1 = match crate::fs::vfs::install(FileHandle::Pipe { pipe: pipe.clone() }, 0) {
+        Ok(g) => g,
+        Err(_) => {
+            context.rax = u64::MAX;
+            return;
         }
+    };
+    let g2 = match crate::fs::vfs::install(FileHandle::Pipe { pipe }, 0) {
+        Ok(g) => g,
+        Err(_) => {
+            crate::fs::vfs::close_file(g1);
+            context.rax = u64::MAX;
+            return;
+        }
+    };
 
-        if g1 != -1 && g2 != -1 {
-            let mut l1 = -1;
-            let mut l2 = -1;
-
-            let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-            let current = tm.current_task;
-            if current >= 0 {
-                if let Some(thread) = tm.tasks[current as usize].as_mut() {
-                    let proc = thread.process.as_ref().expect("Thread has no process");
-                    let mut fd_table = proc.fd_table.lock();
-                    for i in 0..16 {
-                        if fd_table[i] == -1 {
-                            if l1 == -1 {
-                                l1 = i as i32;
-                            } else {
-                                l2 = i as i32;
-                                break;
-                            }
-                        }
-                    }
-
-                    if l1 != -1 && l2 != -1 {
-                        let pipe = Pipe::new();
-                        unsafe {
-                            OPEN_FILES[g1 as usize] = Some(FileHandle::Pipe { pipe: pipe.clone() });
-                            OPEN_FILES[g2 as usize] = Some(FileHandle::Pipe { pipe });
-                            
-                            GLOBAL_FILE_REFCOUNT[g1 as usize] = 1;
-                            GLOBAL_FILE_REFCOUNT[g2 as usize] = 1;
-                        }
-
-                        fd_table[l1 as usize] = g1 as i16;
-                        fd_table[l2 as usize] = g2 as i16;
-
-                        unsafe {
-                            *fds_ptr.add(0) = l1;
-                            *fds_ptr.add(1) = l2;
+    let mut fds = None;
+    {
+        let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+        let current = tm.current_task();
+        if current >= 0 {
+            if let Some(thread) = tm.tasks[current as usize].as_mut() {
+                let proc = thread.process.as_ref().expect("Thread has no process");
+                let limit = proc.limits.lock().cur(crate::interrupts::rlimit::RLIMIT_NOFILE);
+                let mut files = proc.files.lock();
+                if let Some(l1) = files.alloc(g1, 0, limit) {
+                    match files.alloc(g2, 0, limit) {
+                        Some(l2) => fds = Some([l1 as i32, l2 as i32]),
+                        None => {
+                            files.take(l1);
                         }
-                        context.rax = 0;
-                        return;
                     }
                 }
             }
         }
     }
-    context.rax = u64::MAX;
+    if fds.is_none() {
+        crate::fs::vfs::close_file(g1);
+        crate::fs::vfs::close_file(g2);
+    }
+    context.rax = match fds {
+        Some(fds) if crate::memory::uaccess::write_user(fds_ptr, &fds) => 0,
+        _ => u64::MAX,
+    };
 }
 
 pub fn handle_close(context: &mut CPUState) {
     let local_fd = context.rdi as usize;
 
     let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-    let current = tm.current_task;
+    let current = tm.current_task();
     if current >= 0 {
         if let Some(thread) = tm.tasks[current as usize].as_mut() {
             let proc = thread.process.as_ref().expect("Thread has no process");
-            let mut fd_table = proc.fd_table.lock();
-            if local_fd < 16 {
-                let global = fd_table[local_fd];
-                if global != -1 {
-                    crate::fs::vfs::close_file(global as usize);
-                    fd_table[local_fd] = -1;
+            let global = proc.files.lock().take(local_fd);
+            match global {
+                Some(global) => {
+                    crate::fs::vfs::close_file(global);
                     context.rax = 0;
-                } else {
-                    context.rax = u64::MAX;
                 }
-            } else {
-                context.rax = u64::MAX;
+                None => context.rax = u64::MAX,
             }
         } else {
             context.rax = u64::MAX;
@@ -906,6 +950,54 @@ pub fn handle_close(context: &mut CPUState) {
     }
 }
 
+pub const F_GETFD: u64 = 1;
+pub const F_SETFD: u64 = 2;
+pub const F_GETFL: u64 = 3;
+pub const F_SETFL: u64 = 4;
+
+pub fn handle_fcntl(context: &mut CPUState) {
+    use crate::interrupts::task::FD_CLOEXEC;
+    let local_fd = context.rdi as usize;
+    let cmd = context.rsi;
+    let arg = context.rdx;
+
+    let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    let current = tm.current_task();
+    if current < 0 {
+        context.rax = u64::MAX;
+        return;
+    }
+
+    if let Some(thread) = tm.tasks[current as usize].as_ref() {
+        let proc = thread.process.as_ref().expect("Thread has no process");
+        let mut files = proc.files.lock();
+        let file = match files.get(local_fd).and_then(crate::fs::vfs::get_open_file) {
+            Some(file) => file,
+            None => {
+                context.rax = u64::MAX;
+                return;
+            }
+        };
+
+        let flags = files.flags(local_fd);
+        context.rax = match cmd {
+            F_GETFD => (flags & FD_CLOEXEC) as u64,
+            F_SETFD => {
+                files.set_flags(local_fd, (flags & !FD_CLOEXEC) | ((arg as u8) & FD_CLOEXEC));
+                0
+            }
+            F_GETFL => file.flags,
+            F_SETFL => {
+                file.flags = (file.flags & !SETFL_MASK) | (arg & SETFL_MASK);
+                0
+            }
+            _ => u64::MAX,
+        };
+    } else {
+        context.rax = u64::MAX;
+    }
+}
+
 pub fn handle_seek(context: &mut CPUState) {
     let local_fd = context.rdi as usize;
     let offset = context.rsi as i64;
@@ -913,11 +1005,11 @@ pub fn handle_seek(context: &mut CPUState) {
 
     let global_fd_opt = {
         let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        let current = tm.current_task;
-        if current >= 0 && local_fd < 16 {
+        let current = tm.current_task();
+        if current >= 0 {
             if let Some(thread) = tm.tasks[current as usize].as_ref() {
                 let proc = thread.process.as_ref().expect("Thread has no process");
-                Some(proc.fd_table.lock()[local_fd])
+                Some(proc.fd(local_fd))
             } else { None }
         } else {
             None
@@ -925,16 +1017,18 @@ pub fn handle_seek(context: &mut CPUState) {
     };
 
     if let Some(fd_val) = global_fd_opt {
-        if fd_val == -1 {
-            context.rax = u64::MAX;
-            return;
-        }
-        let fd = fd_val as usize;
+        let fd = match fd_val {
+            Some(fd) => fd,
+            None => {
+                context.rax = u64::MAX;
+                return;
+            }
+        };
         if let Some(handle) = crate::fs::vfs::get_file(fd) {
             use crate::fs::vfs::FileHandle;
             match handle {
                 FileHandle::File { node, offset: current_offset } => {
-                    let size = node.size() as i64;
+                    let size = node.lock().size() as i64;
                     let new_offset = match whence {
                         0 => offset,
                         1 => (*current_offset as i64) + offset,
@@ -962,6 +1056,7 @@ pub fn handle_seek(context: &mut CPUState) {
 pub const TIOCGWINSZ: u64 = 0x5413;
 pub const TIOCSWINSZ: u64 = 0x5414;
 
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub struct WinSize {
     pub ws_row: u16,
@@ -970,55 +1065,49 @@ pub struct WinSize {
     pub ws_ypixel: u16,
 }
 
+unsafe impl crate::memory::uaccess::Pod for WinSize {}
+
 pub fn handle_ioctl(context: &mut CPUState) {
     let _fd = context.rdi;
     let request = context.rsi;
-    let arg = context.rdx as *mut WinSize;
+    let arg = context.rdx;
 
     match request {
         TIOCGWINSZ => {
-            let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-            if tm.current_task >= 0 {
-                if let Some(thread) = tm.tasks[tm.current_task as usize].as_ref() {
+            let size = {
+                let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+                tm.current_task_idx().and_then(|idx| tm.tasks[idx].as_ref()).map(|thread| {
                     let proc = thread.process.as_ref().expect("Thread has no process");
-                    if !arg.is_null() {
-                        unsafe {
-                            (*arg).ws_row = *proc.terminal_height.lock();
-                            (*arg).ws_col = *proc.terminal_width.lock();
-                            (*arg).ws_xpixel = 0;
-                            (*arg).ws_ypixel = 0;
-                        }
-                        context.rax = 0;
-                    } else {
-                        context.rax = u64::MAX;
+                    WinSize {
+                        ws_row: *proc.terminal_height.lock(),
+                        ws_col: *proc.terminal_width.lock(),
+                        ws_xpixel: 0,
+                        ws_ypixel: 0,
                     }
-                } else {
-                    context.rax = u64::MAX;
-                }
-            } else {
-                context.rax = u64::MAX;
-            }
+                })
+            };
+            context.rax = match size {
+                Some(size) if crate::memory::uaccess::write_user(arg, &size) => 0,
+                _ => u64::MAX,
+            };
         }
         TIOCSWINSZ => {
-            let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-            let current = tm.current_task;
-            if current >= 0 {
-                if let Some(thread) = tm.tasks[current as usize].as_mut() {
-                    let proc = thread.process.as_ref().expect("Thread has no process");
-                    if !arg.is_null() {
-                        unsafe {
-                            *proc.terminal_height.lock() = (*arg).ws_row;
-                            *proc.terminal_width.lock() = (*arg).ws_col;
-                        }
-                        context.rax = 0;
-                    } else {
-                        context.rax = u64::MAX;
-                    }
-                } else {
+            let size = match crate::memory::uaccess::read_user::<WinSize>(arg) {
+                Some(s) => s,
+                _ => {
                     context.rax = u64::MAX;
+                    return;
                 }
-            } else {
-                context.rax = u64::MAX;
+            };
+            let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+            match tm.current_task_idx().and_then(|idx| tm.tasks[idx].as_ref()) {
+                Some(thread) => {
+                    let proc = thread.process.as_ref().expect("Thread has no process");
+                    *proc.terminal_height.lock() = size.ws_row;
+                    *proc.terminal_width.lock() = size.ws_col;
+                    context.rax = 0;
+                }
+                None => context.rax = u64::MAX,
             }
         }
         _ => {
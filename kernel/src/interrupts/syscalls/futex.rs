@@ -0,0 +1,54 @@
+use crate::interrupts::task::{CPUState, TASK_MANAGER};
+use crate::memory::vmm;
+
+pub const FUTEX_WAIT: u64 = 0;
+pub const FUTEX_WAKE: u64 = 1;
+
+/// Futexes are keyed by the physical word, so processes sharing memory
+/// meet on the same queue. Copy-on-write is broken first so a forked
+/// child never aliases its parent's private futex.
+fn futex_key(uaddr: u64) -> Option<u64> {
+    if (uaddr & 3) != 0 {
+        return None;
+    }
+    vmm::user_virt_to_hhdm(uaddr, crate::interrupts::task::current_pid(), true)
+}
+
+/// rdi = address, rsi = op, rdx = expected value (WAIT) or wake count (WAKE),
+/// r10 = WAIT timeout in ms, 0 for none.
+pub fn handle_futex(context: &mut CPUState) {
+    let uaddr = context.rdi;
+    let op = context.rsi;
+    let val = context.rdx;
+    let timeout = context.r10;
+
+    let key = match futex_key(uaddr) {
+        Some(k) => k,
+        None => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+
+    match op {
+        FUTEX_WAIT => {
+            {
+                let mut tm = TASK_MANAGER.int_lock();
+                let current = unsafe { core::ptr::read_volatile(key as *const u32) };
+                if current != val as u32 {
+                    context.rax = u64::MAX;
+                    return;
+                }
+                let deadline = if timeout != 0 { crate::time::deadline_after(timeout.saturating_mul(1_000_000)) } else { 0 };
+                tm.block_current(key, deadline);
+            }
+
+            context.rax = if crate::interrupts::wait::finish_sleep() { 0 } else { u64::MAX };
+        }
+        FUTEX_WAKE => {
+            let mut tm = TASK_MANAGER.int_lock();
+            context.rax = tm.wake(key, val as usize) as u64;
+        }
+        _ => context.rax = u64::MAX,
+    }
+}
@@ -1,11 +1,12 @@
 use crate::interrupts::task::CPUState;
-use crate::memory::{paging, pmm, vmm};
-use crate::memory::address::PhysAddr;
+use crate::memory::{paging, shm, vmm, vma};
+use crate::memory::vma::{Backing, Vma, VmaKind};
+use alloc::string::String;
 
 pub fn handle_brk(context: &mut CPUState) {
     let new_brk = context.rdi;
     let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-    let current_idx = tm.current_task;
+    let current_idx = tm.current_task();
 
     if current_idx < 0 {
         context.rax = 0;
@@ -17,51 +18,37 @@ pub fn handle_brk(context: &mut CPUState) {
         let mut heap_end = proc.heap_end.lock();
         let current_brk = *heap_end;
 
-        
         if new_brk == 0 {
             context.rax = current_brk;
             return;
         }
+        let heap_start = proc.layout.lock().heap_start;
+        if new_brk < heap_start {
+            context.rax = current_brk;
+            return;
+        }
 
-        let pml4_phys = proc.pml4_phys;
-        let pid = proc.pid;
-
-        
         let aligned_new = (new_brk + 0xFFF) & !0xFFF;
         let aligned_current = (current_brk + 0xFFF) & !0xFFF;
+        let mut vmas = proc.vmas.int_lock();
 
         if aligned_new > aligned_current {
-            
-            let size = aligned_new - aligned_current;
-            let pages = size / 4096;
-            
-            
-            for i in 0..pages {
-                let virt = aligned_current + (i * 4096);
-                if let Some(phys) = pmm::allocate_frame(pid) {
-                    
-                    let flags = paging::PAGE_PRESENT | paging::PAGE_WRITABLE | paging::PAGE_USER;
-                    unsafe {
-                        vmm::map_page(virt, PhysAddr::new(phys), flags, Some(pml4_phys));
-                    }
-                } else {
-                    
-                    context.rax = current_brk;
-                    return;
-                }
+            let limit = proc.limits.lock().cur(crate::interrupts::rlimit::RLIMIT_AS);
+            // Pages are only reserved here; the fault handler backs them on first touch.
+            if vmas.find_free(aligned_current, aligned_new - aligned_current) != Some(aligned_current)
+                || vmas.total_size() + (aligned_new - aligned_current) > limit {
+                context.rax = current_brk;
+                return;
             }
-            
-            *heap_end = new_brk;
-            context.rax = new_brk;
         } else if aligned_new < aligned_current {
-            
-            
-            *heap_end = new_brk;
-            context.rax = new_brk;
-        } else {
-            *heap_end = new_brk;
-            context.rax = new_brk;
+            unsafe {
+                vmm::unmap_user_range(proc.pml4_phys, aligned_new, aligned_current);
+            }
         }
+
+        vmas.resize(heap_start, aligned_new, paging::PAGE_WRITABLE, VmaKind::Heap);
+        *heap_end = new_brk;
+        context.rax = new_brk;
     } else {
         context.rax = 0;
     }
@@ -70,81 +57,249 @@ pub fn handle_brk(context: &mut CPUState) {
 pub fn handle_mmap(context: &mut CPUState) {
     let addr = context.rdi;
     let len = context.rsi;
-    let _prot = context.rdx;
-    let _flags = context.r10;
-    let _fd = context.r8;
-    let _offset = context.r9;
+    let prot = context.rdx;
+    let flags = context.r10;
+    let local_fd = context.r8 as i64;
+    let offset = context.r9;
+
+    let shared = match flags & (vma::MAP_SHARED | vma::MAP_PRIVATE) {
+        vma::MAP_SHARED => true,
+        vma::MAP_PRIVATE => false,
+        _ => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+    let anonymous = (flags & vma::MAP_ANONYMOUS) != 0;
+    let fixed = (flags & vma::MAP_FIXED) != 0;
 
     let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-    let current_idx = tm.current_task;
+    let current_idx = tm.current_task();
     
-    if current_idx < 0 || len == 0 {
+    if current_idx < 0 || len == 0 || len > vma::USER_LIMIT || (offset & 0xFFF) != 0 || (fixed && (addr & 0xFFF) != 0) {
         context.rax = u64::MAX; 
         return;
     }
 
     if let Some(thread) = tm.tasks[current_idx as usize].as_mut() {
         let proc = thread.process.as_ref().expect("Thread has no process");
-        let pml4_phys = proc.pml4_phys;
-        let pid = proc.pid;
-
-        
-        
-        
-        
-        
-        
-        
-        
-        
-        
-        
-        
-        let target_addr = if addr == 0 {
-            
-            
-            let mut heap_end = proc.heap_end.lock();
-            let old_brk = *heap_end;
-            let new_brk = old_brk + len;
-            
-            let aligned_new = (new_brk + 0xFFF) & !0xFFF;
-            *heap_end = aligned_new; 
-            old_brk 
+        let size = (len + 0xFFF) & !0xFFF;
+
+        let backing = if anonymous && shared {
+            match shm::create_anonymous(size) {
+                Some(id) => {
+                    shm::retain(id);
+                    Backing::Shm { id, offset: 0 }
+                }
+                None => {
+                    context.rax = u64::MAX;
+                    return;
+                }
+            }
+        } else if anonymous {
+            Backing::Anonymous
         } else {
-            addr
+            let global_fd = if local_fd >= 0 { proc.fd(local_fd as usize) } else { None };
+            match global_fd.map(|fd| (fd, crate::fs::vfs::get_file(fd))) {
+                Some((fd, Some(crate::fs::vfs::FileHandle::File { .. }))) => {
+                    let backing = Backing::File { fd, offset };
+                    backing.retain();
+                    backing
+                }
+                _ => {
+                    context.rax = u64::MAX;
+                    return;
+                }
+            }
         };
 
-        let start_page = target_addr & !0xFFF;
-        let end_page = (target_addr + len + 0xFFF) & !0xFFF;
-        let pages = (end_page - start_page) / 4096;
-
-        for i in 0..pages {
-            let virt = start_page + (i * 4096);
-            if let Some(phys) = pmm::allocate_frame(pid) {
-                let flags = paging::PAGE_PRESENT | paging::PAGE_WRITABLE | paging::PAGE_USER;
+        let mut vmas = proc.vmas.int_lock();
+        let hint = addr & !0xFFF;
+        let start = if vmas.total_size().saturating_add(size) > proc.limits.lock().cur(crate::interrupts::rlimit::RLIMIT_AS) {
+            None
+        } else if fixed {
+            if hint.checked_add(size).map_or(true, |end| end > vma::USER_LIMIT) {
+                None
+            } else {
+                vma::sync_range(&vmas, proc.pml4_phys, hint, hint + size);
                 unsafe {
-                    vmm::map_page(virt, PhysAddr::new(phys), flags, Some(pml4_phys));
+                    vmm::unmap_user_range(proc.pml4_phys, hint, hint + size);
                 }
-            } else {
+                Some(hint)
+            }
+        } else if hint != 0 && vmas.find_free(hint, size) == Some(hint) {
+            Some(hint)
+        } else {
+            vmas.find_free(proc.layout.lock().mmap_base, size)
+        };
+
+        match start {
+            Some(start) => {
+                vmas.insert(Vma::mapped(start, start + size, prot, backing, shared));
+                context.rax = start;
+            }
+            None => {
+                backing.release();
                 context.rax = u64::MAX;
-                return;
             }
         }
-
-        context.rax = target_addr;
     } else {
         context.rax = u64::MAX;
     }
 }
 
 pub fn handle_munmap(context: &mut CPUState) {
-    let _addr = context.rdi;
-    let _len = context.rsi;
-    
-    context.rax = 0;
+    let addr = context.rdi;
+    let len = context.rsi;
+
+    let end = match addr.checked_add(len) {
+        Some(end) if (addr & 0xFFF) == 0 && len != 0 && end <= vma::USER_LIMIT => (end + 0xFFF) & !0xFFF,
+        _ => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+
+    let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    if let Some(proc) = tm.current_task_idx().and_then(|idx| tm.tasks[idx].as_ref()).and_then(|t| t.process.as_ref()) {
+        let mut vmas = proc.vmas.int_lock();
+        vma::sync_range(&vmas, proc.pml4_phys, addr, end);
+        unsafe {
+            vmm::unmap_user_range(proc.pml4_phys, addr, end);
+        }
+        vmas.remove(addr, end);
+        context.rax = 0;
+    } else {
+        context.rax = u64::MAX;
+    }
 }
 
+pub fn handle_msync(context: &mut CPUState) {
+    let addr = context.rdi;
+    let len = context.rsi;
+
+    let end = match addr.checked_add(len) {
+        Some(end) if (addr & 0xFFF) == 0 && len != 0 && end <= vma::USER_LIMIT => (end + 0xFFF) & !0xFFF,
+        _ => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+
+    let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    if let Some(proc) = tm.current_task_idx().and_then(|idx| tm.tasks[idx].as_ref()).and_then(|t| t.process.as_ref()) {
+        let vmas = proc.vmas.int_lock();
+        vma::sync_range(&vmas, proc.pml4_phys, addr, end);
+        context.rax = 0;
+    } else {
+        context.rax = u64::MAX;
+    }
+}
+
+pub fn handle_shm_open(context: &mut CPUState) {
+    let name = super::fs::copy_string_from_user(context.rdi as *const u8, context.rsi as usize);
+    let size = context.rdx;
+    context.rax = match name.and_then(|name| shm::open(&name, size)) {
+        Some(id) => id as u64,
+        None => u64::MAX,
+    };
+}
+
+/// Maps the whole of shm object `rdi` read-write, at `rsi` if that range is free.
+pub fn handle_shm_map(context: &mut CPUState) {
+    let id = context.rdi as usize;
+    let hint = context.rsi & !0xFFF;
+
+    let size = match shm::size(id) {
+        Some(s) => s,
+        None => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+
+    let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    if let Some(proc) = tm.current_task_idx().and_then(|idx| tm.tasks[idx].as_ref()).and_then(|t| t.process.as_ref()) {
+        let mut vmas = proc.vmas.int_lock();
+        let start = if vmas.total_size() + size > proc.limits.lock().cur(crate::interrupts::rlimit::RLIMIT_AS) {
+            None
+        } else if hint != 0 && vmas.find_free(hint, size) == Some(hint) {
+            Some(hint)
+        } else {
+            vmas.find_free(proc.layout.lock().mmap_base, size)
+        };
+
+        match start {
+            Some(start) => {
+                shm::retain(id);
+                let prot = vma::PROT_READ | vma::PROT_WRITE;
+                vmas.insert(Vma::mapped(start, start + size, prot, Backing::Shm { id, offset: 0 }, true));
+                context.rax = start;
+            }
+            None => context.rax = u64::MAX,
+        }
+    } else {
+        context.rax = u64::MAX;
+    }
+}
+
+pub fn handle_shm_unlink(context: &mut CPUState) {
+    let name = super::fs::copy_string_from_user(context.rdi as *const u8, context.rsi as usize);
+    context.rax = if name.is_some_and(|name| shm::unlink(&name)) { 0 } else { u64::MAX };
+}
+
+/// rdi = pid. Returns resident bytes; if rsi is non-zero, the bytes in swap are written there.
 pub fn handle_get_process_mem(context: &mut CPUState) {
     let pid = context.rdi as u64;
+    let swapped = context.rsi;
+    if swapped != 0 && !crate::memory::uaccess::write_user(swapped, &crate::memory::swap::usage_by_pid(pid)) {
+        context.rax = u64::MAX;
+        return;
+    }
     context.rax = crate::memory::pmm::get_memory_usage_by_pid(pid) as u64;
 }
+
+/// rdi/rsi = path, rdx = size in bytes, r10 = start sector. A bare unmounted
+/// disk such as `@0xF0` is used raw from the start sector (size 0 takes the
+/// rest of the disk); anything else names a swap file, created if missing and
+/// grown to the size (0 keeps the file's own size).
+pub fn handle_swapon(context: &mut CPUState) {
+    let path = match crate::interrupts::syscalls::fs::copy_string_from_user(context.rdi as *const u8, context.rsi as usize) {
+        Some(p) => p,
+        None => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+    let size = context.rdx;
+
+    let raw_disk = path.strip_prefix('@').map(|d| d.trim_end_matches('/')).filter(|d| !d.contains('/'));
+    let result = if let Some(disk) = raw_disk {
+        match crate::fs::vfs::parse_disk_id(disk) {
+            Some(disk_id) => crate::memory::swap::enable_disk(disk_id, context.r10, size),
+            None => Err(String::from("Bad disk")),
+        }
+    } else {
+        match crate::interrupts::syscalls::fs::resolve_path(&crate::interrupts::syscalls::process::current_cwd(), &path) {
+            Some(file) => {
+                let node = crate::fs::vfs::open(&file).or_else(|_| crate::fs::vfs::create(&file, false));
+                node.and_then(|node| crate::memory::swap::enable_file(node, size))
+            }
+            None => Err(String::from("Disk not mounted")),
+        }
+    };
+
+    match result {
+        Ok(()) => context.rax = 0,
+        Err(e) => {
+            crate::debugln!("[Swap] swapon {} failed: {}", path, e);
+            context.rax = u64::MAX;
+        }
+    }
+}
+
+/// rdi = where to write the kernel's `HeapStats`.
+pub fn handle_get_heap_stats(context: &mut CPUState) {
+    let stats = crate::memory::allocator::stats();
+    context.rax = if crate::memory::uaccess::write_user(context.rdi, &stats) { 0 } else { u64::MAX };
+}
@@ -1,13 +1,37 @@
 use crate::interrupts::task::CPUState;
 use alloc::string::String;
 
+/// Bytes of a debug message copied in at a time.
+const DEBUG_CHUNK: usize = 4096;
+
 pub fn handle_debug_print(context: &mut CPUState) {
     let ptr = context.rdi;
     let len = context.rsi as usize;
 
-    let s = unsafe { core::slice::from_raw_parts(ptr as *const u8, len) };
-    let str_val = String::from_utf8_lossy(s);
-    crate::debug_print!("{}", str_val);
+    if !crate::memory::uaccess::access_ok(ptr, len, false) {
+        context.rax = u64::MAX;
+        return;
+    }
+    // Room for the start of a UTF-8 character held back from the last chunk.
+    let mut buf = alloc::vec![0u8; DEBUG_CHUNK + 3];
+    let mut kept = 0;
+    let mut done = 0;
+    while done < len {
+        let n = (len - done).min(DEBUG_CHUNK);
+        if !crate::memory::uaccess::copy_from_user(&mut buf[kept..kept + n], ptr + done as u64) {
+            context.rax = u64::MAX;
+            return;
+        }
+        done += n;
+        let end = kept + n;
+        let valid = match core::str::from_utf8(&buf[..end]) {
+            Err(e) if e.error_len().is_none() && done < len => e.valid_up_to(),
+            _ => end,
+        };
+        crate::debug_print!("{}", String::from_utf8_lossy(&buf[..valid]));
+        buf.copy_within(valid..end, 0);
+        kept = end - valid;
+    }
 
     context.rax = len as u64;
 }
@@ -17,8 +41,15 @@ pub fn handle_time(context: &mut CPUState) {
     context.rax = ((h as u64) << 16) | ((m as u64) << 8) | (s as u64);
 }
 
+/// Milliseconds since boot.
 pub fn handle_ticks(context: &mut CPUState) {
-    unsafe {
-        context.rax = crate::interrupts::task::SYSTEM_TICKS;
+    context.rax = crate::time::uptime_ms();
+}
+
+/// rdi = clock id, rsi = `Timespec` to fill.
+pub fn handle_clock_gettime(context: &mut CPUState) {
+    match crate::time::clock_ns(context.rdi) {
+        Some(ns) if crate::memory::uaccess::write_user(context.rsi, &crate::time::Timespec::from_ns(ns)) => {}
+        _ => context.rax = u64::MAX,
     }
 }
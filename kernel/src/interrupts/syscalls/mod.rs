@@ -9,6 +9,7 @@ pub mod process;
 pub mod memory;
 pub mod window;
 pub mod misc;
+pub mod futex;
 
 pub const SYS_READ: u64 = 0;
 pub const SYS_WRITE: u64 = 1;
@@ -16,26 +17,42 @@ pub const SYS_OPEN: u64 = 2;
 pub const SYS_CLOSE: u64 = 3;
 pub const SYS_STAT: u64 = 4;
 pub const SYS_FSTAT: u64 = 5;
+pub const SYS_LSTAT: u64 = 6;
 pub const SYS_POLL: u64 = 7;
 pub const SYS_LSEEK: u64 = 8;
 pub const SYS_MMAP: u64 = 9;
 pub const SYS_MUNMAP: u64 = 11;
 pub const SYS_BRK: u64 = 12;
+pub const SYS_SIGACTION: u64 = 13;
+pub const SYS_SIGPROCMASK: u64 = 14;
+pub const SYS_SIGRETURN: u64 = 15;
 pub const SYS_IOCTL: u64 = 16;
 pub const SYS_PIPE: u64 = 22;
+pub const SYS_MSYNC: u64 = 26;
 pub const SYS_NANOSLEEP: u64 = 35;
 pub const SYS_GETPID: u64 = 39;
+pub const SYS_FORK: u64 = 57;
 pub const SYS_EXECVE: u64 = 59;
 pub const SYS_EXIT: u64 = 60;
 pub const SYS_WAIT4: u64 = 61;
 pub const SYS_KILL: u64 = 62;
+pub const SYS_FCNTL: u64 = 72;
+pub const SYS_FSYNC: u64 = 74;
+pub const SYS_FDATASYNC: u64 = 75;
 pub const SYS_GETDENTS: u64 = 78;
 pub const SYS_CHDIR: u64 = 80;
 pub const SYS_RENAME: u64 = 82;
 pub const SYS_MKDIR: u64 = 83;
 pub const SYS_RMDIR: u64 = 84;
 pub const SYS_CREATE: u64 = 85;
+pub const SYS_LINK: u64 = 86;
 pub const SYS_UNLINK: u64 = 87;
+pub const SYS_SYMLINK: u64 = 88;
+pub const SYS_READLINK: u64 = 89;
+pub const SYS_CHMOD: u64 = 90;
+pub const SYS_FCHMOD: u64 = 91;
+pub const SYS_CHOWN: u64 = 92;
+pub const SYS_FCHOWN: u64 = 93;
 
 
 pub const SYS_ADD_WINDOW: u64 = 100;
@@ -57,23 +74,44 @@ pub const SYS_SPAWN_THREAD: u64 = 112;
 pub const SYS_THREAD_EXIT: u64 = 113;
 
 pub const SYS_SPAWN_EXT: u64 = 114;
+
+pub const SYS_SHM_OPEN: u64 = 115;
+pub const SYS_SHM_MAP: u64 = 116;
+pub const SYS_SHM_UNLINK: u64 = 117;
+pub const SYS_GET_HEAP_STATS: u64 = 118;
 pub const SYS_DEBUG_PRINT: u64 = 999; 
+pub const SYS_GETRLIMIT: u64 = 97;
+pub const SYS_GETPRIORITY: u64 = 140;
+pub const SYS_SETPRIORITY: u64 = 141;
+pub const SYS_PERSONALITY: u64 = 135;
+pub const SYS_SCHED_SETSCHEDULER: u64 = 144;
+pub const SYS_ARCH_PRCTL: u64 = 158;
+pub const SYS_SCHED_GETSCHEDULER: u64 = 145;
+pub const SYS_SETRLIMIT: u64 = 160;
+pub const SYS_SYNC: u64 = 162;
 pub const SYS_MOUNT: u64 = 165;
+pub const SYS_UMOUNT: u64 = 166;
+pub const SYS_SWAPON: u64 = 167;
+pub const SYS_FUTEX: u64 = 202;
+pub const SYS_CLOCK_GETTIME: u64 = 228;
+pub const SYS_UTIMES: u64 = 235;
 
 #[unsafe(naked)]
 #[unsafe(no_mangle)]
 pub extern "C" fn syscall_entry() {
     unsafe {
         naked_asm!(
-            "mov [rip + {scratch}], r15",
+            "swapgs",
+            "mov gs:[{scratch}], r15",
             "mov r15, rsp",
-            "mov rsp, [rip + {kernel_stack_ptr}]",
+            "mov rsp, gs:[{kernel_stack}]",
             "push QWORD PTR 0x23", 
             "push r15",
             "push r11",
             "push QWORD PTR 0x33", 
             "push rcx",
-            "mov r15, [rip + {scratch}]",
+            "mov r15, gs:[{scratch}]",
+            "swapgs",
             "push rbp",
             "push rax",
             "push rbx",
@@ -108,14 +146,15 @@ pub extern "C" fn syscall_entry() {
             "pop rax",
             "pop rbp",
             "iretq",
-            kernel_stack_ptr = sym crate::interrupts::task::KERNEL_STACK_PTR,
-            scratch = sym crate::interrupts::task::SCRATCH,
+            kernel_stack = const core::mem::offset_of!(crate::smp::PerCpu, kernel_stack),
+            scratch = const core::mem::offset_of!(crate::smp::PerCpu, scratch),
         );
     }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn syscall_dispatcher(context: &mut CPUState) {
+    let _kernel = crate::sync::KERNEL_LOCK.lock();
     let syscall_num = context.rax;
 
     context.rax = 0;
@@ -125,20 +164,26 @@ pub extern "C" fn syscall_dispatcher(context: &mut CPUState) {
         SYS_WRITE => fs::handle_write_file(context),
         SYS_OPEN => fs::handle_open(context),
         SYS_CLOSE => fs::handle_close(context),
-        SYS_STAT => fs::handle_file_size(context),
-        SYS_FSTAT => fs::handle_file_size(context),
+        SYS_STAT | SYS_LSTAT => fs::handle_stat(context),
+        SYS_FSTAT => fs::handle_fstat(context),
         SYS_POLL => fs::handle_poll(context),
         SYS_LSEEK => fs::handle_seek(context),
         SYS_MMAP => memory::handle_mmap(context),
         SYS_MUNMAP => memory::handle_munmap(context),
         SYS_BRK => memory::handle_brk(context),
+        SYS_MSYNC => memory::handle_msync(context),
+        SYS_SIGACTION => process::handle_sigaction(context),
+        SYS_SIGPROCMASK => process::handle_sigprocmask(context),
+        SYS_SIGRETURN => process::handle_sigreturn(context),
         SYS_IOCTL => fs::handle_ioctl(context),
         SYS_PIPE => fs::handle_pipe(context),
         SYS_NANOSLEEP => process::handle_sleep(context),
-        SYS_EXECVE => process::handle_spawn(context),
+        SYS_FORK => process::handle_fork(context),
+        SYS_EXECVE => process::handle_execve(context),
         SYS_EXIT => process::handle_exit(context),
         SYS_WAIT4 => process::handle_wait_pid(context),
         SYS_KILL => process::handle_kill(context),
+        SYS_FCNTL => fs::handle_fcntl(context),
         SYS_GETDENTS => fs::handle_read_dir(context),
         SYS_CHDIR => fs::handle_chdir(context),
         SYS_RENAME => fs::handle_rename(context),
@@ -156,23 +201,50 @@ pub extern "C" fn syscall_dispatcher(context: &mut CPUState) {
         SYS_GET_MOUSE => window::handle_get_mouse(context),
         SYS_GET_TIME => misc::handle_time(context),
         SYS_GET_TICKS => misc::handle_ticks(context),
+        SYS_CLOCK_GETTIME => misc::handle_clock_gettime(context),
         SYS_GET_PROCESS_LIST => process::handle_get_process_list(context),
         SYS_GET_PROCESS_MEM => memory::handle_get_process_mem(context),
+        SYS_GET_HEAP_STATS => memory::handle_get_heap_stats(context),
         SYS_FTRUNCATE => fs::handle_ftruncate(context),
         
         SYS_SPAWN_THREAD => process::handle_spawn_thread(context),
+        SYS_SPAWN_EXT => process::handle_spawn(context),
         SYS_THREAD_EXIT => process::handle_thread_exit(context),
 
+        SYS_SHM_OPEN => memory::handle_shm_open(context),
+        SYS_SHM_MAP => memory::handle_shm_map(context),
+        SYS_SHM_UNLINK => memory::handle_shm_unlink(context),
+
+        SYS_FUTEX => futex::handle_futex(context),
+        SYS_ARCH_PRCTL => process::handle_arch_prctl(context),
+        SYS_PERSONALITY => process::handle_personality(context),
+        SYS_GETRLIMIT => process::handle_getrlimit(context),
+        SYS_SETRLIMIT => process::handle_setrlimit(context),
+        SYS_GETPRIORITY => process::handle_getpriority(context),
+        SYS_SETPRIORITY => process::handle_setpriority(context),
+        SYS_SCHED_SETSCHEDULER => process::handle_sched_setscheduler(context),
+        SYS_SCHED_GETSCHEDULER => process::handle_sched_getscheduler(context),
+
+        SYS_SWAPON => memory::handle_swapon(context),
         SYS_DEBUG_PRINT => misc::handle_debug_print(context),
-        SYS_MOUNT => {
-            context.rax = 0; 
-        }
+        SYS_MOUNT => fs::handle_mount(context),
+        SYS_UMOUNT => fs::handle_umount(context),
+        SYS_FSYNC | SYS_FDATASYNC => fs::handle_fsync(context),
+        SYS_SYNC => fs::handle_sync(context),
+        SYS_LINK => fs::handle_link(context),
+        SYS_SYMLINK => fs::handle_symlink(context),
+        SYS_READLINK => fs::handle_readlink(context),
+        SYS_CHMOD | SYS_FCHMOD => fs::handle_chmod(context),
+        SYS_CHOWN | SYS_FCHOWN => fs::handle_chown(context),
+        SYS_UTIMES => fs::handle_utimes(context),
 
         _ => {
             debugln!("[Syscall] Unknown syscall #{}", syscall_num);
             context.rax = u64::MAX;
         }
     }
+
+    crate::interrupts::signal::deliver_on_syscall_return(context);
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -183,7 +255,12 @@ pub struct PollFd {
     pub revents: i16,
 }
 
+unsafe impl crate::memory::uaccess::Pod for PollFd {}
+
 pub const POLLIN: i16 = 0x001;
 pub const POLLOUT: i16 = 0x004;
 pub const POLLERR: i16 = 0x008;
+pub const POLLHUP: i16 = 0x010;
 pub const POLLNVAL: i16 = 0x020;
+/// Not in POSIX: `fd` is a window id, ready when it has events queued.
+pub const POLLWINDOW: i16 = 0x4000;
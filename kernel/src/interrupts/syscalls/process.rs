@@ -1,55 +1,33 @@
 use crate::interrupts::syscalls::fs::resolve_path;
+use crate::interrupts::signal;
 use crate::interrupts::task::CPUState;
 use crate::debugln;
 use crate::memory::paging;
 use alloc::string::String;
 use alloc::vec::Vec;
 
-pub fn spawn_process(path: &str, args: Option<&[&str]>, fd_inheritance: Option<&[(u8, u8)]>) -> Result<u64, String> {
-// ... (rest of spawn_process remains the same) ...
-
-    let cwd_str = {
-        let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        if tm.current_task >= 0 {
-            if let Some(thread) = tm.tasks[tm.current_task as usize].as_ref() {
-                let proc = thread.process.as_ref().expect("Thread has no process");
-                let cwd = proc.cwd.lock();
-                let cwd_len = cwd.iter().position(|&c| c == 0).unwrap_or(cwd.len());
-                String::from_utf8_lossy(&cwd[..cwd_len]).into_owned()
-            } else {
-                String::from("@0xE0/")
-            }
-        } else {
-            String::from("@0xE0/")
+pub fn current_cwd() -> String {
+    let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    if tm.current_task() >= 0 {
+        if let Some(thread) = tm.tasks[tm.current_task() as usize].as_ref() {
+            let proc = thread.process.as_ref().expect("Thread has no process");
+            let cwd = proc.cwd.lock();
+            let cwd_len = cwd.iter().position(|&c| c == 0).unwrap_or(cwd.len());
+            return String::from_utf8_lossy(&cwd[..cwd_len]).into_owned();
         }
-    };
-
-    let resolved = resolve_path(&cwd_str, path);
-
-    let path_parts: Vec<&str> = resolved.split('/').collect();
-    if path_parts.len() < 1 || !path_parts[0].starts_with('@') {
-        return Err(String::from("Invalid path format"));
     }
+    String::from("/")
+}
 
-    let disk_part = &path_parts[0][1..];
-    let disk_id = if disk_part.starts_with("0x") || disk_part.starts_with("0X") {
-        u8::from_str_radix(&disk_part[2..], 16).unwrap_or(0xFF)
-    } else {
-        disk_part.parse::<u8>().unwrap_or_else(|_| u8::from_str_radix(disk_part, 16).unwrap_or(0xFF))
-    };
-
-    let actual_path = if path_parts.len() > 1 { path_parts[1..].join("/") } else { String::from("") };
-
-    let process_name_str = if let Some(last_slash) = actual_path.rfind('/') {
-        &actual_path[last_slash + 1..]
-    } else {
-        &actual_path
-    };
-    let process_name_bytes = process_name_str.as_bytes();
-
+/// Resolves `path` against the caller's cwd and reads the whole executable.
+/// Returns the file contents and the basename used as the process name.
+fn read_executable(path: &str) -> Result<(Vec<u8>, String), String> {
+    let resolved = resolve_path(&current_cwd(), path).ok_or_else(|| String::from("Disk not mounted"))?;
+    let process_name = String::from(resolved.rsplit('/').next().unwrap_or(""));
 
     let mut file_buf = Vec::new();
-    if let Ok(mut node) = crate::fs::vfs::open(disk_id, &actual_path) {
+    if let Ok(node) = crate::fs::vfs::open(&resolved) {
+        let mut node = node.lock();
         let size = node.size();
         if size > 0 {
             file_buf.resize(size as usize, 0);
@@ -63,65 +41,96 @@ pub fn spawn_process(path: &str, args: Option<&[&str]>, fd_inheritance: Option<&
         return Err(String::from("File not found"));
     }
 
-    
-    let pid_idx = crate::interrupts::task::TASK_MANAGER.int_lock().reserve_pid().map_err(|_| String::from("No free process slots"))?;
-    let pid = pid_idx as u64;
+    Ok((file_buf, process_name))
+}
 
-    
-    
-    let (new_fd_table, term_size) = {
+pub fn spawn_process(path: &str, args: Option<&[&str]>, fd_inheritance: Option<&[(u8, u8)]>) -> Result<u64, String> {
+    let (file_buf, process_name) = read_executable(path)?;
+    let process_name_bytes = process_name.as_bytes();
+
+    let (pid_idx, pid) = {
+        let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+        let slot = tm.reserve_slot();
+        (slot, tm.tid_at(slot))
+    };
+
+    let (new_files, limits, term_size, personality, parent) = {
         let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        let mut fds = [-1i16; 16];
+        let mut files = crate::interrupts::task::FdTable::new();
+        let mut limits = crate::interrupts::rlimit::ResourceLimits::new();
         let mut size = (80u16, 25u16);
-        if tm.current_task >= 0 {
-            if let Some(thread) = tm.tasks[tm.current_task as usize].as_ref() {
+        let mut personality = 0;
+        let mut parent = 0;
+        if tm.current_task() >= 0 {
+            if let Some(thread) = tm.tasks[tm.current_task() as usize].as_ref() {
                 let proc = thread.process.as_ref().expect("Thread has no process");
-                fds = *proc.fd_table.lock();
+                limits = *proc.limits.lock();
                 size = (*proc.terminal_width.lock(), *proc.terminal_height.lock());
-
-                if let Some(map) = fd_inheritance {
-                    let mut custom_fds = [-1i16; 16];
-                    for &(child_fd, parent_fd) in map {
-                        if (parent_fd as usize) < 16 && (child_fd as usize) < 16 {
-                            custom_fds[child_fd as usize] = fds[parent_fd as usize];
+                personality = *proc.personality.lock();
+                parent = proc.pid;
+
+                let parent_files = proc.files.lock();
+                match fd_inheritance {
+                    Some(map) => {
+                        for &(child_fd, parent_fd) in map {
+                            if let Some(global) = parent_files.get(parent_fd as usize) {
+                                files.install(child_fd as usize, global, 0, crate::interrupts::rlimit::RLIM_INFINITY);
+                            }
                         }
                     }
-                    fds = custom_fds;
+                    None => files = parent_files.clone(),
                 }
             }
         }
-        (fds, size)
+        (files, limits, size, personality, parent)
     };
 
-    for &g_fd in new_fd_table.iter() {
-        if g_fd != -1 {
-            crate::fs::vfs::increment_ref(g_fd as usize);
-        }
+    for (_, global) in new_files.iter() {
+        crate::fs::vfs::increment_ref(global);
     }
 
     
     {
         let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
         
-        tm.init_user_task(pid_idx, 0, 0, args, Some(new_fd_table), process_name_bytes, term_size).map_err(|_| String::from("Failed to init task"))?;
+        tm.init_user_task(pid_idx, parent, 0, personality, args, Some(new_files), process_name_bytes, term_size).map_err(|_| String::from("Failed to init task"))?;
     }
 
     
-    let target_pml4_phys = {
+    let target_proc = {
         let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        tm.tasks[pid_idx].as_ref().unwrap().process.as_ref().unwrap().pml4_phys
+        tm.tasks[pid_idx].as_ref().unwrap().process.clone().unwrap()
     };
+    *target_proc.limits.lock() = limits;
+    let layout = *target_proc.layout.lock();
+
+    let mut image_vmas = crate::memory::vma::VmaList::new();
+    match crate::fs::elf::load_elf(&file_buf, target_proc.pml4_phys, pid, layout.image_base, &mut image_vmas) {
+        Ok(image) => {
+            let mut vmas = target_proc.vmas.int_lock();
+            vmas.extend(&image_vmas);
+            *target_proc.tls.lock() = image.tls;
+            let tls = match image.tls {
+                Some(template) => match crate::interrupts::tls::create_area(&template, target_proc.pml4_phys, pid, layout.mmap_base, &mut vmas) {
+                    Some(area) => area,
+                    None => {
+                        drop(vmas);
+                        crate::interrupts::task::TASK_MANAGER.int_lock().kill_process(pid);
+                        return Err(String::from("Failed to set up TLS"));
+                    }
+                },
+                None => crate::interrupts::tls::TlsArea::default(),
+            };
+            drop(vmas);
 
-    match crate::fs::elf::load_elf(&file_buf, target_pml4_phys, pid) {
-        Ok(entry_point) => {
-            
             let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
             let task = tm.tasks[pid_idx].as_mut().unwrap();
-            
+            task.tls = tls;
+            task.fs_base = tls.pointer;
             
             unsafe {
                 let cpu_state = &mut *(task.cpu_state_ptr as *mut crate::interrupts::task::CPUState);
-                cpu_state.rip = entry_point;
+                cpu_state.rip = image.entry;
             }
             
             Ok(pid)
@@ -138,44 +147,30 @@ pub fn handle_exit(context: &mut CPUState) {
     let exit_code = context.rdi;
     debugln!("[Syscall] Process exited with code {}", exit_code);
     {
-        use crate::window_manager::composer::COMPOSER;
-
         let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        let current = tm.current_task;
+        let current = tm.current_task();
         if current >= 0 {
-            if let Some(thread) = tm.tasks[current as usize].as_mut() {
+            let proc = tm.tasks[current as usize].as_mut().and_then(|thread| {
                 thread.exit_code = exit_code;
-                thread.state = crate::interrupts::task::TaskState::Zombie;
-
-                unsafe {
-                    (*(&raw mut COMPOSER)).remove_windows_by_pid(current as u64);
-                }
-
-                let proc = thread.process.as_ref().expect("Thread has no process");
-                let mut fd_table = proc.fd_table.lock();
-                for i in 0..16 {
-                    let global = fd_table[i];
-                    if global != -1 {
-                        crate::fs::vfs::close_file(global as usize);
-                        fd_table[i] = -1;
-                    }
-                }
+                thread.process.clone()
+            });
+            // Takes the other threads down too, wherever they run.
+            if let Some(proc) = proc {
+                tm.kill_process(proc.pid);
+                proc.close_all_fds();
             }
         }
     }
 
-    unsafe {
-        core::arch::asm!("sti");
-        loop { core::arch::asm!("hlt"); }
-    }
+    crate::interrupts::signal::halt_current();
 }
 
 pub fn handle_spawn(context: &mut CPUState) {
     let path_ptr = context.rdi as *const u8;
     let path_len = context.rsi as usize;
-    let args_ptr = context.rdx as *const *const u8;
+    let args_ptr = context.rdx;
     let args_len = context.r10 as usize;
-    let fd_map_ptr = context.r8 as *const (u8, u8);
+    let fd_map_ptr = context.r8;
     let fd_map_len = context.r9 as usize;
 
     if path_ptr.is_null() || path_len == 0 {
@@ -183,31 +178,46 @@ pub fn handle_spawn(context: &mut CPUState) {
         return;
     }
 
-    let path_slice = unsafe { core::slice::from_raw_parts(path_ptr, path_len) };
-    let path_str = String::from_utf8_lossy(path_slice);
-
-    
-    let mut args_vec = Vec::new();
-    if !args_ptr.is_null() && args_len > 0 {
-        let args_ptrs = unsafe { core::slice::from_raw_parts(args_ptr, args_len) };
-        for &ptr in args_ptrs {
-            if !ptr.is_null() {
-                let s = unsafe { core::ffi::CStr::from_ptr(ptr as *const i8).to_string_lossy() };
-                args_vec.push(s);
-            }
+    let path_str = match crate::interrupts::syscalls::fs::copy_string_from_user(path_ptr, path_len) {
+        Some(p) => p,
+        None => {
+            context.rax = u64::MAX;
+            return;
         }
+    };
+
+    if args_len > MAX_EXEC_STRINGS || fd_map_len > crate::interrupts::syscalls::fs::nofile_limit() {
+        context.rax = u64::MAX;
+        return;
     }
-    
-    let args_refs: Vec<&str> = args_vec.iter().map(|s| s.as_str()).collect();
-    let args_opt = if args_refs.is_empty() { None } else { Some(args_refs.as_slice()) };
 
-    let fd_map = if !fd_map_ptr.is_null() && fd_map_len > 0 {
-        unsafe { Some(core::slice::from_raw_parts(fd_map_ptr, fd_map_len)) }
+    let args_ptrs = if args_ptr != 0 && args_len > 0 {
+        crate::memory::uaccess::read_user_slice::<u64>(args_ptr, args_len)
     } else {
-        None
+        Some(Vec::new())
+    };
+    let args_vec: Option<Vec<String>> = args_ptrs.and_then(|ptrs| {
+        ptrs.into_iter().filter(|&p| p != 0).map(|p| {
+            crate::memory::uaccess::strncpy_from_user(p, MAX_EXEC_STRING_LEN).map(|s| String::from_utf8_lossy(&s).into_owned())
+        }).collect()
+    });
+    let fd_map = if fd_map_ptr != 0 && fd_map_len > 0 {
+        crate::memory::uaccess::read_user_slice::<(u8, u8)>(fd_map_ptr, fd_map_len).map(Some)
+    } else {
+        Some(None)
+    };
+    let (args_vec, fd_map) = match (args_vec, fd_map) {
+        (Some(args), Some(fd_map)) => (args, fd_map),
+        _ => {
+            context.rax = u64::MAX;
+            return;
+        }
     };
 
-    match spawn_process(&path_str, args_opt, fd_map) {
+    let args_refs: Vec<&str> = args_vec.iter().map(|s| s.as_str()).collect();
+    let args_opt = if args_refs.is_empty() { None } else { Some(args_refs.as_slice()) };
+
+    match spawn_process(&path_str, args_opt, fd_map.as_deref()) {
         Ok(pid) => context.rax = pid,
         Err(e) => {
             crate::debugln!("Spawn Error: {}", e);
@@ -216,144 +226,625 @@ pub fn handle_spawn(context: &mut CPUState) {
     }
 }
 
-pub fn handle_kill(context: &mut CPUState) {
-    let pid = context.rdi as u64;
-    let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-    tm.kill_process(pid);
-    context.rax = 0;
+/// Reads a NULL-terminated array of C string pointers, as passed to execve.
+fn copy_string_array_from_user(ptr: u64) -> Option<Vec<Vec<u8>>> {
+    let mut out = Vec::new();
+    if ptr == 0 {
+        return Some(out);
+    }
+
+    for i in 0..MAX_EXEC_STRINGS {
+        let s = crate::memory::uaccess::read_user::<u64>(ptr + (i * size_of::<u64>()) as u64)?;
+        if s == 0 {
+            break;
+        }
+        out.push(crate::memory::uaccess::strncpy_from_user(s, MAX_EXEC_STRING_LEN)?);
+    }
+    Some(out)
 }
 
-pub fn handle_wait_pid(context: &mut CPUState) {
-    let target_pid = context.rdi as usize;
-    if target_pid >= crate::interrupts::task::MAX_TASKS {
+const MAX_EXEC_STRINGS: usize = 256;
+/// Longest argument or environment string, as on Linux.
+const MAX_EXEC_STRING_LEN: usize = 128 * 1024;
+
+pub fn handle_execve(context: &mut CPUState) {
+    let path_ptr = context.rdi as *const u8;
+    let path_len = context.rsi as usize;
+    let argv_ptr = context.rdx;
+    let envp_ptr = context.r10;
+
+    if path_ptr.is_null() || path_len == 0 {
         context.rax = u64::MAX;
         return;
     }
 
-    let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-    let task_opt = &mut tm.tasks[target_pid];
+    let path_str = crate::interrupts::syscalls::fs::copy_string_from_user(path_ptr, path_len);
+    let argv = copy_string_array_from_user(argv_ptr);
+    let envp = copy_string_array_from_user(envp_ptr);
+    let (path_str, argv, envp) = match (path_str, argv, envp) {
+        (Some(path), Some(argv), Some(envp)) => (path, argv, envp),
+        _ => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
 
-    if let Some(task) = task_opt {
-        match task.state {
-            crate::interrupts::task::TaskState::Zombie => {
-                let exit_code = task.exit_code;
-                context.rax = exit_code;
+    let (file_buf, process_name) = match read_executable(&path_str) {
+        Ok(r) => r,
+        Err(e) => {
+            crate::debugln!("Execve Error: {}", e);
+            context.rax = u64::MAX;
+            return;
+        }
+    };
 
-                let pid = target_pid as u64;
-                let k_stack_top = task.kernel_stack;
+    match elfic::Elf64::new(&file_buf) {
+        Ok(elf) if elf.header.e_type == 3 => {}
+        _ => {
+            context.rax = u64::MAX;
+            return;
+        }
+    }
 
-                crate::memory::pmm::free_frames_by_pid(pid);
+    // Past this point the old image is gone, so failures end the process.
+    let (proc, pid, pml4_phys, layout) = {
+        use crate::interrupts::task::{TaskState, FD_CLOEXEC};
 
-                if k_stack_top != 0 {
-                    let k_stack_start = k_stack_top - (1024 * 1024 + paging::HHDM_OFFSET);
-                    crate::memory::pmm::free_frame(k_stack_start);
+        let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+        let current = tm.current_task() as usize;
+        let proc = tm.tasks[current].as_ref().and_then(|t| t.process.clone()).expect("Thread has no process");
+
+        for (i, slot) in tm.tasks.iter_mut().enumerate() {
+            if i == current { continue; }
+            if let Some(thread) = slot {
+                if thread.process.as_ref().map_or(false, |p| p.pid == proc.pid) {
+                    thread.state = TaskState::Zombie;
                 }
+            }
+        }
+
+        for global in proc.files.lock().take_matching(FD_CLOEXEC) {
+            crate::fs::vfs::close_file(global);
+        }
+
+        let layout = crate::memory::aslr::Layout::new(*proc.personality.lock());
+        *proc.layout.lock() = layout;
+        *proc.heap_end.lock() = layout.heap_start;
+        proc.signals.int_lock().reset_for_exec();
+        {
+            let mut vmas = proc.vmas.int_lock();
+            crate::memory::vma::sync_range(&vmas, proc.pml4_phys, 0, crate::memory::vma::USER_LIMIT);
+            vmas.clear();
+        }
+
+        let (pid, pml4_phys) = (proc.pid, proc.pml4_phys);
+        (proc, pid, pml4_phys, layout)
+    };
+
+    // Siblings still running on other CPUs leave at their next tick. Nobody
+    // waits for them, so once they are all off their slots are freed here. The
+    // main thread stays for the parent to reap if another thread is the one
+    // calling exec.
+    loop {
+        let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+        if tm.process_on_other_cpus(pid) {
+            drop(tm);
+            crate::interrupts::task::yield_now();
+            continue;
+        }
+        let current = tm.current_task() as usize;
+        let siblings: Vec<usize> = tm.tasks.iter().enumerate()
+            .filter(|&(i, t)| i != current && t.as_ref().map_or(false, |t| {
+                t.tid != pid
+                    && t.state == crate::interrupts::task::TaskState::Zombie
+                    && t.process.as_ref().map_or(false, |p| p.pid == pid)
+            }))
+            .map(|(i, _)| i)
+            .collect();
+        for slot in siblings {
+            let tid = tm.tid_at(slot);
+            tm.cancel_wait(slot);
+            // Only the kernel stack is charged to a thread's own tid.
+            crate::memory::pmm::free_frames_by_pid(tid);
+            tm.release_slot(slot);
+        }
+        break;
+    }
 
-                *task_opt = crate::interrupts::task::NULL_TASK;
+    unsafe {
+        (*(&raw mut crate::window_manager::composer::COMPOSER)).remove_windows_by_pid(pid);
+        crate::memory::vmm::unmap_user_space(pml4_phys);
+    }
+
+    let mut argv_refs: Vec<&[u8]> = argv.iter().map(|a| a.as_slice()).collect();
+    if argv_refs.is_empty() {
+        argv_refs.push(process_name.as_bytes());
+    }
+    let envp_refs: Vec<&[u8]> = envp.iter().map(|e| e.as_slice()).collect();
+
+    let mut image_vmas = crate::memory::vma::VmaList::new();
+    let stack = crate::interrupts::task::setup_user_stack(pml4_phys, pid, layout.stack_top, &mut image_vmas, &argv_refs, &envp_refs);
+    let entry = crate::fs::elf::load_elf(&file_buf, pml4_phys, pid, layout.image_base, &mut image_vmas);
+    let tls = {
+        let mut vmas = proc.vmas.int_lock();
+        vmas.extend(&image_vmas);
+        let template = entry.as_ref().ok().and_then(|image| image.tls);
+        *proc.tls.lock() = template;
+        match template {
+            Some(template) => crate::interrupts::tls::create_area(&template, pml4_phys, pid, layout.mmap_base, &mut vmas),
+            None => Some(crate::interrupts::tls::TlsArea::default()),
+        }
+    };
+
+    match (stack, entry, tls) {
+        (Ok((stack_top, user_sp)), Ok(image), Some(tls)) => {
+            {
+                let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+                let thread = tm.current_thread_mut();
+                thread.name = [0; 32];
+                let len = core::cmp::min(process_name.len(), 32);
+                thread.name[..len].copy_from_slice(&process_name.as_bytes()[..len]);
+                thread.user_stack = stack_top;
+                thread.tls = tls;
+                thread.fs_base = tls.pointer;
             }
-            crate::interrupts::task::TaskState::Null => {
-                context.rax = 0;
+            crate::interrupts::tls::set_fs_base(tls.pointer);
+
+            unsafe {
+                let mxcsr: u32 = 0x1F80;
+                core::arch::asm!("fninit", "ldmxcsr [{}]", in(reg) &mxcsr);
+                core::ptr::write_bytes(context as *mut CPUState, 0, 1);
             }
-            _ => {
+            context.rip = image.entry;
+            context.cs = 0x33;
+            context.rflags = 0x202;
+            context.rsp = user_sp;
+            context.ss = 0x23;
+        }
+        _ => {
+            crate::debugln!("Execve Error: failed to build new image for PID {}", pid);
+            context.rdi = 127;
+            handle_exit(context);
+        }
+    }
+}
+
+pub fn handle_fork(context: &mut CPUState) {
+    let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    let current = tm.current_task();
+
+    if current < 0 {
+        context.rax = u64::MAX;
+        return;
+    }
+
+    match tm.fork_process(current as usize, context) {
+        Ok(pid) => context.rax = pid as u64,
+        Err(_) => context.rax = u64::MAX,
+    }
+}
+
+pub fn handle_kill(context: &mut CPUState) {
+    let pid = context.rdi as u64;
+    let sig = context.rsi;
+    if sig != 0 && !signal::is_valid(sig) {
+        context.rax = u64::MAX;
+        return;
+    }
+
+    let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    context.rax = if signal::send_signal(&mut tm, pid, sig as u32) { 0 } else { u64::MAX };
+}
+
+fn current_process() -> Option<alloc::sync::Arc<crate::interrupts::task::Process>> {
+    let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    tm.current_task_idx().and_then(|idx| tm.tasks[idx].as_ref()).and_then(|t| t.process.clone())
+}
+
+pub fn handle_sigaction(context: &mut CPUState) {
+    let sig = context.rdi;
+    let act_ptr = context.rsi;
+    let old_ptr = context.rdx;
+
+    if !signal::is_valid(sig) || (act_ptr != 0 && (sig == signal::SIGKILL as u64 || sig == signal::SIGSTOP as u64)) {
+        context.rax = u64::MAX;
+        return;
+    }
+    let act = if act_ptr != 0 {
+        match crate::memory::uaccess::read_user::<signal::SigAction>(act_ptr) {
+            Some(a) => Some(a),
+            None => {
                 context.rax = u64::MAX;
+                return;
             }
         }
     } else {
-        context.rax = 0;
+        None
+    };
+    let proc = match current_process() {
+        Some(p) => p,
+        None => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+
+    let old = {
+        let mut state = proc.signals.int_lock();
+        let old = state.actions[sig as usize];
+        if let Some(act) = act {
+            state.actions[sig as usize] = act;
+            if act.handler == signal::SIG_IGN {
+                state.pending &= !signal::sig_bit(sig as u32);
+            }
+        }
+        old
+    };
+    context.rax = if old_ptr == 0 || crate::memory::uaccess::write_user(old_ptr, &old) { 0 } else { u64::MAX };
+}
+
+pub fn handle_sigprocmask(context: &mut CPUState) {
+    let how = context.rdi;
+    let set_ptr = context.rsi;
+    let old_ptr = context.rdx;
+
+    let set = if set_ptr != 0 {
+        match crate::memory::uaccess::read_user::<u32>(set_ptr) {
+            Some(set) => Some(set & !(signal::sig_bit(signal::SIGKILL) | signal::sig_bit(signal::SIGSTOP))),
+            None => {
+                context.rax = u64::MAX;
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    let proc = match current_process() {
+        Some(p) => p,
+        None => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+
+    let old = {
+        let mut state = proc.signals.int_lock();
+        let old = state.blocked;
+        if let Some(set) = set {
+            match how {
+                signal::SIG_BLOCK => state.blocked |= set,
+                signal::SIG_UNBLOCK => state.blocked &= !set,
+                signal::SIG_SETMASK => state.blocked = set,
+                _ => {
+                    context.rax = u64::MAX;
+                    return;
+                }
+            }
+        }
+        old
+    };
+    context.rax = if old_ptr == 0 || crate::memory::uaccess::write_user(old_ptr, &old) { 0 } else { u64::MAX };
+}
+
+pub fn handle_sigreturn(context: &mut CPUState) {
+    signal::sigreturn(context);
+}
+
+pub const WNOHANG: u64 = 1;
+
+/// rdi = pid, rsi = options. Sleeps until the target exits unless `WNOHANG` is set.
+pub fn handle_wait_pid(context: &mut CPUState) {
+    use crate::interrupts::task::TaskState;
+
+    let target_pid = context.rdi;
+    let options = context.rsi;
+
+    if (options & WNOHANG) == 0 {
+        crate::interrupts::wait::EXIT_WAIT.wait_until(0, |tm| {
+            tm.slot_of(target_pid).map_or(true, |slot| match &tm.tasks[slot] {
+                Some(t) if t.state == TaskState::Zombie => tm.reapable(slot),
+                Some(t) => t.state == TaskState::Null,
+                None => true,
+            })
+        });
+    }
+
+    let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    let slot = match tm.slot_of(target_pid) {
+        Some(s) => s,
+        None => {
+            context.rax = 0;
+            return;
+        }
+    };
+
+    let task = tm.tasks[slot].as_ref().unwrap();
+    match task.state {
+        // Still on its way off another CPU; its stack and pages are in use.
+        TaskState::Zombie if !tm.reapable(slot) => {
+            context.rax = u64::MAX;
+        }
+        TaskState::Zombie => {
+            context.rax = task.exit_code;
+            let k_stack_top = task.kernel_stack;
+
+            if let Some(proc) = task.process.as_ref() {
+                if proc.pid == target_pid {
+                    let mut vmas = proc.vmas.int_lock();
+                    crate::memory::vma::sync_range(&vmas, proc.pml4_phys, 0, crate::memory::vma::USER_LIMIT);
+                    vmas.clear();
+                    unsafe { crate::memory::vmm::release_shared_pages(proc.pml4_phys); }
+                }
+            }
+
+            crate::memory::pmm::free_frames_by_pid(target_pid);
+
+            if k_stack_top != 0 {
+                let k_stack_start = k_stack_top - (1024 * 1024 + paging::HHDM_OFFSET);
+                crate::memory::pmm::free_frame(k_stack_start);
+            }
+
+            tm.cancel_wait(slot);
+            tm.release_slot(slot);
+        }
+        TaskState::Null => {
+            context.rax = 0;
+        }
+        _ => {
+            context.rax = u64::MAX;
+        }
     }
 }
 
 pub fn handle_get_process_list(context: &mut CPUState) {
-    let buf_ptr = context.rdi as *mut u8;
+    let buf_ptr = context.rdi;
     let max_count = context.rsi as usize;
 
-    if buf_ptr.is_null() || max_count == 0 {
+    if buf_ptr == 0 || max_count == 0 {
         context.rax = 0;
         return;
     }
 
+    // Entries past `max_count` are counted but not written, so callers can retry with a bigger buffer.
     let mut count = 0;
-    let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-
-    let struct_size = 48;
+    let mut entries = Vec::new();
+    {
+        let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+        for task in tm.tasks.iter().flatten() {
+            if task.state == crate::interrupts::task::TaskState::Null {
+                continue;
+            }
+            count += 1;
+            if count > max_count {
+                continue;
+            }
 
-    for (i, task_opt) in tm.tasks.iter().enumerate() {
-        if let Some(task) = task_opt {
-            if task.state != crate::interrupts::task::TaskState::Null {
-                if count >= max_count {
-                    break;
-                }
+            let mut entry = [0u8; 80];
+            entry[0..8].copy_from_slice(&task.tid.to_ne_bytes());
+            let state: u64 = match task.state {
+                crate::interrupts::task::TaskState::Null => 0,
+                crate::interrupts::task::TaskState::Reserved => 1,
+                crate::interrupts::task::TaskState::Ready => 2,
+                crate::interrupts::task::TaskState::Zombie => 3,
+                crate::interrupts::task::TaskState::Sleeping => 4,
+                crate::interrupts::task::TaskState::Blocked => 4,
+                crate::interrupts::task::TaskState::Stopped => 5,
+                _ => 0,
+            };
+            entry[8..16].copy_from_slice(&state.to_ne_bytes());
+            entry[16..48].copy_from_slice(&task.name);
+            entry[48..56].copy_from_slice(&(task.user_ns / 1_000_000).to_ne_bytes());
+            entry[56..64].copy_from_slice(&(task.kernel_ns / 1_000_000).to_ne_bytes());
+            entry[64..72].copy_from_slice(&(task.sched_class as u64).to_ne_bytes());
+            entry[72..80].copy_from_slice(&(task.nice as i64).to_ne_bytes());
+            entries.extend_from_slice(&entry);
+        }
+    }
+    context.rax = if crate::memory::uaccess::copy_to_user(buf_ptr, &entries) { count as u64 } else { u64::MAX };
+}
 
-                let offset = count * struct_size;
-                unsafe {
-                    let ptr = buf_ptr.add(offset);
-                    *(ptr as *mut u64) = i as u64;
-                    *(ptr.add(8) as *mut u64) = match task.state {
-                        crate::interrupts::task::TaskState::Null => 0,
-                        crate::interrupts::task::TaskState::Reserved => 1,
-                        crate::interrupts::task::TaskState::Ready => 2,
-                        crate::interrupts::task::TaskState::Zombie => 3,
-                        crate::interrupts::task::TaskState::Sleeping => 4,
-                        _ => 0,
-                    };
-
-                    let name_ptr = ptr.add(16);
-                    core::ptr::copy_nonoverlapping(task.name.as_ptr(), name_ptr, 32);
-                }
-                count += 1;
-            }
+/// Applies `f` to every thread of process `pid` (0 for the caller's). Returns false if there is none.
+fn for_each_thread_of(tm: &mut crate::interrupts::task::TaskManager, pid: u64, mut f: impl FnMut(&mut crate::interrupts::task::Thread)) -> bool {
+    let pid = if pid == 0 { crate::interrupts::task::current_pid() } else { pid };
+    let mut found = false;
+    for task in tm.tasks.iter_mut().flatten() {
+        if task.process.as_ref().map_or(false, |p| p.pid == pid) && task.state != crate::interrupts::task::TaskState::Zombie {
+            f(task);
+            found = true;
         }
     }
-    context.rax = count as u64;
+    found
 }
 
-pub fn handle_sleep(context: &mut CPUState) {
-    let duration = context.rdi;
+/// Returns `20 - nice` of process `rdi`, so the result is never negative.
+pub fn handle_getpriority(context: &mut CPUState) {
     let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-    let current = tm.current_task;
+    let mut nice = None;
+    for_each_thread_of(&mut tm, context.rdi, |t| { nice.get_or_insert(t.nice); });
+    context.rax = match nice {
+        Some(n) => (20 - n as i64) as u64,
+        None => u64::MAX,
+    };
+}
+
+/// Whether the current process may change how process `pid` is scheduled:
+/// it must be the caller itself or one of its children, unless the caller is
+/// privileged.
+fn may_reschedule(tm: &crate::interrupts::task::TaskManager, caller: &crate::interrupts::task::Process, pid: u64) -> bool {
+    pid == 0 || pid == caller.pid || caller.privileged()
+        || tm.tasks.iter().flatten().filter_map(|t| t.process.as_ref()).any(|p| p.pid == pid && p.parent == caller.pid)
+}
 
-    if current >= 0 {
-        if let Some(task) = tm.tasks[current as usize].as_mut() {
-            task.wake_ticks = unsafe { crate::interrupts::task::SYSTEM_TICKS } + duration;
-            task.state = crate::interrupts::task::TaskState::Sleeping;
+/// Only privileged callers may lower a nice value.
+pub fn handle_setpriority(context: &mut CPUState) {
+    let nice = (context.rsi as i64).clamp(crate::interrupts::task::NICE_MIN as i64, crate::interrupts::task::NICE_MAX as i64) as i8;
+    let caller = match current_process() {
+        Some(p) => p,
+        None => {
+            context.rax = u64::MAX;
+            return;
         }
+    };
+    let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    let mut old = None;
+    for_each_thread_of(&mut tm, context.rdi, |t| { old.get_or_insert(t.nice); });
+    let allowed = may_reschedule(&tm, &caller, context.rdi)
+        && old.map_or(false, |old| nice >= old || caller.privileged());
+    context.rax = if allowed && for_each_thread_of(&mut tm, context.rdi, |t| t.nice = nice) { 0 } else { u64::MAX };
+}
+
+pub fn handle_sched_setscheduler(context: &mut CPUState) {
+    let class = match crate::interrupts::task::SchedClass::from_u64(context.rsi) {
+        Some(c) => c,
+        None => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+    let caller = match current_process() {
+        Some(p) => p,
+        None => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+    let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    let allowed = may_reschedule(&tm, &caller, context.rdi);
+    context.rax = if allowed && for_each_thread_of(&mut tm, context.rdi, |t| t.sched_class = class) { 0 } else { u64::MAX };
+}
+
+pub fn handle_sched_getscheduler(context: &mut CPUState) {
+    let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    let mut class = None;
+    for_each_thread_of(&mut tm, context.rdi, |t| { class.get_or_insert(t.sched_class); });
+    context.rax = class.map_or(u64::MAX, |c| c as u64);
+}
+
+/// rdi = resource, rsi = `RLimit` to fill in.
+pub fn handle_getrlimit(context: &mut CPUState) {
+    let limit = current_process().and_then(|proc| proc.limits.lock().get(context.rdi as usize));
+    match limit {
+        Some(limit) if crate::memory::uaccess::write_user(context.rsi, &limit) => context.rax = 0,
+        _ => context.rax = u64::MAX,
     }
 }
 
+/// rdi = resource, rsi = new `RLimit`.
+pub fn handle_setrlimit(context: &mut CPUState) {
+    let new = match crate::memory::uaccess::read_user::<crate::interrupts::rlimit::RLimit>(context.rsi) {
+        Some(new) => new,
+        None => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+    let ok = current_process().map_or(false, |proc| proc.limits.lock().set(context.rdi as usize, new));
+    context.rax = if ok { 0 } else { u64::MAX };
+}
+
+/// rdi = `Timespec` with the time to sleep.
+pub fn handle_sleep(context: &mut CPUState) {
+    let ns = match crate::memory::uaccess::read_user::<crate::time::Timespec>(context.rdi).and_then(|req| req.to_ns()) {
+        Some(ns) => ns,
+        None => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+    crate::interrupts::task::TASK_MANAGER.int_lock().sleep_current(crate::time::deadline_after(ns));
+    crate::interrupts::task::yield_now();
+}
+
 pub fn handle_spawn_thread(context: &mut CPUState) {
     let entry = context.rdi;
     let stack = context.rsi;
     let arg = context.rdx;
 
     let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-    let current = tm.current_task;
+    let current = tm.current_task();
 
     if current < 0 {
         context.rax = u64::MAX;
         return;
     }
 
+    let within_limit = tm.tasks[current as usize].as_ref().and_then(|t| t.process.as_ref()).map_or(false, |proc| {
+        (tm.live_threads_of(proc.pid) as u64) < proc.limits.lock().cur(crate::interrupts::rlimit::RLIMIT_NPROC)
+    });
+    if !within_limit {
+        context.rax = u64::MAX;
+        return;
+    }
+
     match tm.spawn_thread(current as usize, entry, stack, arg) {
         Ok(tid) => context.rax = tid as u64,
         Err(_) => context.rax = u64::MAX,
     }
 }
 
+/// rdi = `ARCH_SET_FS` with rsi the new FS base, or `ARCH_GET_FS` with rsi
+/// where to store the current one.
+pub fn handle_arch_prctl(context: &mut CPUState) {
+    use crate::interrupts::tls::{ARCH_GET_FS, ARCH_SET_FS};
+
+    let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    let thread = match tm.current_task_idx().and_then(|idx| tm.tasks[idx].as_mut()) {
+        Some(t) => t,
+        None => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+    match context.rdi {
+        ARCH_SET_FS if context.rsi < crate::memory::vma::USER_LIMIT => {
+            thread.fs_base = context.rsi;
+            crate::interrupts::tls::set_fs_base(context.rsi);
+        }
+        ARCH_GET_FS => {
+            let fs_base = thread.fs_base;
+            drop(tm);
+            if !crate::memory::uaccess::write_user(context.rsi, &fs_base) {
+                context.rax = u64::MAX;
+            }
+        }
+        _ => context.rax = u64::MAX,
+    }
+}
+
+/// rdi = new personality, or 0xFFFFFFFF to only query it. Returns the old one.
+/// `ADDR_NO_RANDOMIZE` takes effect at the next exec and is inherited by children.
+pub fn handle_personality(context: &mut CPUState) {
+    let proc = match current_process() {
+        Some(p) => p,
+        None => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
+    let mut personality = proc.personality.lock();
+    context.rax = *personality;
+    if context.rdi != 0xFFFF_FFFF {
+        *personality = context.rdi;
+    }
+}
+
 pub fn handle_thread_exit(context: &mut CPUState) {
     debugln!("[Syscall] Thread exited");
     {
         let mut tm = crate::interrupts::task::TASK_MANAGER.int_lock();
-        let current = tm.current_task;
+        let current = tm.current_task();
         if current >= 0 {
             if let Some(task) = tm.tasks[current as usize].as_mut() {
                 task.state = crate::interrupts::task::TaskState::Zombie;
                 task.exit_code = 0; 
+                let tls = core::mem::take(&mut task.tls);
+                if let Some(proc) = task.process.as_ref() {
+                    crate::interrupts::tls::free_area(&tls, proc.pml4_phys, &mut proc.vmas.int_lock());
+                }
             }
         }
+        tm.wake(crate::interrupts::wait::EXIT_WAIT.key(), usize::MAX);
     }
 
-    unsafe {
-        core::arch::asm!("sti");
-        loop { core::arch::asm!("hlt"); }
-    }
+    crate::interrupts::signal::halt_current();
 }
\ No newline at end of file
@@ -2,7 +2,7 @@ use crate::interrupts::task::CPUState;
 use crate::window_manager::composer::COMPOSER;
 use crate::window_manager::display::DISPLAY_SERVER;
 use crate::window_manager::input::MOUSE;
-use crate::window_manager::window::Window;
+use crate::window_manager::window::{Items, Window};
 
 #[derive(Debug, Clone, Copy)]
 struct Mapping {
@@ -12,10 +12,87 @@ struct Mapping {
 
 static mut WINDOW_MAPPINGS: [Mapping; 256] = [Mapping { user_addr: 0, kernel_addr: 0 }; 256];
 
+/// `Window` as user space lays it out, with its flags and type as plain
+/// integers so that any bytes the caller passes are a valid value.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UserWindow {
+    id: usize,
+    buffer: usize,
+    pid: u64,
+    x: isize,
+    y: isize,
+    z: usize,
+    width: usize,
+    height: usize,
+    can_move: u8,
+    can_resize: u8,
+    transparent: u8,
+    treat_as_transparent: u8,
+    min_width: usize,
+    min_height: usize,
+    event_handler: usize,
+    w_type: u32,
+}
+
+unsafe impl crate::memory::uaccess::Pod for UserWindow {}
+
+const _: () = assert!(size_of::<UserWindow>() == size_of::<Window>());
+
+impl UserWindow {
+    /// The `Window` this describes, or None if a flag or the type is out of range.
+    fn to_window(&self) -> Option<Window> {
+        let flag = |b: u8| match b {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        };
+        let w_type = match self.w_type {
+            0 => Items::Wallpaper,
+            1 => Items::Bar,
+            2 => Items::Popup,
+            3 => Items::Window,
+            4 => Items::Null,
+            _ => return None,
+        };
+        Some(Window {
+            id: self.id,
+            buffer: self.buffer,
+            pid: self.pid,
+            x: self.x,
+            y: self.y,
+            z: self.z,
+            width: self.width,
+            height: self.height,
+            can_move: flag(self.can_move)?,
+            can_resize: flag(self.can_resize)?,
+            transparent: flag(self.transparent)?,
+            treat_as_transparent: flag(self.treat_as_transparent)?,
+            min_width: self.min_width,
+            min_height: self.min_height,
+            event_handler: self.event_handler,
+            w_type,
+        })
+    }
+}
+
+/// Reads the caller's `Window` at `ptr`, if it is well formed and its pixel
+/// buffer is readable memory of the caller.
+fn read_window(ptr: u64) -> Option<Window> {
+    let w = crate::memory::uaccess::read_user::<UserWindow>(ptr)?.to_window()?;
+    let buffer_size = w.width.checked_mul(w.height)?.checked_mul(4)?;
+    crate::memory::uaccess::access_ok(w.buffer as u64, buffer_size, false).then_some(w)
+}
+
 pub fn handle_add_window(context: &mut CPUState) {
-    let window_ptr = context.rdi as *const Window;
+    let mut w = match read_window(context.rdi) {
+        Some(w) => w,
+        None => {
+            context.rax = u64::MAX;
+            return;
+        }
+    };
     unsafe {
-        let mut w = *window_ptr;
         let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
         if let Some(current) = tm.current_task_idx() {
             if let Some(thread) = tm.tasks[current].as_ref() {
@@ -55,9 +132,14 @@ pub fn handle_add_window(context: &mut CPUState) {
 }
 
 pub fn handle_update_window(context: &mut CPUState) {
-    let window_ptr = context.rdi as *const Window;
+    let w = match read_window(context.rdi) {
+        Some(w) => w,
+        None => {
+            context.rax = 0;
+            return;
+        }
+    };
     unsafe {
-        let w = *window_ptr;
         let composer = &mut *(&raw mut COMPOSER);
         
         // Lockless cache check for common cases (no dimension change)
@@ -136,25 +218,18 @@ pub fn handle_update_window_area(context: &mut CPUState) {
 
 pub fn handle_get_events(context: &mut CPUState) {
     let wid = context.rdi as u32;
-    let buf_ptr = context.rsi as *mut crate::window_manager::events::Event;
+    let buf_ptr = context.rsi;
     let max_events = context.rdx as usize;
 
-    unsafe {
-        use crate::window_manager::events::GLOBAL_EVENT_QUEUE;
-        let events = GLOBAL_EVENT_QUEUE.lock().get_and_remove_events(wid, max_events);
-
-        if !events.is_empty() {}
-
-        let user_slice = core::slice::from_raw_parts_mut(buf_ptr, max_events);
-        let mut count = 0;
-        for (i, evt) in events.into_iter().enumerate() {
-            if i < max_events {
-                user_slice[i] = evt;
-                count += 1;
-            }
-        }
-        context.rax = count as u64;
+    use crate::window_manager::events::{Event, GLOBAL_EVENT_QUEUE};
+    let fits = max_events.checked_mul(size_of::<Event>()).is_some_and(|len| crate::memory::uaccess::access_ok(buf_ptr, len, true));
+    if !fits {
+        context.rax = u64::MAX;
+        return;
     }
+    let mut events = GLOBAL_EVENT_QUEUE.lock().get_and_remove_events(wid, max_events);
+    events.truncate(max_events);
+    context.rax = if crate::memory::uaccess::write_user_slice(buf_ptr, &events) { events.len() as u64 } else { u64::MAX };
 }
 
 pub fn handle_get_width(context: &mut CPUState) {
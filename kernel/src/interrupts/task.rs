@@ -1,24 +1,118 @@
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 use alloc::sync::Arc;
+use alloc::collections::{BTreeMap, VecDeque};
 use crate::memory::{paging, pmm, vmm};
+use crate::memory::aslr::Layout;
 use crate::memory::address::PhysAddr;
+use crate::memory::vma::{Vma, VmaKind, VmaList};
 use core::arch::{asm, naked_asm};
 use crate::sync::Mutex;
+use crate::interrupts::rlimit::{ResourceLimits, RLIMIT_NOFILE};
+use crate::time::TimerQueue;
+use crate::fs::elf::TlsTemplate;
+use crate::interrupts::tls::{self, TlsArea};
 
-pub(crate) const MAX_THREADS: usize = 128;
-pub(crate) const MAX_PROCESSES: usize = 64;
+pub const FD_CLOEXEC: u8 = 1;
 const STACK_SIZE: u64 = 1024 * 1024;
 
 #[derive(Debug)]
 pub struct Process {
     pub pid: u64,
+    /// Pid of the process that started this one, 0 if the kernel did.
+    pub parent: u64,
     pub pml4_phys: u64,
-    pub fd_table: Mutex<[i16; 16]>,
+    pub files: Mutex<FdTable>,
+    pub limits: Mutex<ResourceLimits>,
     pub cwd: Mutex<[u8; 128]>,
     pub terminal_width: Mutex<u16>,
     pub terminal_height: Mutex<u16>,
-    pub heap_start: u64,
+    /// Placement of the current image, picked anew on every exec.
+    pub layout: Mutex<Layout>,
+    /// `personality` flags, kept across fork and exec.
+    pub personality: Mutex<u64>,
     pub heap_end: Mutex<u64>,
+    pub signals: Mutex<crate::interrupts::signal::SignalState>,
+    pub vmas: Mutex<VmaList>,
+    /// Image every new thread's TLS block starts from; None without `PT_TLS`.
+    pub tls: Mutex<Option<TlsTemplate>>,
+}
+
+/// Local descriptors of a process, each naming an open file description in
+/// `OPEN_FILES` and its `FD_*` flags. Grows on demand, bounded by `RLIMIT_NOFILE`.
+#[derive(Debug, Clone)]
+pub struct FdTable {
+    fds: Vec<Option<usize>>,
+    flags: Vec<u8>,
+}
+
+impl FdTable {
+    pub const fn new() -> Self {
+        Self { fds: Vec::new(), flags: Vec::new() }
+    }
+
+    /// Global fd behind `fd`, if it is open.
+    pub fn get(&self, fd: usize) -> Option<usize> {
+        self.fds.get(fd).copied().flatten()
+    }
+
+    pub fn flags(&self, fd: usize) -> u8 {
+        self.flags.get(fd).copied().unwrap_or(0)
+    }
+
+    pub fn set_flags(&mut self, fd: usize, flags: u8) {
+        if let Some(f) = self.flags.get_mut(fd) {
+            *f = flags;
+        }
+    }
+
+    /// Binds the lowest free descriptor below `limit` to `global`.
+    pub fn alloc(&mut self, global: usize, flags: u8, limit: u64) -> Option<usize> {
+        let fd = self.fds.iter().position(|g| g.is_none()).unwrap_or(self.fds.len());
+        if self.install(fd, global, flags, limit) { Some(fd) } else { None }
+    }
+
+    /// Binds `fd` to `global`, growing the table as needed. Whatever `fd`
+    /// held before is dropped without being closed.
+    pub fn install(&mut self, fd: usize, global: usize, flags: u8, limit: u64) -> bool {
+        if fd as u64 >= limit {
+            return false;
+        }
+        if fd >= self.fds.len() {
+            self.fds.resize(fd + 1, None);
+            self.flags.resize(fd + 1, 0);
+        }
+        self.fds[fd] = Some(global);
+        self.flags[fd] = flags;
+        true
+    }
+
+    /// Unbinds `fd` and returns the global fd it held, for the caller to close.
+    pub fn take(&mut self, fd: usize) -> Option<usize> {
+        let global = self.get(fd)?;
+        self.fds[fd] = None;
+        self.flags[fd] = 0;
+        while self.fds.last() == Some(&None) {
+            self.fds.pop();
+            self.flags.pop();
+        }
+        Some(global)
+    }
+
+    /// Unbinds every descriptor whose flags match `mask` (all of them for 0)
+    /// and returns the global fds they held.
+    pub fn take_matching(&mut self, mask: u8) -> Vec<usize> {
+        let fds: Vec<usize> = self.iter()
+            .filter(|&(fd, _)| mask == 0 || (self.flags[fd] & mask) != 0)
+            .map(|(fd, _)| fd)
+            .collect();
+        fds.into_iter().filter_map(|fd| self.take(fd)).collect()
+    }
+
+    /// Open descriptors as `(local, global)` pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.fds.iter().enumerate().filter_map(|(fd, &g)| Some((fd, g?)))
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -30,17 +124,73 @@ pub enum ThreadState {
     Sleeping,
     Blocked,
     Reserved,
+    Stopped,
+}
+
+/// Scheduling class. Higher classes always run before lower ones; a CPU's
+/// idle thread runs only when nothing else can.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u64)]
+pub enum SchedClass {
+    Idle = 0,
+    Normal = 1,
+    Interactive = 2,
+}
+
+impl SchedClass {
+    pub fn from_u64(v: u64) -> Option<Self> {
+        match v {
+            0 => Some(SchedClass::Idle),
+            1 => Some(SchedClass::Normal),
+            2 => Some(SchedClass::Interactive),
+            _ => None,
+        }
+    }
 }
 
+pub const NICE_MIN: i8 = -20;
+pub const NICE_MAX: i8 = 19;
+
+/// Load weight per nice level, -20 first; each step is roughly 10% of CPU.
+const NICE_WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906, 3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423, 335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45, 36, 29, 23, 18, 15,
+];
+const NICE_0_WEIGHT: u64 = 1024;
+/// Longest a thread runs before the scheduler looks again, in ns.
+const SLICE_NS: u64 = 10_000_000;
+/// How far behind the pack a thread waking from sleep may start, in us.
+const SLEEPER_CREDIT: u64 = 2 * SLICE_NS / 1000;
+
 #[repr(C, align(16))]
 pub struct Thread {
     pub fpu_state: [u8; 512],
+    /// Thread id, never reused. A process's pid is the tid of its first thread.
+    pub tid: u64,
     pub kernel_stack: u64,
     pub user_stack: u64,
     pub cpu_state_ptr: u64,
     pub state: ThreadState,
-    pub wake_ticks: u64,
+    /// Deadline in ns since boot while `Sleeping`, and while `Blocked` when non-zero.
+    pub wake_at: u64,
+    /// User FS base, the thread pointer for TLS. Loaded on every switch in.
+    pub fs_base: u64,
+    /// The thread's own TLS block, freed when it exits.
+    pub tls: TlsArea,
+    /// Wait queue key while `Blocked`; cleared by `TaskManager::wake`.
+    pub wait_key: u64,
+    pub sched_class: SchedClass,
+    pub nice: i8,
+    /// Weighted run time in us; the `Normal` thread with the least runs next.
+    pub vruntime: u64,
+    /// Time charged while running in ring 3 and ring 0, in ns.
+    pub user_ns: u64,
+    pub kernel_ns: u64,
     pub exit_code: u64,
+    /// CPU whose run queue holds the thread.
+    pub cpu: usize,
     pub name: [u8; 32],
     pub process: Option<Arc<Process>>,
 }
@@ -72,47 +222,112 @@ pub struct CPUState {
 }
 
 impl Process {
-    pub fn new(pid: u64, pml4_phys: u64) -> Arc<Self> {
+    pub fn new(pid: u64, parent: u64, pml4_phys: u64, personality: u64) -> Arc<Self> {
+        let layout = Layout::new(personality);
         let mut cwd = [0; 128];
-        let root = b"@0xE0/";
+        let root = b"/";
         cwd[..root.len()].copy_from_slice(root);
 
         Arc::new(Self {
             pid,
+            parent,
             pml4_phys,
-            fd_table: Mutex::new([-1; 16]),
+            files: Mutex::new(FdTable::new()),
+            limits: Mutex::new(ResourceLimits::new()),
             cwd: Mutex::new(cwd),
             terminal_width: Mutex::new(80),
             terminal_height: Mutex::new(25),
-            heap_start: 0x40000000,
-            heap_end: Mutex::new(0x40000000),
+            layout: Mutex::new(layout),
+            personality: Mutex::new(personality),
+            heap_end: Mutex::new(layout.heap_start),
+            signals: Mutex::new(crate::interrupts::signal::SignalState::new()),
+            vmas: Mutex::new(VmaList::new()),
+            tls: Mutex::new(None),
         })
     }
+
+    /// Started by the kernel rather than by another process. With no user ids
+    /// yet, these stand in for root.
+    pub fn privileged(&self) -> bool {
+        self.parent == 0
+    }
+
+    /// Global fd behind local descriptor `fd`.
+    pub fn fd(&self, fd: usize) -> Option<usize> {
+        self.files.lock().get(fd)
+    }
+
+    /// Binds the lowest free descriptor to `global`, within `RLIMIT_NOFILE`.
+    pub fn alloc_fd(&self, global: usize, flags: u8) -> Option<usize> {
+        let limit = self.limits.lock().cur(RLIMIT_NOFILE);
+        self.files.lock().alloc(global, flags, limit)
+    }
+
+    /// Closes every descriptor, as on exit.
+    pub fn close_all_fds(&self) {
+        for global in self.files.lock().take_matching(0) {
+            crate::fs::vfs::close_file(global);
+        }
+    }
 }
 
 // Compatibility aliases
 pub type Task = Thread;
 pub type TaskState = ThreadState;
-pub const MAX_TASKS: usize = MAX_THREADS;
-pub const NULL_TASK: Option<Thread> = None;
+
+/// Scheduling state of one CPU.
+#[derive(Debug, Clone, Copy)]
+pub struct RunQueue {
+    pub online: bool,
+    /// Slot of the thread running on this CPU.
+    pub current: isize,
+    /// Slot of this CPU's idle thread.
+    pub idle: usize,
+    /// Floor for `vruntime` of this CPU's runnable `Normal` threads; only grows.
+    pub min_vruntime: u64,
+    /// When the running thread was last charged for its time, in ns since boot.
+    pub since: u64,
+}
+
+impl RunQueue {
+    const OFFLINE: Self = Self { online: false, current: -1, idle: 0, min_vruntime: 0, since: 0 };
+}
 
 pub struct TaskManager {
-    pub current_task: isize,
     pub thread_count: usize,
-    pub tasks: [Option<Thread>; MAX_THREADS],
+    /// Thread slots. Grows when full; freed slots are reused, tids are not.
+    pub tasks: Vec<Option<Box<Thread>>>,
+    /// Slot of every live tid.
+    pub slots: BTreeMap<u64, usize>,
+    pub next_tid: u64,
+    /// Blocked thread slots in FIFO order, keyed by what they wait on.
+    pub wait_queues: BTreeMap<u64, VecDeque<usize>>,
+    /// Run queues, indexed by CPU id.
+    pub cpus: Vec<RunQueue>,
+    /// Deadlines of sleeping threads and of blocked ones with a timeout.
+    pub timers: TimerQueue,
 }
 
 pub static TASK_MANAGER: Mutex<TaskManager> = Mutex::new(TaskManager {
-    current_task: -1,
     thread_count: 0,
-    tasks: [const { None }; MAX_THREADS],
+    tasks: Vec::new(),
+    slots: BTreeMap::new(),
+    next_tid: 1,
+    wait_queues: BTreeMap::new(),
+    cpus: Vec::new(),
+    timers: TimerQueue::new(),
 });
 
-#[unsafe(no_mangle)]
-pub static mut KERNEL_STACK_PTR: u64 = 0;
+/// PID of the thread running on this CPU, readable from fault handlers
+/// that cannot take `TASK_MANAGER`.
+pub fn current_pid() -> u64 {
+    unsafe { (*crate::smp::this_cpu()).current_pid }
+}
 
-#[unsafe(no_mangle)]
-pub static mut SCRATCH: u64 = 0;
+/// Process of the thread running on this CPU, for the demand-paging fault path.
+pub fn current_process_ptr() -> *const Process {
+    unsafe { (*crate::smp::this_cpu()).current_process }
+}
 
 impl Thread {
     pub fn new(name: &[u8]) -> Self {
@@ -127,12 +342,22 @@ impl Thread {
 
         Self {
             fpu_state,
+            tid: 0,
             kernel_stack: 0,
             user_stack: 0,
             cpu_state_ptr: 0,
             state: ThreadState::Null,
-            wake_ticks: 0,
+            wake_at: 0,
+            fs_base: 0,
+            tls: TlsArea::default(),
+            wait_key: 0,
+            sched_class: SchedClass::Normal,
+            nice: 0,
+            vruntime: 0,
+            user_ns: 0,
+            kernel_ns: 0,
             exit_code: 0,
+            cpu: 0,
             name: t_name,
             process: None,
         }
@@ -148,10 +373,11 @@ impl TaskManager {
     pub fn init(&mut self) {
         let mut idle_thread = Thread::new(b"idle");
         idle_thread.state = ThreadState::Ready;
+        idle_thread.sched_class = SchedClass::Idle;
         
         unsafe {
             let kernel_pml4 = (*(&raw const crate::boot::BOOT_INFO)).pml4;
-            let kernel_proc = Process::new(0, kernel_pml4);
+            let kernel_proc = Process::new(0, 0, kernel_pml4, 0);
             idle_thread.process = Some(kernel_proc);
             
             let stack_pages = (STACK_SIZE / 4096) as usize;
@@ -168,41 +394,182 @@ impl TaskManager {
             (*state_ptr).rsp = idle_thread.kernel_stack;
             (*state_ptr).ss = 0x10;
 
-            self.tasks[0] = Some(idle_thread);
+            self.tasks.push(Some(Box::new(idle_thread)));
+            self.slots.insert(0, 0);
             self.thread_count = 1;
-            self.current_task = 0;
+            self.cpus = alloc::vec![RunQueue::OFFLINE; crate::smp::MAX_CPUS];
+            self.cpus[0] = RunQueue { online: true, current: 0, idle: 0, min_vruntime: 0, since: 0 };
+        }
+    }
+
+    /// Brings `cpu` into scheduling. The caller becomes its idle thread,
+    /// running on `stack`; its state is saved at the first switch.
+    pub fn add_cpu(&mut self, cpu: usize, stack: u64) {
+        let slot = self.reserve_slot();
+        let kernel_proc = self.tasks[0].as_ref().and_then(|t| t.process.clone());
+        let thread = self.tasks[slot].as_mut().unwrap();
+        thread.name = Thread::new(b"idle").name;
+        thread.state = ThreadState::Ready;
+        thread.sched_class = SchedClass::Idle;
+        thread.kernel_stack = stack;
+        thread.cpu = cpu;
+        thread.process = kernel_proc;
+        let min_vruntime = self.cpus[0].min_vruntime;
+        self.cpus[cpu] = RunQueue { online: true, current: slot as isize, idle: slot, min_vruntime, since: crate::time::now_ns() };
+    }
+
+    /// Slot of the thread running on the calling CPU.
+    pub fn current_task(&self) -> isize {
+        self.cpus.get(crate::smp::cpu_id()).map_or(-1, |rq| rq.current)
+    }
+
+    /// Whether the thread in `slot` is running on some CPU.
+    pub fn on_cpu(&self, slot: usize) -> bool {
+        self.cpus.iter().any(|rq| rq.online && rq.current == slot as isize)
+    }
+
+    fn is_idle_slot(&self, slot: usize) -> bool {
+        self.cpus.iter().any(|rq| rq.online && rq.idle == slot)
+    }
+
+    /// Whether a thread of process `pid` is running on a CPU other than the caller's.
+    pub fn process_on_other_cpus(&self, pid: u64) -> bool {
+        let me = crate::smp::cpu_id();
+        self.cpus.iter().enumerate()
+            .filter(|&(cpu, rq)| cpu != me && rq.online && rq.current >= 0)
+            .filter_map(|(_, rq)| self.tasks[rq.current as usize].as_ref())
+            .any(|t| t.process.as_ref().map_or(false, |p| p.pid == pid))
+    }
+
+    /// Whether the zombie in `slot` can be freed: it is off every CPU and, for
+    /// a main thread, whose reaping frees the address space, so is the rest of its process.
+    pub fn reapable(&self, slot: usize) -> bool {
+        let thread = match &self.tasks[slot] { Some(t) => t, None => return true };
+        if self.on_cpu(slot) {
+            return false;
+        }
+        match &thread.process {
+            Some(p) if p.pid == thread.tid => !self.process_on_other_cpus(p.pid),
+            _ => true,
+        }
+    }
+
+    /// Online CPU with the fewest threads, for a new thread.
+    fn pick_cpu(&self) -> usize {
+        let mut load = alloc::vec![0usize; self.cpus.len()];
+        for (i, thread) in self.tasks.iter().enumerate() {
+            if let Some(t) = thread {
+                if !matches!(t.state, ThreadState::Zombie | ThreadState::Reserved) && !self.is_idle_slot(i) {
+                    load[t.cpu] += 1;
+                }
+            }
         }
+        (0..self.cpus.len())
+            .filter(|&c| self.cpus[c].online)
+            .min_by_key(|&c| load[c])
+            .unwrap_or(0)
+    }
+
+    /// Slot holding thread `tid`.
+    pub fn slot_of(&self, tid: u64) -> Option<usize> {
+        self.slots.get(&tid).copied()
     }
 
     pub fn current_task_idx(&self) -> Option<usize> {
-        if self.current_task >= 0 {
-            Some(self.current_task as usize)
+        let current = self.current_task();
+        if current >= 0 {
+            Some(current as usize)
         } else {
             None
         }
     }
 
-    pub fn schedule(&mut self, cpu_state: *mut CPUState) -> (*mut CPUState, u64, u64) {
-        for i in 0..MAX_THREADS {
-            if let Some(thread) = &mut self.tasks[i] {
-                if thread.state == ThreadState::Sleeping && unsafe { SYSTEM_TICKS } >= thread.wake_ticks {
-                    thread.state = ThreadState::Ready;
-                }
+    /// Charges the running thread for the time since it was last charged.
+    pub fn account(&mut self, now: u64, user: bool) {
+        let cpu = crate::smp::cpu_id();
+        let elapsed = now.saturating_sub(core::mem::replace(&mut self.cpus[cpu].since, now));
+        let idx = match self.current_task_idx() { Some(i) => i, None => return };
+        if let Some(thread) = self.tasks[idx].as_mut() {
+            if user {
+                thread.user_ns += elapsed;
+            } else {
+                thread.kernel_ns += elapsed;
+            }
+            let weight = NICE_WEIGHTS[(thread.nice - NICE_MIN) as usize];
+            thread.vruntime += elapsed / 1000 * NICE_0_WEIGHT / weight;
+        }
+    }
+
+    /// Makes runnable every sleeper whose deadline has passed.
+    fn expire_timers(&mut self, now: u64) {
+        let mut woken = false;
+        while let Some((deadline, tid)) = self.timers.pop_expired(now) {
+            let thread = match self.slot_of(tid).and_then(|slot| self.tasks[slot].as_mut()) {
+                Some(t) => t,
+                None => continue,
+            };
+            if thread.wake_at == deadline && matches!(thread.state, ThreadState::Sleeping | ThreadState::Blocked) {
+                thread.state = ThreadState::Ready;
+                woken = true;
+            }
+        }
+        if woken {
+            self.kick_idle_cpus();
+        }
+    }
+
+    /// When `cpu` next needs its timer: at the end of the running thread's
+    /// slice or at the earliest deadline. None leaves an idle CPU halted until kicked.
+    pub fn next_event(&self, cpu: usize, now: u64) -> Option<u64> {
+        let rq = &self.cpus[cpu];
+        let slice = (rq.current >= 0 && rq.current as usize != rq.idle).then(|| now + SLICE_NS);
+        match (slice, self.timers.next()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Interrupts CPUs halted in their idle thread when a thread is waiting to
+    /// run, so one picks it up, from its own queue or by stealing.
+    pub fn kick_idle_cpus(&self) {
+        let waiting = (0..self.tasks.len()).any(|i| {
+            matches!(&self.tasks[i], Some(t) if t.state == ThreadState::Ready) && !self.on_cpu(i) && !self.is_idle_slot(i)
+        });
+        if !waiting {
+            return;
+        }
+        for (cpu, rq) in self.cpus.iter().enumerate() {
+            if rq.online && rq.current == rq.idle as isize {
+                crate::smp::kick(cpu);
             }
         }
+    }
+
+    pub fn schedule(&mut self, cpu_state: *mut CPUState, yielding: bool) -> (*mut CPUState, u64, u64) {
+        crate::interrupts::wait::run_deferred(self);
+        self.expire_timers(crate::time::now_ns());
 
-        if self.current_task >= 0 {
-            if let Some(thread) = &mut self.tasks[self.current_task as usize] {
+        let cpu = crate::smp::cpu_id();
+        let current = self.cpus[cpu].current;
+        let mut exited = false;
+        if current >= 0 {
+            if let Some(thread) = &mut self.tasks[current as usize] {
                 thread.cpu_state_ptr = cpu_state as u64;
+                exited = thread.state == ThreadState::Zombie;
             }
         }
+        // Reapers wait for a zombie to be off every CPU, not just marked.
+        if exited {
+            self.wake(crate::interrupts::wait::EXIT_WAIT.key(), usize::MAX);
+        }
 
-        self.current_task = self.get_next_thread();
-        if self.current_task < 0 {
+        let next = self.get_next_thread(cpu, yielding);
+        self.cpus[cpu].current = next;
+        if next < 0 {
             return (cpu_state, 0, 0);
         }
 
-        let thread = self.tasks[self.current_task as usize].as_ref().unwrap();
+        let thread = self.tasks[next as usize].as_ref().unwrap();
         let pml4 = if let Some(proc) = &thread.process {
             proc.pml4_phys
         } else {
@@ -216,36 +583,221 @@ impl TaskManager {
         )
     }
 
-    fn get_next_thread(&self) -> isize {
-        let mut i = (self.current_task + 1) as usize;
-        for _ in 0..MAX_THREADS {
-            if i >= MAX_THREADS { i = 0; }
+    /// Parks the current thread on `key` until `wake`, or until `deadline` (ns
+    /// since boot) when it is non-zero. The caller must yield afterwards.
+    pub fn block_current(&mut self, key: u64, deadline: u64) {
+        let idx = match self.current_task_idx() { Some(i) => i, None => return };
+        if let Some(thread) = self.tasks[idx].as_mut() {
+            // Killed from another CPU while in a syscall: stay dead.
+            if thread.state == ThreadState::Zombie {
+                return;
+            }
+            thread.state = ThreadState::Blocked;
+            thread.wait_key = key;
+            thread.wake_at = deadline;
+            let tid = thread.tid;
+            self.wait_queues.entry(key).or_default().push_back(idx);
+            if deadline != 0 {
+                self.timers.push(deadline, tid);
+            }
+        }
+    }
+
+    /// Puts the current thread to sleep until `deadline`, in ns since boot.
+    /// The caller must yield afterwards.
+    pub fn sleep_current(&mut self, deadline: u64) {
+        let idx = match self.current_task_idx() { Some(i) => i, None => return };
+        if let Some(thread) = self.tasks[idx].as_mut() {
+            if thread.state == ThreadState::Zombie {
+                return;
+            }
+            thread.state = ThreadState::Sleeping;
+            thread.wake_at = deadline;
+            let tid = thread.tid;
+            self.timers.push(deadline, tid);
+        }
+    }
+
+    /// Makes up to `count` threads blocked on `key` runnable. Returns how many woke.
+    pub fn wake(&mut self, key: u64, count: usize) -> usize {
+        let mut woken = 0;
+        if let Some(queue) = self.wait_queues.get_mut(&key) {
+            while woken < count {
+                let idx = match queue.pop_front() { Some(i) => i, None => break };
+                if let Some(thread) = self.tasks[idx].as_mut() {
+                    if thread.wait_key == key {
+                        thread.wait_key = 0;
+                        if thread.state == ThreadState::Blocked {
+                            thread.state = ThreadState::Ready;
+                        }
+                        woken += 1;
+                    }
+                }
+            }
+            if queue.is_empty() {
+                self.wait_queues.remove(&key);
+            }
+        }
+        if woken > 0 {
+            self.kick_idle_cpus();
+        }
+        woken
+    }
+
+    /// Takes thread `idx` off its wait queue after a timeout or signal.
+    /// Returns false if a `wake` got to it first.
+    pub fn cancel_wait(&mut self, idx: usize) -> bool {
+        let key = match self.tasks[idx].as_mut() {
+            Some(thread) if thread.wait_key != 0 => core::mem::replace(&mut thread.wait_key, 0),
+            _ => return false,
+        };
+        if let Some(queue) = self.wait_queues.get_mut(&key) {
+            queue.retain(|&i| i != idx);
+            if queue.is_empty() {
+                self.wait_queues.remove(&key);
+            }
+        }
+        true
+    }
+
+    /// Picks the next thread on `cpu`'s queue: interactive threads round-robin,
+    /// then the normal thread with the least `vruntime`, then idle-class threads
+    /// round-robin, then a thread stolen from another CPU.
+    /// A yielding thread gives way to any other runnable thread, whatever its
+    /// class, so polling loops in the interactive class cannot starve the rest.
+    fn get_next_thread(&mut self, cpu: usize, yielding: bool) -> isize {
+        let rq = self.cpus[cpu];
+        let skip = if yielding { rq.current } else { -1 };
+        let pick = self.next_round_robin(cpu, SchedClass::Interactive, skip)
+            .or_else(|| self.next_fair(cpu, skip))
+            .or_else(|| self.next_round_robin(cpu, SchedClass::Idle, skip))
+            .or_else(|| self.steal(cpu));
+        if let Some(i) = pick {
+            return i as isize;
+        }
+
+        if skip >= 0 && skip as usize != rq.idle {
+            if let Some(t) = &self.tasks[skip as usize] {
+                if t.state == ThreadState::Ready {
+                    return skip;
+                }
+            }
+        }
+        match &self.tasks[rq.idle] {
+            Some(t) if t.state == ThreadState::Ready => rq.idle as isize,
+            _ => -1,
+        }
+    }
+
+    fn next_round_robin(&self, cpu: usize, class: SchedClass, skip: isize) -> Option<usize> {
+        let rq = &self.cpus[cpu];
+        let len = self.tasks.len();
+        let mut i = (rq.current + 1) as usize;
+        for _ in 0..len {
+            if i >= len { i = 0; }
             if let Some(thread) = &self.tasks[i] {
-                if thread.state == ThreadState::Ready {
-                    return i as isize;
+                if i != rq.idle && i as isize != skip && thread.cpu == cpu
+                    && thread.state == ThreadState::Ready && thread.sched_class == class {
+                    return Some(i);
                 }
             }
             i += 1;
         }
-        -1
+        None
+    }
+
+    /// Moves a runnable thread that is not running anywhere onto `cpu`, keeping
+    /// its `vruntime` at the same distance from the floor of its new queue.
+    fn steal(&mut self, cpu: usize) -> Option<usize> {
+        let victim = (0..self.tasks.len()).find(|&i| {
+            matches!(&self.tasks[i], Some(t) if t.cpu != cpu && t.state == ThreadState::Ready)
+                && !self.on_cpu(i) && !self.is_idle_slot(i)
+        })?;
+        let floor = self.cpus[cpu].min_vruntime;
+        let thread = self.tasks[victim].as_mut().unwrap();
+        let old_floor = self.cpus[thread.cpu].min_vruntime;
+        thread.vruntime = thread.vruntime.saturating_sub(old_floor) + floor;
+        thread.cpu = cpu;
+        Some(victim)
+    }
+
+    fn next_fair(&mut self, cpu: usize, skip: isize) -> Option<usize> {
+        let floor = self.cpus[cpu].min_vruntime.saturating_sub(SLEEPER_CREDIT);
+        let mut best: Option<(usize, u64)> = None;
+
+        for i in 1..self.tasks.len() {
+            if let Some(thread) = self.tasks[i].as_mut() {
+                if thread.cpu != cpu || thread.state != ThreadState::Ready || thread.sched_class != SchedClass::Normal {
+                    continue;
+                }
+                if thread.vruntime < floor {
+                    thread.vruntime = floor;
+                }
+                if i as isize != skip && best.map_or(true, |(_, v)| thread.vruntime < v) {
+                    best = Some((i, thread.vruntime));
+                }
+            }
+        }
+
+        let (pick, v) = best?;
+        if v > self.cpus[cpu].min_vruntime {
+            self.cpus[cpu].min_vruntime = v;
+        }
+        Some(pick)
     }
 
-    pub fn reserve_pid(&mut self) -> Result<usize, pmm::FrameError> {
-        for i in 0..MAX_THREADS {
-            if self.tasks[i].is_none() {
-                let mut t = Thread::new(b"reserved");
-                t.state = ThreadState::Reserved;
-                self.tasks[i] = Some(t);
-                self.thread_count += 1;
-                return Ok(i);
+    /// Claims a slot with a fresh tid for a thread under construction.
+    /// Returns the slot; `fill_slot` or `release_slot` must follow.
+    pub fn reserve_slot(&mut self) -> usize {
+        let slot = match self.tasks.iter().skip(1).position(|t| t.is_none()) {
+            Some(i) => i + 1,
+            None => {
+                self.tasks.push(None);
+                self.tasks.len() - 1
             }
+        };
+        let mut t = Thread::new(b"reserved");
+        t.state = ThreadState::Reserved;
+        t.tid = self.next_tid;
+        self.next_tid += 1;
+        self.slots.insert(t.tid, slot);
+        self.tasks[slot] = Some(Box::new(t));
+        self.thread_count += 1;
+        slot
+    }
+
+    /// Tid of the thread in `slot`.
+    pub fn tid_at(&self, slot: usize) -> u64 {
+        self.tasks[slot].as_ref().map_or(0, |t| t.tid)
+    }
+
+    fn fill_slot(&mut self, slot: usize, mut thread: Thread) {
+        thread.tid = self.tid_at(slot);
+        self.tasks[slot] = Some(Box::new(thread));
+        self.kick_idle_cpus();
+    }
+
+    /// Frees `slot` for reuse. Its tid is retired.
+    pub fn release_slot(&mut self, slot: usize) {
+        if let Some(thread) = self.tasks[slot].take() {
+            self.slots.remove(&thread.tid);
+            self.thread_count -= 1;
         }
-        Err(pmm::FrameError::NoMemory)
+        while self.tasks.len() > 1 && self.tasks.last().map_or(false, |t| t.is_none()) {
+            self.tasks.pop();
+        }
+    }
+
+    /// Threads of process `pid` that have not exited.
+    pub fn live_threads_of(&self, pid: u64) -> usize {
+        self.tasks.iter().flatten()
+            .filter(|t| t.state != ThreadState::Zombie && t.process.as_ref().map_or(false, |p| p.pid == pid))
+            .count()
     }
 
     pub fn kill_process(&mut self, pid: u64) {
-        for i in 0..MAX_THREADS {
-            if let Some(thread) = &mut self.tasks[i] {
+        for slot in self.tasks.iter_mut() {
+            if let Some(thread) = slot {
                 if let Some(proc) = &thread.process {
                     if proc.pid == pid {
                         thread.state = ThreadState::Zombie;
@@ -256,17 +808,18 @@ impl TaskManager {
                 }
             }
         }
+        self.wake(crate::interrupts::wait::EXIT_WAIT.key(), usize::MAX);
     }
 
-    pub fn init_user_task(&mut self, slot: usize, entry_point: u64, _pml4: u64, args: Option<&[&str]>, fd_table: Option<[i16; 16]>, name: &[u8], terminal_size: (u16, u16)) -> Result<(), pmm::FrameError> {
-        let pid = slot as u64;
+    pub fn init_user_task(&mut self, slot: usize, parent: u64, entry_point: u64, personality: u64, args: Option<&[&str]>, files: Option<FdTable>, name: &[u8], terminal_size: (u16, u16)) -> Result<(), pmm::FrameError> {
+        let pid = self.tid_at(slot);
         let mut thread = Thread::new(name);
         
         let user_pml4 = unsafe { vmm::create_user_pml4().ok_or(pmm::FrameError::NoMemory)? };
-        let proc = Process::new(pid, user_pml4);
+        let proc = Process::new(pid, parent, user_pml4, personality);
         
-        if let Some(fds) = fd_table {
-            *proc.fd_table.lock() = fds;
+        if let Some(files) = files {
+            *proc.files.lock() = files;
         }
         *proc.terminal_width.lock() = terminal_size.0;
         *proc.terminal_height.lock() = terminal_size.1;
@@ -276,73 +829,42 @@ impl TaskManager {
         let k_frame = pmm::allocate_frames(16, pid).ok_or(pmm::FrameError::NoMemory)?;
         thread.kernel_stack = k_frame + 4096 * 16 + paging::HHDM_OFFSET;
 
-        let stack_pages = (STACK_SIZE / 4096) as usize;
-        let u_frame_phys = pmm::allocate_frames(stack_pages, pid).ok_or(pmm::FrameError::NoMemory)?;
-        let u_stack_virt = 0x0000_7FFF_FFFF_0000 - STACK_SIZE; 
-        
-        for i in 0..stack_pages {
-            let offset = i as u64 * 4096;
-            vmm::map_page(u_stack_virt + offset, PhysAddr::new(u_frame_phys + offset), 
-                          paging::PAGE_PRESENT | paging::PAGE_WRITABLE | paging::PAGE_USER, 
-                          Some(user_pml4));
+        let mut argv: Vec<&[u8]> = Vec::new();
+        argv.push(name);
+        if let Some(a_list) = args {
+            for &a in a_list {
+                argv.push(a.as_bytes());
+            }
         }
-        thread.user_stack = u_stack_virt + STACK_SIZE;
+        let (stack_top, user_sp) = {
+            let proc = thread.process.as_ref().unwrap();
+            let stack_top = proc.layout.lock().stack_top;
+            setup_user_stack(user_pml4, pid, stack_top, &mut proc.vmas.lock(), &argv, &[])?
+        };
+        thread.user_stack = stack_top;
 
         let state_size = core::mem::size_of::<CPUState>();
         let state_ptr = (thread.kernel_stack - state_size as u64) as *mut CPUState;
         thread.cpu_state_ptr = state_ptr as u64;
 
         unsafe {
-            let stack_phys_base = u_frame_phys + paging::HHDM_OFFSET;
-            let mut current_virt_sp = thread.user_stack;
-            
-            let mut arg_ptrs = Vec::new();
-            let mut push_str = |s: &[u8]| {
-                let len = s.len() + 1;
-                current_virt_sp -= len as u64;
-                let offset = current_virt_sp - u_stack_virt;
-                let dest = (stack_phys_base + offset) as *mut u8;
-                core::ptr::copy_nonoverlapping(s.as_ptr(), dest, s.len());
-                *dest.add(s.len()) = 0; 
-                current_virt_sp
-            };
-
-            arg_ptrs.push(push_str(name));
-            if let Some(a_list) = args {
-                for &a in a_list {
-                    arg_ptrs.push(push_str(a.as_bytes()));
-                }
-            }
-
-            current_virt_sp &= !15; 
-            let mut push_u64 = |val: u64| {
-                current_virt_sp -= 8;
-                let offset = current_virt_sp - u_stack_virt;
-                let dest = (stack_phys_base + offset) as *mut u64;
-                *dest = val;
-            };
-
-            push_u64(0); push_u64(0); 
-            for &ptr in arg_ptrs.iter().rev() { push_u64(ptr); }
-            push_u64(arg_ptrs.len() as u64); 
-
             (*state_ptr).rax = 0;
             (*state_ptr).rip = entry_point;
             (*state_ptr).cs = 0x33;
             (*state_ptr).rflags = 0x202;
-            (*state_ptr).rsp = current_virt_sp;
+            (*state_ptr).rsp = user_sp;
             (*state_ptr).ss = 0x23;
         }
 
         thread.state = ThreadState::Ready;
-        self.tasks[slot] = Some(thread);
+        thread.cpu = self.pick_cpu();
+        self.fill_slot(slot, thread);
         Ok(())
     }
 
-    pub fn spawn_thread(&mut self, parent_tid: usize, entry_point: u64, user_stack: u64, arg: u64) -> Result<usize, pmm::FrameError> {
-        let tid = self.reserve_pid()?;
-
-        let parent_process = if let Some(t) = &self.tasks[parent_tid] {
+    /// Starts a thread in the process of the thread in `parent_slot`. Returns its tid.
+    pub fn spawn_thread(&mut self, parent_slot: usize, entry_point: u64, user_stack: u64, arg: u64) -> Result<u64, pmm::FrameError> {
+        let parent_process = if let Some(t) = &self.tasks[parent_slot] {
             if let Some(p) = &t.process {
                 p.clone()
             } else {
@@ -354,8 +876,36 @@ impl TaskManager {
 
         let mut thread = Thread::new(b"thread");
         thread.process = Some(parent_process.clone());
+        if let Some(parent) = &self.tasks[parent_slot] {
+            thread.sched_class = parent.sched_class;
+            thread.nice = parent.nice;
+        }
 
-        let k_frame = pmm::allocate_frames(16, tid as u64).ok_or(pmm::FrameError::NoMemory)?;
+        let slot = self.reserve_slot();
+        let tid = self.tid_at(slot);
+        let template = *parent_process.tls.lock();
+        if let Some(template) = template {
+            let mmap_base = parent_process.layout.lock().mmap_base;
+            let area = tls::create_area(&template, parent_process.pml4_phys, parent_process.pid, mmap_base, &mut parent_process.vmas.int_lock());
+            match area {
+                Some(area) => {
+                    thread.tls = area;
+                    thread.fs_base = area.pointer;
+                }
+                None => {
+                    self.release_slot(slot);
+                    return Err(pmm::FrameError::NoMemory);
+                }
+            }
+        }
+        let k_frame = match pmm::allocate_frames(16, tid) {
+            Some(f) => f,
+            None => {
+                tls::free_area(&thread.tls, parent_process.pml4_phys, &mut parent_process.vmas.int_lock());
+                self.release_slot(slot);
+                return Err(pmm::FrameError::NoMemory);
+            }
+        };
         thread.kernel_stack = k_frame + 4096 * 16 + paging::HHDM_OFFSET;
 
         let state_size = core::mem::size_of::<CPUState>();
@@ -375,21 +925,212 @@ impl TaskManager {
         }
 
         thread.state = ThreadState::Ready;
-        self.tasks[tid] = Some(thread);
+        thread.cpu = self.pick_cpu();
+        self.fill_slot(slot, thread);
 
         Ok(tid)
     }
 
-    pub fn get_tasks(&self) -> &[Option<Thread>; MAX_THREADS] {
+    /// Starts a thread of the kernel process running `entry`. Returns its tid.
+    pub fn spawn_kernel_thread(&mut self, name: &[u8], entry: fn() -> !) -> Result<u64, pmm::FrameError> {
+        let mut thread = Thread::new(name);
+        thread.process = self.tasks[0].as_ref().and_then(|t| t.process.clone());
+
+        let slot = self.reserve_slot();
+        let tid = self.tid_at(slot);
+        let k_frame = match pmm::allocate_frames(16, 0) {
+            Some(f) => f,
+            None => {
+                self.release_slot(slot);
+                return Err(pmm::FrameError::NoMemory);
+            }
+        };
+        thread.kernel_stack = k_frame + 4096 * 16 + paging::HHDM_OFFSET;
+
+        let state_size = core::mem::size_of::<CPUState>();
+        let state_ptr = (thread.kernel_stack - state_size as u64) as *mut CPUState;
+        thread.cpu_state_ptr = state_ptr as u64;
+
+        unsafe {
+            core::ptr::write_bytes(state_ptr, 0, 1);
+            (*state_ptr).rip = entry as u64;
+            (*state_ptr).cs = 0x08;
+            // Interrupts off, as in a syscall: the thread runs until it sleeps.
+            (*state_ptr).rflags = 0x002;
+            (*state_ptr).rsp = thread.kernel_stack - 8;
+            (*state_ptr).ss = 0x10;
+        }
+
+        thread.state = ThreadState::Ready;
+        thread.cpu = self.pick_cpu();
+        self.fill_slot(slot, thread);
+
+        Ok(tid)
+    }
+
+    /// Copies the process of the thread in `parent_slot`. Returns the child's pid.
+    pub fn fork_process(&mut self, parent_slot: usize, context: &CPUState) -> Result<u64, pmm::FrameError> {
+        let (parent_process, name, user_stack, sched_class, nice, fs_base, tls) = match &self.tasks[parent_slot] {
+            Some(t) => match &t.process {
+                Some(p) => (p.clone(), t.name, t.user_stack, t.sched_class, t.nice, t.fs_base, t.tls),
+                None => return Err(pmm::FrameError::IndexOutOfBounds),
+            },
+            None => return Err(pmm::FrameError::IndexOutOfBounds),
+        };
+
+        let slot = self.reserve_slot();
+        let pid = self.tid_at(slot);
+
+        let child_pml4 = match unsafe { vmm::create_user_pml4() } {
+            Some(p) => p,
+            None => {
+                self.release_slot(slot);
+                return Err(pmm::FrameError::NoMemory);
+            }
+        };
+        // Taken before anything is shared with the parent, so failing here
+        // has nothing to undo but the table and the slot.
+        let k_frame = match pmm::allocate_frames(16, pid) {
+            Some(f) => f,
+            None => {
+                pmm::free_frame(child_pml4);
+                self.release_slot(slot);
+                return Err(pmm::FrameError::NoMemory);
+            }
+        };
+
+        let cloned = unsafe { vmm::clone_user_space(parent_process.pml4_phys, child_pml4, &parent_process.vmas.int_lock()) };
+        unsafe {
+            let cr3: u64;
+            asm!("mov {}, cr3", out(reg) cr3);
+            asm!("mov cr3, {}", in(reg) cr3);
+        }
+        // With the task manager held the OOM path cannot reclaim, so a low
+        // memory fork fails here instead of waiting for it.
+        if let Err(e) = cloned {
+            unsafe { vmm::unmap_user_space(child_pml4); }
+            pmm::free_frame(child_pml4);
+            pmm::free_frame(k_frame);
+            self.release_slot(slot);
+            return Err(e);
+        }
+
+        let proc = Arc::new(Process {
+            pid,
+            parent: parent_process.pid,
+            pml4_phys: child_pml4,
+            files: Mutex::new(parent_process.files.lock().clone()),
+            limits: Mutex::new(*parent_process.limits.lock()),
+            cwd: Mutex::new(*parent_process.cwd.lock()),
+            terminal_width: Mutex::new(*parent_process.terminal_width.lock()),
+            terminal_height: Mutex::new(*parent_process.terminal_height.lock()),
+            layout: Mutex::new(*parent_process.layout.lock()),
+            personality: Mutex::new(*parent_process.personality.lock()),
+            heap_end: Mutex::new(*parent_process.heap_end.lock()),
+            signals: Mutex::new(parent_process.signals.int_lock().forked()),
+            vmas: Mutex::new(parent_process.vmas.int_lock().clone()),
+            tls: Mutex::new(*parent_process.tls.lock()),
+        });
+
+        for (_, global) in proc.files.lock().iter() {
+            crate::fs::vfs::increment_ref(global);
+        }
+
+        let mut thread = Thread::new(&name);
+        thread.process = Some(proc);
+        thread.user_stack = user_stack;
+        thread.sched_class = sched_class;
+        thread.nice = nice;
+        thread.fs_base = fs_base;
+        thread.tls = tls;
+        unsafe {
+            let fpu_ptr = thread.fpu_state.as_mut_ptr();
+            asm!("fxsave [{}]", in(reg) fpu_ptr);
+        }
+
+        thread.kernel_stack = k_frame + 4096 * 16 + paging::HHDM_OFFSET;
+
+        let state_size = core::mem::size_of::<CPUState>();
+        let state_ptr = (thread.kernel_stack - state_size as u64) as *mut CPUState;
+        thread.cpu_state_ptr = state_ptr as u64;
+
+        unsafe {
+            *state_ptr = *context;
+            (*state_ptr).rax = 0;
+        }
+
+        thread.state = ThreadState::Ready;
+        thread.cpu = self.pick_cpu();
+        self.fill_slot(slot, thread);
+
+        Ok(pid)
+    }
+
+    pub fn get_tasks(&self) -> &[Option<Box<Thread>>] {
         &self.tasks
     }
 
     pub fn current_thread(&self) -> &Thread {
-        self.tasks[self.current_task as usize].as_ref().expect("No current thread")
+        self.tasks[self.current_task() as usize].as_ref().expect("No current thread")
     }
 
     pub fn current_thread_mut(&mut self) -> &mut Thread {
-        self.tasks[self.current_task as usize].as_mut().expect("No current thread")
+        let current = self.current_task();
+        self.tasks[current as usize].as_mut().expect("No current thread")
+    }
+}
+
+/// Maps a fresh user stack into `pml4` and lays out `argc`, `argv` and `envp`
+/// on it the way `_start` expects, below `stack_top`. Returns the stack top and the initial rsp.
+pub(crate) fn setup_user_stack(pml4: u64, pid: u64, stack_top: u64, vmas: &mut VmaList, argv: &[&[u8]], envp: &[&[u8]]) -> Result<(u64, u64), pmm::FrameError> {
+    let stack_pages = (STACK_SIZE / 4096) as usize;
+    let u_frame_phys = pmm::allocate_frames(stack_pages, pid).ok_or(pmm::FrameError::NoMemory)?;
+    let u_stack_virt = stack_top - STACK_SIZE;
+    vmas.insert(Vma::new(u_stack_virt, stack_top, paging::PAGE_WRITABLE, VmaKind::Stack));
+
+    for i in 0..stack_pages {
+        let offset = i as u64 * 4096;
+        vmm::map_page(u_stack_virt + offset, PhysAddr::new(u_frame_phys + offset),
+                      paging::PAGE_PRESENT | paging::PAGE_WRITABLE | paging::PAGE_USER,
+                      Some(pml4))?;
+    }
+
+    unsafe {
+        let stack_phys_base = u_frame_phys + paging::HHDM_OFFSET;
+        let mut current_virt_sp = stack_top;
+
+        let mut push_str = |s: &[u8]| {
+            let len = s.len() + 1;
+            current_virt_sp -= len as u64;
+            let offset = current_virt_sp - u_stack_virt;
+            let dest = (stack_phys_base + offset) as *mut u8;
+            core::ptr::copy_nonoverlapping(s.as_ptr(), dest, s.len());
+            *dest.add(s.len()) = 0;
+            current_virt_sp
+        };
+
+        let arg_ptrs: Vec<u64> = argv.iter().map(|a| push_str(a)).collect();
+        let env_ptrs: Vec<u64> = envp.iter().map(|e| push_str(e)).collect();
+
+        current_virt_sp &= !15;
+        if (arg_ptrs.len() + env_ptrs.len()) % 2 == 0 {
+            current_virt_sp -= 8;
+        }
+
+        let mut push_u64 = |val: u64| {
+            current_virt_sp -= 8;
+            let offset = current_virt_sp - u_stack_virt;
+            let dest = (stack_phys_base + offset) as *mut u64;
+            *dest = val;
+        };
+
+        push_u64(0);
+        for &ptr in env_ptrs.iter().rev() { push_u64(ptr); }
+        push_u64(0);
+        for &ptr in arg_ptrs.iter().rev() { push_u64(ptr); }
+        push_u64(arg_ptrs.len() as u64);
+
+        Ok((stack_top, current_virt_sp))
     }
 }
 
@@ -405,7 +1146,7 @@ pub extern "C" fn timer_handler() {
         naked_asm!(
             "push rbp", "push rax", "push rbx", "push rcx", "push rdx", "push rsi", "push rdi",
             "push r8", "push r9", "push r10", "push r11", "push r12", "push r13", "push r14", "push r15",
-            "mov rdi, rsp", "call switch_timer", "mov rsp, rax",
+            "mov rdi, rsp", "call switch_timer", "mov rsp, rax", "call finish_switch",
             "pop r15", "pop r14", "pop r13", "pop r12", "pop r11", "pop r10", "pop r9", "pop r8",
             "pop rdi", "pop rsi", "pop rdx", "pop rcx", "pop rbx", "pop rax", "pop rbp",
             "iretq",
@@ -419,7 +1160,7 @@ pub extern "C" fn yield_handler() {
         naked_asm!(
             "push rbp", "push rax", "push rbx", "push rcx", "push rdx", "push rsi", "push rdi",
             "push r8", "push r9", "push r10", "push r11", "push r12", "push r13", "push r14", "push r15",
-            "mov rdi, rsp", "call switch_yield", "mov rsp, rax",
+            "mov rdi, rsp", "call switch_yield", "mov rsp, rax", "call finish_switch",
             "pop r15", "pop r14", "pop r13", "pop r12", "pop r11", "pop r10", "pop r9", "pop r8",
             "pop rdi", "pop rsi", "pop rdx", "pop rcx", "pop rbx", "pop rax", "pop rbp",
             "iretq",
@@ -427,9 +1168,6 @@ pub extern "C" fn yield_handler() {
     }
 }
 
-#[unsafe(no_mangle)]
-pub static mut SYSTEM_TICKS: u64 = 0;
-
 #[unsafe(no_mangle)]
 pub extern "C" fn switch_timer(rsp: u64) -> u64 {
     unsafe { common_switch(rsp, true) }
@@ -440,48 +1178,82 @@ pub extern "C" fn switch_yield(rsp: u64) -> u64 {
     unsafe { common_switch(rsp, false) }
 }
 
+/// Drops the task manager lock `common_switch` kept across the stack switch,
+/// so no other CPU can pick the outgoing thread while its stack is in use.
+#[unsafe(no_mangle)]
+pub extern "C" fn finish_switch() {
+    unsafe { TASK_MANAGER.force_unlock(); }
+}
+
+/// Gives up the CPU from kernel code, letting other CPUs into the kernel meanwhile.
+pub fn yield_now() {
+    let depth = crate::sync::KERNEL_LOCK.release_all();
+    unsafe { asm!("int 0x81"); }
+    crate::sync::KERNEL_LOCK.reacquire(depth);
+}
+
 unsafe fn common_switch(rsp: u64, is_timer: bool) -> u64 {
     unsafe {
-        if is_timer {
-            SYSTEM_TICKS = SYSTEM_TICKS.wrapping_add(10);
-        }
+        let cpu = crate::smp::this_cpu();
         let mut tm = TASK_MANAGER.lock();
 
-        let current_task = tm.current_task;
+        let current_task = tm.current_task();
         if current_task >= 0 {
             if let Some(thread) = &mut tm.tasks[current_task as usize] {
                 let fpu_ptr = thread.fpu_state.as_mut_ptr();
                 asm!("fxsave [{}]", in(reg) fpu_ptr);
+                thread.fs_base = tls::fs_base();
             }
         }
 
-        let (new_state, k_stack, pml4_phys) = tm.schedule(rsp as *mut CPUState);
+        let now = crate::time::now_ns();
+        tm.account(now, ((*(rsp as *const CPUState)).cs & 3) == 3);
 
-        let current_task = tm.current_task;
+        let (new_state, k_stack, pml4_phys) = tm.schedule(rsp as *mut CPUState, !is_timer);
+
+        let current_task = tm.current_task();
         if current_task >= 0 {
             if let Some(thread) = &tm.tasks[current_task as usize] {
                 let fpu_ptr = thread.fpu_state.as_ptr();
                 asm!("fxrstor [{}]", in(reg) fpu_ptr);
+                tls::set_fs_base(thread.fs_base);
             }
         }
 
         if k_stack != 0 {
             crate::tss::set_tss(k_stack);
-            KERNEL_STACK_PTR = k_stack;
+            (*cpu).kernel_stack = k_stack;
+        }
+
+        if let Some(idx) = tm.current_task_idx() {
+            if let Some(proc) = tm.tasks[idx].as_ref().and_then(|t| t.process.as_ref()) {
+                (*cpu).current_pid = proc.pid;
+                (*cpu).current_process = Arc::as_ptr(proc);
+            }
         }
         
         if pml4_phys != 0 {
             let current_cr3: u64;
             asm!("mov {}, cr3", out(reg) current_cr3);
             if current_cr3 != pml4_phys {
+                // Published first, so a TLB shootdown racing with the switch still reaches us.
+                (*cpu).cr3.store(pml4_phys, core::sync::atomic::Ordering::SeqCst);
                 asm!("mov cr3, {}", in(reg) pml4_phys);
             }
         }
 
+        if ((*new_state).cs & 3) == 3 {
+            if let Some(proc) = tm.current_task_idx().and_then(|idx| tm.tasks[idx].as_ref()).and_then(|t| t.process.as_ref()) {
+                crate::interrupts::signal::deliver_on_resume(proc, &mut *new_state);
+            }
+        }
+
+        crate::interrupts::apic::arm_timer(tm.next_event((*cpu).id, now).map(|t| t.saturating_sub(now)));
         if is_timer {
-            (*(&raw const crate::interrupts::pic::PICS)).end_interrupt(crate::interrupts::exceptions::TIMER_INT);
+            crate::interrupts::apic::end_interrupt(crate::interrupts::exceptions::TIMER_INT);
         }
 
+        core::mem::forget(tm);
         new_state as u64
     }
 }
\ No newline at end of file
@@ -0,0 +1,74 @@
+use crate::fs::elf::TlsTemplate;
+use crate::memory::{paging, vma, vmm};
+use crate::memory::vma::{Vma, VmaKind, VmaList};
+
+pub const ARCH_SET_FS: u64 = 0x1002;
+pub const ARCH_GET_FS: u64 = 0x1003;
+
+const FS_BASE_MSR: u32 = 0xC000_0100;
+/// Thread control block at the thread pointer. Only its first word, the
+/// pointer to itself that `%fs:0` reads, is filled in.
+const TCB_SIZE: u64 = 64;
+
+/// A thread's TLS block and TCB, as mapped in its process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TlsArea {
+    pub start: u64,
+    pub end: u64,
+    /// Thread pointer, the value for FS_BASE.
+    pub pointer: u64,
+}
+
+/// HHDM address backing user address `virt` of `pml4`, faulting the page in first.
+fn user_hhdm(virt: u64, pml4: u64, pid: u64, vmas: &VmaList) -> Option<u64> {
+    unsafe {
+        if vmm::get_phys(virt, pml4).is_none() {
+            vma::populate(&vmas.find(virt)?, virt, pml4, pid);
+        }
+        vmm::get_phys(virt, pml4).map(|phys| phys + paging::HHDM_OFFSET)
+    }
+}
+
+/// Maps a TLS block for a new thread of `pid` and fills it from `template`:
+/// `.tdata` copied from the image, `.tbss` zeroed, then the TCB above it.
+pub fn create_area(template: &TlsTemplate, pml4: u64, pid: u64, mmap_base: u64, vmas: &mut VmaList) -> Option<TlsArea> {
+    let align = template.align.max(16);
+    let block = template.block_size();
+    let len = (block + align + TCB_SIZE + paging::PAGE_SIZE - 1) & !(paging::PAGE_SIZE - 1);
+    let start = vmas.find_free(mmap_base, len)?;
+    vmas.insert(Vma::new(start, start + len, paging::PAGE_WRITABLE, VmaKind::Anonymous));
+
+    let pointer = (start + block + align - 1) / align * align;
+    let mut copied = 0;
+    while copied < template.file_size {
+        let src = template.image + copied;
+        let dst = pointer - block + copied;
+        let chunk = (template.file_size - copied)
+            .min(paging::PAGE_SIZE - (src & 0xFFF))
+            .min(paging::PAGE_SIZE - (dst & 0xFFF));
+        let from = user_hhdm(src, pml4, pid, vmas)?;
+        let to = user_hhdm(dst, pml4, pid, vmas)?;
+        unsafe { core::ptr::copy_nonoverlapping(from as *const u8, to as *mut u8, chunk as usize); }
+        copied += chunk;
+    }
+
+    let tcb = user_hhdm(pointer, pml4, pid, vmas)?;
+    unsafe { *(tcb as *mut u64) = pointer; }
+    Some(TlsArea { start, end: start + len, pointer })
+}
+
+/// Unmaps an area made by `create_area`, when its thread is gone.
+pub fn free_area(area: &TlsArea, pml4: u64, vmas: &mut VmaList) {
+    if area.end > area.start {
+        unsafe { vmm::unmap_user_range(pml4, area.start, area.end); }
+        vmas.remove(area.start, area.end);
+    }
+}
+
+pub fn fs_base() -> u64 {
+    unsafe { crate::rdmsr(FS_BASE_MSR) }
+}
+
+pub fn set_fs_base(value: u64) {
+    unsafe { crate::wrmsr(FS_BASE_MSR, value) }
+}
@@ -0,0 +1,115 @@
+use crate::interrupts::task::{TaskManager, TASK_MANAGER, current_process_ptr};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+const MAX_DEFERRED: usize = 32;
+
+/// Wakeups raised while the task manager was locked (IRQs, or paths such as
+/// `close_file` that run under it). `schedule` drains them.
+static DEFERRED: [AtomicU64; MAX_DEFERRED] = [const { AtomicU64::new(0) }; MAX_DEFERRED];
+static DEFERRED_OVERFLOW: AtomicBool = AtomicBool::new(false);
+
+/// Woken by anything `poll` may be waiting for: pipe data, keyboard input and window events.
+pub static POLL_WAIT: WaitQueue = WaitQueue::new();
+/// Woken whenever a thread becomes a zombie.
+pub static EXIT_WAIT: WaitQueue = WaitQueue::new();
+
+/// A place threads sleep until some event happens. Its address is its key.
+pub struct WaitQueue {
+    _key: u8,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self { _key: 0 }
+    }
+
+    pub fn key(&self) -> u64 {
+        self as *const Self as u64
+    }
+
+    pub fn wake_one(&self) {
+        wake(self.key(), 1);
+    }
+
+    pub fn wake_all(&self) {
+        wake(self.key(), usize::MAX);
+    }
+
+    pub fn wait_until(&self, timeout_ms: u64, ready: impl FnMut(&mut TaskManager) -> bool) -> bool {
+        wait_until(self.key(), timeout_ms, ready)
+    }
+}
+
+fn signal_pending() -> bool {
+    let proc = current_process_ptr();
+    if proc.is_null() {
+        return false;
+    }
+    let state = unsafe { (*proc).signals.int_lock() };
+    (state.pending & !state.blocked) != 0
+}
+
+/// Sleeps on `key` until `ready` holds. `ready` is checked with the task
+/// manager locked and handed to it, so a waker that changes state before calling `wake` can't
+/// be missed. Returns false on timeout (`timeout_ms` of 0 waits forever) or
+/// when a signal arrives.
+pub fn wait_until(key: u64, timeout_ms: u64, mut ready: impl FnMut(&mut TaskManager) -> bool) -> bool {
+    let deadline = if timeout_ms != 0 { crate::time::deadline_after(timeout_ms.saturating_mul(1_000_000)) } else { 0 };
+    loop {
+        {
+            let mut tm = TASK_MANAGER.int_lock();
+            if ready(&mut tm) {
+                return true;
+            }
+            if (deadline != 0 && crate::time::now_ns() >= deadline) || signal_pending() {
+                return false;
+            }
+            tm.block_current(key, deadline);
+        }
+        finish_sleep();
+    }
+}
+
+/// Yields after `block_current`. Returns true if the thread was woken, false
+/// if it ran again because of a timeout or a signal.
+pub fn finish_sleep() -> bool {
+    crate::interrupts::task::yield_now();
+
+    let mut tm = TASK_MANAGER.int_lock();
+    match tm.current_task_idx() {
+        Some(idx) => !tm.cancel_wait(idx),
+        None => true,
+    }
+}
+
+/// Wakes up to `count` threads sleeping on `key`. Safe to call from IRQ
+/// handlers and with the task manager held by this CPU: the wakeup is deferred then.
+pub fn wake(key: u64, count: usize) {
+    if !TASK_MANAGER.held_by_this_cpu() {
+        TASK_MANAGER.int_lock().wake(key, count);
+        return;
+    }
+    for slot in DEFERRED.iter() {
+        if slot.load(Ordering::Relaxed) == key
+            || slot.compare_exchange(0, key, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+            return;
+        }
+    }
+    DEFERRED_OVERFLOW.store(true, Ordering::Release);
+}
+
+/// Delivers deferred wakeups. Deferred wakes always wake every waiter.
+pub fn run_deferred(tm: &mut TaskManager) {
+    if DEFERRED_OVERFLOW.swap(false, Ordering::AcqRel) {
+        let keys: alloc::vec::Vec<u64> = tm.wait_queues.keys().copied().collect();
+        for key in keys {
+            tm.wake(key, usize::MAX);
+        }
+    }
+    for slot in DEFERRED.iter() {
+        let key = slot.swap(0, Ordering::AcqRel);
+        if key != 0 {
+            tm.wake(key, usize::MAX);
+        }
+    }
+}
@@ -1,5 +1,4 @@
 #![feature(abi_x86_interrupt)]
-#![feature(str_as_str)]
 #![no_std]
 #![no_main]
 
@@ -12,13 +11,14 @@ mod drivers;
 mod fs;
 mod memory;
 mod tss;
+mod smp;
+mod time;
 pub mod debug;
 pub mod window_manager;
 pub mod sync;
 
 use crate::boot::{BootInfo, BOOT_INFO};
 use crate::fs::ext2::fs::Ext2;
-use crate::memory::pmm;
 use core::arch::asm;
 use window_manager::display::DISPLAY_SERVER;
 use crate::interrupts::gdt::reload_gdt_high_half;
@@ -28,8 +28,7 @@ const STAR_MSR: u32 = 0xC0000081;
 const LSTAR_MSR: u32 = 0xC0000082;
 const SFMASK_MSR: u32 = 0xC0000084;
 const PAT_MSR: u32 = 0x277;
-use crate::memory::paging::{phys_to_virt, active_level_4_table};
-use crate::memory::address::PhysAddr;
+use crate::memory::paging::active_level_4_table;
 
 #[unsafe(no_mangle)]
 #[unsafe(link_section = ".start")]
@@ -58,6 +57,8 @@ pub extern "C" fn _start(bootinfo_ptr: u64) -> ! {
         asm!("mov cr3, {}", in(reg) cr3);
     }
 
+    smp::init_bsp();
+
     debugln!("SIGNPOST: Initializing ISTs...");
     crate::tss::init_ists();
 
@@ -66,13 +67,8 @@ pub extern "C" fn _start(bootinfo_ptr: u64) -> ! {
 
     debugln!("SIGNPOST: Kernel fully initialized.");
 
-    let heap_size = 0xA0_0000;
-    let heap_pages = heap_size / 4096;
-    let heap_phys_addr = pmm::allocate_frames(heap_pages as usize, 0).expect("Failed to allocate heap memory from PMM");
-    let heap_virt_ptr = phys_to_virt(PhysAddr::new(heap_phys_addr)).as_mut_ptr::<u8>();
-    
-    
-    crate::memory::allocator::init_heap(heap_virt_ptr, heap_size as usize);
+    crate::memory::allocator::init_heap();
+    crate::memory::slab::init();
 
     debugln!("SIGNPOST: Heap initialized.");
 
@@ -83,21 +79,31 @@ pub extern "C" fn _start(bootinfo_ptr: u64) -> ! {
     window_manager::events::GLOBAL_EVENT_QUEUE.lock().init();
     interrupts::task::TASK_MANAGER.lock().init();
 
+    time::init();
+    init_irq_routing();
+
     unsafe { (*(&raw mut DISPLAY_SERVER)).init(); }
 
     debugln!("SIGNPOST: Drivers initialized.");
 
     drivers::periferics::mouse::init_mouse();
-    drivers::periferics::timer::init_pit(100);
 
     crate::debugln!("Mounting Ext2...");
     match Ext2::new(0xE0, 16384) {
-        Ok(fs) => crate::fs::vfs::mount(0xE0, fs),
+        Ok(fs) => crate::fs::vfs::mount("/", 0xE0, 16384, fs).expect("Root mount failed"),
         Err(e) => { crate::debugln!("Failed to mount Ext2: {}", e); loop {} }
     }
+    if let Err(e) = interrupts::task::TASK_MANAGER.lock().spawn_kernel_thread(b"flush", crate::fs::bcache::flusher) {
+        crate::debugln!("Failed to start the flusher: {:?}", e);
+    }
+    if let Ok(node) = crate::fs::vfs::open("/swapfile") {
+        if let Err(e) = crate::memory::swap::enable_file(node, 0) {
+            crate::debugln!("Failed to enable swapfile: {}", e);
+        }
+    }
 
     crate::debugln!("Spawning init process...");
-    match crate::interrupts::syscalls::spawn_process("@0xE0/user.elf", None, None) {
+    match crate::interrupts::syscalls::spawn_process("/user.elf", None, None) {
         Ok(pid) => crate::debugln!("Init process spawned with PID {}", pid),
         Err(e) => { crate::debugln!("Failed to spawn init: {}", e); loop {} }
     }
@@ -146,11 +152,37 @@ fn init_syscall_msrs() {
         let star_value = ((sysret_cs_base as u64) << 48) | ((syscall_cs_base as u64) << 32);
         wrmsr(STAR_MSR, star_value);
         wrmsr(LSTAR_MSR, interrupts::syscalls::syscall_entry as u64);
-        let rflags_mask = (1 << 9) | (1 << 8);
+        // IF, TF and AC, so user code cannot enter the kernel with SMAP lifted.
+        let rflags_mask = (1 << 18) | (1 << 9) | (1 << 8);
         wrmsr(SFMASK_MSR, rflags_mask);
     }
 }
 
+/// Routes device IRQs and starts the scheduler timer: through the I/O APIC and
+/// per-CPU one-shot APIC timers when the MADT lists them, else through the
+/// 8259s and a periodic PIT tick.
+fn init_irq_routing() {
+    let madt = match drivers::acpi::madt() {
+        Some(m) if !m.io_apics.is_empty() => m,
+        _ => {
+            crate::debugln!("No I/O APIC, using the 8259 PIC and PIT on the BSP only");
+            unsafe { (*(&raw const interrupts::pic::PICS)).unmask_legacy(); }
+            drivers::periferics::timer::init_pit(100);
+            return;
+        }
+    };
+
+    interrupts::apic::init(madt.lapic_addr);
+    interrupts::apic::enable();
+    interrupts::ioapic::init(&madt, interrupts::apic::id());
+    interrupts::ioapic::route_legacy_devices();
+    interrupts::apic::calibrate_timer();
+    interrupts::apic::start_timer(interrupts::exceptions::TIMER_INT);
+
+    debugln!("SIGNPOST: Starting application processors...");
+    smp::init(&madt);
+}
+
 pub fn load_idt() {
     unsafe {
         (*(&raw mut interrupts::idt::IDT)).init();
@@ -3,7 +3,7 @@ use core::ptr::write_bytes;
 use core::{
     alloc::{GlobalAlloc, Layout},
     ptr::NonNull,
-    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
 };
 
 const MAGIC_USED: u32 = 0xDEAD_BEEF;
@@ -117,9 +117,60 @@ fn align_up(addr: usize, align: usize) -> usize {
     (addr + align - 1) & !(align - 1)
 }
 
-unsafe fn grow_heap(_min_size: usize) -> bool {
-    // Kernel heap doesn't grow automatically via syscalls
-    false
+/// Start of the virtual range the kernel heap grows through. Its PML4 entry
+/// is made by `vmm::init`, so every address space shares the heap.
+pub const HEAP_BASE: u64 = 0xFFFF_C000_0000_0000;
+const HEAP_MAX: u64 = 4 << 30;
+const INITIAL_HEAP: u64 = 4 << 20;
+const GROW_STEP: u64 = 1 << 20;
+
+/// End of the mapped part of the heap range.
+static mut HEAP_END: u64 = HEAP_BASE;
+/// Bytes handed out, headers included, and how many allocations that is.
+static HEAP_USED: AtomicUsize = AtomicUsize::new(0);
+static HEAP_ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+/// Backs `[HEAP_END, HEAP_END + size)` with fresh frames. Returns how much
+/// got mapped before memory ran out.
+unsafe fn map_heap(size: u64) -> u64 {
+    unsafe {
+        let start = HEAP_END;
+        let size = size.min(HEAP_BASE + HEAP_MAX - start);
+        let mut mapped = 0;
+        while mapped < size {
+            let frame = match crate::memory::pmm::allocate_frame(0) {
+                Some(f) => f,
+                None => break,
+            };
+            if crate::memory::vmm::map_page(start + mapped, crate::memory::address::PhysAddr::new(frame),
+                crate::memory::paging::PAGE_PRESENT | crate::memory::paging::PAGE_WRITABLE | crate::memory::paging::PAGE_NO_EXECUTE, None).is_err() {
+                crate::memory::pmm::free_frame(frame);
+                break;
+            }
+            mapped += 4096;
+        }
+        HEAP_END += mapped;
+        mapped
+    }
+}
+
+/// Size of the kernel heap and what is in it, as `SYS_GET_HEAP_STATS` reports it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapStats {
+    pub heap_size: u64,
+    pub heap_used: u64,
+    pub allocations: u64,
+    pub caches: [crate::memory::slab::CacheStats; crate::memory::slab::CACHE_COUNT],
+}
+
+pub fn stats() -> HeapStats {
+    HeapStats {
+        heap_size: unsafe { HEAP_END - HEAP_BASE },
+        heap_used: HEAP_USED.load(Ordering::Relaxed) as u64,
+        allocations: HEAP_ALLOCS.load(Ordering::Relaxed) as u64,
+        caches: crate::memory::slab::stats(),
+    }
 }
 
 pub fn init(base: *mut u8, size: usize) {
@@ -220,6 +271,10 @@ unsafe impl GlobalAlloc for Allocator {
             return core::ptr::null_mut();
         }
 
+        if let Some(cache) = crate::memory::slab::cache_for(&layout) {
+            return crate::memory::slab::alloc(cache);
+        }
+
         self.lock();
 
         
@@ -240,6 +295,7 @@ unsafe impl GlobalAlloc for Allocator {
                     let used = bin_head as *mut Used;
                     (*used).magic = MAGIC_USED;
                     (*used).size = aligned_total - size_of::<Used>();
+                    account_alloc(aligned_total);
 
                     self.unlock();
                     return (used as *mut u8).add(size_of::<Used>());
@@ -291,6 +347,7 @@ unsafe impl GlobalAlloc for Allocator {
                         }
                     }
 
+                    account_alloc(size_of::<Used>() + alloc_layout.size());
                     self.unlock();
                     return payload_ptr;
                 }
@@ -299,9 +356,8 @@ unsafe impl GlobalAlloc for Allocator {
                 cur_ptr = (*cur_ptr).next;
             }
 
-            
-            let required = size_of::<Used>() + layout.size();
-            if !grow_heap(required) {
+            let required = size_of::<Used>() + size_of::<Free>() + layout.size() + layout.align();
+            if !self.grow(required) {
                 break;
             }
         }
@@ -310,11 +366,18 @@ unsafe impl GlobalAlloc for Allocator {
         core::ptr::null_mut()
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         if ptr.is_null() {
             return;
         }
 
+        if crate::memory::slab::owns(ptr) {
+            if let Some(cache) = crate::memory::slab::cache_for(&layout) {
+                crate::memory::slab::free(cache, ptr);
+            }
+            return;
+        }
+
         self.lock();
 
         if !in_heap_bounds(ptr as *const u8) {
@@ -337,6 +400,8 @@ unsafe impl GlobalAlloc for Allocator {
         (*hdr).magic = 0;
 
         let total_size = (*hdr).size + size_of::<Used>();
+        HEAP_USED.fetch_sub(total_size, Ordering::Relaxed);
+        HEAP_ALLOCS.fetch_sub(1, Ordering::Relaxed);
         let free_block = hdr as *mut Free;
         (*free_block).size = (*hdr).size;
 
@@ -348,55 +413,84 @@ unsafe impl GlobalAlloc for Allocator {
             return;
         }
 
-        (*free_block).next = core::ptr::null_mut();
+        self.insert_free(free_block);
+        self.unlock();
+    }
+}
 
-        let mut prev: *mut Free = core::ptr::null_mut();
-        let mut current = self.first_free.load(Ordering::Relaxed);
+fn account_alloc(size: usize) {
+    HEAP_USED.fetch_add(size, Ordering::Relaxed);
+    HEAP_ALLOCS.fetch_add(1, Ordering::Relaxed);
+}
 
-        
-        
-        
-        
-        
+impl Allocator {
+    /// Puts a block on the address-ordered free list, merging it with its neighbours.
+    unsafe fn insert_free(&self, free_block: *mut Free) {
+        unsafe {
+            (*free_block).next = core::ptr::null_mut();
 
-        while !current.is_null() && current < free_block {
-            prev = current;
-            current = (*current).next;
-        }
+            let mut prev: *mut Free = core::ptr::null_mut();
+            let mut current = self.first_free.load(Ordering::Relaxed);
 
-        (*free_block).next = current;
-        if prev.is_null() {
-            self.first_free.store(free_block, Ordering::Relaxed);
-        } else {
-            (*prev).next = free_block;
-        }
+            while !current.is_null() && current < free_block {
+                prev = current;
+                current = (*current).next;
+            }
 
-        
-        if !(*free_block).next.is_null() {
-            let next_block = (*free_block).next;
-            let free_end = (*free_block).end();
-            if free_end == next_block as *mut u8 {
-                (*free_block).size += (*next_block).size + size_of::<Free>();
-                (*free_block).next = (*next_block).next;
+            (*free_block).next = current;
+            if prev.is_null() {
+                self.first_free.store(free_block, Ordering::Relaxed);
+            } else {
+                (*prev).next = free_block;
             }
-        }
 
-        
-        if !prev.is_null() {
-            let prev_end = (*prev).end();
-            if prev_end == free_block as *mut u8 {
-                (*prev).size += (*free_block).size + size_of::<Free>();
-                (*prev).next = (*free_block).next;
+            if !(*free_block).next.is_null() {
+                let next_block = (*free_block).next;
+                let free_end = (*free_block).end();
+                if free_end == next_block as *mut u8 {
+                    (*free_block).size += (*next_block).size + size_of::<Free>();
+                    (*free_block).next = (*next_block).next;
+                }
+            }
+
+            if !prev.is_null() {
+                let prev_end = (*prev).end();
+                if prev_end == free_block as *mut u8 {
+                    (*prev).size += (*free_block).size + size_of::<Free>();
+                    (*prev).next = (*free_block).next;
+                }
             }
         }
+    }
 
-        self.unlock();
+    /// Maps at least `min_size` more bytes onto the end of the heap. Called with the lock held.
+    unsafe fn grow(&self, min_size: usize) -> bool {
+        unsafe {
+            let start = HEAP_END;
+            let size = align_up(min_size, GROW_STEP as usize) as u64;
+            let mapped = map_heap(size);
+            if mapped < size_of::<Free>() as u64 * 2 {
+                return false;
+            }
+
+            let last = HEAP_REGION_COUNT - 1;
+            HEAP_REGIONS[last].end = HEAP_END as usize;
+
+            let block = start as *mut Free;
+            (*block).size = mapped as usize - size_of::<Free>();
+            self.insert_free(block);
+            true
+        }
     }
 }
 
 #[global_allocator]
 static ALLOCATOR: Allocator = Allocator::new();
 
-pub fn init_heap(base: *mut u8, size: usize) {
-    init(base, size);
+/// Maps the first part of the heap range and hands it to the allocator.
+pub fn init_heap() {
+    unsafe {
+        let size = map_heap(INITIAL_HEAP);
+        init(HEAP_BASE as *mut u8, size as usize);
+    }
 }
@@ -0,0 +1,80 @@
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::memory::{paging, vma};
+
+/// `personality` flag that gives the process and its children fixed layouts.
+pub const ADDR_NO_RANDOMIZE: u64 = 0x0040000;
+
+const IMAGE_BASE: u64 = 0x40_0000;
+const HEAP_BASE: u64 = 0x4000_0000;
+pub const STACK_TOP: u64 = 0x0000_7FFF_FFFF_0000;
+
+/// How far each region may slide up (down for the stack), in pages.
+const IMAGE_SLIDE: u64 = 1 << 16;
+const HEAP_SLIDE: u64 = 1 << 16;
+const MMAP_SLIDE: u64 = 1 << 28;
+const STACK_SLIDE: u64 = 1 << 14;
+
+/// Where the image, heap, `mmap` area and stack of a process image start.
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    /// Load bias for the PIE image.
+    pub image_base: u64,
+    pub heap_start: u64,
+    /// Lowest address `mmap` hands out without a hint.
+    pub mmap_base: u64,
+    pub stack_top: u64,
+}
+
+impl Layout {
+    pub const FIXED: Self = Self {
+        image_base: IMAGE_BASE,
+        heap_start: HEAP_BASE,
+        mmap_base: vma::MMAP_BASE,
+        stack_top: STACK_TOP,
+    };
+
+    /// Layout for a new image of a process with `personality`.
+    pub fn new(personality: u64) -> Self {
+        if (personality & ADDR_NO_RANDOMIZE) != 0 {
+            return Self::FIXED;
+        }
+        let slide = |pages: u64| (random() % pages) * paging::PAGE_SIZE;
+        Self {
+            image_base: IMAGE_BASE + slide(IMAGE_SLIDE),
+            heap_start: HEAP_BASE + slide(HEAP_SLIDE),
+            mmap_base: vma::MMAP_BASE + slide(MMAP_SLIDE),
+            stack_top: STACK_TOP - slide(STACK_SLIDE),
+        }
+    }
+}
+
+static MIX: AtomicU64 = AtomicU64::new(0x9E37_79B9_7F4A_7C15);
+
+fn rdrand() -> Option<u64> {
+    if (unsafe { __cpuid(1) }.ecx & (1 << 30)) == 0 {
+        return None;
+    }
+    for _ in 0..10 {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            core::arch::asm!("rdrand {}", "setc {}", out(reg) value, out(reg_byte) ok, options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// 64 random bits from RDRAND, or from TSC jitter stirred into a running
+/// state on CPUs without it. Not for cryptographic use.
+pub fn random() -> u64 {
+    let seed = rdrand().unwrap_or_else(|| unsafe { core::arch::x86_64::_rdtsc() });
+    // splitmix64 over the seed and everything drawn before.
+    let mut z = MIX.fetch_add(seed | 1, Ordering::Relaxed).wrapping_add(seed);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,102 @@
+use crate::fs::vfs::InodeRef;
+use crate::memory::{oom, paging, pmm};
+use crate::sync::Mutex;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Pages of a mapped file, shared by every `MAP_SHARED` mapping of it so
+/// that writers and readers see the same memory. Each mapped page holds a
+/// shared-frame reference on top of the one kept here.
+struct MappedFile {
+    node: InodeRef,
+    /// Frame of each page index read in so far.
+    pages: BTreeMap<u64, u64>,
+    /// Live VMAs backed by this inode.
+    maps: usize,
+}
+
+static MAPPED_FILES: Mutex<Vec<MappedFile>> = Mutex::new(Vec::new());
+
+fn find<'a>(files: &'a mut Vec<MappedFile>, node: &InodeRef) -> Option<&'a mut MappedFile> {
+    files.iter_mut().find(|f| Arc::ptr_eq(&f.node, node))
+}
+
+fn destroy(file: MappedFile) {
+    for frame in file.pages.into_values() {
+        if pmm::is_shared_frame(frame) {
+            pmm::release_shared_frame(frame);
+        } else {
+            pmm::free_frame(frame);
+        }
+    }
+}
+
+pub fn retain(node: &InodeRef) {
+    let mut files = MAPPED_FILES.int_lock();
+    match find(&mut files, node) {
+        Some(file) => file.maps += 1,
+        None => files.push(MappedFile { node: node.clone(), pages: BTreeMap::new(), maps: 1 }),
+    }
+}
+
+/// Drops one mapping of `node`, giving its pages back with the last one.
+pub fn release(node: &InodeRef) {
+    let mut files = MAPPED_FILES.int_lock();
+    let idx = match files.iter().position(|f| Arc::ptr_eq(&f.node, node)) {
+        Some(idx) => idx,
+        None => return,
+    };
+    files[idx].maps -= 1;
+    if files[idx].maps == 0 {
+        let file = files.swap_remove(idx);
+        drop(files);
+        destroy(file);
+    }
+}
+
+/// Frame holding the page of `node` at byte `pos`, read in on first use,
+/// with a new shared reference taken for the caller to map. Bytes past end
+/// of file stay zero.
+pub fn share_page(node: &InodeRef, pos: u64, pid: u64) -> Option<u64> {
+    let index = pos / paging::PAGE_SIZE;
+    {
+        let mut files = MAPPED_FILES.int_lock();
+        if let Some(&frame) = find(&mut files, node)?.pages.get(&index) {
+            pmm::share_frame(frame);
+            return Some(frame);
+        }
+    }
+
+    let frame = oom::allocate_frame(pid)?;
+    let buf = unsafe {
+        let ptr = (frame + paging::HHDM_OFFSET) as *mut u8;
+        core::ptr::write_bytes(ptr, 0, paging::PAGE_SIZE as usize);
+        core::slice::from_raw_parts_mut(ptr, paging::PAGE_SIZE as usize)
+    };
+    let start = index * paging::PAGE_SIZE;
+    {
+        let mut node = node.lock();
+        if start < node.size() && node.read(start, buf).is_err() {
+            pmm::free_frame(frame);
+            return None;
+        }
+    }
+
+    // Another mapper may have read the page in meanwhile.
+    let mut files = MAPPED_FILES.int_lock();
+    let cached = match find(&mut files, node) {
+        Some(file) => *file.pages.entry(index).or_insert(frame),
+        None => {
+            drop(files);
+            pmm::free_frame(frame);
+            return None;
+        }
+    };
+    pmm::share_frame(cached);
+    drop(files);
+    if cached != frame {
+        pmm::free_frame(frame);
+    }
+    Some(cached)
+}
@@ -1,10 +1,18 @@
 pub mod pmm;
 pub mod vmm;
+pub mod vma;
+pub mod shm;
+pub mod filemap;
 pub mod paging;
 pub mod address;
 pub mod mapper;
 pub mod mmio;
 pub mod allocator;
+pub mod slab;
+pub mod swap;
+pub mod oom;
+pub mod aslr;
+pub mod uaccess;
 
 pub fn init() {
     pmm::init();
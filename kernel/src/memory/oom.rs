@@ -0,0 +1,85 @@
+use alloc::sync::Arc;
+use crate::interrupts::task::{Process, ThreadState, TASK_MANAGER};
+use crate::memory::{pmm, swap, vma, vmm};
+
+/// Pages paged out before resorting to killing.
+const RECLAIM_BATCH: usize = 32;
+
+/// Allocates `count` frames for `pid` on behalf of user memory. When the
+/// PMM is exhausted it pages out to swap, then kills the process using the
+/// most memory and retries, until it succeeds or nothing is left to kill.
+pub fn allocate_frames(count: usize, pid: u64) -> Option<u64> {
+    let mut reclaimed = false;
+    loop {
+        if let Some(frame) = pmm::allocate_frames(count, pid) {
+            return Some(frame);
+        }
+        if !reclaimed {
+            reclaimed = true;
+            if swap::reclaim(RECLAIM_BATCH.max(count)) > 0 {
+                continue;
+            }
+        }
+        if !kill_victim() {
+            return None;
+        }
+    }
+}
+
+pub fn allocate_frame(pid: u64) -> Option<u64> {
+    allocate_frames(1, pid)
+}
+
+/// Kills the process with the largest resident plus swapped footprint and
+/// frees its address space right away. Returns false if there was no victim
+/// or the victim is the caller, whose memory is only freed once it unwinds.
+fn kill_victim() -> bool {
+    // Picking and killing need the task list; callers holding it just fail.
+    if TASK_MANAGER.held_by_this_cpu() {
+        return false;
+    }
+    let current = crate::interrupts::task::current_process_ptr();
+
+    let mut tm = TASK_MANAGER.int_lock();
+    let mut victim: Option<(Arc<Process>, [u8; 32], u64)> = None;
+    for thread in tm.tasks.iter().flatten() {
+        if matches!(thread.state, ThreadState::Null | ThreadState::Zombie) {
+            continue;
+        }
+        let proc = match thread.process.as_ref() {
+            Some(p) if p.pid != 0 => p,
+            _ => continue,
+        };
+        let usage = pmm::get_memory_usage_by_pid(proc.pid) as u64 + swap::usage_by_pid(proc.pid);
+        if victim.as_ref().map_or(true, |v| usage > v.2) {
+            victim = Some((proc.clone(), thread.name, usage));
+        }
+    }
+    let (proc, name, usage) = match victim {
+        Some(v) => v,
+        None => {
+            crate::debugln!("[OOM] Out of memory and no process left to kill");
+            return false;
+        }
+    };
+
+    let name_len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    crate::debugln!("[OOM] Out of memory: killing {} (PID {}) using {} KiB",
+        core::str::from_utf8(&name[..name_len]).unwrap_or("?"), proc.pid, usage / 1024);
+    crate::interrupts::signal::send_signal(&mut tm, proc.pid, crate::interrupts::signal::SIGKILL);
+    drop(tm);
+
+    if core::ptr::eq(Arc::as_ptr(&proc), current) {
+        return false;
+    }
+
+    // Its threads are zombies now; whichever still runs elsewhere faults on
+    // the emptied address space and parks itself.
+    {
+        let mut vmas = proc.vmas.lock();
+        vma::sync_range(&vmas, proc.pml4_phys, 0, vma::USER_LIMIT);
+        vmas.clear();
+    }
+    unsafe { vmm::unmap_user_space(proc.pml4_phys); }
+    true
+}
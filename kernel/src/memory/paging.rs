@@ -15,6 +15,12 @@ pub const PAGE_ACCESSED: u64 = 1 << 5;
 pub const PAGE_DIRTY: u64 = 1 << 6;
 pub const PAGE_HUGE: u64 = 1 << 7;
 pub const PAGE_GLOBAL: u64 = 1 << 8;
+/// Software bit: page is shared copy-on-write and was writable before the share.
+pub const PAGE_COW: u64 = 1 << 9;
+/// Software bit: entry is not present and its address field holds a swap slot.
+pub const PAGE_SWAPPED: u64 = 1 << 10;
+/// Software bit: page belongs to a shared mapping and is never paged out.
+pub const PAGE_PINNED: u64 = 1 << 11;
 pub const PAGE_NO_EXECUTE: u64 = 1 << 63;
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -1,7 +1,9 @@
 use crate::boot::BOOT_INFO;
 use crate::debugln;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use super::address::PhysAddr;
+use crate::sync::Mutex;
+use alloc::collections::BTreeMap;
 
 pub const PAGE_SIZE: u64 = 4096;
 
@@ -12,207 +14,387 @@ pub enum FrameError {
     IndexOutOfBounds,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct FrameAllocation {
-    pub pid: u64,
-    pub start: PhysAddr,
-    pub count: usize,
-    pub used: bool,
+/// Ranges of physical memory, by which devices can reach them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    /// Below 16 MiB, for ISA-style DMA such as the PIIX4 bus master.
+    Dma,
+    /// Below 4 GiB, for devices and code limited to 32-bit addresses.
+    Dma32,
+    Normal,
+}
+
+const ZONES: [Zone; 3] = [Zone::Dma, Zone::Dma32, Zone::Normal];
+const DMA_END: u64 = (16 << 20) / PAGE_SIZE;
+const DMA32_END: u64 = (4 << 30) / PAGE_SIZE;
+
+impl Zone {
+    fn of(frame: u64) -> Zone {
+        if frame < DMA_END {
+            Zone::Dma
+        } else if frame < DMA32_END {
+            Zone::Dma32
+        } else {
+            Zone::Normal
+        }
+    }
+
+    /// First and one-past-last frame of the zone.
+    fn bounds(self) -> (u64, u64) {
+        match self {
+            Zone::Dma => (0, DMA_END),
+            Zone::Dma32 => (DMA_END, DMA32_END),
+            Zone::Normal => (DMA32_END, u64::MAX),
+        }
+    }
 }
 
-const MAX_ALLOCS: usize = 32768;
+/// Kernel image and boot data; never handed out.
+const RESERVED_END: u64 = 0xA00000;
+/// Largest buddy block is 2^MAX_ORDER frames, 4 MiB. Zone bounds are
+/// multiples of it, so no block straddles two zones.
+const MAX_ORDER: usize = 10;
+const NONE: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum FrameState {
+    /// Not RAM, or kept back for the kernel.
+    Reserved = 0,
+    /// First frame of a free block, on its zone's list for `order`.
+    Free,
+    /// Inside a free block.
+    FreeTail,
+    /// First frame of an allocation.
+    Head,
+    /// Rest of an allocation, freed with its head by `free_frame`.
+    Tail,
+}
+
+/// One per physical frame. `word` is the owner while allocated and the
+/// free-list links, `next | prev << 32`, while free.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Frame {
+    word: u64,
+    state: FrameState,
+    order: u8,
+}
+
+#[derive(Clone, Copy)]
+struct FreeArea {
+    lists: [u32; MAX_ORDER + 1],
+    pages: u64,
+}
 
 pub struct StructPmm {
-    allocations: [FrameAllocation; MAX_ALLOCS],
+    frames: *mut Frame,
+    frame_count: u64,
+    zones: [FreeArea; 3],
+    /// Frames the allocator manages, free or not.
+    managed: u64,
     total_ram: PhysAddr,
     lock: AtomicBool,
 }
 
 static mut PMM: StructPmm = StructPmm {
-    allocations: [FrameAllocation { pid: 0, start: PhysAddr::new(0), count: 0, used: false }; MAX_ALLOCS],
+    frames: core::ptr::null_mut(),
+    frame_count: 0,
+    zones: [FreeArea { lists: [NONE; MAX_ORDER + 1], pages: 0 }; 3],
+    managed: 0,
     total_ram: PhysAddr::new(0),
     lock: AtomicBool::new(false),
 };
 
-pub fn init() {
-    unsafe {
-        let mmap = (*(&raw mut BOOT_INFO)).mmap;
+/// Pages held by each owner other than PID 0.
+static USAGE: Mutex<BTreeMap<u64, usize>> = Mutex::new(BTreeMap::new());
+/// Pages held by PID 0, kept apart from `USAGE` since the kernel heap
+/// allocates from here as PID 0.
+static KERNEL_PAGES: AtomicUsize = AtomicUsize::new(0);
 
-        debugln!("--- PMM Init: Memory Map ---");
-        let mut max_addr: u64 = 0;
-        for i in 0..32 {
-            let entry = mmap.entries[i];
-
-            if entry.length > 0 {
-                let base = entry.base;
-                let len = entry.length;
-                let type_ = entry.memory_type;
-                debugln!("  [{}] Base: {:#x}, Len: {:#x}, Type: {}", i, base, len, type_);
-                let end = base + len;
-                if end > max_addr { max_addr = end; }
-            }
+fn account(pid: u64, pages: usize, add: bool) {
+    if pid == 0 {
+        if add {
+            KERNEL_PAGES.fetch_add(pages, Ordering::Relaxed);
+        } else {
+            KERNEL_PAGES.fetch_sub(pages, Ordering::Relaxed);
         }
-        debugln!("----------------------------");
+        return;
+    }
+    let mut usage = USAGE.int_lock();
+    let entry = usage.entry(pid).or_insert(0);
+    if add {
+        *entry += pages;
+    } else {
+        *entry = entry.saturating_sub(pages);
+        if *entry == 0 {
+            usage.remove(&pid);
+        }
+    }
+}
 
-        let pmm_ptr = &raw mut PMM;
-        (*pmm_ptr).total_ram = PhysAddr::new(max_addr);
+/// Smallest order whose block holds `count` frames.
+fn order_for(count: u64) -> usize {
+    count.next_power_of_two().trailing_zeros() as usize
+}
 
-        let pages = (0xA00000 / PAGE_SIZE) as usize;
-        add_allocation(0, PhysAddr::new(0), pages);
+impl StructPmm {
+    fn frame(&mut self, idx: u64) -> &mut Frame {
+        unsafe { &mut *self.frames.add(idx as usize) }
     }
-}
 
-unsafe fn add_allocation(pid: u64, start: PhysAddr, count: usize) -> bool {
-    unsafe {
-        let pmm_ptr = &raw mut PMM;
+    fn state(&mut self, idx: u64) -> FrameState {
+        if idx < self.frame_count { self.frame(idx).state } else { FrameState::Reserved }
+    }
 
-        let mut count_used = 0;
-        for i in 0..MAX_ALLOCS {
-            if (*pmm_ptr).allocations[i].used {
-                count_used += 1;
-            }
+    fn set_next(&mut self, idx: u32, next: u32) {
+        let f = self.frame(idx as u64);
+        f.word = (f.word & !0xFFFF_FFFF) | next as u64;
+    }
+
+    fn set_prev(&mut self, idx: u32, prev: u32) {
+        let f = self.frame(idx as u64);
+        f.word = (f.word & 0xFFFF_FFFF) | (prev as u64) << 32;
+    }
+
+    fn push(&mut self, idx: u64, order: usize) {
+        let zone = Zone::of(idx) as usize;
+        let head = self.zones[zone].lists[order];
+        if head != NONE {
+            self.set_prev(head, idx as u32);
         }
+        let f = self.frame(idx);
+        f.state = FrameState::Free;
+        f.order = order as u8;
+        f.word = head as u64 | (NONE as u64) << 32;
+        self.zones[zone].lists[order] = idx as u32;
+        self.zones[zone].pages += 1 << order;
+    }
 
-        if count_used >= MAX_ALLOCS {
-            return false;
+    fn unlink(&mut self, idx: u64, order: usize) {
+        let zone = Zone::of(idx) as usize;
+        let word = self.frame(idx).word;
+        let (next, prev) = (word as u32, (word >> 32) as u32);
+        if prev == NONE {
+            self.zones[zone].lists[order] = next;
+        } else {
+            self.set_next(prev, next);
+        }
+        if next != NONE {
+            self.set_prev(next, prev);
         }
+        self.zones[zone].pages -= 1 << order;
+        self.frame(idx).state = FrameState::FreeTail;
+    }
 
-        let mut idx = 0;
-        while idx < count_used {
-            if (*pmm_ptr).allocations[idx].start > start {
+    /// Frees the block at `idx`, merging it with its buddy while that is free too.
+    fn free_block(&mut self, mut idx: u64, mut order: usize) {
+        while order < MAX_ORDER {
+            let buddy = idx ^ (1 << order);
+            if self.state(buddy) != FrameState::Free || self.frame(buddy).order as usize != order {
                 break;
             }
-            idx += 1;
+            self.unlink(buddy, order);
+            self.frame(idx.max(buddy)).state = FrameState::FreeTail;
+            idx = idx.min(buddy);
+            order += 1;
         }
+        self.push(idx, order);
+    }
 
-        
-        if idx > 0 {
-            let prev_idx = idx - 1;
-            let prev = &mut (*pmm_ptr).allocations[prev_idx];
-            if prev.pid == pid {
-                let prev_end = prev.start + (prev.count as u64 * PAGE_SIZE);
-                if prev_end == start {
-                    
-                    prev.count += count;
-                    
-                    if idx < count_used {
-                        let next = &(*pmm_ptr).allocations[idx];
-                        let current_end = prev.start + (prev.count as u64 * PAGE_SIZE);
-                        if next.pid == pid && current_end == next.start {
-                            prev.count += next.count;
-                            
-                            for i in idx..(count_used - 1) {
-                                (*pmm_ptr).allocations[i] = (*pmm_ptr).allocations[i + 1];
-                            }
-                            (*pmm_ptr).allocations[count_used - 1].used = false;
-                        }
-                    }
-                    return true;
-                }
+    /// Puts `[start, end)`, all `FreeTail`, on the lists as the largest
+    /// aligned blocks that fit, without merging.
+    fn free_range(&mut self, mut start: u64, end: u64) {
+        while start < end {
+            let mut order = (start.trailing_zeros() as usize).min(MAX_ORDER);
+            while start + (1 << order) > end {
+                order -= 1;
             }
+            self.push(start, order);
+            start += 1 << order;
         }
+    }
 
-        
-        if idx < count_used {
-            let next = &mut (*pmm_ptr).allocations[idx];
-            if next.pid == pid {
-                let current_end = start + (count as u64 * PAGE_SIZE);
-                if current_end == next.start {
-                    
-                    next.start = start;
-                    next.count += count;
-                    return true;
-                }
-            }
+    /// Takes `count` contiguous frames from `zone`, returning the first.
+    fn take(&mut self, zone: Zone, count: u64) -> Option<u64> {
+        let order = order_for(count);
+        if order > MAX_ORDER {
+            return self.take_contig(zone, count);
         }
-
-        
-        if idx < count_used {
-            for i in (idx..count_used).rev() {
-                (*pmm_ptr).allocations[i + 1] = (*pmm_ptr).allocations[i];
-            }
+        let lists = self.zones[zone as usize].lists;
+        let found = (order..=MAX_ORDER).find(|&o| lists[o] != NONE)?;
+        let idx = lists[found] as u64;
+        self.unlink(idx, found);
+        for o in (order..found).rev() {
+            self.push(idx + (1 << o), o);
         }
-
-        (*pmm_ptr).allocations[idx] = FrameAllocation {
-            pid,
-            start,
-            count,
-            used: true,
-        };
-
-        true
+        self.free_range(idx + count, idx + (1 << order));
+        Some(idx)
     }
-}
 
-unsafe fn remove_allocation(start: PhysAddr) {
-    unsafe {
-        let pmm_ptr = &raw mut PMM;
-        let mut found_idx = MAX_ALLOCS;
-        let mut count_used = 0;
-
-        for i in 0..MAX_ALLOCS {
-            if (*pmm_ptr).allocations[i].used {
-                count_used += 1;
-                if (*pmm_ptr).allocations[i].start == start {
-                    found_idx = i;
+    /// Takes a run too big for one block by finding enough neighbouring free
+    /// blocks of the largest order.
+    fn take_contig(&mut self, zone: Zone, count: u64) -> Option<u64> {
+        let block = 1u64 << MAX_ORDER;
+        let blocks = count.div_ceil(block);
+        let (lo, hi) = zone.bounds();
+        let hi = hi.min(self.frame_count);
+
+        let mut run = 0;
+        let mut idx = lo;
+        while idx + block <= hi {
+            let f = *self.frame(idx);
+            run = if f.state == FrameState::Free && f.order as usize == MAX_ORDER { run + 1 } else { 0 };
+            idx += block;
+            if run == blocks {
+                let start = idx - blocks * block;
+                for i in 0..blocks {
+                    self.unlink(start + i * block, MAX_ORDER);
                 }
-            } else {
-                break;
+                self.free_range(start + count, idx);
+                return Some(start);
             }
         }
+        None
+    }
 
-        if found_idx != MAX_ALLOCS {
-            let start_addr = (*pmm_ptr).allocations[found_idx].start;
-            let size = (*pmm_ptr).allocations[found_idx].count as u64 * PAGE_SIZE;
-            
-            
-            let virt_ptr = (start_addr.as_u64() + crate::memory::paging::HHDM_OFFSET) as *mut u8;
-            core::ptr::write_bytes(virt_ptr, 0, size as usize);
-
-            for i in found_idx..(count_used - 1) {
-                (*pmm_ptr).allocations[i] = (*pmm_ptr).allocations[i + 1];
+    /// Carves frame `idx` out of the free block holding it.
+    fn take_frame(&mut self, idx: u64) -> bool {
+        for order in 0..=MAX_ORDER {
+            let head = idx & !((1 << order) - 1);
+            if self.state(head) == FrameState::Free && self.frame(head).order as usize == order {
+                self.unlink(head, order);
+                self.free_range(head, idx);
+                self.free_range(idx + 1, head + (1 << order));
+                return true;
             }
-            (*pmm_ptr).allocations[count_used - 1].used = false;
         }
+        false
     }
-}
 
-unsafe fn is_overlap(start: PhysAddr, count: usize) -> bool {
-    unsafe {
-        let end = start + (count as u64 * PAGE_SIZE);
-        let pmm_ptr = &raw mut PMM;
+    /// Marks `count` frames from `idx` as one allocation of `owner`.
+    fn claim(&mut self, idx: u64, count: u64, owner: u64) {
+        for i in 0..count {
+            let f = self.frame(idx + i);
+            f.word = owner;
+            f.state = if i == 0 { FrameState::Head } else { FrameState::Tail };
+            f.order = 0;
+        }
+    }
 
-        for i in 0..MAX_ALLOCS {
-            let alloc = &(*pmm_ptr).allocations[i];
-            if alloc.used {
-                let alloc_end = alloc.start + (alloc.count as u64 * PAGE_SIZE);
-                if start < alloc_end && end > alloc.start {
-                    return true;
-                }
-            } else {
+    /// Frees one allocated frame, splitting its allocation if it has more
+    /// after it. Returns the owner.
+    fn release(&mut self, idx: u64) -> Option<u64> {
+        let state = self.state(idx);
+        if state != FrameState::Head && state != FrameState::Tail {
+            return None;
+        }
+        if self.state(idx + 1) == FrameState::Tail {
+            self.frame(idx + 1).state = FrameState::Head;
+        }
+        let owner = self.frame(idx).word;
+        self.frame(idx).state = FrameState::FreeTail;
+        self.free_block(idx, 0);
+        Some(owner)
+    }
+
+    /// Frees the allocation starting at `idx`. Returns the owner and its length.
+    fn release_run(&mut self, idx: u64) -> Option<(u64, usize)> {
+        if self.state(idx) != FrameState::Head {
+            return None;
+        }
+        let owner = self.frame(idx).word;
+        let mut count = 0;
+        loop {
+            self.frame(idx + count).state = FrameState::FreeTail;
+            self.free_block(idx + count, 0);
+            count += 1;
+            if self.state(idx + count) != FrameState::Tail {
                 break;
             }
         }
-        false
+        Some((owner, count as usize))
+    }
+
+    fn free_pages(&self) -> u64 {
+        self.zones.iter().map(|z| z.pages).sum()
     }
 }
 
-unsafe fn is_valid_ram(start: PhysAddr, count: usize) -> bool {
+/// Builds the frame array and free lists from the boot memory map. The array
+/// itself goes in the first usable region that fits it, above the DMA zone
+/// if it can.
+pub fn init() {
     unsafe {
-        let end = start + (count as u64 * PAGE_SIZE);
         let mmap = (*(&raw mut BOOT_INFO)).mmap;
 
+        debugln!("--- PMM Init: Memory Map ---");
+        let mut max_addr: u64 = 0;
+        let mut usable_end: u64 = 0;
         for i in 0..32 {
             let entry = mmap.entries[i];
-            if entry.memory_type == 1 && entry.length > 0 {
-                let entry_base = PhysAddr::new(entry.base);
-                let entry_end = entry_base + entry.length;
-                if start >= entry_base && end <= entry_end {
-                    return true;
-                }
+
+            if entry.length > 0 {
+                let base = entry.base;
+                let len = entry.length;
+                let type_ = entry.memory_type;
+                debugln!("  [{}] Base: {:#x}, Len: {:#x}, Type: {}", i, base, len, type_);
+                let end = base + len;
+                if end > max_addr { max_addr = end; }
+                if type_ == 1 && end > usable_end { usable_end = end; }
             }
         }
-        false
+        debugln!("----------------------------");
+
+        let pmm = &mut *(&raw mut PMM);
+        pmm.total_ram = PhysAddr::new(max_addr);
+
+        let usable = |i: usize| {
+            let entry = mmap.entries[i];
+            let (base, len, type_) = (entry.base, entry.length, entry.memory_type);
+            let start = base.max(RESERVED_END).next_multiple_of(PAGE_SIZE);
+            let end = (base + len) & !(PAGE_SIZE - 1);
+            (type_ == 1 && start < end).then_some((start, end))
+        };
+
+        let frame_count = usable_end / PAGE_SIZE;
+        let meta_bytes = (frame_count * core::mem::size_of::<Frame>() as u64).next_multiple_of(PAGE_SIZE);
+        let fits = |floor: u64| (0..32)
+            .filter_map(usable)
+            .map(|(start, end)| (start.max(floor), end))
+            .find(|&(start, end)| start < end && end - start >= meta_bytes)
+            .map(|(start, _)| start);
+        let meta = fits(DMA_END * PAGE_SIZE).or_else(|| fits(0)).expect("PMM: no room for the frame array");
+
+        pmm.frames = (meta + crate::memory::paging::HHDM_OFFSET) as *mut Frame;
+        pmm.frame_count = frame_count;
+        core::ptr::write_bytes(pmm.frames, 0, frame_count as usize);
+
+        for (start, end) in (0..32).filter_map(usable) {
+            for idx in start / PAGE_SIZE..end / PAGE_SIZE {
+                pmm.frame(idx).state = FrameState::FreeTail;
+            }
+        }
+        pmm.claim(meta / PAGE_SIZE, meta_bytes / PAGE_SIZE, 0);
+        account(0, (meta_bytes / PAGE_SIZE) as usize, true);
+
+        let mut idx = 0;
+        while idx < frame_count {
+            if pmm.state(idx) != FrameState::FreeTail {
+                idx += 1;
+                continue;
+            }
+            let start = idx;
+            while pmm.state(idx) == FrameState::FreeTail {
+                idx += 1;
+            }
+            pmm.free_range(start, idx);
+        }
+        pmm.managed = pmm.free_pages() + meta_bytes / PAGE_SIZE;
+
+        for zone in ZONES {
+            debugln!("PMM: zone {:?}: {} free pages", zone, pmm.zones[zone as usize].pages);
+        }
     }
 }
 
@@ -242,103 +424,36 @@ pub fn allocate_frames(count: usize, pid: u64) -> Option<u64> {
 
 pub fn allocate_memory(bytes: usize, pid: u64) -> Option<u64> {
     let pages = (bytes + PAGE_SIZE as usize - 1) / PAGE_SIZE as usize;
-    if pages == 0 { return None; }
-
-    unsafe {
-        lock_pmm();
-        let pmm_ptr = &raw mut PMM;
-
-        let mut count_used = 0;
-        for i in 0..MAX_ALLOCS {
-            if (*pmm_ptr).allocations[i].used {
-                count_used += 1;
-            } else {
-                break;
-            }
-        }
-
-        let mut found_addr = PhysAddr::new(0);
-        let mut found = false;
-
-        let mut prev_end = PhysAddr::new(0);
-
-        if count_used > 0 {
-            prev_end = (*pmm_ptr).allocations[0].start + ((*pmm_ptr).allocations[0].count as u64 * PAGE_SIZE);
-        } else {
-            prev_end = PhysAddr::new(0xA00000);
-        }
-
-        if prev_end.as_u64() < 0xA00000 {
-            prev_end = PhysAddr::new(0xA00000);
-        }
+    allocate_frames_in(Zone::Normal, pages, pid)
+}
 
-        for i in 0..count_used {
-            let current = (*pmm_ptr).allocations[i];
+/// Allocates `count` zeroed, contiguous frames from `zone` or, if it has
+/// none, from the zones below it.
+pub fn allocate_frames_in(zone: Zone, count: usize, pid: u64) -> Option<u64> {
+    if count == 0 { return None; }
 
-            if current.start > prev_end {
-                let gap_size = current.start - prev_end;
-                if gap_size >= (pages as u64 * PAGE_SIZE) {
-                    if is_valid_ram(prev_end, pages) {
-                        found_addr = prev_end;
-                        found = true;
-                        break;
-                    }
-                }
-            }
-
-            let current_end = current.start + (current.count as u64 * PAGE_SIZE);
-            if current_end > prev_end {
-                prev_end = current_end;
-            }
+    let found = unsafe {
+        lock_pmm();
+        let pmm = &mut *(&raw mut PMM);
+        let found = ZONES[..=zone as usize].iter().rev().find_map(|&z| pmm.take(z, count as u64));
+        if let Some(idx) = found {
+            pmm.claim(idx, count as u64, pid);
         }
-
-        if !found {
-            let mmap = (*(&raw mut BOOT_INFO)).mmap;
-            let req_size = pages as u64 * PAGE_SIZE;
-
-            for i in 0..32 {
-                let entry = mmap.entries[i];
-                if entry.memory_type == 1 && entry.length > 0 {
-                    let entry_base = PhysAddr::new(entry.base);
-                    let entry_end = entry_base + entry.length;
-                    
-                    
-                    if entry_end <= prev_end { continue; }
-
-                    
-                    let mut candidate_start = if entry_base > prev_end { entry_base } else { prev_end };
-                    
-                    
-                    if !candidate_start.is_aligned(PAGE_SIZE) {
-                        candidate_start = candidate_start.align_up(PAGE_SIZE);
-                    }
-
-                    if candidate_start < entry_end {
-                        let available = entry_end - candidate_start;
-                        if available >= req_size {
-                            found_addr = candidate_start;
-                            found = true;
-                            break;
-                        }
-                    }
-                }
-            }
+        unlock_pmm();
+        found
+    };
+
+    match found {
+        Some(idx) => {
+            let addr = idx * PAGE_SIZE;
+            unsafe { core::ptr::write_bytes((addr + crate::memory::paging::HHDM_OFFSET) as *mut u8, 0, count * PAGE_SIZE as usize); }
+            account(pid, count, true);
+            Some(addr)
         }
-
-        if found {
-            if add_allocation(pid, found_addr, pages) {
-                
-                let virt_ptr = (found_addr.as_u64() + crate::memory::paging::HHDM_OFFSET) as *mut u8;
-                core::ptr::write_bytes(virt_ptr, 0, pages * PAGE_SIZE as usize);
-
-                unlock_pmm();
-                return Some(found_addr.as_u64());
-            }
+        None => {
+            debugln!("PMM: FAILED to allocate {} pages in {:?} for PID {}", count, zone, pid);
+            None
         }
-
-        unlock_pmm();
-        debugln!("PMM: FAILED to allocate {} bytes for PID {}. prev_end={:#x}, count_used={}", bytes, pid, prev_end.as_u64(), count_used);
-        None
     }
 }
 
@@ -346,114 +461,196 @@ pub fn reserve_frame(addr: u64) -> bool {
     reserve_frames(addr, 1)
 }
 
+/// Takes the given frames for the kernel, if they are all free.
 pub fn reserve_frames(addr: u64, count: usize) -> bool {
-    unsafe {
+    let start = addr / PAGE_SIZE;
+    let res = unsafe {
         lock_pmm();
-        if is_overlap(PhysAddr::new(addr), count) {
-            unlock_pmm();
-            return false;
-        }
-        let res = add_allocation(0, PhysAddr::new(addr), count);
+        let pmm = &mut *(&raw mut PMM);
+        let res = (start..start + count as u64)
+            .all(|idx| matches!(pmm.state(idx), FrameState::Free | FrameState::FreeTail));
         if res {
-            let virt_ptr = (addr + crate::memory::paging::HHDM_OFFSET) as *mut u8;
-            core::ptr::write_bytes(virt_ptr, 0, count * PAGE_SIZE as usize);
+            for idx in start..start + count as u64 {
+                pmm.take_frame(idx);
+            }
+            pmm.claim(start, count as u64, 0);
         }
         unlock_pmm();
         res
+    };
+    if res {
+        unsafe { core::ptr::write_bytes((addr + crate::memory::paging::HHDM_OFFSET) as *mut u8, 0, count * PAGE_SIZE as usize); }
+        account(0, count, true);
     }
+    res
 }
 
+/// Frees the whole allocation starting at `addr`.
 pub fn free_frame(addr: u64) {
-    unsafe {
+    let freed = unsafe {
         lock_pmm();
-        remove_allocation(PhysAddr::new(addr));
+        let freed = (*(&raw mut PMM)).release_run(addr / PAGE_SIZE);
         unlock_pmm();
+        freed
+    };
+    if let Some((owner, count)) = freed {
+        account(owner, count, false);
     }
 }
 
+/// Frees every frame owned by `pid`, or by any thread of its process if
+/// `pid` has no thread part.
 pub fn free_frames_by_pid(pid: u64) {
+    let target_main = pid >> 32;
+    let target_child = pid & 0xFFFFFFFF;
+    let matches = |owner: u64| if target_child == 0 { owner >> 32 == target_main } else { owner == pid };
+
+    USAGE.int_lock().retain(|&owner, _| !matches(owner));
+    if matches(0) {
+        KERNEL_PAGES.store(0, Ordering::Relaxed);
+    }
+
     unsafe {
         lock_pmm();
-        let pmm_ptr = &raw mut PMM;
-
-        let target_main = pid >> 32;
-        let target_child = pid & 0xFFFFFFFF;
+        let pmm = &mut *(&raw mut PMM);
+        for idx in 0..pmm.frame_count {
+            let f = *pmm.frame(idx);
+            if matches!(f.state, FrameState::Head | FrameState::Tail) && matches(f.word) {
+                pmm.release(idx);
+            }
+        }
+        unlock_pmm();
+    }
+}
 
-        let mut i = 0;
-        while i < MAX_ALLOCS {
-            if (*pmm_ptr).allocations[i].used {
-                let alloc_pid = (*pmm_ptr).allocations[i].pid;
-                let alloc_main = alloc_pid >> 32;
+/// Owner recorded for frames mapped by more than one address space.
+/// Such frames are freed by refcount, never by `free_frames_by_pid`.
+pub const SHARED_PID: u64 = u64::MAX;
 
+static FRAME_REFS: Mutex<BTreeMap<u64, u32>> = Mutex::new(BTreeMap::new());
 
-                let should_free = if target_child == 0 {
-                    alloc_main == target_main
-                } else {
-                    alloc_pid == pid
-                };
+/// Frees one frame, even if it sits in the middle of a larger allocation.
+pub fn free_single_frame(addr: u64) {
+    let owner = unsafe {
+        lock_pmm();
+        let owner = (*(&raw mut PMM)).release(addr / PAGE_SIZE);
+        unlock_pmm();
+        owner
+    };
+    if let Some(owner) = owner {
+        account(owner, 1, false);
+    }
+}
 
-                if should_free {
-                    let start_addr = (*pmm_ptr).allocations[i].start;
-                    let size = (*pmm_ptr).allocations[i].count as u64 * PAGE_SIZE;
-                    let virt_ptr = (start_addr.as_u64() + crate::memory::paging::HHDM_OFFSET) as *mut u8;
-                    core::ptr::write_bytes(virt_ptr, 0, size as usize);
+/// Owner of the allocated frame at `addr`.
+pub fn owner_of(addr: u64) -> Option<u64> {
+    unsafe {
+        lock_pmm();
+        let pmm = &mut *(&raw mut PMM);
+        let idx = addr / PAGE_SIZE;
+        let owner = match pmm.state(idx) {
+            FrameState::Head | FrameState::Tail => Some(pmm.frame(idx).word),
+            _ => None,
+        };
+        unlock_pmm();
+        owner
+    }
+}
 
-                    let count_used = {
-                        let mut c = 0;
-                        for k in 0..MAX_ALLOCS {
-                            if (*pmm_ptr).allocations[k].used { c += 1; }
-                        }
-                        c
-                    };
+/// Moves ownership of a single frame to `pid` without touching its contents.
+pub fn transfer_frame(addr: u64, pid: u64) -> bool {
+    let idx = addr / PAGE_SIZE;
+    let old = unsafe {
+        lock_pmm();
+        let pmm = &mut *(&raw mut PMM);
+        let old = match pmm.state(idx) {
+            FrameState::Head | FrameState::Tail => {
+                if pmm.state(idx + 1) == FrameState::Tail {
+                    pmm.frame(idx + 1).state = FrameState::Head;
+                }
+                let old = pmm.frame(idx).word;
+                pmm.claim(idx, 1, pid);
+                Some(old)
+            }
+            _ => None,
+        };
+        unlock_pmm();
+        old
+    };
+    match old {
+        Some(old) => {
+            account(old, 1, false);
+            account(pid, 1, true);
+            true
+        }
+        None => false,
+    }
+}
 
-                    for k in i..(count_used - 1) {
-                        (*pmm_ptr).allocations[k] = (*pmm_ptr).allocations[k + 1];
-                    }
-                    (*pmm_ptr).allocations[count_used - 1].used = false;
+/// Records one more address space mapping `addr`. The first share hands
+/// the frame over to `SHARED_PID` so that neither owner frees it on exit.
+pub fn share_frame(addr: u64) {
+    let mut refs = FRAME_REFS.int_lock();
+    if let Some(count) = refs.get_mut(&addr) {
+        *count += 1;
+    } else {
+        transfer_frame(addr, SHARED_PID);
+        refs.insert(addr, 2);
+    }
+}
 
+pub fn is_shared_frame(addr: u64) -> bool {
+    FRAME_REFS.int_lock().contains_key(&addr)
+}
 
-                    continue;
-                }
-            } else {
-                break;
-            }
-            i += 1;
+/// Drops one reference to a shared frame, freeing it with the last one.
+pub fn release_shared_frame(addr: u64) {
+    let mut refs = FRAME_REFS.int_lock();
+    if let Some(count) = refs.get_mut(&addr) {
+        *count -= 1;
+        if *count == 0 {
+            refs.remove(&addr);
+            free_single_frame(addr);
         }
+    }
+}
 
-        unlock_pmm();
+/// Gives a shared frame back to `pid` if it is the last one mapping it.
+pub fn claim_shared_frame(addr: u64, pid: u64) -> bool {
+    let mut refs = FRAME_REFS.int_lock();
+    match refs.get(&addr) {
+        Some(&1) => {
+            refs.remove(&addr);
+            transfer_frame(addr, pid)
+        }
+        Some(_) => false,
+        None => true,
     }
 }
 
 pub fn print_allocations() {
     unsafe {
         lock_pmm();
-        let pmm_ptr = &raw mut PMM;
+        let pmm = &mut *(&raw mut PMM);
 
         debugln!("--- PMM Allocations ---");
 
-        let mut count_used = 0;
-        for i in 0..MAX_ALLOCS {
-            if (*pmm_ptr).allocations[i].used {
-                count_used += 1;
-            } else {
-                break;
+        let mut idx = 0;
+        while idx < pmm.frame_count {
+            if pmm.state(idx) != FrameState::Head {
+                idx += 1;
+                continue;
             }
-        }
-
-        for i in 0..count_used {
-            let alloc = (*pmm_ptr).allocations[i];
-            let start = alloc.start;
-            let end = start + (alloc.count as u64 * PAGE_SIZE);
-
-            debugln!("PID {}: {:#x} -> {:#x} ({} pages)", alloc.pid, start.as_u64(), end.as_u64(), alloc.count);
-
-            if i > 0 {
-                let prev = (*pmm_ptr).allocations[i - 1];
-                let prev_end = prev.start + (prev.count as u64 * PAGE_SIZE);
-                if start < prev_end {
-                    debugln!("!!! COLLISION DETECTED with previous allocation !!!");
-                }
+            let owner = pmm.frame(idx).word;
+            let start = idx;
+            idx += 1;
+            while pmm.state(idx) == FrameState::Tail {
+                idx += 1;
             }
+            debugln!("PID {}: {:#x} -> {:#x} ({} pages)", owner, start * PAGE_SIZE, idx * PAGE_SIZE, idx - start);
+        }
+        for zone in ZONES {
+            debugln!("Zone {:?}: {} free pages", zone, pmm.zones[zone as usize].pages);
         }
         debugln!("--- End of Allocations ---");
 
@@ -464,14 +661,8 @@ pub fn print_allocations() {
 #[allow(dead_code)]
 pub fn get_used_memory() -> usize {
     unsafe {
-        let pmm_ptr = &raw mut PMM;
-        let mut total = 0;
-        for i in 0..MAX_ALLOCS {
-            if (*pmm_ptr).allocations[i].used {
-                total += (*pmm_ptr).allocations[i].count;
-            }
-        }
-        total * PAGE_SIZE as usize
+        let pmm = &*(&raw const PMM);
+        ((pmm.managed - pmm.free_pages()) * PAGE_SIZE) as usize
     }
 }
 
@@ -481,20 +672,10 @@ pub fn get_total_memory() -> usize {
 }
 
 pub fn get_memory_usage_by_pid(pid: u64) -> usize {
-    unsafe {
-        let pmm_ptr = &raw mut PMM;
-        let mut total_pages = 0;
-
-        for i in 0..MAX_ALLOCS {
-            if (*pmm_ptr).allocations[i].used {
-                if (*pmm_ptr).allocations[i].pid == pid {
-                    total_pages += (*pmm_ptr).allocations[i].count;
-                }
-            } else {
-                break;
-            }
-        }
-
-        total_pages * PAGE_SIZE as usize
-    }
-}
\ No newline at end of file
+    let pages = if pid == 0 {
+        KERNEL_PAGES.load(Ordering::Relaxed)
+    } else {
+        USAGE.int_lock().get(&pid).copied().unwrap_or(0)
+    };
+    pages * PAGE_SIZE as usize
+}
@@ -0,0 +1,133 @@
+use crate::memory::{paging, pmm};
+use crate::sync::Mutex;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const MAX_SHM_OBJECTS: usize = 64;
+/// Largest object a process may create.
+pub const MAX_SHM_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A named block of frames that any number of address spaces can map.
+/// Each mapped page holds a shared-frame reference on top of the object's own.
+struct ShmObject {
+    name: String,
+    frames: Vec<u64>,
+    /// Live VMAs pointing at this object.
+    maps: usize,
+    unlinked: bool,
+}
+
+static SHM_OBJECTS: Mutex<[Option<ShmObject>; MAX_SHM_OBJECTS]> = Mutex::new([const { None }; MAX_SHM_OBJECTS]);
+
+fn destroy(obj: ShmObject) {
+    for frame in obj.frames {
+        if pmm::is_shared_frame(frame) {
+            pmm::release_shared_frame(frame);
+        } else {
+            pmm::free_frame(frame);
+        }
+    }
+}
+
+/// Opens the object called `name`, creating it with `size` bytes of zeroed
+/// memory if it does not exist. Returns its id.
+pub fn open(name: &str, size: u64) -> Option<usize> {
+    let mut objects = SHM_OBJECTS.int_lock();
+    for (id, slot) in objects.iter().enumerate() {
+        if let Some(obj) = slot {
+            if !obj.unlinked && obj.name == name {
+                return if size <= obj.frames.len() as u64 * paging::PAGE_SIZE { Some(id) } else { None };
+            }
+        }
+    }
+
+    if name.is_empty() {
+        return None;
+    }
+    create(&mut objects, name, size, false)
+}
+
+/// Creates an object with no name, which lives only as long as its mappings.
+/// Backs `MAP_SHARED | MAP_ANONYMOUS`.
+pub fn create_anonymous(size: u64) -> Option<usize> {
+    let mut objects = SHM_OBJECTS.int_lock();
+    create(&mut objects, "", size, true)
+}
+
+fn create(objects: &mut [Option<ShmObject>; MAX_SHM_OBJECTS], name: &str, size: u64, unlinked: bool) -> Option<usize> {
+    if size == 0 || size > MAX_SHM_SIZE {
+        return None;
+    }
+    let id = objects.iter().position(|s| s.is_none())?;
+
+    let pages = ((size + paging::PAGE_SIZE - 1) / paging::PAGE_SIZE) as usize;
+    let mut frames = Vec::with_capacity(pages);
+    for _ in 0..pages {
+        match pmm::allocate_frame(0) {
+            Some(frame) => {
+                unsafe {
+                    core::ptr::write_bytes((frame + paging::HHDM_OFFSET) as *mut u8, 0, paging::PAGE_SIZE as usize);
+                }
+                frames.push(frame);
+            }
+            None => {
+                for frame in frames {
+                    pmm::free_frame(frame);
+                }
+                return None;
+            }
+        }
+    }
+
+    objects[id] = Some(ShmObject { name: String::from(name), frames, maps: 0, unlinked });
+    Some(id)
+}
+
+/// Removes `name` from the namespace. The memory goes away once the last mapping does.
+pub fn unlink(name: &str) -> bool {
+    let mut objects = SHM_OBJECTS.int_lock();
+    for slot in objects.iter_mut() {
+        if let Some(obj) = slot {
+            if !obj.unlinked && obj.name == name {
+                obj.unlinked = true;
+                if obj.maps == 0 {
+                    destroy(slot.take().unwrap());
+                }
+                return true;
+            }
+        }
+    }
+    false
+}
+
+pub fn size(id: usize) -> Option<u64> {
+    let objects = SHM_OBJECTS.int_lock();
+    objects.get(id)?.as_ref().map(|o| o.frames.len() as u64 * paging::PAGE_SIZE)
+}
+
+/// Frame backing byte `offset` of object `id`, with a new shared reference
+/// taken for the caller to map.
+pub fn share_page(id: usize, offset: u64) -> Option<u64> {
+    let objects = SHM_OBJECTS.int_lock();
+    let frame = *objects.get(id)?.as_ref()?.frames.get((offset / paging::PAGE_SIZE) as usize)?;
+    pmm::share_frame(frame);
+    Some(frame)
+}
+
+pub fn retain(id: usize) {
+    if let Some(Some(obj)) = SHM_OBJECTS.int_lock().get_mut(id) {
+        obj.maps += 1;
+    }
+}
+
+pub fn release(id: usize) {
+    let mut objects = SHM_OBJECTS.int_lock();
+    if let Some(slot) = objects.get_mut(id) {
+        if let Some(obj) = slot {
+            obj.maps = obj.maps.saturating_sub(1);
+            if obj.maps == 0 && obj.unlinked {
+                destroy(slot.take().unwrap());
+            }
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use core::alloc::Layout;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::memory::{allocator, paging, pmm};
+
+/// Caches for the hot fixed-size kernel objects. The global allocator sends
+/// every allocation whose layout matches one here, so `Box<Thread>` or
+/// `Arc<Process>` land in slabs without naming the cache.
+pub const THREAD: usize = 0;
+pub const PROCESS: usize = 1;
+pub const PIPE: usize = 2;
+pub const VFS_NODE: usize = 3;
+pub const CACHE_COUNT: usize = 4;
+
+/// Objects a new slab is sized to hold at least.
+const OBJECTS_PER_SLAB: usize = 8;
+
+/// Header at the start of every slab. Slabs are naturally aligned runs of
+/// frames, so an object's slab is found by rounding its address down to
+/// its cache's slab size.
+struct Slab {
+    next: *mut Slab,
+    free: *mut FreeObject,
+    in_use: usize,
+}
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+pub struct SlabCache {
+    name: &'static str,
+    size: usize,
+    align: usize,
+    slab_bytes: usize,
+    /// Slabs with at least one free object.
+    partial: *mut Slab,
+    slabs: usize,
+    in_use: usize,
+    lock: AtomicBool,
+}
+
+impl SlabCache {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            size: 0,
+            align: 0,
+            slab_bytes: 0,
+            partial: core::ptr::null_mut(),
+            slabs: 0,
+            in_use: 0,
+            lock: AtomicBool::new(false),
+        }
+    }
+
+    fn lock(&self) {
+        while self.lock.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn unlock(&self) {
+        self.lock.store(false, Ordering::Release);
+    }
+
+    /// Offset of the first object in a slab.
+    fn first_offset(&self) -> usize {
+        size_of::<Slab>().next_multiple_of(self.align)
+    }
+
+    unsafe fn new_slab(&mut self) -> Option<*mut Slab> {
+        let pages = self.slab_bytes / paging::PAGE_SIZE as usize;
+        let phys = pmm::allocate_frames(pages, 0)?;
+        let base = (phys + paging::HHDM_OFFSET) as usize;
+
+        let mut free: *mut FreeObject = core::ptr::null_mut();
+        let mut offset = self.first_offset();
+        while offset + self.size <= self.slab_bytes {
+            let obj = (base + offset) as *mut FreeObject;
+            unsafe { (*obj).next = free; }
+            free = obj;
+            offset += self.size;
+        }
+
+        let slab = base as *mut Slab;
+        unsafe { slab.write(Slab { next: core::ptr::null_mut(), free, in_use: 0 }); }
+        self.slabs += 1;
+        Some(slab)
+    }
+
+    unsafe fn unlink(&mut self, slab: *mut Slab) {
+        unsafe {
+            let mut link = &raw mut self.partial;
+            while !(*link).is_null() {
+                if *link == slab {
+                    *link = (*slab).next;
+                    return;
+                }
+                link = &raw mut (**link).next;
+            }
+        }
+    }
+}
+
+static mut CACHES: [SlabCache; CACHE_COUNT] = [
+    SlabCache::new("thread"),
+    SlabCache::new("process"),
+    SlabCache::new("pipe"),
+    SlabCache::new("vfs-node"),
+];
+
+/// Layout of the allocation behind an `Arc<T>`: the two counts, then `T`.
+fn arc_layout<T>() -> Layout {
+    Layout::new::<[usize; 2]>().extend(Layout::new::<T>()).unwrap().0.pad_to_align()
+}
+
+fn register(cache: usize, layout: Layout) {
+    let cache = unsafe { &mut (*(&raw mut CACHES))[cache] };
+    cache.align = layout.align().max(size_of::<FreeObject>());
+    cache.size = layout.size().max(size_of::<FreeObject>()).next_multiple_of(cache.align);
+    let min_bytes = size_of::<Slab>().next_multiple_of(cache.align) + OBJECTS_PER_SLAB * cache.size;
+    cache.slab_bytes = min_bytes.next_power_of_two().max(paging::PAGE_SIZE as usize);
+}
+
+pub fn init() {
+    register(THREAD, Layout::new::<crate::interrupts::task::Thread>());
+    register(PROCESS, arc_layout::<crate::interrupts::task::Process>());
+    register(PIPE, arc_layout::<crate::sync::Mutex<crate::fs::pipe::PipeBuffer>>());
+    register(VFS_NODE, Layout::new::<crate::fs::ext2::fs::Ext2Node>());
+}
+
+/// Cache serving allocations of `layout`, if any.
+pub fn cache_for(layout: &Layout) -> Option<usize> {
+    let caches = unsafe { &*(&raw const CACHES) };
+    caches.iter().position(|c| {
+        c.size != 0 && layout.align() <= c.align
+            && layout.size().max(size_of::<FreeObject>()).next_multiple_of(c.align) == c.size
+    })
+}
+
+/// Whether `ptr` came from a slab. Slabs live in the direct map, the heap above it.
+pub fn owns(ptr: *mut u8) -> bool {
+    let addr = ptr as u64;
+    addr >= paging::HHDM_OFFSET && addr < allocator::HEAP_BASE
+}
+
+pub unsafe fn alloc(cache: usize) -> *mut u8 {
+    let c = unsafe { &mut (*(&raw mut CACHES))[cache] };
+    c.lock();
+    if c.partial.is_null() {
+        match unsafe { c.new_slab() } {
+            Some(slab) => c.partial = slab,
+            None => {
+                c.unlock();
+                return core::ptr::null_mut();
+            }
+        }
+    }
+
+    let obj = unsafe {
+        let slab = c.partial;
+        let obj = (*slab).free;
+        (*slab).free = (*obj).next;
+        (*slab).in_use += 1;
+        if (*slab).free.is_null() {
+            c.partial = (*slab).next;
+            (*slab).next = core::ptr::null_mut();
+        }
+        obj
+    };
+    c.in_use += 1;
+    c.unlock();
+    obj as *mut u8
+}
+
+/// Returns an object of `cache` to its slab. An emptied slab goes back to
+/// the PMM unless it is the cache's only partial one.
+pub unsafe fn free(cache: usize, ptr: *mut u8) {
+    unsafe {
+        let c = &mut (*(&raw mut CACHES))[cache];
+        let slab = (ptr as usize & !(c.slab_bytes - 1)) as *mut Slab;
+
+        c.lock();
+        let was_full = (*slab).free.is_null();
+        let obj = ptr as *mut FreeObject;
+        (*obj).next = (*slab).free;
+        (*slab).free = obj;
+        (*slab).in_use -= 1;
+        c.in_use -= 1;
+
+        if was_full {
+            (*slab).next = c.partial;
+            c.partial = slab;
+        }
+        if (*slab).in_use == 0 && !(c.partial == slab && (*slab).next.is_null()) {
+            c.unlink(slab);
+            c.slabs -= 1;
+            pmm::free_frame(slab as u64 - paging::HHDM_OFFSET);
+        }
+        c.unlock();
+    }
+}
+
+/// Per-cache figures for `HeapStats`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub name: [u8; 16],
+    pub object_size: u64,
+    pub objects: u64,
+    pub slabs: u64,
+    pub slab_bytes: u64,
+}
+
+pub fn stats() -> [CacheStats; CACHE_COUNT] {
+    let caches = unsafe { &*(&raw const CACHES) };
+    core::array::from_fn(|i| {
+        let c = &caches[i];
+        let mut name = [0; 16];
+        let len = c.name.len().min(16);
+        name[..len].copy_from_slice(&c.name.as_bytes()[..len]);
+        CacheStats {
+            name,
+            object_size: c.size as u64,
+            objects: c.in_use as u64,
+            slabs: c.slabs as u64,
+            slab_bytes: c.slab_bytes as u64,
+        }
+    })
+}
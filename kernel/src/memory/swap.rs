@@ -0,0 +1,302 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::fs::vfs::InodeRef;
+use crate::memory::{paging, pmm, vmm};
+use crate::memory::address::PhysAddr;
+use crate::memory::paging::PageTableFlags;
+use crate::sync::Mutex;
+
+const ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+enum Device {
+    /// Raw sectors of an ATA disk from `start_lba` on.
+    Disk { disk: u8, start_lba: u64 },
+    /// A preallocated file; slots are written over its existing blocks.
+    File(InodeRef),
+}
+
+impl Device {
+    fn read(&mut self, slot: usize, buf: &mut [u8]) -> Result<(), String> {
+        match self {
+            Device::Disk { disk, start_lba } => {
+                crate::fs::disk::read(*start_lba + slot as u64 * (paging::PAGE_SIZE / 512), *disk, buf);
+                Ok(())
+            }
+            Device::File(node) => node.lock().read(slot as u64 * paging::PAGE_SIZE, buf).map(|_| ()),
+        }
+    }
+
+    fn write(&mut self, slot: usize, buf: &[u8]) -> Result<(), String> {
+        match self {
+            Device::Disk { disk, start_lba } => {
+                crate::fs::disk::write(*start_lba + slot as u64 * (paging::PAGE_SIZE / 512), *disk, buf);
+                Ok(())
+            }
+            Device::File(node) => node.lock().write(slot as u64 * paging::PAGE_SIZE, buf).map(|_| ()),
+        }
+    }
+}
+
+struct Swap {
+    device: Option<Device>,
+    /// Page tables naming each slot; 0 means free.
+    refs: Vec<u16>,
+    /// Process each slot is charged to.
+    owners: Vec<u64>,
+    /// Slots in use per owner.
+    usage: BTreeMap<u64, usize>,
+    /// First slot that may be free.
+    next_free: usize,
+    /// Address space the clock scan resumes at.
+    hand: usize,
+}
+
+impl Swap {
+    fn alloc_slot(&mut self, owner: u64) -> Option<usize> {
+        let slot = (self.next_free..self.refs.len()).find(|&s| self.refs[s] == 0)?;
+        self.refs[slot] = 1;
+        self.owners[slot] = owner;
+        *self.usage.entry(owner).or_insert(0) += 1;
+        self.next_free = slot + 1;
+        Some(slot)
+    }
+
+    fn put_slot(&mut self, slot: usize) {
+        if slot >= self.refs.len() || self.refs[slot] == 0 {
+            return;
+        }
+        self.refs[slot] -= 1;
+        if self.refs[slot] == 0 {
+            let owner = self.owners[slot];
+            if let Some(count) = self.usage.get_mut(&owner) {
+                *count -= 1;
+                if *count == 0 {
+                    self.usage.remove(&owner);
+                }
+            }
+            self.next_free = self.next_free.min(slot);
+        }
+    }
+}
+
+/// Held across every page-out and page-in, so slot contents never race.
+static SWAP: Mutex<Swap> = Mutex::new(Swap {
+    device: None,
+    refs: Vec::new(),
+    owners: Vec::new(),
+    usage: BTreeMap::new(),
+    next_free: 0,
+    hand: 0,
+});
+
+/// Whether `raw` is a page table entry for a page that lives in swap.
+pub fn is_swap_entry(raw: u64) -> bool {
+    (raw & (paging::PAGE_SWAPPED | paging::PAGE_PRESENT)) == paging::PAGE_SWAPPED
+}
+
+fn slot_of(raw: u64) -> usize {
+    ((raw & ADDR_MASK) >> 12) as usize
+}
+
+fn enable(device: Device, bytes: u64) -> Result<(), String> {
+    let slots = (bytes / paging::PAGE_SIZE) as usize;
+    if slots == 0 {
+        return Err(String::from("Swap area too small"));
+    }
+    let mut swap = SWAP.lock();
+    if swap.device.is_some() {
+        return Err(String::from("Swap already enabled"));
+    }
+    swap.device = Some(device);
+    swap.refs = vec![0; slots];
+    swap.owners = vec![0; slots];
+    swap.next_free = 0;
+    crate::debugln!("[Swap] {} KiB enabled", slots * 4);
+    Ok(())
+}
+
+/// Swaps to `bytes` of disk `disk` starting at `start_lba`, or to the rest
+/// of the disk for `bytes` of 0. The disk must not be mounted, and the area
+/// must lie on it. The virtio driver only serves the boot disk, so this needs ATA.
+pub fn enable_disk(disk: u8, start_lba: u64, bytes: u64) -> Result<(), String> {
+    if crate::fs::virtio::is_active() {
+        return Err(String::from("Swap disks need the ATA driver"));
+    }
+    if crate::fs::vfs::mount_point(disk).is_some() {
+        return Err(String::from("Disk is mounted"));
+    }
+    let sectors = crate::fs::disk::sector_count(disk).ok_or_else(|| String::from("No such disk"))?;
+    if start_lba >= sectors {
+        return Err(String::from("Start is past the end of the disk"));
+    }
+    let available = (sectors - start_lba) * 512;
+    let bytes = if bytes == 0 { available } else { bytes };
+    if bytes > available {
+        return Err(String::from("Swap area runs past the end of the disk"));
+    }
+    enable(Device::Disk { disk, start_lba }, bytes)
+}
+
+/// Swaps to `node`, first growing it with zeros to `bytes` so page-outs
+/// never allocate blocks. `bytes` of 0 uses the file's current size.
+pub fn enable_file(node: InodeRef, bytes: u64) -> Result<(), String> {
+    let bytes = {
+        let mut node = node.lock();
+        let bytes = if bytes == 0 { node.size() } else { bytes };
+        let zeros = vec![0u8; 64 * 1024];
+        let mut pos = node.size();
+        while pos < bytes {
+            let len = core::cmp::min(zeros.len() as u64, bytes - pos) as usize;
+            node.write(pos, &zeros[..len])?;
+            pos += len as u64;
+        }
+        bytes
+    };
+    enable(Device::File(node), bytes)
+}
+
+/// Bytes of swap holding pages of `pid`.
+pub fn usage_by_pid(pid: u64) -> u64 {
+    let pages = SWAP.lock().usage.get(&pid).copied().unwrap_or(0);
+    pages as u64 * paging::PAGE_SIZE
+}
+
+/// Takes another reference on the slot behind swap entry `raw`, for fork.
+pub fn retain(raw: u64) {
+    let mut swap = SWAP.lock();
+    let slot = slot_of(raw);
+    if slot < swap.refs.len() {
+        swap.refs[slot] += 1;
+    }
+}
+
+/// Drops the reference a page table held through swap entry `raw`.
+pub fn release(raw: u64) {
+    SWAP.lock().put_slot(slot_of(raw));
+}
+
+/// Reads the page behind swap entry `entry` into a fresh frame and maps it back.
+pub fn swap_in(entry: &mut paging::PageTableEntry, pid: u64) -> bool {
+    let raw = entry.as_u64();
+    let frame = match crate::memory::oom::allocate_frame(pid) {
+        Some(f) => f,
+        None => return false,
+    };
+
+    let mut swap = SWAP.lock();
+    let buf = unsafe { core::slice::from_raw_parts_mut((frame + paging::HHDM_OFFSET) as *mut u8, paging::PAGE_SIZE as usize) };
+    if swap.device.as_mut().map_or(true, |d| d.read(slot_of(raw), buf).is_err()) {
+        drop(swap);
+        pmm::free_frame(frame);
+        return false;
+    }
+    swap.put_slot(slot_of(raw));
+    let flags = (raw & !ADDR_MASK & !paging::PAGE_SWAPPED) | paging::PAGE_PRESENT;
+    entry.set_addr(PhysAddr::new(frame), PageTableFlags::from_bits_truncate(flags));
+    true
+}
+
+fn swappable(raw: u64, pid: u64) -> bool {
+    let wanted = paging::PAGE_PRESENT | paging::PAGE_USER;
+    (raw & wanted) == wanted
+        && (raw & (paging::PAGE_PINNED | paging::PAGE_COW)) == 0
+        && pmm::owner_of(raw & ADDR_MASK) == Some(pid)
+}
+
+/// Live user address spaces as `(pid, pml4)`, one per process.
+fn address_spaces() -> Vec<(u64, u64)> {
+    let tm = crate::interrupts::task::TASK_MANAGER.int_lock();
+    let mut spaces: Vec<(u64, u64)> = Vec::new();
+    for thread in tm.tasks.iter().flatten() {
+        if matches!(thread.state, crate::interrupts::task::ThreadState::Null | crate::interrupts::task::ThreadState::Zombie) {
+            continue;
+        }
+        if let Some(proc) = thread.process.as_ref() {
+            if proc.pid != 0 && !spaces.iter().any(|&(_, pml4)| pml4 == proc.pml4_phys) {
+                spaces.push((proc.pid, proc.pml4_phys));
+            }
+        }
+    }
+    spaces
+}
+
+/// Pages out up to `wanted` user pages with a clock scan over every process:
+/// a page whose accessed bit is set gets it cleared and a second chance, one
+/// found clear is written to swap and its frame freed. Returns pages freed.
+pub fn reclaim(wanted: usize) -> usize {
+    // The scan needs the task list; callers holding it get no help.
+    if crate::interrupts::task::TASK_MANAGER.held_by_this_cpu() {
+        return 0;
+    }
+    let spaces = address_spaces();
+    let mut swap = SWAP.lock();
+    if swap.device.is_none() || spaces.is_empty() {
+        return 0;
+    }
+
+    let cr3: u64;
+    unsafe { core::arch::asm!("mov {}, cr3", out(reg) cr3); }
+    let active = cr3 & ADDR_MASK;
+
+    let start = swap.hand;
+    let mut freed = 0;
+    // Two turns of the hand: pages referenced on the first are fair game on the second.
+    for step in 0..2 * spaces.len() {
+        if freed >= wanted {
+            break;
+        }
+        let (pid, pml4) = spaces[(start + step) % spaces.len()];
+        swap.hand = (start + step + 1) % spaces.len();
+
+        let mut victims = Vec::new();
+        unsafe {
+            vmm::for_each_user_page(pml4, |virt, entry| {
+                let raw = entry.as_u64();
+                if (raw & paging::PAGE_ACCESSED) != 0 {
+                    entry.set_flags(PageTableFlags::from_bits_truncate(raw & !paging::PAGE_ACCESSED));
+                } else if victims.len() < wanted - freed && swappable(raw, pid) {
+                    victims.push(virt);
+                }
+            });
+        }
+
+        // Unmap first, so nothing writes to a frame after it is copied out.
+        let mut evicted = Vec::new();
+        for virt in victims {
+            let entry = match unsafe { vmm::get_pte(virt, pml4) } {
+                Some(e) => e,
+                None => continue,
+            };
+            let slot = match swap.alloc_slot(pid) {
+                Some(s) => s,
+                None => break,
+            };
+            let raw = entry.as_u64();
+            let flags = (raw & !ADDR_MASK & !paging::PAGE_PRESENT) | paging::PAGE_SWAPPED;
+            entry.set_addr(PhysAddr::new((slot as u64) << 12), PageTableFlags::from_bits_truncate(flags));
+            if pml4 == active {
+                unsafe { core::arch::asm!("invlpg [{}]", in(reg) virt, options(nostack, preserves_flags)); }
+            }
+            evicted.push((virt, raw, slot));
+        }
+        if evicted.is_empty() {
+            continue;
+        }
+        crate::smp::flush_tlb_others(pml4);
+
+        for (virt, raw, slot) in evicted {
+            let phys = raw & ADDR_MASK;
+            let data = unsafe { core::slice::from_raw_parts((phys + paging::HHDM_OFFSET) as *const u8, paging::PAGE_SIZE as usize) };
+            if swap.device.as_mut().unwrap().write(slot, data).is_ok() {
+                pmm::free_single_frame(phys);
+                freed += 1;
+            } else if let Some(entry) = unsafe { vmm::get_pte(virt, pml4) } {
+                entry.set_addr(PhysAddr::new(phys), PageTableFlags::from_bits_truncate(raw & !ADDR_MASK));
+                swap.put_slot(slot);
+            }
+        }
+    }
+    freed
+}
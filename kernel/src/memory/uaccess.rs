@@ -0,0 +1,206 @@
+use alloc::vec::Vec;
+use core::arch::{asm, global_asm, x86_64::__cpuid_count};
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::interrupts::task::CPUState;
+use crate::memory::{paging, vma};
+
+const CR4_SMEP: u64 = 1 << 20;
+const CR4_SMAP: u64 = 1 << 21;
+
+/// Whether `stac`/`clac` exist; they fault on CPUs without SMAP.
+static SMAP: AtomicBool = AtomicBool::new(false);
+
+// rdi = destination, rsi = source, rdx = length. Returns the bytes not copied.
+// A fault on the `rep movsb` resumes at `uaccess_copy_done` with rcx still
+// counting what is left.
+global_asm!(
+    ".global uaccess_copy",
+    ".global uaccess_copy_insn",
+    ".global uaccess_copy_done",
+    "uaccess_copy:",
+    "    mov rcx, rdx",
+    "uaccess_copy_insn:",
+    "    rep movsb",
+    "uaccess_copy_done:",
+    "    mov rax, rcx",
+    "    ret",
+);
+
+unsafe extern "C" {
+    fn uaccess_copy(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    static uaccess_copy_insn: u8;
+    static uaccess_copy_done: u8;
+}
+
+/// Turns on SMEP and SMAP where the CPU has them. The kernel then faults on
+/// executing user pages, and on touching them outside the copy helpers here.
+pub fn enable_smep_smap() {
+    let ebx = __cpuid_count(7, 0).ebx;
+    let mut bits = 0;
+    if (ebx & (1 << 7)) != 0 {
+        bits |= CR4_SMEP;
+    }
+    if (ebx & (1 << 20)) != 0 {
+        bits |= CR4_SMAP;
+        SMAP.store(true, Ordering::Relaxed);
+    }
+    unsafe {
+        let mut cr4: u64;
+        asm!("mov {}, cr4", out(reg) cr4);
+        cr4 |= bits;
+        asm!("mov cr4, {}", in(reg) cr4);
+    }
+}
+
+/// Sends a kernel page fault in the copy routine, one demand paging could not
+/// resolve, back to its caller as a short copy. Returns false for any other fault.
+pub fn fixup(state: &mut CPUState) -> bool {
+    if state.rip != (&raw const uaccess_copy_insn) as u64 {
+        return false;
+    }
+    state.rip = (&raw const uaccess_copy_done) as u64;
+    true
+}
+
+/// Whether the current process may access `len` bytes at `addr`: the range
+/// lies below `USER_LIMIT` inside its VMAs, all writable if `write`.
+pub fn access_ok(addr: u64, len: usize, write: bool) -> bool {
+    if len == 0 {
+        return true;
+    }
+    let end = match addr.checked_add(len as u64) {
+        Some(end) if end <= vma::USER_LIMIT => end,
+        _ => return false,
+    };
+    let proc = crate::interrupts::task::current_process_ptr();
+    if proc.is_null() {
+        return false;
+    }
+    let vmas = unsafe { &*proc }.vmas.int_lock();
+    let mut at = addr;
+    while at < end {
+        match vmas.find(at) {
+            Some(v) if v.accessible() && (!write || v.writable()) => at = v.end,
+            _ => return false,
+        }
+    }
+    true
+}
+
+unsafe fn copy(dst: *mut u8, src: *const u8, len: usize) -> bool {
+    let smap = SMAP.load(Ordering::Relaxed);
+    unsafe {
+        if smap {
+            asm!("stac", options(nostack));
+        }
+        let left = uaccess_copy(dst, src, len);
+        if smap {
+            asm!("clac", options(nostack));
+        }
+        left == 0
+    }
+}
+
+/// Types for which every bit pattern is a valid value, so they may be filled
+/// straight from user memory.
+///
+/// # Safety
+/// Only for integers, and arrays, tuples and structs made of nothing else:
+/// no `bool`, enums, references or other types with invalid values.
+pub unsafe trait Pod: Copy {}
+
+macro_rules! impl_pod {
+    ($($t:ty),*) => { $(unsafe impl Pod for $t {})* };
+}
+impl_pod!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+unsafe impl<A: Pod, B: Pod> Pod for (A, B) {}
+
+// The copies below may page in user memory, so callers must not hold the task
+// manager or the caller's VMA list.
+
+/// Copies `dst.len()` bytes from user address `src`. False if any of it is not readable.
+pub fn copy_from_user(dst: &mut [u8], src: u64) -> bool {
+    access_ok(src, dst.len(), false) && unsafe { copy(dst.as_mut_ptr(), src as *const u8, dst.len()) }
+}
+
+/// Pages in `len` bytes at `addr`, breaking copy-on-write if `write`, so a
+/// copy right after cannot fault. For callers on an exception stack, where a
+/// nested page fault would reuse the same stack.
+pub fn fault_in(addr: u64, len: usize, write: bool) -> bool {
+    if !access_ok(addr, len, write) {
+        return false;
+    }
+    let pid = crate::interrupts::task::current_pid();
+    let end = addr + len as u64;
+    let mut page = addr & !(paging::PAGE_SIZE - 1);
+    while page < end {
+        if crate::memory::vmm::user_virt_to_hhdm(page, pid, write).is_none() {
+            return false;
+        }
+        page += paging::PAGE_SIZE;
+    }
+    true
+}
+
+/// Copies `src` to user address `dst`. False if any of it is not writable.
+pub fn copy_to_user(dst: u64, src: &[u8]) -> bool {
+    access_ok(dst, src.len(), true) && unsafe { copy(dst as *mut u8, src.as_ptr(), src.len()) }
+}
+
+pub fn read_user<T: Pod>(src: u64) -> Option<T> {
+    let mut value = core::mem::MaybeUninit::<T>::uninit();
+    let len = size_of::<T>();
+    if access_ok(src, len, false) && unsafe { copy(value.as_mut_ptr() as *mut u8, src as *const u8, len) } {
+        Some(unsafe { value.assume_init() })
+    } else {
+        None
+    }
+}
+
+pub fn write_user<T: Copy>(dst: u64, value: &T) -> bool {
+    write_user_slice(dst, core::slice::from_ref(value))
+}
+
+/// Reads `count` values of `T` from user address `src`.
+pub fn read_user_slice<T: Pod>(src: u64, count: usize) -> Option<Vec<T>> {
+    let len = count.checked_mul(size_of::<T>())?;
+    if !access_ok(src, len, false) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(count);
+    if !unsafe { copy(out.as_mut_ptr() as *mut u8, src as *const u8, len) } {
+        return None;
+    }
+    unsafe { out.set_len(count); }
+    Some(out)
+}
+
+pub fn write_user_slice<T: Copy>(dst: u64, values: &[T]) -> bool {
+    let len = size_of_val(values);
+    access_ok(dst, len, true) && unsafe { copy(dst as *mut u8, values.as_ptr() as *const u8, len) }
+}
+
+/// Copies a NUL-terminated string from user address `src`, without the NUL.
+/// Stops after `max` bytes if no terminator comes first.
+pub fn strncpy_from_user(src: u64, max: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut chunk = [0u8; 256];
+    while out.len() < max {
+        let at = src.checked_add(out.len() as u64)?;
+        let len = (max - out.len())
+            .min(chunk.len())
+            .min((paging::PAGE_SIZE - (at & 0xFFF)) as usize);
+        if !copy_from_user(&mut chunk[..len], at) {
+            return None;
+        }
+        match chunk[..len].iter().position(|&c| c == 0) {
+            Some(nul) => {
+                out.extend_from_slice(&chunk[..nul]);
+                return Some(out);
+            }
+            None => out.extend_from_slice(&chunk[..len]),
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,382 @@
+use crate::memory::{filemap, oom, paging, pmm, shm, swap, vmm};
+use crate::memory::address::PhysAddr;
+use crate::memory::paging::PageTableFlags;
+use crate::fs::vfs;
+use alloc::vec::Vec;
+
+/// Start of the `mmap` area without randomisation; see `aslr::Layout`.
+pub const MMAP_BASE: u64 = 0x0000_1000_0000_0000;
+pub const USER_LIMIT: u64 = 0x0000_8000_0000_0000;
+
+pub const PROT_NONE: u64 = 0;
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+
+pub const MAP_SHARED: u64 = 0x01;
+pub const MAP_PRIVATE: u64 = 0x02;
+pub const MAP_FIXED: u64 = 0x10;
+pub const MAP_ANONYMOUS: u64 = 0x20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaKind {
+    Image,
+    Heap,
+    Stack,
+    Anonymous,
+}
+
+/// Where the contents of a page come from when it is first touched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    Anonymous,
+    /// `fd` is a global open-file slot the area holds a reference on; `offset`
+    /// is the file position that corresponds to the area's `start`.
+    File { fd: usize, offset: u64 },
+    /// Shared memory object `id`; `offset` is the byte of the object at `start`.
+    Shm { id: usize, offset: u64 },
+}
+
+fn file_node(fd: usize) -> Option<vfs::InodeRef> {
+    match vfs::get_file(fd) {
+        Some(vfs::FileHandle::File { node, .. }) => Some(node.clone()),
+        _ => None,
+    }
+}
+
+impl Backing {
+    /// Takes another reference on the open file or shared memory object.
+    pub fn retain(&self) {
+        match *self {
+            Backing::File { fd, .. } => {
+                vfs::increment_ref(fd);
+                if let Some(node) = file_node(fd) {
+                    filemap::retain(&node);
+                }
+            }
+            Backing::Shm { id, .. } => shm::retain(id),
+            Backing::Anonymous => {}
+        }
+    }
+
+    pub fn release(&self) {
+        match *self {
+            Backing::File { fd, .. } => {
+                if let Some(node) = file_node(fd) {
+                    filemap::release(&node);
+                }
+                vfs::close_file(fd);
+            }
+            Backing::Shm { id, .. } => shm::release(id),
+            Backing::Anonymous => {}
+        }
+    }
+}
+
+/// A page-aligned range of user address space. Pages inside it that are not
+/// yet present are filled from `backing` (or zeroed) on first touch.
+#[derive(Debug, Clone, Copy)]
+pub struct Vma {
+    pub start: u64,
+    pub end: u64,
+    pub flags: u64,
+    pub kind: VmaKind,
+    pub backing: Backing,
+    /// Writes are visible to other mappers and written back to the file.
+    pub shared: bool,
+}
+
+impl Vma {
+    pub fn new(start: u64, end: u64, flags: u64, kind: VmaKind) -> Self {
+        Self {
+            start,
+            end,
+            flags: flags | paging::PAGE_PRESENT | paging::PAGE_USER,
+            kind,
+            backing: Backing::Anonymous,
+            shared: false,
+        }
+    }
+
+    /// Area created by `mmap`. `PROT_NONE` areas reserve address space but never fault in.
+    pub fn mapped(start: u64, end: u64, prot: u64, backing: Backing, shared: bool) -> Self {
+        let mut vma = Self::new(start, end, 0, VmaKind::Anonymous);
+        if (prot & PROT_WRITE) != 0 {
+            vma.flags |= paging::PAGE_WRITABLE;
+        }
+        if (prot & (PROT_READ | PROT_WRITE | PROT_EXEC)) == 0 {
+            vma.flags &= !paging::PAGE_PRESENT;
+        }
+        if shared {
+            vma.flags |= paging::PAGE_PINNED;
+        }
+        Self { backing, shared, ..vma }
+    }
+
+    pub fn accessible(&self) -> bool {
+        (self.flags & paging::PAGE_PRESENT) != 0
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.start && addr < self.end
+    }
+
+    pub fn writable(&self) -> bool {
+        (self.flags & paging::PAGE_WRITABLE) != 0
+    }
+
+    /// Same area narrowed to `[start, end)`, with the file offset moved along.
+    fn slice(&self, start: u64, end: u64) -> Self {
+        let backing = match self.backing {
+            Backing::File { fd, offset } => Backing::File { fd, offset: offset + (start - self.start) },
+            Backing::Shm { id, offset } => Backing::Shm { id, offset: offset + (start - self.start) },
+            Backing::Anonymous => Backing::Anonymous,
+        };
+        Self { start, end, backing, ..*self }
+    }
+}
+
+/// Sorted, non-overlapping list of the areas a process may touch. Every
+/// file or shm backed area owns one reference on its backing object.
+#[derive(Debug)]
+pub struct VmaList {
+    areas: Vec<Vma>,
+}
+
+impl Clone for VmaList {
+    fn clone(&self) -> Self {
+        for v in self.areas.iter() {
+            v.backing.retain();
+        }
+        Self { areas: self.areas.clone() }
+    }
+}
+
+impl Drop for VmaList {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl VmaList {
+    pub const fn new() -> Self {
+        Self { areas: Vec::new() }
+    }
+
+    pub fn extend(&mut self, other: &VmaList) {
+        for v in other.areas.iter() {
+            v.backing.retain();
+            self.insert(*v);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for v in self.areas.drain(..) {
+            v.backing.release();
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.areas.iter()
+    }
+
+    /// Bytes of address space covered by all areas, for `RLIMIT_AS`.
+    pub fn total_size(&self) -> u64 {
+        self.areas.iter().map(|v| v.end - v.start).sum()
+    }
+
+    pub fn find(&self, addr: u64) -> Option<Vma> {
+        self.areas.iter().find(|v| v.contains(addr)).copied()
+    }
+
+    /// Adds `vma`, replacing whatever part of existing areas it overlaps.
+    /// A file or shm backed `vma` must already carry its own reference.
+    pub fn insert(&mut self, vma: Vma) {
+        self.remove(vma.start, vma.end);
+        let pos = self.areas.iter().position(|v| v.start > vma.start).unwrap_or(self.areas.len());
+        self.areas.insert(pos, vma);
+    }
+
+    /// Drops `[start, end)` from the list, splitting areas that straddle it.
+    pub fn remove(&mut self, start: u64, end: u64) {
+        let mut result = Vec::with_capacity(self.areas.len() + 1);
+        for v in self.areas.drain(..) {
+            if v.end <= start || v.start >= end {
+                result.push(v);
+                continue;
+            }
+            let keep_left = v.start < start;
+            let keep_right = v.end > end;
+            if keep_left {
+                result.push(v.slice(v.start, start));
+            }
+            if keep_right {
+                result.push(v.slice(end, v.end));
+            }
+            match (keep_left, keep_right) {
+                (true, true) => v.backing.retain(),
+                (false, false) => v.backing.release(),
+                _ => {}
+            }
+        }
+        self.areas = result;
+    }
+
+    /// Moves the end of the area starting at `start`, creating it if needed.
+    pub fn resize(&mut self, start: u64, end: u64, flags: u64, kind: VmaKind) {
+        self.areas.retain(|v| !(v.start == start && v.kind == kind));
+        if end > start {
+            self.insert(Vma::new(start, end, flags, kind));
+        }
+    }
+
+    /// First-fit search for `len` bytes of unused address space at or above `hint`.
+    pub fn find_free(&self, hint: u64, len: u64) -> Option<u64> {
+        let mut candidate = hint;
+        for v in self.areas.iter() {
+            if v.end <= candidate {
+                continue;
+            }
+            if v.start >= candidate.saturating_add(len) {
+                break;
+            }
+            candidate = v.end;
+        }
+        if candidate.checked_add(len).map_or(false, |end| end <= USER_LIMIT) { Some(candidate) } else { None }
+    }
+}
+
+/// Maps `frame`, on which the caller took a shared reference, dropping the
+/// reference if that fails.
+fn map_shared(vma: &Vma, page: u64, frame: Option<u64>, pml4_phys: u64) -> bool {
+    match frame {
+        Some(frame) => {
+            let mapped = vmm::map_page(page, PhysAddr::new(frame), vma.flags, Some(pml4_phys)).is_ok();
+            if !mapped {
+                pmm::release_shared_frame(frame);
+            }
+            mapped
+        }
+        None => false,
+    }
+}
+
+/// Maps a frame for `virt` according to `vma`: the page read back from swap,
+/// the shm object's own frame, the file's shared page for `MAP_SHARED`, or a
+/// zeroed one filled from the backing file if there is one. Bytes past end of
+/// file stay zero.
+pub fn populate(vma: &Vma, virt: u64, pml4_phys: u64, pid: u64) -> bool {
+    let page = virt & !(paging::PAGE_SIZE - 1);
+    if let Some(entry) = unsafe { vmm::get_pte(page, pml4_phys) } {
+        if swap::is_swap_entry(entry.as_u64()) {
+            return swap::swap_in(entry, pid);
+        }
+    }
+    match vma.backing {
+        Backing::Shm { id, offset } => {
+            return map_shared(vma, page, shm::share_page(id, offset + (page - vma.start)), pml4_phys);
+        }
+        Backing::File { fd, offset } if vma.shared => {
+            let frame = file_node(fd).and_then(|node| filemap::share_page(&node, offset + (page - vma.start), pid));
+            return map_shared(vma, page, frame, pml4_phys);
+        }
+        _ => {}
+    }
+
+    let frame = match oom::allocate_frame(pid) {
+        Some(f) => f,
+        None => return false,
+    };
+    let buf = unsafe {
+        let ptr = (frame + paging::HHDM_OFFSET) as *mut u8;
+        core::ptr::write_bytes(ptr, 0, paging::PAGE_SIZE as usize);
+        core::slice::from_raw_parts_mut(ptr, paging::PAGE_SIZE as usize)
+    };
+
+    if let Backing::File { fd, offset } = vma.backing {
+        if let Some(vfs::FileHandle::File { node, .. }) = vfs::get_file(fd) {
+            let pos = offset + (page - vma.start);
+            let mut node = node.lock();
+            if pos < node.size() && node.read(pos, buf).is_err() {
+                pmm::free_frame(frame);
+                return false;
+            }
+        }
+    }
+
+    if vmm::map_page(page, PhysAddr::new(frame), vma.flags, Some(pml4_phys)).is_err() {
+        pmm::free_frame(frame);
+        return false;
+    }
+    true
+}
+
+/// Writes dirty pages of shared file-backed areas inside `[start, end)` back
+/// to their files and marks them clean.
+pub fn sync_range(vmas: &VmaList, pml4_phys: u64, start: u64, end: u64) {
+    let mut cleaned = false;
+    for v in vmas.iter() {
+        let (fd, offset) = match v.backing {
+            Backing::File { fd, offset } if v.shared && v.writable() => (fd, offset),
+            _ => continue,
+        };
+        let mut node = match vfs::get_file(fd) {
+            Some(vfs::FileHandle::File { node, .. }) => node.lock(),
+            _ => continue,
+        };
+
+        let mut page = core::cmp::max(v.start, start & !(paging::PAGE_SIZE - 1));
+        while page < core::cmp::min(v.end, end) {
+            unsafe {
+                if let Some(entry) = vmm::get_pte(page, pml4_phys) {
+                    let raw = entry.as_u64();
+                    if (raw & paging::PAGE_PRESENT) != 0 && (raw & paging::PAGE_DIRTY) != 0 {
+                        let pos = offset + (page - v.start);
+                        let size = node.size();
+                        if pos < size {
+                            let len = core::cmp::min(paging::PAGE_SIZE, size - pos) as usize;
+                            let data = core::slice::from_raw_parts((entry.addr().as_u64() + paging::HHDM_OFFSET) as *const u8, len);
+                            let _ = node.write(pos, data);
+                        }
+                        entry.set_flags(PageTableFlags::from_bits_truncate(raw & !paging::PAGE_DIRTY));
+                        core::arch::asm!("invlpg [{}]", in(reg) page, options(nostack, preserves_flags));
+                        cleaned = true;
+                    }
+                }
+            }
+            page += paging::PAGE_SIZE;
+        }
+    }
+    // Stale entries elsewhere would write without setting the dirty bit again.
+    if cleaned {
+        crate::smp::flush_tlb_others(pml4_phys);
+    }
+}
+
+/// Resolves a not-present fault at `virt` for the running process. Returns false
+/// when the address is outside every area or the access is not permitted.
+pub fn handle_demand_fault(virt: u64, write: bool) -> bool {
+    if virt >= USER_LIMIT {
+        return false;
+    }
+    unsafe {
+        let proc = crate::interrupts::task::current_process_ptr();
+        if proc.is_null() {
+            return false;
+        }
+        let proc = &*proc;
+
+        let vmas = proc.vmas.int_lock();
+        let vma = match vmas.find(virt) {
+            Some(v) => v,
+            None => return false,
+        };
+        if !vma.accessible() || (write && !vma.writable()) {
+            return false;
+        }
+        if vmm::get_phys(virt, proc.pml4_phys).is_some() {
+            return true;
+        }
+        populate(&vma, virt, proc.pml4_phys, proc.pid)
+    }
+}
@@ -3,6 +3,9 @@ use crate::memory::address::PhysAddr;
 use crate::memory::paging::PageTableFlags;
 use core::arch::asm;
 
+const CR0_WRITE_PROTECT: u64 = 1 << 16;
+const PTE_FLAGS_MASK: u64 = !0x000F_FFFF_FFFF_F000;
+
 pub fn init() {
     unsafe {
         
@@ -22,22 +25,42 @@ pub fn init() {
         
         let pml4_virt = paging::phys_to_virt(new_pml4_addr);
         let pml4 = unsafe { &mut *(pml4_virt.as_mut_ptr() as *mut paging::PageTable) };
-        let p4_idx = (KERNEL_MAPPING_HEAD >> 39) & 0x1FF;
-        
-        if pml4[p4_idx as usize].is_unused() {
-             let pdpt_frame = pmm::allocate_frame(0).expect("VMM: OOM for Shared Kernel PDPT");
-             let mut entry = paging::PageTableEntry::new();
-             entry.set_addr(PhysAddr::new(pdpt_frame), 
-                paging::PageTableFlags::PRESENT | paging::PageTableFlags::WRITABLE);
-             pml4[p4_idx as usize] = entry;
-             
-             let pdpt_virt = paging::phys_to_virt(PhysAddr::new(pdpt_frame));
-             core::ptr::write_bytes(pdpt_virt.as_mut_ptr::<u8>(), 0, 4096);
+        for base in [KERNEL_MAPPING_HEAD, crate::memory::allocator::HEAP_BASE] {
+            let p4_idx = (base >> 39) & 0x1FF;
+
+            if pml4[p4_idx as usize].is_unused() {
+                 let pdpt_frame = pmm::allocate_frame(0).expect("VMM: OOM for Shared Kernel PDPT");
+                 let mut entry = paging::PageTableEntry::new();
+                 entry.set_addr(PhysAddr::new(pdpt_frame), 
+                    paging::PageTableFlags::PRESENT | paging::PageTableFlags::WRITABLE);
+                 pml4[p4_idx as usize] = entry;
+                 
+                 let pdpt_virt = paging::phys_to_virt(PhysAddr::new(pdpt_frame));
+                 core::ptr::write_bytes(pdpt_virt.as_mut_ptr::<u8>(), 0, 4096);
+            }
+        }
+
+        // The bootloader maps the kernel and direct map user-accessible. Every
+        // user PML4 copies these entries, so clearing the bit here is enough
+        // for SMEP and SMAP to treat the upper half as supervisor memory.
+        for i in core::iter::once(0).chain(256..512) {
+            let mut flags = pml4[i].flags();
+            if flags.contains(paging::PageTableFlags::PRESENT) {
+                flags.remove(paging::PageTableFlags::USER_ACCESSIBLE);
+                pml4[i].set_flags(flags);
+            }
         }
 
-        
         asm!("mov cr3, {}", in(reg) new_pml4_phys);
 
+        // Make supervisor writes honour read-only PTEs, so the kernel copying into
+        // a copy-on-write user page faults like the user would.
+        let mut cr0: u64;
+        asm!("mov {}, cr0", out(reg) cr0);
+        cr0 |= CR0_WRITE_PROTECT;
+        asm!("mov cr0, {}", in(reg) cr0);
+        crate::memory::uaccess::enable_smep_smap();
+
         
         (*(&raw mut crate::boot::BOOT_INFO)).pml4 = new_pml4_phys;
 
@@ -45,7 +68,9 @@ pub fn init() {
     }
 }
 
-pub fn map_page(virt: u64, phys: PhysAddr, flags: u64, target_pml4_phys: Option<u64>) {
+/// Maps `virt` to `phys` in `target_pml4_phys`, or the active space for None.
+/// Fails with nothing mapped if a page table cannot be allocated.
+pub fn map_page(virt: u64, phys: PhysAddr, flags: u64, target_pml4_phys: Option<u64>) -> Result<(), pmm::FrameError> {
     unsafe {
         let pml4_table = if let Some(pml4_addr) = target_pml4_phys {
             paging::get_table_from_phys(pml4_addr).expect("VMM: Invalid target PML4")
@@ -59,11 +84,18 @@ pub fn map_page(virt: u64, phys: PhysAddr, flags: u64, target_pml4_phys: Option<
         let p1_idx = (virt >> 12) & 0x1FF;
 
         let is_user = (flags & paging::PAGE_USER) != 0;
+        // User page tables may evict or kill under pressure; kernel ones, such
+        // as the heap's, are allocated with allocator locks held and must not.
+        let table_frame = || if virt < crate::memory::vma::USER_LIMIT {
+            crate::memory::oom::allocate_frame(0)
+        } else {
+            pmm::allocate_frame(0)
+        };
 
         
         let mut p3_entry = pml4_table[p4_idx as usize];
         if p3_entry.is_unused() {
-            let frame = pmm::allocate_frame(0).expect("VMM: OOM for PDPT");
+            let frame = table_frame().ok_or(pmm::FrameError::NoMemory)?;
             let mut new_entry = paging::PageTableEntry::new();
             let table_flags = paging::PageTableFlags::from_bits_truncate(paging::PAGE_PRESENT | paging::PAGE_WRITABLE | paging::PAGE_USER);
             new_entry.set_addr(PhysAddr::new(frame), table_flags);
@@ -80,7 +112,7 @@ pub fn map_page(virt: u64, phys: PhysAddr, flags: u64, target_pml4_phys: Option<
         
         let mut p2_entry = p3[p3_idx as usize];
         if p2_entry.is_unused() {
-            let frame = pmm::allocate_frame(0).expect("VMM: OOM for PD");
+            let frame = table_frame().ok_or(pmm::FrameError::NoMemory)?;
             let mut new_entry = paging::PageTableEntry::new();
             let table_flags = paging::PageTableFlags::from_bits_truncate(paging::PAGE_PRESENT | paging::PAGE_WRITABLE | paging::PAGE_USER);
             new_entry.set_addr(PhysAddr::new(frame), table_flags);
@@ -89,7 +121,7 @@ pub fn map_page(virt: u64, phys: PhysAddr, flags: u64, target_pml4_phys: Option<
             p2_entry = new_entry;
         } else if (p2_entry.as_u64() & paging::PAGE_HUGE) != 0 {
             
-            let frame = pmm::allocate_frame(0).expect("VMM: OOM for L2 shattering");
+            let frame = pmm::allocate_frame(0).ok_or(pmm::FrameError::NoMemory)?;
             let new_table = paging::get_table_from_phys(frame).unwrap();
             let base_phys = p2_entry.addr().as_u64();
             let huge_flags = paging::PageTableFlags::from_bits_truncate(p2_entry.as_u64() & 0xFFF);
@@ -110,7 +142,7 @@ pub fn map_page(virt: u64, phys: PhysAddr, flags: u64, target_pml4_phys: Option<
         
         let mut p1_entry = p2[p2_idx as usize];
         if p1_entry.is_unused() {
-            let frame = pmm::allocate_frame(0).expect("VMM: OOM for PT");
+            let frame = table_frame().ok_or(pmm::FrameError::NoMemory)?;
             let mut new_entry = paging::PageTableEntry::new();
             let table_flags = paging::PageTableFlags::from_bits_truncate(paging::PAGE_PRESENT | paging::PAGE_WRITABLE | paging::PAGE_USER);
             new_entry.set_addr(PhysAddr::new(frame), table_flags);
@@ -119,7 +151,7 @@ pub fn map_page(virt: u64, phys: PhysAddr, flags: u64, target_pml4_phys: Option<
             p1_entry = new_entry;
         } else if (p1_entry.as_u64() & paging::PAGE_HUGE) != 0 {
             
-            let frame = pmm::allocate_frame(0).expect("VMM: OOM for L1 shattering");
+            let frame = pmm::allocate_frame(0).ok_or(pmm::FrameError::NoMemory)?;
             let new_table = paging::get_table_from_phys(frame).unwrap();
             let base_phys = p1_entry.addr().as_u64();
             let huge_flags = paging::PageTableFlags::from_bits_truncate(p1_entry.as_u64() & 0xFFF);
@@ -150,6 +182,7 @@ pub fn map_page(virt: u64, phys: PhysAddr, flags: u64, target_pml4_phys: Option<
             asm!("invlpg [{}]", in(reg) virt);
         }
     }
+    Ok(())
 }
 
 pub unsafe fn create_user_pml4() -> Option<u64> {
@@ -196,10 +229,281 @@ pub unsafe fn get_phys(virt: u64, pml4_phys: u64) -> Option<u64> {
     let p1 = &*(paging::phys_to_virt(p1_entry.addr()).as_ptr() as *const paging::PageTable);
 
     let final_entry = p1[p1_idx as usize];
-    if final_entry.is_unused() { return None; }
+    if (final_entry.as_u64() & paging::PAGE_PRESENT) == 0 { return None; }
     Some(final_entry.addr().as_u64() + (virt & 0xFFF))
 }
 
+pub unsafe fn get_pte(virt: u64, pml4_phys: u64) -> Option<&'static mut paging::PageTableEntry> {
+    let pml4 = paging::get_table_from_phys(pml4_phys)?;
+
+    let p3_entry = pml4[((virt >> 39) & 0x1FF) as usize];
+    if p3_entry.is_unused() { return None; }
+    let p3 = paging::get_table_from_phys(p3_entry.addr().as_u64())?;
+
+    let p2_entry = p3[((virt >> 30) & 0x1FF) as usize];
+    if p2_entry.is_unused() || (p2_entry.as_u64() & paging::PAGE_HUGE) != 0 { return None; }
+    let p2 = paging::get_table_from_phys(p2_entry.addr().as_u64())?;
+
+    let p1_entry = p2[((virt >> 21) & 0x1FF) as usize];
+    if p1_entry.is_unused() || (p1_entry.as_u64() & paging::PAGE_HUGE) != 0 { return None; }
+    let p1 = paging::get_table_from_phys(p1_entry.addr().as_u64())?;
+
+    let entry = &mut p1[((virt >> 12) & 0x1FF) as usize];
+    if entry.is_unused() { None } else { Some(entry) }
+}
+
+/// Calls `f` for every present 4 KiB page in the user half of `pml4_phys`.
+pub unsafe fn for_each_user_page<F: FnMut(u64, &mut paging::PageTableEntry)>(pml4_phys: u64, f: F) {
+    walk_user_entries(pml4_phys, paging::PAGE_PRESENT, f);
+}
+
+/// Calls `f` for every user page of `pml4_phys` that is out in swap.
+pub unsafe fn for_each_swapped_page<F: FnMut(u64, &mut paging::PageTableEntry)>(pml4_phys: u64, f: F) {
+    walk_user_entries(pml4_phys, paging::PAGE_SWAPPED, f);
+}
+
+unsafe fn walk_user_entries<F: FnMut(u64, &mut paging::PageTableEntry)>(pml4_phys: u64, bit: u64, mut f: F) {
+    let pml4 = match paging::get_table_from_phys(pml4_phys) { Some(t) => t, None => return };
+
+    for i4 in 0..256 {
+        if pml4[i4].is_unused() { continue; }
+        let p3 = paging::get_table_from_phys(pml4[i4].addr().as_u64()).unwrap();
+        for i3 in 0..512 {
+            if p3[i3].is_unused() || (p3[i3].as_u64() & paging::PAGE_HUGE) != 0 { continue; }
+            let p2 = paging::get_table_from_phys(p3[i3].addr().as_u64()).unwrap();
+            for i2 in 0..512 {
+                if p2[i2].is_unused() || (p2[i2].as_u64() & paging::PAGE_HUGE) != 0 { continue; }
+                let p1 = paging::get_table_from_phys(p2[i2].addr().as_u64()).unwrap();
+                for i1 in 0..512 {
+                    if (p1[i1].as_u64() & bit) == 0 { continue; }
+                    let virt = ((i4 as u64) << 39) | ((i3 as u64) << 30) | ((i2 as u64) << 21) | ((i1 as u64) << 12);
+                    f(virt, &mut p1[i1]);
+                }
+            }
+        }
+    }
+}
+
+/// Maps every user page of `parent_pml4` into `child_pml4`. Writable pages become
+/// read-only copy-on-write in both spaces, except those in shared areas of `vmas`,
+/// which stay writable in both; the caller must flush the parent's TLB. On
+/// failure the child holds references only to what it mapped, so
+/// `unmap_user_space` undoes it.
+pub unsafe fn clone_user_space(parent_pml4: u64, child_pml4: u64, vmas: &crate::memory::vma::VmaList) -> Result<(), pmm::FrameError> {
+    let mut result = Ok(());
+    for_each_user_page(parent_pml4, |virt, entry| {
+        if result.is_err() {
+            return;
+        }
+        let phys = entry.addr().as_u64();
+        let mut flags = entry.as_u64() & PTE_FLAGS_MASK;
+        let shared = vmas.find(virt).map_or(false, |v| v.shared);
+        if (flags & paging::PAGE_WRITABLE) != 0 && !shared {
+            flags = (flags & !paging::PAGE_WRITABLE) | paging::PAGE_COW;
+            entry.set_addr(PhysAddr::new(phys), PageTableFlags::from_bits_truncate(flags));
+        }
+        result = map_page(virt, PhysAddr::new(phys), flags, Some(child_pml4));
+        if result.is_ok() {
+            pmm::share_frame(phys);
+        }
+    });
+    for_each_swapped_page(parent_pml4, |virt, entry| {
+        if result.is_err() {
+            return;
+        }
+        result = map_page(virt, entry.addr(), entry.as_u64() & PTE_FLAGS_MASK, Some(child_pml4));
+        if result.is_ok() {
+            crate::memory::swap::retain(entry.as_u64());
+        }
+    });
+    // Parent threads on other CPUs must stop writing to pages that just became COW.
+    crate::smp::flush_tlb_others(parent_pml4);
+    result
+}
+
+/// Drops this address space's references to frames it shares with others
+/// and to its pages in swap.
+pub unsafe fn release_shared_pages(pml4_phys: u64) {
+    for_each_user_page(pml4_phys, |_, entry| {
+        let phys = entry.addr().as_u64();
+        if pmm::is_shared_frame(phys) {
+            pmm::release_shared_frame(phys);
+            entry.set_unused();
+        }
+    });
+    release_swapped_pages(pml4_phys);
+}
+
+unsafe fn release_swapped_pages(pml4_phys: u64) {
+    for_each_swapped_page(pml4_phys, |_, entry| {
+        crate::memory::swap::release(entry.as_u64());
+        entry.set_unused();
+    });
+}
+
+/// Unmaps and frees every user page and user page table of `pml4_phys`,
+/// leaving only the shared kernel half. Used when a process replaces its image
+/// and when the OOM killer strips a victim that may still run elsewhere.
+pub unsafe fn unmap_user_space(pml4_phys: u64) {
+    let pml4 = match paging::get_table_from_phys(pml4_phys) { Some(t) => t, None => return };
+
+    // Detach the user half before freeing, so no CPU can reach a freed frame.
+    let mut detached = [paging::PageTableEntry::new(); 256];
+    for i4 in 0..256 {
+        detached[i4] = pml4[i4];
+        pml4[i4].set_unused();
+    }
+    let cr3: u64;
+    asm!("mov {}, cr3", out(reg) cr3);
+    if (cr3 & 0x000F_FFFF_FFFF_F000) == pml4_phys {
+        asm!("mov cr3, {}", in(reg) cr3);
+    }
+    crate::smp::flush_tlb_others(pml4_phys);
+
+    for p4 in detached.iter().filter(|e| !e.is_unused()) {
+        let p3 = paging::get_table_from_phys(p4.addr().as_u64()).unwrap();
+        for i3 in 0..512 {
+            if p3[i3].is_unused() || (p3[i3].as_u64() & paging::PAGE_HUGE) != 0 { continue; }
+            let p2 = paging::get_table_from_phys(p3[i3].addr().as_u64()).unwrap();
+            for i2 in 0..512 {
+                if p2[i2].is_unused() || (p2[i2].as_u64() & paging::PAGE_HUGE) != 0 { continue; }
+                let p1 = paging::get_table_from_phys(p2[i2].addr().as_u64()).unwrap();
+                for i1 in 0..512 {
+                    release_user_entry(&mut p1[i1]);
+                }
+                pmm::free_single_frame(p2[i2].addr().as_u64());
+            }
+            pmm::free_single_frame(p3[i3].addr().as_u64());
+        }
+        pmm::free_single_frame(p4.addr().as_u64());
+    }
+}
+
+/// Drops the frame or swap slot behind a user page entry and clears it.
+unsafe fn release_user_entry(entry: &mut paging::PageTableEntry) {
+    let raw = entry.as_u64();
+    if (raw & paging::PAGE_PRESENT) != 0 {
+        let phys = entry.addr().as_u64();
+        if pmm::is_shared_frame(phys) {
+            pmm::release_shared_frame(phys);
+        } else {
+            pmm::free_single_frame(phys);
+        }
+    } else if crate::memory::swap::is_swap_entry(raw) {
+        crate::memory::swap::release(raw);
+    }
+    entry.set_unused();
+}
+
+/// Unmaps and frees every page in `[start, end)` of `pml4_phys`, in memory or in swap.
+pub unsafe fn unmap_user_range(pml4_phys: u64, start: u64, end: u64) {
+    let cr3: u64;
+    asm!("mov {}, cr3", out(reg) cr3);
+    let active = (cr3 & 0x000F_FFFF_FFFF_F000) == pml4_phys;
+
+    let mut frames = alloc::vec::Vec::new();
+    let mut virt = start & !0xFFF;
+    while virt < end {
+        if let Some(entry) = get_pte(virt, pml4_phys) {
+            if crate::memory::swap::is_swap_entry(entry.as_u64()) {
+                crate::memory::swap::release(entry.as_u64());
+            } else {
+                frames.push(entry.addr().as_u64());
+            }
+            entry.set_unused();
+            if active {
+                asm!("invlpg [{}]", in(reg) virt);
+            }
+        }
+        virt += paging::PAGE_SIZE;
+    }
+
+    // Another CPU may still reach the frames through its TLB until it flushes.
+    crate::smp::flush_tlb_others(pml4_phys);
+    for phys in frames {
+        if pmm::is_shared_frame(phys) {
+            pmm::release_shared_frame(phys);
+        } else {
+            pmm::free_single_frame(phys);
+        }
+    }
+}
+
+/// Resolves a write to a copy-on-write page in the active address space.
+/// Returns false if `virt` is not a COW page, so the caller treats it as a real fault.
+pub fn handle_cow_fault(virt: u64, pid: u64) -> bool {
+    unsafe {
+        let cr3: u64;
+        asm!("mov {}, cr3", out(reg) cr3);
+
+        let entry = match get_pte(virt, cr3 & 0x000F_FFFF_FFFF_F000) {
+            Some(e) => e,
+            None => return false,
+        };
+
+        let flags = entry.as_u64() & PTE_FLAGS_MASK;
+        if (flags & paging::PAGE_COW) == 0 {
+            // Another thread of the process broke the sharing first; our TLB was stale.
+            let writable = paging::PAGE_PRESENT | paging::PAGE_WRITABLE | paging::PAGE_USER;
+            if (flags & writable) == writable {
+                asm!("invlpg [{}]", in(reg) virt & !0xFFF);
+                return true;
+            }
+            return false;
+        }
+
+        let old_phys = entry.addr().as_u64();
+        let new_flags = PageTableFlags::from_bits_truncate((flags & !paging::PAGE_COW) | paging::PAGE_WRITABLE);
+
+        if pmm::claim_shared_frame(old_phys, pid) {
+            entry.set_addr(PhysAddr::new(old_phys), new_flags);
+        } else {
+            let new_phys = match crate::memory::oom::allocate_frame(pid) {
+                Some(p) => p,
+                None => return false,
+            };
+            core::ptr::copy_nonoverlapping(
+                (old_phys + paging::HHDM_OFFSET) as *const u8,
+                (new_phys + paging::HHDM_OFFSET) as *mut u8,
+                paging::PAGE_SIZE as usize,
+            );
+            entry.set_addr(PhysAddr::new(new_phys), new_flags);
+            crate::smp::flush_tlb_others(cr3 & 0x000F_FFFF_FFFF_F000);
+            pmm::release_shared_frame(old_phys);
+        }
+
+        asm!("invlpg [{}]", in(reg) virt & !0xFFF);
+        true
+    }
+}
+
+/// Returns the HHDM address backing user address `virt` in the active address space,
+/// breaking copy-on-write first when `write` is set. Never faults.
+pub fn user_virt_to_hhdm(virt: u64, pid: u64, write: bool) -> Option<u64> {
+    if virt >= 0x0000_8000_0000_0000 {
+        return None;
+    }
+    unsafe {
+        let cr3: u64;
+        asm!("mov {}, cr3", out(reg) cr3);
+        let pml4_phys = cr3 & 0x000F_FFFF_FFFF_F000;
+
+        let present = get_pte(virt, pml4_phys).map_or(false, |e| (e.as_u64() & paging::PAGE_PRESENT) != 0);
+        if !present && !crate::memory::vma::handle_demand_fault(virt, write) {
+            return None;
+        }
+        let flags = get_pte(virt, pml4_phys)?.as_u64() & PTE_FLAGS_MASK;
+        if (flags & paging::PAGE_USER) == 0 || (flags & paging::PAGE_PRESENT) == 0 {
+            return None;
+        }
+        if write && (flags & paging::PAGE_WRITABLE) == 0 && !handle_cow_fault(virt, pid) {
+            return None;
+        }
+        let entry = get_pte(virt, pml4_phys)?;
+        Some(entry.addr().as_u64() + (virt & 0xFFF) + paging::HHDM_OFFSET)
+    }
+}
+
 static mut MMIO_VIRT_HEAD: u64 = 0xFFFF_A000_0000_0000;
 static mut KERNEL_MAPPING_HEAD: u64 = 0xFFFF_FA00_0000_0000; 
 
@@ -210,7 +514,8 @@ pub fn map_mmio(phys: u64, size: usize) -> u64 {
         for i in 0..pages {
             let offset = i as u64 * 4096;
             map_page(start_virt + offset, PhysAddr::new(phys + offset), 
-                     paging::PAGE_PRESENT | paging::PAGE_WRITABLE | paging::PAGE_NO_CACHE, None);
+                     paging::PAGE_PRESENT | paging::PAGE_WRITABLE | paging::PAGE_NO_CACHE, None)
+                .expect("VMM: OOM mapping MMIO");
         }
         MMIO_VIRT_HEAD += (pages as u64 * 4096) + 4096;
         start_virt
@@ -228,10 +533,15 @@ pub fn map_user_memory_into_kernel(user_virt: u64, size: usize, user_pml4: u64)
         let mut mapped = true;
         for i in 0..pages {
             let offset = i as u64 * 4096;
-            if let Some(phys) = get_phys(user_virt_aligned + offset, user_pml4) {
-                map_page(start_virt + offset, PhysAddr::new(phys & !0xFFF), 
-                         paging::PAGE_PRESENT | paging::PAGE_WRITABLE, None);
-            } else {
+            let phys = match get_phys(user_virt_aligned + offset, user_pml4) {
+                Some(phys) => phys,
+                None => {
+                    mapped = false;
+                    break;
+                }
+            };
+            if map_page(start_virt + offset, PhysAddr::new(phys & !0xFFF),
+                        paging::PAGE_PRESENT | paging::PAGE_WRITABLE, None).is_err() {
                 mapped = false;
                 break;
             }
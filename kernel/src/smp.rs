@@ -0,0 +1,387 @@
+use crate::boot::{TaskStateSegment, BOOT_INFO};
+use crate::interrupts::apic;
+use crate::interrupts::gdt::{self, GDT_ENTRIES};
+use crate::interrupts::task::{Process, TASK_MANAGER};
+use crate::memory::paging::HHDM_OFFSET;
+use crate::memory::pmm;
+use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+pub const MAX_CPUS: usize = 16;
+
+const KERNEL_GS_BASE_MSR: u32 = 0xC0000102;
+const EFER_MSR: u32 = 0xC0000080;
+/// EFER bits the trampoline sets before paging is on: SCE, LME and NXE.
+const EFER_BOOT_MASK: u64 = (1 << 0) | (1 << 8) | (1 << 11);
+
+/// Physical page the trampoline is copied to; the SIPI vector is its page number.
+const TRAMPOLINE_PHYS: u64 = 0x8000;
+const AP_STACK_PAGES: usize = 16;
+
+/// Wakes a halted CPU to look for work; idle CPUs take no timer interrupts.
+/// Handled like a timer interrupt.
+pub const RESCHED_IPI: u8 = 0xF0;
+/// Asks a CPU to reload CR3; see `flush_tlb_others`.
+pub const TLB_IPI: u8 = 0xF1;
+
+/// State owned by one CPU. The syscall entry reaches it through `gs` after
+/// `swapgs`, the rest of the kernel through `this_cpu`.
+#[repr(C, align(64))]
+pub struct PerCpu {
+    /// Stack `syscall_entry` switches to: the running thread's kernel stack.
+    pub kernel_stack: u64,
+    /// Holds the user r15 while `syscall_entry` switches stacks.
+    pub scratch: u64,
+    pub id: usize,
+    pub apic_id: u32,
+    pub online: AtomicBool,
+    tlb_flush: AtomicBool,
+    /// Page table the CPU runs on, for TLB shootdowns.
+    pub cr3: AtomicU64,
+    pub current_pid: u64,
+    pub current_process: *const Process,
+    gdt: [u64; GDT_ENTRIES],
+    tss: TaskStateSegment,
+}
+
+impl PerCpu {
+    const fn new(id: usize) -> Self {
+        Self {
+            kernel_stack: 0,
+            scratch: 0,
+            id,
+            apic_id: 0,
+            online: AtomicBool::new(false),
+            tlb_flush: AtomicBool::new(false),
+            cr3: AtomicU64::new(0),
+            current_pid: 0,
+            current_process: core::ptr::null(),
+            gdt: [0; GDT_ENTRIES],
+            tss: TaskStateSegment {
+                reserved1: 0,
+                rsp0: 0,
+                rsp1: 0,
+                rsp2: 0,
+                reserved2: 0,
+                ist1: 0,
+                ist2: 0,
+                ist3: 0,
+                ist4: 0,
+                ist5: 0,
+                ist6: 0,
+                ist7: 0,
+                reserved3: 0,
+                reserved4: 0,
+                iopb_offset: core::mem::size_of::<TaskStateSegment>() as u16,
+            },
+        }
+    }
+}
+
+static mut CPUS: [PerCpu; MAX_CPUS] = {
+    let mut cpus = [const { PerCpu::new(0) }; MAX_CPUS];
+    let mut i = 0;
+    while i < MAX_CPUS {
+        cpus[i].id = i;
+        i += 1;
+    }
+    cpus
+};
+
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(1);
+
+/// The calling CPU's `PerCpu`. Kept in IA32_KERNEL_GS_BASE, which only
+/// `syscall_entry` swaps, and only while interrupts are off.
+pub fn this_cpu() -> *mut PerCpu {
+    let ptr = unsafe { crate::rdmsr(KERNEL_GS_BASE_MSR) };
+    if ptr == 0 {
+        return unsafe { &raw mut CPUS[0] };
+    }
+    ptr as *mut PerCpu
+}
+
+pub fn cpu_id() -> usize {
+    unsafe { (*this_cpu()).id }
+}
+
+pub fn online_cpus() -> usize {
+    ONLINE_CPUS.load(Ordering::Relaxed)
+}
+
+/// Sets up the BSP's `PerCpu`. Must run before anything takes a lock.
+pub fn init_bsp() {
+    unsafe {
+        let cpu = &raw mut CPUS[0];
+        (*cpu).online.store(true, Ordering::Relaxed);
+        (*cpu).cr3.store(BOOT_INFO.pml4, Ordering::Relaxed);
+        crate::wrmsr(KERNEL_GS_BASE_MSR, cpu as u64);
+    }
+}
+
+/// Body of every spin loop; keeps TLB shootdowns from deadlocking against lock waiters.
+pub fn relax() {
+    service_tlb_flush();
+    core::hint::spin_loop();
+}
+
+fn service_tlb_flush() {
+    unsafe {
+        let cpu = this_cpu();
+        if (*cpu).tlb_flush.load(Ordering::Acquire) {
+            asm!("mov {0}, cr3", "mov cr3, {0}", out(reg) _);
+            (*cpu).tlb_flush.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Makes every other CPU running on `pml4` drop its TLB, and waits until it has.
+/// Call after changing or removing entries of a page table that may be live elsewhere.
+pub fn flush_tlb_others(pml4: u64) {
+    if online_cpus() < 2 {
+        return;
+    }
+    let me = cpu_id();
+    core::sync::atomic::fence(Ordering::SeqCst);
+    unsafe {
+        let cpus = &*(&raw const CPUS);
+        let mut pending = false;
+        for cpu in cpus.iter().filter(|c| c.id != me && c.online.load(Ordering::Acquire)) {
+            if cpu.cr3.load(Ordering::SeqCst) == pml4 {
+                cpu.tlb_flush.store(true, Ordering::Release);
+                apic::send_ipi(cpu.apic_id, TLB_IPI);
+                pending = true;
+            }
+        }
+        while pending {
+            pending = cpus.iter().any(|c| c.tlb_flush.load(Ordering::Acquire));
+            relax();
+        }
+    }
+}
+
+/// Makes `cpu` run its scheduler. Without local APICs there is a single CPU,
+/// ticking periodically, so there is nothing to do.
+pub fn kick(cpu: usize) {
+    if apic::is_enabled() {
+        apic::send_ipi(unsafe { CPUS[cpu].apic_id }, RESCHED_IPI);
+    }
+}
+
+pub extern "x86-interrupt" fn tlb_flush_handler(_info: &mut crate::interrupts::exceptions::StackFrame) {
+    service_tlb_flush();
+    apic::eoi();
+}
+
+// Real-mode entry for the APs, copied to `TRAMPOLINE_PHYS`. It climbs to long
+// mode on a temporary page table identity-mapping low memory, then calls
+// `ap_entry` on the stack the BSP left in `ap_tramp_stack`.
+global_asm!(
+    ".pushsection .text.ap_trampoline, \"ax\"",
+    ".code16",
+    ".global ap_trampoline_start",
+    "ap_trampoline_start:",
+    "cli",
+    "cld",
+    "xor %ax, %ax",
+    "mov %ax, %ds",
+    "lgdtl ap_tramp_gdtr - ap_trampoline_start + 0x8000",
+    "mov %cr0, %eax",
+    "or $1, %eax",
+    "mov %eax, %cr0",
+    "ljmpl $0x08, $(ap_tramp_32 - ap_trampoline_start + 0x8000)",
+    ".code32",
+    "ap_tramp_32:",
+    "mov $0x10, %ax",
+    "mov %ax, %ds",
+    "mov %ax, %es",
+    "mov %ax, %ss",
+    "mov ap_tramp_cr4 - ap_trampoline_start + 0x8000, %eax",
+    "mov %eax, %cr4",
+    "mov ap_tramp_cr3 - ap_trampoline_start + 0x8000, %eax",
+    "mov %eax, %cr3",
+    "mov $0xC0000080, %ecx",
+    "mov ap_tramp_efer - ap_trampoline_start + 0x8000, %eax",
+    "xor %edx, %edx",
+    "wrmsr",
+    "mov ap_tramp_cr0 - ap_trampoline_start + 0x8000, %eax",
+    "mov %eax, %cr0",
+    "ljmpl $0x18, $(ap_tramp_64 - ap_trampoline_start + 0x8000)",
+    ".code64",
+    "ap_tramp_64:",
+    "mov ap_tramp_stack - ap_trampoline_start + 0x8000, %rsp",
+    "mov ap_tramp_cpu - ap_trampoline_start + 0x8000, %rdi",
+    "mov ap_tramp_entry - ap_trampoline_start + 0x8000, %rax",
+    "call *%rax",
+    "2: hlt",
+    "jmp 2b",
+    ".balign 8",
+    "ap_tramp_gdt:",
+    ".quad 0",
+    ".quad 0x00CF9A000000FFFF",
+    ".quad 0x00CF92000000FFFF",
+    ".quad 0x00AF9A000000FFFF",
+    "ap_tramp_gdtr:",
+    ".word 31",
+    ".long ap_tramp_gdt - ap_trampoline_start + 0x8000",
+    ".balign 8",
+    ".global ap_tramp_cr0",
+    "ap_tramp_cr0: .quad 0",
+    ".global ap_tramp_cr3",
+    "ap_tramp_cr3: .quad 0",
+    ".global ap_tramp_cr4",
+    "ap_tramp_cr4: .quad 0",
+    ".global ap_tramp_efer",
+    "ap_tramp_efer: .quad 0",
+    ".global ap_tramp_stack",
+    "ap_tramp_stack: .quad 0",
+    ".global ap_tramp_cpu",
+    "ap_tramp_cpu: .quad 0",
+    ".global ap_tramp_entry",
+    "ap_tramp_entry: .quad 0",
+    ".global ap_trampoline_end",
+    "ap_trampoline_end:",
+    ".popsection",
+    options(att_syntax),
+);
+
+unsafe extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_tramp_cr0: u8;
+    static ap_tramp_cr3: u8;
+    static ap_tramp_cr4: u8;
+    static ap_tramp_efer: u8;
+    static ap_tramp_stack: u8;
+    static ap_tramp_cpu: u8;
+    static ap_tramp_entry: u8;
+}
+
+/// Where trampoline variable `var` lives in its low-memory copy.
+unsafe fn tramp_var(var: *const u8) -> *mut u64 {
+    let offset = var as u64 - (&raw const ap_trampoline_start) as u64;
+    (TRAMPOLINE_PHYS + offset + HHDM_OFFSET) as *mut u64
+}
+
+/// Busy-waits about `us` microseconds; each write to port 0x80 takes roughly one.
+fn udelay(us: u64) {
+    for _ in 0..us {
+        crate::drivers::port::outb(0x80, 0);
+    }
+}
+
+/// Starts every processor listed in the MADT. The BSP keeps CPU id 0.
+/// Needs the local APIC set up on the BSP.
+pub fn init(madt: &crate::drivers::acpi::Madt) {
+    let bsp_apic = apic::id();
+    unsafe { CPUS[0].apic_id = bsp_apic; }
+
+    let boot_pml4 = match unsafe { install_trampoline() } {
+        Some(p) => p,
+        None => {
+            crate::debugln!("SMP: out of memory for the AP trampoline");
+            return;
+        }
+    };
+
+    let mut next_id = 1;
+    for &apic_id in madt.cpus.iter().filter(|&&id| id as u32 != bsp_apic) {
+        if next_id >= MAX_CPUS {
+            break;
+        }
+        if unsafe { start_ap(next_id, apic_id as u32) } {
+            ONLINE_CPUS.fetch_add(1, Ordering::AcqRel);
+            next_id += 1;
+        } else {
+            crate::debugln!("SMP: CPU with APIC id {} did not start", apic_id);
+        }
+    }
+
+    pmm::free_frame(boot_pml4);
+    crate::debugln!("SMP: {} CPUs online", online_cpus());
+}
+
+/// Copies the trampoline to low memory and fills in what every AP shares.
+/// Returns the temporary PML4: the kernel's, plus an identity map of the low 4GB.
+unsafe fn install_trampoline() -> Option<u64> {
+    unsafe {
+        let start = &raw const ap_trampoline_start;
+        let len = (&raw const ap_trampoline_end) as usize - start as usize;
+        core::ptr::copy_nonoverlapping(start, (TRAMPOLINE_PHYS + HHDM_OFFSET) as *mut u8, len);
+
+        let boot_pml4 = pmm::allocate_frames_in(pmm::Zone::Dma32, 1, 0)?;
+        let dst = (boot_pml4 + HHDM_OFFSET) as *mut u64;
+        core::ptr::copy_nonoverlapping((BOOT_INFO.pml4 + HHDM_OFFSET) as *const u64, dst, 512);
+        *dst = *dst.add(256);
+
+        let (cr0, cr4): (u64, u64);
+        asm!("mov {}, cr0", out(reg) cr0);
+        asm!("mov {}, cr4", out(reg) cr4);
+        *tramp_var(&raw const ap_tramp_cr0) = cr0;
+        *tramp_var(&raw const ap_tramp_cr3) = boot_pml4;
+        *tramp_var(&raw const ap_tramp_cr4) = cr4;
+        *tramp_var(&raw const ap_tramp_efer) = crate::rdmsr(EFER_MSR) & EFER_BOOT_MASK;
+        *tramp_var(&raw const ap_tramp_entry) = ap_entry as u64;
+        Some(boot_pml4)
+    }
+}
+
+/// INIT-SIPI-SIPI for one AP. Returns once it reports online, or false after ~100ms.
+unsafe fn start_ap(id: usize, apic_id: u32) -> bool {
+    unsafe {
+        let stack = match pmm::allocate_frames(AP_STACK_PAGES, 0) {
+            Some(s) => s + (AP_STACK_PAGES as u64 * 4096) + HHDM_OFFSET,
+            None => return false,
+        };
+
+        let cpu = &raw mut CPUS[id];
+        (*cpu).apic_id = apic_id;
+        (*cpu).kernel_stack = stack;
+        (*cpu).cr3.store(BOOT_INFO.pml4, Ordering::Relaxed);
+        let tss = (&raw const (*cpu).tss) as u64;
+        gdt::clone_for_cpu(&mut (*cpu).gdt, tss);
+
+        *tramp_var(&raw const ap_tramp_stack) = stack;
+        *tramp_var(&raw const ap_tramp_cpu) = id as u64;
+
+        apic::send_init(apic_id);
+        udelay(10_000);
+        for _ in 0..2 {
+            apic::send_startup(apic_id, (TRAMPOLINE_PHYS >> 12) as u8);
+            udelay(200);
+            if (*cpu).online.load(Ordering::Acquire) {
+                return true;
+            }
+        }
+        for _ in 0..100_000 {
+            if (*cpu).online.load(Ordering::Acquire) {
+                return true;
+            }
+            udelay(1);
+        }
+        false
+    }
+}
+
+/// First Rust code on an AP, entered from the trampoline on its boot stack,
+/// which becomes the stack of its idle thread.
+extern "C" fn ap_entry(id: usize) -> ! {
+    unsafe {
+        let cpu = &raw mut CPUS[id];
+        gdt::load(&(*cpu).gdt);
+        asm!("mov cr3, {}", in(reg) BOOT_INFO.pml4);
+        crate::wrmsr(KERNEL_GS_BASE_MSR, cpu as u64);
+        (*(&raw const crate::interrupts::idt::IDT)).load();
+        crate::tss::init_ists();
+        crate::init_syscall_msrs();
+        apic::enable();
+        apic::start_timer(crate::interrupts::exceptions::TIMER_INT);
+
+        TASK_MANAGER.lock().add_cpu(id, (*cpu).kernel_stack);
+        (*cpu).online.store(true, Ordering::Release);
+
+        asm!("sti");
+        loop {
+            asm!("hlt");
+        }
+    }
+}
@@ -1,15 +1,50 @@
 use core::cell::UnsafeCell;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Ticket spinlock shared by both guard kinds. `owner` is the holding CPU
+/// plus one, or 0 when free.
+struct RawLock {
+    next: AtomicU32,
+    serving: AtomicU32,
+    owner: AtomicUsize,
+}
+
+impl RawLock {
+    const fn new() -> Self {
+        Self {
+            next: AtomicU32::new(0),
+            serving: AtomicU32::new(0),
+            owner: AtomicUsize::new(0),
+        }
+    }
+
+    fn acquire(&self) {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.serving.load(Ordering::Acquire) != ticket {
+            crate::smp::relax();
+        }
+        self.owner.store(crate::smp::cpu_id() + 1, Ordering::Relaxed);
+    }
+
+    fn release(&self) {
+        self.owner.store(0, Ordering::Relaxed);
+        self.serving.fetch_add(1, Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.next.load(Ordering::Relaxed) != self.serving.load(Ordering::Relaxed)
+    }
+}
 
 pub struct Mutex<T> {
-    lock: AtomicBool,
+    lock: RawLock,
     data: UnsafeCell<T>,
 }
 
 impl<T: core::fmt::Debug> core::fmt::Debug for Mutex<T> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("Mutex")
-            .field("locked", &self.lock.load(Ordering::Relaxed))
+            .field("locked", &self.lock.is_locked())
             .finish()
     }
 }
@@ -18,28 +53,43 @@ unsafe impl<T: Send> Sync for Mutex<T> {}
 unsafe impl<T: Send> Send for Mutex<T> {}
 
 pub struct MutexGuard<'a, T> {
-    lock: &'a AtomicBool,
+    lock: &'a RawLock,
     data: &'a mut T,
 }
 
 impl<T> Mutex<T> {
     pub const fn new(data: T) -> Self {
         Self {
-            lock: AtomicBool::new(false),
+            lock: RawLock::new(),
             data: UnsafeCell::new(data),
         }
     }
 
     pub fn lock(&self) -> MutexGuard<'_, T> {
-        while self.lock.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
-            core::hint::spin_loop();
-        }
+        self.lock.acquire();
         MutexGuard {
             lock: &self.lock,
             data: unsafe { &mut *self.data.get() },
         }
     }
 
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_locked()
+    }
+
+    /// Whether the calling CPU is the one holding the lock.
+    pub fn held_by_this_cpu(&self) -> bool {
+        self.lock.owner.load(Ordering::Relaxed) == crate::smp::cpu_id() + 1
+    }
+
+    /// Releases a lock whose guard was forgotten, e.g. across a stack switch.
+    ///
+    /// # Safety
+    /// The caller must own the lock and no guard for it may be dropped later.
+    pub unsafe fn force_unlock(&self) {
+        self.lock.release();
+    }
+
     pub fn int_lock(&self) -> IntMutexGuard<'_, T> {
         let rflags: u64;
         unsafe {
@@ -47,9 +97,7 @@ impl<T> Mutex<T> {
             core::arch::asm!("cli");
         }
 
-        while self.lock.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
-            core::hint::spin_loop();
-        }
+        self.lock.acquire();
         IntMutexGuard {
             lock: &self.lock,
             data: unsafe { &mut *self.data.get() },
@@ -73,12 +121,12 @@ impl<'a, T> core::ops::DerefMut for MutexGuard<'a, T> {
 
 impl<'a, T> Drop for MutexGuard<'a, T> {
     fn drop(&mut self) {
-        self.lock.store(false, Ordering::Release);
+        self.lock.release();
     }
 }
 
 pub struct IntMutexGuard<'a, T> {
-    lock: &'a AtomicBool,
+    lock: &'a RawLock,
     data: &'a mut T,
     rflags: u64,
 }
@@ -98,11 +146,69 @@ impl<'a, T> core::ops::DerefMut for IntMutexGuard<'a, T> {
 
 impl<'a, T> Drop for IntMutexGuard<'a, T> {
     fn drop(&mut self) {
-        self.lock.store(false, Ordering::Release);
+        self.lock.release();
         unsafe {
             if (self.rflags & 0x200) != 0 {
                 core::arch::asm!("sti");
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Serialises the parts of the kernel that still assume one CPU: syscalls,
+/// IRQ handlers and user page faults. Re-entrant on the owning CPU.
+pub static KERNEL_LOCK: KernelLock = KernelLock::new();
+
+pub struct KernelLock {
+    owner: AtomicUsize,
+    depth: AtomicUsize,
+}
+
+pub struct KernelLockGuard {
+    _private: (),
+}
+
+impl KernelLock {
+    const fn new() -> Self {
+        Self { owner: AtomicUsize::new(0), depth: AtomicUsize::new(0) }
+    }
+
+    /// Must be called with interrupts disabled.
+    pub fn lock(&self) -> KernelLockGuard {
+        self.reacquire(1);
+        KernelLockGuard { _private: () }
+    }
+
+    /// Drops every level held by this CPU before it sleeps, returning how many.
+    pub fn release_all(&self) -> usize {
+        let me = crate::smp::cpu_id() + 1;
+        if self.owner.load(Ordering::Relaxed) != me {
+            return 0;
+        }
+        let depth = self.depth.swap(0, Ordering::Relaxed);
+        self.owner.store(0, Ordering::Release);
+        depth
+    }
+
+    /// Takes back the levels returned by `release_all`.
+    pub fn reacquire(&self, depth: usize) {
+        if depth == 0 {
+            return;
+        }
+        let me = crate::smp::cpu_id() + 1;
+        if self.owner.load(Ordering::Relaxed) != me {
+            while self.owner.compare_exchange(0, me, Ordering::Acquire, Ordering::Relaxed).is_err() {
+                crate::smp::relax();
+            }
+        }
+        self.depth.fetch_add(depth, Ordering::Relaxed);
+    }
+}
+
+impl Drop for KernelLockGuard {
+    fn drop(&mut self) {
+        if KERNEL_LOCK.depth.fetch_sub(1, Ordering::Relaxed) == 1 {
+            KERNEL_LOCK.owner.store(0, Ordering::Release);
+        }
+    }
+}
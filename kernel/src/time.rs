@@ -0,0 +1,163 @@
+use alloc::collections::BinaryHeap;
+use core::cmp::Reverse;
+use crate::drivers::hpet;
+
+pub const CLOCK_REALTIME: u64 = 0;
+pub const CLOCK_MONOTONIC: u64 = 1;
+
+pub const NANOS_PER_SEC: u64 = 1_000_000_000;
+const NANOS_PER_MS: u64 = 1_000_000;
+/// Length of the window the TSC is measured over.
+const CALIBRATION_MS: u64 = 50;
+
+/// `struct timespec` as user space passes it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+unsafe impl crate::memory::uaccess::Pod for Timespec {}
+
+impl Timespec {
+    pub fn from_ns(ns: u64) -> Self {
+        Self { tv_sec: (ns / NANOS_PER_SEC) as i64, tv_nsec: (ns % NANOS_PER_SEC) as i64 }
+    }
+
+    /// Length in ns, or None if negative or out of range.
+    pub fn to_ns(&self) -> Option<u64> {
+        if self.tv_sec < 0 || !(0..NANOS_PER_SEC as i64).contains(&self.tv_nsec) {
+            return None;
+        }
+        (self.tv_sec as u64).checked_mul(NANOS_PER_SEC)?.checked_add(self.tv_nsec as u64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+    Tsc,
+    Hpet,
+}
+
+static mut SOURCE: ClockSource = ClockSource::Tsc;
+/// TSC counts per millisecond; 0 until `init` has run.
+static mut TSC_PER_MS: u64 = 0;
+/// Reading of the clock source at boot, in its own units.
+static mut START: u64 = 0;
+/// Wall-clock time at boot, in ns since the Unix epoch.
+static mut BOOT_REALTIME: u64 = 0;
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Whether the TSC ticks at a constant rate through P- and C-states.
+fn invariant_tsc() -> bool {
+    let max_ext = core::arch::x86_64::__cpuid(0x8000_0000).eax;
+    max_ext >= 0x8000_0007 && core::arch::x86_64::__cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+/// Counts TSC ticks over `CALIBRATION_MS`, timed by the HPET when there is one, else by the PIT.
+fn calibrate_tsc() -> u64 {
+    use crate::drivers::periferics::timer;
+
+    if hpet::is_present() {
+        let end = hpet::nanos() + CALIBRATION_MS * NANOS_PER_MS;
+        let start = rdtsc();
+        while hpet::nanos() < end {
+            core::hint::spin_loop();
+        }
+        return (rdtsc() - start) / CALIBRATION_MS;
+    }
+
+    timer::start_oneshot(CALIBRATION_MS as u32);
+    let start = rdtsc();
+    while !timer::oneshot_done() {
+        core::hint::spin_loop();
+    }
+    (rdtsc() - start) / CALIBRATION_MS
+}
+
+/// Picks and calibrates the clock source and reads the wall clock. The TSC
+/// is preferred when invariant; otherwise the HPET, if present, is slower
+/// to read but keeps time.
+pub fn init() {
+    let has_hpet = hpet::init();
+    let tsc_per_ms = calibrate_tsc();
+    let source = if has_hpet && !invariant_tsc() { ClockSource::Hpet } else { ClockSource::Tsc };
+    unsafe {
+        TSC_PER_MS = tsc_per_ms;
+        SOURCE = source;
+        START = match source {
+            ClockSource::Tsc => rdtsc(),
+            ClockSource::Hpet => hpet::nanos(),
+        };
+        BOOT_REALTIME = crate::drivers::rtc::unix_time() * NANOS_PER_SEC;
+    }
+    crate::debugln!("TIME: {:?} clock source, TSC at {} kHz", source, tsc_per_ms);
+}
+
+/// Nanoseconds since boot.
+pub fn now_ns() -> u64 {
+    unsafe {
+        match SOURCE {
+            ClockSource::Tsc => (rdtsc().saturating_sub(START) as u128 * NANOS_PER_MS as u128)
+                .checked_div(TSC_PER_MS as u128)
+                .unwrap_or(0) as u64,
+            ClockSource::Hpet => hpet::nanos().saturating_sub(START),
+        }
+    }
+}
+
+pub fn uptime_ms() -> u64 {
+    now_ns() / NANOS_PER_MS
+}
+
+/// Nanoseconds since the Unix epoch.
+pub fn realtime_ns() -> u64 {
+    unsafe { BOOT_REALTIME + now_ns() }
+}
+
+/// Reading of `clock` in ns, or None for an unknown clock.
+pub fn clock_ns(clock: u64) -> Option<u64> {
+    match clock {
+        CLOCK_REALTIME => Some(realtime_ns()),
+        CLOCK_MONOTONIC => Some(now_ns()),
+        _ => None,
+    }
+}
+
+/// Deadline for something `ns` from now; saturates instead of wrapping.
+pub fn deadline_after(ns: u64) -> u64 {
+    now_ns().saturating_add(ns)
+}
+
+/// Pending wakeups ordered by deadline. Entries are (deadline, tid); one that
+/// no longer matches its thread's deadline is stale and skipped on expiry.
+pub struct TimerQueue {
+    heap: BinaryHeap<Reverse<(u64, u64)>>,
+}
+
+impl TimerQueue {
+    pub const fn new() -> Self {
+        Self { heap: BinaryHeap::new() }
+    }
+
+    pub fn push(&mut self, deadline: u64, tid: u64) {
+        self.heap.push(Reverse((deadline, tid)));
+    }
+
+    /// Earliest deadline, if any.
+    pub fn next(&self) -> Option<u64> {
+        self.heap.peek().map(|Reverse((deadline, _))| *deadline)
+    }
+
+    /// Takes the next entry due by `now`.
+    pub fn pop_expired(&mut self, now: u64) -> Option<(u64, u64)> {
+        match self.heap.peek() {
+            Some(Reverse((deadline, _))) if *deadline <= now => self.heap.pop().map(|Reverse(e)| e),
+            _ => None,
+        }
+    }
+}
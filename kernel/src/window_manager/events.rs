@@ -91,6 +91,11 @@ impl EventQueue {
         self.queue[self.head] = event;
         self.head = (self.head + 1) % QUEUE_SIZE;
         self.count += 1;
+        crate::interrupts::wait::POLL_WAIT.wake_all();
+    }
+
+    pub fn has_events(&self, window_id: u32) -> bool {
+        (0..self.count).any(|i| self.queue[(self.tail + i) % QUEUE_SIZE].get_window_id() == window_id)
     }
 
     pub fn get_and_remove_events(&mut self, window_id: u32, max_events: usize) -> Vec<Event> {
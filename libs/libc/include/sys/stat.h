@@ -28,6 +28,7 @@ extern int lstat(const char *path, struct stat *buf);
 extern int fstat(int fd, struct stat *buf);
 extern int mkdir(const char *path, mode_t mode);
 extern int chmod(const char *path, mode_t mode);
+extern int fchmod(int fd, mode_t mode);
 
 #define S_IFMT  0170000
 #define S_IFSOCK 0140000
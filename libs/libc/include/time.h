@@ -3,9 +3,18 @@
 
 typedef long time_t;
 typedef long clock_t;
+typedef int clockid_t;
 
 #define CLOCKS_PER_SEC 1000
 
+#define CLOCK_REALTIME 0
+#define CLOCK_MONOTONIC 1
+
+struct timespec {
+    time_t tv_sec;
+    long tv_nsec;
+};
+
 struct tm {
     int tm_sec;
     int tm_min;
@@ -25,5 +34,7 @@ extern struct tm *gmtime(const time_t *timep);
 extern struct tm *localtime(const time_t *timep);
 extern size_t strftime(char *s, size_t max, const char *format, const struct tm *tm);
 extern double difftime(time_t time1, time_t time0);
+extern int clock_gettime(clockid_t clock_id, struct timespec *tp);
+extern int nanosleep(const struct timespec *req, struct timespec *rem);
 
 #endif
@@ -23,8 +23,14 @@ int access(const char *pathname, int mode);
 int isatty(int fd);
 pid_t getpid(void);
 int unlink(const char *pathname);
+int link(const char *oldpath, const char *newpath);
+int symlink(const char *target, const char *linkpath);
+ssize_t readlink(const char *pathname, char *buf, size_t bufsiz);
 int gethostname(char *name, size_t len);
 int fsync(int fd);
+int fdatasync(int fd);
+void sync(void);
+int chown(const char *path, uid_t owner, gid_t group);
 int fchown(int fd, uid_t owner, gid_t group);
 int fchmod(int fd, mode_t mode);
 int chmod(const char *path, mode_t mode);
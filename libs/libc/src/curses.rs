@@ -161,6 +161,12 @@ pub unsafe extern "C" fn wgetch(win: *mut WINDOW) -> c_int {
     let non_blocking = if !win.is_null() { (*win)._delay } else { false };
 
     loop {
+        if non_blocking {
+            let mut fds = [std::os::PollFd { fd: 0, events: std::os::POLLIN, revents: 0 }];
+            if std::os::poll(&mut fds, 0) <= 0 {
+                return -1;
+            }
+        }
         let mut buf = [0u8; 1];
         let n = std::os::file_read(0, &mut buf);
         if n == 1 { 
@@ -389,9 +395,6 @@ pub unsafe extern "C" fn wctomb(s: *mut c_char, wc: u32) -> c_int {
     1
 }
 
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn sigaction(_sig: c_int, _act: *const c_void, _oact: *mut c_void) -> c_int { 0 }
-
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn tgetstr(_id: *const c_char, _area: *mut *mut c_char) -> *mut c_char { core::ptr::null_mut() }
 
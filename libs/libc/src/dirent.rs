@@ -50,6 +50,7 @@ pub unsafe extern "C" fn readdir(dirp: *mut DIR) -> *mut dirent {
         dir.current.d_type = match entry.file_type {
             std::fs::FileType::Directory => 4, 
             std::fs::FileType::File => 8, 
+            std::fs::FileType::Symlink => 10,
             _ => 0,
         };
 
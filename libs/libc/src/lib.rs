@@ -1,6 +1,7 @@
 #![feature(c_variadic)]
 #![no_std]
 #![feature(naked_functions)]
+#![feature(thread_local)]
 
 extern crate alloc;
 #[macro_use]
@@ -24,5 +25,11 @@ pub mod pthread;
 #[cfg(feature = "userland")]
 pub use std::runtime::*;
 
-#[unsafe(no_mangle)]
+#[thread_local]
 pub static mut errno: c_int = 0;
+
+/// Address of the calling thread's `errno`, which the `errno` macro reads through.
+#[unsafe(no_mangle)]
+pub extern "C" fn __errno_location() -> *mut c_int {
+    &raw mut errno
+}
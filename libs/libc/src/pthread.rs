@@ -1,6 +1,7 @@
 use core::ffi::{c_int, c_void};
 use std::thread::JoinHandle;
-use std::sync::Mutex;
+use std::sync::{Condvar, Mutex, RawMutex};
+use core::sync::atomic::{AtomicU32, Ordering};
 use alloc::collections::BTreeMap;
 
 pub type pthread_t = usize;
@@ -85,3 +86,129 @@ pub unsafe extern "C" fn pthread_detach(thread: pthread_t) -> c_int {
         -1
     }
 }
+
+const EBUSY: c_int = 16;
+const EAGAIN: c_int = 11;
+
+#[repr(C)]
+pub struct pthread_mutex_t {
+    lock: RawMutex,
+}
+
+#[repr(C)]
+pub struct pthread_cond_t {
+    cond: Condvar,
+}
+
+#[repr(C)]
+pub struct sem_t {
+    value: AtomicU32,
+    waiters: AtomicU32,
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pthread_mutex_init(mutex: *mut pthread_mutex_t, _attr: *const c_void) -> c_int {
+    core::ptr::write(mutex, pthread_mutex_t { lock: RawMutex::new() });
+    0
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pthread_mutex_destroy(_mutex: *mut pthread_mutex_t) -> c_int {
+    0
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pthread_mutex_lock(mutex: *mut pthread_mutex_t) -> c_int {
+    (*mutex).lock.lock();
+    0
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pthread_mutex_trylock(mutex: *mut pthread_mutex_t) -> c_int {
+    if (*mutex).lock.try_lock() { 0 } else { EBUSY }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pthread_mutex_unlock(mutex: *mut pthread_mutex_t) -> c_int {
+    (*mutex).lock.unlock();
+    0
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pthread_cond_init(cond: *mut pthread_cond_t, _attr: *const c_void) -> c_int {
+    core::ptr::write(cond, pthread_cond_t { cond: Condvar::new() });
+    0
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pthread_cond_destroy(_cond: *mut pthread_cond_t) -> c_int {
+    0
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pthread_cond_wait(cond: *mut pthread_cond_t, mutex: *mut pthread_mutex_t) -> c_int {
+    (*cond).cond.wait_raw(&(*mutex).lock, 0);
+    0
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pthread_cond_signal(cond: *mut pthread_cond_t) -> c_int {
+    (*cond).cond.notify_one();
+    0
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pthread_cond_broadcast(cond: *mut pthread_cond_t) -> c_int {
+    (*cond).cond.notify_all();
+    0
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sem_init(sem: *mut sem_t, _pshared: c_int, value: u32) -> c_int {
+    core::ptr::write(sem, sem_t { value: AtomicU32::new(value), waiters: AtomicU32::new(0) });
+    0
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sem_destroy(_sem: *mut sem_t) -> c_int {
+    0
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sem_trywait(sem: *mut sem_t) -> c_int {
+    let value = &(*sem).value;
+    let mut v = value.load(Ordering::Relaxed);
+    while v > 0 {
+        match value.compare_exchange_weak(v, v - 1, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => return 0,
+            Err(cur) => v = cur,
+        }
+    }
+    crate::errno = EAGAIN;
+    -1
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sem_wait(sem: *mut sem_t) -> c_int {
+    while sem_trywait(sem) != 0 {
+        (*sem).waiters.fetch_add(1, Ordering::SeqCst);
+        std::os::futex_wait(&(*sem).value, 0, 0);
+        (*sem).waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+    0
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sem_post(sem: *mut sem_t) -> c_int {
+    (*sem).value.fetch_add(1, Ordering::SeqCst);
+    if (*sem).waiters.load(Ordering::SeqCst) != 0 {
+        std::os::futex_wake(&(*sem).value, 1);
+    }
+    0
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sem_getvalue(sem: *mut sem_t, sval: *mut c_int) -> c_int {
+    *sval = (*sem).value.load(Ordering::Relaxed) as c_int;
+    0
+}
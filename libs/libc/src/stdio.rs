@@ -167,12 +167,12 @@ pub unsafe extern "C" fn fputs(s: *const c_char, stream: *mut c_void) -> c_int {
 
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn tmpfile() -> *mut c_void {
-    fopen(b"@0xE0/tmp/temp\0".as_ptr() as *const c_char, b"wb+\0".as_ptr() as *const c_char)
+    fopen(b"/tmp/temp\0".as_ptr() as *const c_char, b"wb+\0".as_ptr() as *const c_char)
 }
 
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn tmpnam(s: *mut c_char) -> *mut c_char {
-    let name = b"@0xE0/tmp/lua_tmp\0";
+    let name = b"/tmp/lua_tmp\0";
     if !s.is_null() {
         core::ptr::copy_nonoverlapping(name.as_ptr(), s as *mut u8, name.len());
         s
@@ -328,45 +328,36 @@ pub unsafe extern "C" fn putc(c: c_int, stream: *mut c_void) -> c_int {
     if fwrite(buf.as_ptr() as *const c_void, 1, 1, stream) == 1 { c } else { -1 }
 }
 
-#[repr(C)]
-struct Stat {
-    st_dev: u64,
-    st_ino: u64,
-    st_mode: u32,
-    _pad1: u32, 
-    st_nlink: u64,
-    st_uid: u32,
-    st_gid: u32,
-    st_rdev: u64,
-    st_size: u64,
-    st_blksize: u64,
-    st_blocks: u64,
-    st_atime: u64,
-    st_mtime: u64,
-    st_ctime: u64,
+/// Fills `buf` from `meta`, which is laid out as `struct stat`.
+unsafe fn put_stat(meta: std::io::Result<std::fs::Metadata>, buf: *mut c_void) -> c_int {
+    match meta {
+        Ok(meta) => {
+            *(buf as *mut std::fs::Metadata) = meta;
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+unsafe fn stat_path(path: *const c_char) -> alloc::borrow::Cow<'static, str> {
+    let p_str = core::ffi::CStr::from_ptr(path).to_string_lossy();
+    if p_str.is_empty() { alloc::borrow::Cow::Borrowed("/") } else { alloc::borrow::Cow::Owned(p_str.into_owned()) }
 }
 
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn stat(path: *const c_char, buf: *mut c_void) -> c_int {
-    let mut p_str = core::ffi::CStr::from_ptr(path).to_string_lossy();
-    if p_str.is_empty() {
-        p_str = alloc::borrow::Cow::Borrowed("@0xE0/");
-    }
-    if let Ok(file) = std::fs::File::open(&p_str) {
-        let size = file.size();
-        let is_dir = std::fs::read_dir(&p_str).is_ok();
-
-        let s = &mut *(buf as *mut Stat);
-        core::ptr::write_bytes(buf as *mut u8, 0, core::mem::size_of::<Stat>());
+    put_stat(std::fs::metadata(&stat_path(path)), buf)
+}
 
-        s.st_mode = if is_dir { 0o040000 | 0o777 } else { 0o100000 | 0o666 };
-        s.st_size = size as u64;
-        s.st_blksize = 1024;
-        s.st_blocks = (size as u64 + 511) / 512;
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lstat(path: *const c_char, buf: *mut c_void) -> c_int {
+    put_stat(std::fs::symlink_metadata(&stat_path(path)), buf)
+}
 
-        return 0;
-    }
-    -1
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fstat(fd: c_int, buf: *mut c_void) -> c_int {
+    let file = core::mem::ManuallyDrop::new(std::fs::File::from_raw_fd(fd as usize));
+    put_stat(file.metadata(), buf)
 }
 
 #[unsafe(no_mangle)]
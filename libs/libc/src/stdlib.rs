@@ -156,7 +156,7 @@ pub unsafe extern "C" fn realpath(path: *const c_char, resolved_path: *mut c_cha
     if p_str == "." || p_str.is_empty() {
         resolved = alloc::string::String::from("/");
     } else if !p_str.starts_with('@') && !p_str.starts_with('/') {
-        resolved = alloc::format!("@0xE0/{}", p_str);
+        resolved = alloc::format!("/{}", p_str);
     } else {
         resolved = p_str.into_owned();
     }
@@ -152,15 +152,38 @@ pub unsafe extern "C" fn krake_window_draw(wid: usize) {
 
 
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn kill(pid: c_int, sig: c_int) -> c_int {
-    
-    if sig == 9 {
-        krake_syscall(62, pid as u64, 9, 0, 0) as c_int
-    } else {
-        0
+pub unsafe extern "C" fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void {
+    std::os::mmap(addr as usize, len, prot as u64, flags as u64, fd, offset as u64) as *mut c_void
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn munmap(addr: *mut c_void, len: usize) -> c_int {
+    std::os::munmap(addr as usize, len)
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn msync(addr: *mut c_void, len: usize, _flags: c_int) -> c_int {
+    std::os::msync(addr as usize, len)
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn getrlimit(resource: c_int, rlim: *mut std::os::RLimit) -> c_int {
+    match std::os::getrlimit(resource as usize) {
+        Some(limit) if !rlim.is_null() => { *rlim = limit; 0 }
+        _ => -1,
     }
 }
 
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn setrlimit(resource: c_int, rlim: *const std::os::RLimit) -> c_int {
+    if !rlim.is_null() && std::os::setrlimit(resource as usize, &*rlim) { 0 } else { -1 }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kill(pid: c_int, sig: c_int) -> c_int {
+    std::os::kill(pid as usize, sig as u32)
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn raise(sig: c_int) -> c_int {
     kill(crate::unistd::getpid(), sig)
@@ -189,11 +212,46 @@ pub unsafe extern "C" fn sigdelset(set: *mut u32, signum: c_int) -> c_int {
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn sigismember(set: *const u32, signum: c_int) -> c_int { if (*set & (1 << (signum - 1))) != 0 { 1 } else { 0 } }
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn sigprocmask(_how: c_int, _set: *const u32, _oldset: *mut u32) -> c_int { 0 }
+pub unsafe extern "C" fn sigprocmask(how: c_int, set: *const u32, oldset: *mut u32) -> c_int {
+    std::os::sigprocmask(how as u64, set.as_ref(), oldset.as_mut())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn signal(signum: c_int, handler: *const c_void) -> *const c_void {
+    std::os::signal(signum as u32, handler as u64) as *const c_void
+}
+
+const SA_SIGINFO: c_int = 4;
+
+#[repr(C)]
+pub struct sigaction {
+    pub sa_handler: *const c_void,
+    pub sa_sigaction: *const c_void,
+    pub sa_mask: u32,
+    pub sa_flags: c_int,
+    pub sa_restorer: *const c_void,
+}
 
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn signal(_signum: c_int, _handler: *const c_void) -> *const c_void {
-    core::ptr::null()
+pub unsafe extern "C" fn sigaction(sig: c_int, act: *const sigaction, oact: *mut sigaction) -> c_int {
+    let new = act.as_ref().map(|a| std::os::SigAction {
+        handler: if (a.sa_flags & SA_SIGINFO) != 0 { a.sa_sigaction as u64 } else { a.sa_handler as u64 },
+        flags: a.sa_flags as u32,
+        mask: a.sa_mask,
+        restorer: a.sa_restorer as u64,
+    });
+    let mut old = std::os::SigAction { handler: 0, flags: 0, mask: 0, restorer: 0 };
+    let res = std::os::sigaction(sig as u32, new.as_ref(), Some(&mut old));
+    if res == 0 {
+        if let Some(o) = oact.as_mut() {
+            o.sa_handler = old.handler as *const c_void;
+            o.sa_sigaction = old.handler as *const c_void;
+            o.sa_mask = old.mask;
+            o.sa_flags = old.flags as c_int;
+            o.sa_restorer = old.restorer as *const c_void;
+        }
+    }
+    res
 }
 
 #[repr(C)]
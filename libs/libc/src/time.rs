@@ -1,4 +1,4 @@
-use core::ffi::c_long;
+use core::ffi::{c_int, c_long};
 use crate::sys::krake_get_time_ms;
 
 #[repr(C)]
@@ -14,6 +14,36 @@ pub struct tm {
     pub tm_isdst: i32,
 }
 
+#[repr(C)]
+pub struct timespec {
+    pub tv_sec: c_long,
+    pub tv_nsec: c_long,
+}
+
+pub const CLOCK_REALTIME: c_int = 0;
+pub const CLOCK_MONOTONIC: c_int = 1;
+const EINVAL: c_int = 22;
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn clock_gettime(clock_id: c_int, tp: *mut timespec) -> c_int {
+    if tp.is_null() || std::os::syscall(228, clock_id as u64, tp as u64, 0) == u64::MAX {
+        crate::errno = EINVAL;
+        return -1;
+    }
+    0
+}
+
+/// Sleeps for `req`. Sleeps are not cut short by signals, so `rem` is never written.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nanosleep(req: *const timespec, _rem: *mut timespec) -> c_int {
+    if req.is_null() || (*req).tv_sec < 0 || !(0..1_000_000_000).contains(&(*req).tv_nsec) {
+        crate::errno = EINVAL;
+        return -1;
+    }
+    std::os::sleep_ns((*req).tv_sec as u64 * 1_000_000_000 + (*req).tv_nsec as u64);
+    0
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn clock() -> c_long {
     krake_get_time_ms() as c_long
@@ -1,17 +1,14 @@
-use crate::sys::krake_sleep;
 use core::ffi::{c_char, c_int, c_long, c_uint, c_ulong, c_void};
 
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn usleep(usec: c_uint) -> c_int {
-    let ms = (usec + 999) / 1000;
-    krake_sleep(ms as usize);
+    std::os::sleep_ns(usec as u64 * 1000);
     0
 }
 
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn time(t: *mut c_long) -> c_long {
-    let ticks = std::os::syscall(109, 0, 0, 0); // SYS_GET_TICKS
-    let seconds = (ticks / 1000) as c_long;
+    let seconds = (std::os::clock_gettime(std::os::CLOCK_REALTIME) / 1_000_000_000) as c_long;
     if !t.is_null() {
         *t = seconds;
     }
@@ -47,7 +44,10 @@ pub unsafe extern "C" fn ioctl(fd: c_int, request: c_ulong, mut args: ...) -> c_
 }
 
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn fcntl(_fd: c_int, _cmd: c_int, ...) -> c_int { 0 }
+pub unsafe extern "C" fn fcntl(fd: c_int, cmd: c_int, mut args: ...) -> c_int {
+    let arg = args.arg::<c_int>();
+    std::os::fcntl(fd as usize, cmd as u64, arg as u64)
+}
 
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn close(fd: c_int) -> c_int {
@@ -95,6 +95,33 @@ pub unsafe extern "C" fn unlink(path: *const c_char) -> c_int {
     if std::fs::remove_file(&path_str).is_ok() { 0 } else { -1 }
 }
 
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn link(oldpath: *const c_char, newpath: *const c_char) -> c_int {
+    let old = core::ffi::CStr::from_ptr(oldpath).to_string_lossy();
+    let new = core::ffi::CStr::from_ptr(newpath).to_string_lossy();
+    if std::fs::hard_link(&old, &new).is_ok() { 0 } else { -1 }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn symlink(target: *const c_char, linkpath: *const c_char) -> c_int {
+    let target = core::ffi::CStr::from_ptr(target).to_string_lossy();
+    let linkpath = core::ffi::CStr::from_ptr(linkpath).to_string_lossy();
+    if std::fs::symlink(&target, &linkpath).is_ok() { 0 } else { -1 }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn readlink(path: *const c_char, buf: *mut c_char, bufsiz: usize) -> isize {
+    let path = core::ffi::CStr::from_ptr(path).to_string_lossy();
+    match std::fs::read_link(&path) {
+        Ok(target) => {
+            let len = target.len().min(bufsiz);
+            core::ptr::copy_nonoverlapping(target.as_ptr(), buf as *mut u8, len);
+            len as isize
+        }
+        Err(_) => -1,
+    }
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn gethostname(name: *mut c_char, len: usize) -> c_int {
     let host = b"krakeos\0";
@@ -104,11 +131,36 @@ pub unsafe extern "C" fn gethostname(name: *mut c_char, len: usize) -> c_int {
 }
 
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn fsync(_fd: c_int) -> c_int { 0 }
+pub unsafe extern "C" fn fsync(fd: c_int) -> c_int {
+    if std::os::syscall(74, fd as u64, 0, 0) == 0 { 0 } else { -1 } // SYS_FSYNC
+}
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn fchown(_fd: c_int, _owner: u32, _group: u32) -> c_int { 0 }
+pub unsafe extern "C" fn fdatasync(fd: c_int) -> c_int {
+    if std::os::syscall(75, fd as u64, 0, 0) == 0 { 0 } else { -1 } // SYS_FDATASYNC
+}
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sync() {
+    std::os::syscall(162, 0, 0, 0); // SYS_SYNC
+}
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chown(path: *const c_char, owner: u32, group: u32) -> c_int {
+    let path = core::ffi::CStr::from_ptr(path).to_string_lossy();
+    let id = |v: u32| if v == u32::MAX { None } else { Some(v) };
+    if std::fs::chown(&path, id(owner), id(group)).is_ok() { 0 } else { -1 }
+}
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn chmod(_path: *const c_char, _mode: u32) -> c_int { 0 }
+pub unsafe extern "C" fn fchown(fd: c_int, owner: u32, group: u32) -> c_int {
+    if std::os::syscall(93, fd as u64, owner as u64, group as u64) == 0 { 0 } else { -1 } // SYS_FCHOWN
+}
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chmod(path: *const c_char, mode: u32) -> c_int {
+    let path = core::ffi::CStr::from_ptr(path).to_string_lossy();
+    if std::fs::set_permissions(&path, mode).is_ok() { 0 } else { -1 }
+}
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fchmod(fd: c_int, mode: u32) -> c_int {
+    if std::os::syscall(91, fd as u64, mode as u64, 0) == 0 { 0 } else { -1 } // SYS_FCHMOD
+}
 
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn wait(status: *mut c_int) -> c_int {
@@ -116,8 +168,15 @@ pub unsafe extern "C" fn wait(status: *mut c_int) -> c_int {
 }
 
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn waitpid(pid: c_int, status: *mut c_int, _options: c_int) -> c_int {
-    let res = std::os::waitpid(pid as usize);
+pub unsafe extern "C" fn waitpid(pid: c_int, status: *mut c_int, options: c_int) -> c_int {
+    let res = if (options as u64 & std::os::WNOHANG) != 0 {
+        match std::os::try_waitpid(pid as usize) {
+            Some(res) => res,
+            None => return 0,
+        }
+    } else {
+        std::os::waitpid(pid as usize)
+    };
     if !status.is_null() {
         *status = (res as c_int) << 8; 
     }
@@ -125,7 +184,10 @@ pub unsafe extern "C" fn waitpid(pid: c_int, status: *mut c_int, _options: c_int
 }
 
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn fork() -> c_int { -1 }
+pub unsafe extern "C" fn fork() -> c_int {
+    let pid = std::os::fork();
+    if pid == usize::MAX { -1 } else { pid as c_int }
+}
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn pipe(fds: *mut c_int) -> c_int {
     let mut safe_fds = [0i32; 2];
@@ -143,7 +205,37 @@ pub unsafe extern "C" fn tcsetattr(_fd: c_int, _opt: c_int, _termios: *const c_v
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn tcgetattr(_fd: c_int, _termios: *mut c_void) -> c_int { 0 }
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn execl(_path: *const c_char, _arg0: *const c_char, ...) -> c_int { -1 }
+pub unsafe extern "C" fn execve(path: *const c_char, argv: *const *const c_char, envp: *const *const c_char) -> c_int {
+    let path_bytes = core::ffi::CStr::from_ptr(path).to_bytes();
+    std::os::syscall4(59, path_bytes.as_ptr() as u64, path_bytes.len() as u64, argv as u64, envp as u64);
+    -1
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn execv(path: *const c_char, argv: *const *const c_char) -> c_int {
+    let env_strings: alloc::vec::Vec<alloc::string::String> = std::env::vars()
+        .map(|(k, v)| alloc::format!("{}={}\0", k, v))
+        .collect();
+    let mut envp: alloc::vec::Vec<*const c_char> = env_strings.iter().map(|s| s.as_ptr() as *const c_char).collect();
+    envp.push(core::ptr::null());
+    execve(path, argv, envp.as_ptr())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn execl(path: *const c_char, arg0: *const c_char, mut args: ...) -> c_int {
+    let mut argv: alloc::vec::Vec<*const c_char> = alloc::vec::Vec::new();
+    argv.push(arg0);
+    if !arg0.is_null() {
+        loop {
+            let arg = args.arg::<*const c_char>();
+            argv.push(arg);
+            if arg.is_null() {
+                break;
+            }
+        }
+    }
+    execv(path, argv.as_ptr())
+}
 
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn getuid() -> u32 { 0 }
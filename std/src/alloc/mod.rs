@@ -151,8 +151,7 @@ unsafe fn grow_heap(min_size: usize) -> bool {
         let end = new_brk;
         let ptr = start as *mut u8;
 
-        // Initialize memory (brk usually returns zeroed pages, but safe to be sure)
-        write_bytes(ptr, 0, aligned_size);
+        // brk hands back zero-filled pages on first touch, so no need to clear them here.
 
         // Check if we can merge with the last region
         if HEAP_REGION_COUNT > 0 && HEAP_REGIONS[HEAP_REGION_COUNT - 1].end == start {
@@ -32,14 +32,19 @@ impl File {
     }
 
     pub fn size(&self) -> usize {
-        unsafe {
-            let res = syscall(5, self.fd as u64, 0, 0);
-            if res == u64::MAX {
-                0
-            } else {
-                res as usize
-            }
-        }
+        self.metadata().map_or(0, |m| m.len() as usize)
+    }
+
+    pub fn metadata(&self) -> Result<Metadata> {
+        let mut meta = Metadata::default();
+        let res = unsafe { syscall(5, self.fd as u64, &mut meta as *mut Metadata as u64, 0) };
+        if res == 0 { Ok(meta) } else { Err(Error::from_raw_os_error(9)) }
+    }
+
+    /// Sets the permission bits of the open file to `mode`.
+    pub fn set_permissions(&self, mode: u32) -> Result<()> {
+        let res = unsafe { syscall(91, self.fd as u64, mode as u64, 0) };
+        if res == 0 { Ok(()) } else { Err(Error::from_raw_os_error(1)) }
     }
 
     pub fn as_raw_fd(&self) -> usize {
@@ -58,6 +63,12 @@ impl File {
             Err(Error::from_raw_os_error(5))
         }
     }
+
+    /// Waits until everything written to the file has reached the disk.
+    pub fn sync_all(&self) -> Result<()> {
+        let res = unsafe { syscall(74, self.fd as u64, 0, 0) };
+        if res == 0 { Ok(()) } else { Err(Error::from_raw_os_error(5)) }
+    }
 }
 
 impl Read for File {
@@ -143,9 +154,41 @@ pub fn rename(from: &str, to: &str) -> Result<()> {
     if res == 0 { Ok(()) } else { Err(Error::from_raw_os_error(1)) }
 }
 
-pub fn mount(disk_id: u8, fs_type: &str) -> Result<()> {
+/// Makes `link` another name for the file at `original`. Both must be on
+/// the same filesystem.
+pub fn hard_link(original: &str, link: &str) -> Result<()> {
+    let res = unsafe {
+        crate::os::syscall4(86, original.as_ptr() as u64, original.len() as u64, link.as_ptr() as u64, link.len() as u64)
+    };
+    if res == 0 { Ok(()) } else { Err(Error::from_raw_os_error(1)) }
+}
+
+/// Creates a symlink at `link` pointing to `original`. A relative
+/// `original` is resolved from the directory holding `link`.
+pub fn symlink(original: &str, link: &str) -> Result<()> {
     let res = unsafe {
-        syscall(165, disk_id as u64, fs_type.as_ptr() as u64, fs_type.len() as u64)
+        crate::os::syscall4(88, original.as_ptr() as u64, original.len() as u64, link.as_ptr() as u64, link.len() as u64)
+    };
+    if res == 0 { Ok(()) } else { Err(Error::from_raw_os_error(1)) }
+}
+
+/// Target of the symlink at `path`.
+pub fn read_link(path: &str) -> Result<String> {
+    let mut buf = [0u8; 4096];
+    let res = unsafe {
+        crate::os::syscall4(89, path.as_ptr() as u64, path.len() as u64, buf.as_mut_ptr() as u64, buf.len() as u64)
+    };
+    if res == u64::MAX {
+        return Err(Error::from_raw_os_error(22));
+    }
+    Ok(String::from_utf8_lossy(&buf[..res as usize]).into_owned())
+}
+
+/// Mounts the `fs_type` filesystem that starts at sector `start_lba` of disk
+/// `disk_id` on directory `target`. Only `ext2` is supported.
+pub fn mount(disk_id: u8, start_lba: u64, target: &str, fs_type: &str) -> Result<()> {
+    let res = unsafe {
+        crate::os::syscall6(165, disk_id as u64, start_lba, target.as_ptr() as u64, target.len() as u64, fs_type.as_ptr() as u64, fs_type.len() as u64)
     };
 
     if res == 0 {
@@ -155,12 +198,120 @@ pub fn mount(disk_id: u8, fs_type: &str) -> Result<()> {
     }
 }
 
+/// Writes every cached change of every filesystem back to disk.
+pub fn sync() {
+    unsafe { syscall(162, 0, 0, 0); }
+}
+
+/// Detaches the filesystem mounted on `target`. Fails while it is in use.
+pub fn umount(target: &str) -> Result<()> {
+    let res = unsafe {
+        syscall(166, target.as_ptr() as u64, target.len() as u64, 0)
+    };
+    if res == 0 { Ok(()) } else { Err(Error::from_raw_os_error(16)) }
+}
+
+/// What `stat` reports about a file. Times are seconds since the Unix epoch.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Metadata {
+    pub dev: u64,
+    pub ino: u64,
+    /// File type and permission bits, as in `st_mode`.
+    pub mode: u32,
+    _pad: u32,
+    pub nlink: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u64,
+    pub size: u64,
+    pub blksize: u64,
+    /// Space allocated, in 512-byte units.
+    pub blocks: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+const S_IFMT: u32 = 0o170000;
+
+impl Metadata {
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    pub fn file_type(&self) -> FileType {
+        match self.mode & S_IFMT {
+            0o100000 => FileType::File,
+            0o040000 => FileType::Directory,
+            0o020000 | 0o060000 => FileType::Device,
+            0o120000 => FileType::Symlink,
+            _ => FileType::Unknown,
+        }
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type() == FileType::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == FileType::Directory
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type() == FileType::Symlink
+    }
+
+    pub fn permissions(&self) -> u32 {
+        self.mode & 0o7777
+    }
+}
+
+fn stat_syscall(num: u64, path: &str) -> Result<Metadata> {
+    let mut meta = Metadata::default();
+    let res = unsafe { syscall(num, path.as_ptr() as u64, path.len() as u64, &mut meta as *mut Metadata as u64) };
+    if res == 0 { Ok(meta) } else { Err(Error::from_raw_os_error(2)) }
+}
+
+/// Metadata of the file at `path`, following symlinks.
+pub fn metadata(path: &str) -> Result<Metadata> {
+    stat_syscall(4, path)
+}
+
+/// Metadata of the file at `path`; a symlink describes itself.
+pub fn symlink_metadata(path: &str) -> Result<Metadata> {
+    stat_syscall(6, path)
+}
+
+/// Sets the permission bits of the file at `path` to `mode`.
+pub fn set_permissions(path: &str, mode: u32) -> Result<()> {
+    let res = unsafe { syscall(90, path.as_ptr() as u64, path.len() as u64, mode as u64) };
+    if res == 0 { Ok(()) } else { Err(Error::from_raw_os_error(1)) }
+}
+
+/// Changes the owner and group of the file at `path`; None keeps one as it is.
+pub fn chown(path: &str, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+    let res = unsafe {
+        crate::os::syscall4(92, path.as_ptr() as u64, path.len() as u64, uid.unwrap_or(u32::MAX) as u64, gid.unwrap_or(u32::MAX) as u64)
+    };
+    if res == 0 { Ok(()) } else { Err(Error::from_raw_os_error(1)) }
+}
+
+/// Sets the access and modification times of the file at `path`, in seconds
+/// since the Unix epoch.
+pub fn set_times(path: &str, atime: u64, mtime: u64) -> Result<()> {
+    let times: [i64; 4] = [atime as i64, 0, mtime as i64, 0];
+    let res = unsafe { syscall(235, path.as_ptr() as u64, path.len() as u64, times.as_ptr() as u64) };
+    if res == 0 { Ok(()) } else { Err(Error::from_raw_os_error(1)) }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileType {
     Unknown = 0,
     File = 1,
     Directory = 2,
     Device = 3,
+    Symlink = 4,
 }
 
 #[derive(Debug, Clone)]
@@ -205,6 +356,7 @@ pub fn read_dir(path: &str) -> Result<Vec<DirEntry>> {
                 1 => FileType::File,
                 2 => FileType::Directory,
                 3 => FileType::Device,
+                4 => FileType::Symlink,
                 _ => FileType::Unknown,
             };
 
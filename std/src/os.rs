@@ -13,21 +13,70 @@ pub fn debug_print(s: &str) {
 }
 
 pub fn sleep(ms: u64) {
-    if ms > 10 {
-        unsafe {
-            syscall(35, ms, 0, 0);
-        }
+    sleep_ns(ms.saturating_mul(1_000_000));
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+pub const CLOCK_REALTIME: u64 = 0;
+pub const CLOCK_MONOTONIC: u64 = 1;
 
-        yield_task();
+pub fn sleep_ns(ns: u64) {
+    let ts = Timespec { tv_sec: (ns / 1_000_000_000) as i64, tv_nsec: (ns % 1_000_000_000) as i64 };
+    unsafe {
+        syscall(35, &ts as *const Timespec as u64, 0, 0);
     }
 }
 
+/// Reads `clock` in nanoseconds: since boot for `CLOCK_MONOTONIC`, since
+/// the Unix epoch for `CLOCK_REALTIME`.
+pub fn clock_gettime(clock: u64) -> u64 {
+    let mut ts = Timespec::default();
+    unsafe {
+        syscall(228, clock, &mut ts as *mut Timespec as u64, 0);
+    }
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+pub const ARCH_SET_FS: u64 = 0x1002;
+pub const ARCH_GET_FS: u64 = 0x1003;
+
+/// Sets the calling thread's FS base, its thread pointer.
+pub fn set_fs_base(base: u64) -> bool {
+    unsafe { syscall(158, ARCH_SET_FS, base, 0) != u64::MAX }
+}
+
+pub fn fs_base() -> u64 {
+    let mut base = 0u64;
+    unsafe {
+        syscall(158, ARCH_GET_FS, &mut base as *mut u64 as u64, 0);
+    }
+    base
+}
+
 pub fn yield_task() {
     unsafe {
         asm!("int 0x81");
     }
 }
 
+/// Sleeps while `*word == expected`, until woken or `timeout_ms` passes (0 waits
+/// forever). Returns false on a value mismatch, timeout or signal.
+pub fn futex_wait(word: &core::sync::atomic::AtomicU32, expected: u32, timeout_ms: u64) -> bool {
+    unsafe { syscall4(202, word.as_ptr() as u64, 0, expected as u64, timeout_ms) == 0 }
+}
+
+/// Wakes up to `count` threads waiting on `word`. Returns how many woke.
+pub fn futex_wake(word: &core::sync::atomic::AtomicU32, count: usize) -> usize {
+    let woken = unsafe { syscall(202, word.as_ptr() as u64, 1, count as u64) };
+    if woken == u64::MAX { 0 } else { woken as usize }
+}
+
 
 pub fn read(buffer: &mut [u8]) -> usize {
     unsafe {
@@ -134,7 +183,7 @@ pub fn spawn_with_fds(path: &str, args: &[&str], fds: &[(u8, u8)]) -> usize {
     let arg_ptrs: Vec<*const u8> = c_args.iter().map(|s| s.as_ptr()).collect();
 
     unsafe {
-        syscall6(59, 
+        syscall6(114, 
             path.as_ptr() as u64, 
             path.len() as u64, 
             arg_ptrs.as_ptr() as u64, 
@@ -145,6 +194,131 @@ pub fn spawn_with_fds(path: &str, args: &[&str], fds: &[(u8, u8)]) -> usize {
     }
 }
 
+/// Replaces the current process image. Only returns if the exec failed.
+pub fn execve(path: &str, args: &[&str], envs: &[&str]) -> usize {
+    use rust_alloc::vec::Vec;
+    use rust_alloc::string::String;
+
+    let to_c = |list: &[&str]| -> Vec<String> {
+        list.iter().map(|&a| {
+            let mut s = String::from(a);
+            s.push('\0');
+            s
+        }).collect()
+    };
+
+    let c_args = to_c(args);
+    let c_envs = to_c(envs);
+
+    let mut arg_ptrs: Vec<*const u8> = c_args.iter().map(|s| s.as_ptr()).collect();
+    arg_ptrs.push(core::ptr::null());
+    let mut env_ptrs: Vec<*const u8> = c_envs.iter().map(|s| s.as_ptr()).collect();
+    env_ptrs.push(core::ptr::null());
+
+    unsafe {
+        syscall4(59,
+            path.as_ptr() as u64,
+            path.len() as u64,
+            arg_ptrs.as_ptr() as u64,
+            env_ptrs.as_ptr() as u64
+        ) as usize
+    }
+}
+
+pub const F_GETFD: u64 = 1;
+pub const F_SETFD: u64 = 2;
+pub const F_GETFL: u64 = 3;
+pub const F_SETFL: u64 = 4;
+pub const FD_CLOEXEC: u64 = 1;
+pub const O_NONBLOCK: u64 = 0o4000;
+
+pub fn fcntl(fd: usize, cmd: u64, arg: u64) -> i32 {
+    unsafe {
+        syscall(72, fd as u64, cmd, arg) as i32
+    }
+}
+
+pub fn fork() -> usize {
+    unsafe { syscall(57, 0, 0, 0) as usize }
+}
+
+pub const SIGHUP: u32 = 1;
+pub const SIGINT: u32 = 2;
+pub const SIGQUIT: u32 = 3;
+pub const SIGILL: u32 = 4;
+pub const SIGABRT: u32 = 6;
+pub const SIGFPE: u32 = 8;
+pub const SIGKILL: u32 = 9;
+pub const SIGUSR1: u32 = 10;
+pub const SIGSEGV: u32 = 11;
+pub const SIGUSR2: u32 = 12;
+pub const SIGPIPE: u32 = 13;
+pub const SIGALRM: u32 = 14;
+pub const SIGTERM: u32 = 15;
+pub const SIGCHLD: u32 = 17;
+pub const SIGCONT: u32 = 18;
+pub const SIGSTOP: u32 = 19;
+pub const SIGTSTP: u32 = 20;
+
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+pub const SIG_ERR: u64 = u64::MAX;
+
+pub const SIG_BLOCK: u64 = 0;
+pub const SIG_UNBLOCK: u64 = 1;
+pub const SIG_SETMASK: u64 = 2;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SigAction {
+    pub handler: u64,
+    pub flags: u32,
+    pub mask: u32,
+    pub restorer: u64,
+}
+
+/// Return address for signal handlers; hands the saved frame back to the kernel.
+#[unsafe(naked)]
+pub extern "C" fn sigreturn_trampoline() {
+    core::arch::naked_asm!("mov rax, 15", "syscall", "ud2");
+}
+
+pub fn kill(pid: usize, sig: u32) -> i32 {
+    unsafe { syscall(62, pid as u64, sig as u64, 0) as i32 }
+}
+
+pub fn sigaction(sig: u32, act: Option<&SigAction>, old: Option<&mut SigAction>) -> i32 {
+    let act = act.map(|a| {
+        let mut a = *a;
+        if a.restorer == 0 {
+            a.restorer = sigreturn_trampoline as u64;
+        }
+        a
+    });
+    let act_ptr = act.as_ref().map_or(core::ptr::null(), |a| a as *const SigAction);
+    let old_ptr = old.map_or(core::ptr::null_mut(), |o| o as *mut SigAction);
+    unsafe { syscall(13, sig as u64, act_ptr as u64, old_ptr as u64) as i32 }
+}
+
+/// Installs `handler` (or `SIG_DFL`/`SIG_IGN`) for `sig` and returns the previous one.
+pub fn signal(sig: u32, handler: u64) -> u64 {
+    let act = SigAction { handler, flags: 0, mask: 0, restorer: 0 };
+    let mut old = SigAction { handler: 0, flags: 0, mask: 0, restorer: 0 };
+    if sigaction(sig, Some(&act), Some(&mut old)) != 0 {
+        return SIG_ERR;
+    }
+    old.handler
+}
+
+pub fn sigprocmask(how: u64, set: Option<&u32>, old: Option<&mut u32>) -> i32 {
+    let set_ptr = set.map_or(core::ptr::null(), |s| s as *const u32);
+    let old_ptr = old.map_or(core::ptr::null_mut(), |o| o as *mut u32);
+    unsafe { syscall(14, how, set_ptr as u64, old_ptr as u64) as i32 }
+}
+
+pub const WNOHANG: u64 = 1;
+
+/// Sleeps until `pid` exits and returns its exit code.
 pub fn waitpid(pid: usize) -> usize {
     unsafe {
         loop {
@@ -152,11 +326,16 @@ pub fn waitpid(pid: usize) -> usize {
             if status != u64::MAX {
                 return status as usize;
             }
-            yield_task();
         }
     }
 }
 
+/// Reaps `pid` if it has exited, without waiting.
+pub fn try_waitpid(pid: usize) -> Option<usize> {
+    let status = unsafe { syscall(61, pid as u64, WNOHANG, 0) };
+    if status != u64::MAX { Some(status as usize) } else { None }
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct PollFd {
@@ -167,6 +346,11 @@ pub struct PollFd {
 
 pub const POLLIN: i16 = 0x001;
 pub const POLLOUT: i16 = 0x004;
+pub const POLLERR: i16 = 0x008;
+pub const POLLHUP: i16 = 0x010;
+pub const POLLNVAL: i16 = 0x020;
+/// `fd` holds a window id; ready when the window has events queued.
+pub const POLLWINDOW: i16 = 0x4000;
 
 pub fn poll(fds: &mut [PollFd], timeout: i32) -> i32 {
     unsafe {
@@ -192,35 +376,184 @@ pub struct ProcessInfo {
     pub pid: u64,
     pub state: u64,
     pub name: [u8; 32],
+    /// CPU time in ring 3 and ring 0, in the same units as `get_system_ticks`.
+    pub user_ticks: u64,
+    pub kernel_ticks: u64,
+    pub sched_class: u64,
+    pub nice: i64,
 }
 
-pub fn get_process_list() -> rust_alloc::vec::Vec<ProcessInfo> {
-    let max_count = 128;
-    let mut processes = rust_alloc::vec::Vec::with_capacity(max_count);
+pub const PROC_STATE_READY: u64 = 2;
+pub const PROC_STATE_ZOMBIE: u64 = 3;
+pub const PROC_STATE_SLEEPING: u64 = 4;
+pub const PROC_STATE_STOPPED: u64 = 5;
 
-    
-    processes.resize(max_count, ProcessInfo { pid: 0, state: 0, name: [0; 32] });
+pub fn get_process_list() -> rust_alloc::vec::Vec<ProcessInfo> {
+    let mut max_count = 128;
+    loop {
+        let mut processes = rust_alloc::vec::Vec::with_capacity(max_count);
+        processes.resize(max_count, ProcessInfo { pid: 0, state: 0, name: [0; 32], user_ticks: 0, kernel_ticks: 0, sched_class: 0, nice: 0 });
 
-    let count = unsafe {
-        syscall(110, processes.as_mut_ptr() as u64, max_count as u64, 0) as usize
-    };
+        let count = unsafe {
+            syscall(110, processes.as_mut_ptr() as u64, max_count as u64, 0) as usize
+        };
 
-    
-    if count <= max_count {
-        processes.truncate(count);
+        if count <= max_count {
+            processes.truncate(count);
+            return processes;
+        }
+        max_count = count + 16;
     }
-    processes
 }
 
 pub fn get_process_memory(pid: u64) -> usize {
     unsafe { syscall(111, pid, 0, 0) as usize }
 }
 
+/// Bytes of `pid`'s pages currently paged out to swap.
+pub fn get_process_swap(pid: u64) -> usize {
+    let mut swapped = 0u64;
+    unsafe {
+        syscall(111, pid, &mut swapped as *mut u64 as u64, 0);
+    }
+    swapped as usize
+}
+
+/// Starts swapping to `path`: a bare unmounted disk such as `@0xF0` is used
+/// raw from sector `start_lba`, any other path is a swap file created or grown
+/// to `size` bytes and `start_lba` is ignored.
+pub fn swapon(path: &str, start_lba: u64, size: u64) -> i32 {
+    unsafe { syscall4(167, path.as_ptr() as u64, path.len() as u64, size, start_lba) as i32 }
+}
+
+/// One of the kernel's slab caches.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct SlabCacheInfo {
+    pub name: [u8; 16],
+    pub object_size: u64,
+    pub objects: u64,
+    pub slabs: u64,
+    pub slab_bytes: u64,
+}
+
+/// Kernel heap figures; sizes are in bytes.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct HeapStats {
+    pub heap_size: u64,
+    pub heap_used: u64,
+    pub allocations: u64,
+    pub caches: [SlabCacheInfo; 4],
+}
+
+pub fn get_heap_stats() -> HeapStats {
+    let mut stats = HeapStats::default();
+    unsafe {
+        syscall(118, &mut stats as *mut HeapStats as u64, 0, 0);
+    }
+    stats
+}
+
+pub const SCHED_IDLE: u64 = 0;
+pub const SCHED_NORMAL: u64 = 1;
+/// Runs ahead of every normal thread; meant for the UI and input paths.
+pub const SCHED_INTERACTIVE: u64 = 2;
+
+/// Sets the scheduling class of every thread in `pid` (0 for this process).
+pub fn sched_setscheduler(pid: u64, class: u64) -> i32 {
+    unsafe { syscall(144, pid, class, 0) as i32 }
+}
+
+pub fn sched_getscheduler(pid: u64) -> i64 {
+    unsafe { syscall(145, pid, 0, 0) as i64 }
+}
+
+/// Sets the nice value (-20..=19) of every thread in `pid` (0 for this process).
+pub fn setpriority(pid: u64, nice: i32) -> i32 {
+    unsafe { syscall(141, pid, nice as i64 as u64, 0) as i32 }
+}
+
+pub fn getpriority(pid: u64) -> Option<i32> {
+    let raw = unsafe { syscall(140, pid, 0, 0) };
+    if raw == u64::MAX { None } else { Some(20 - raw as i32) }
+}
+
+/// `personality` flag: children started after this get unrandomised layouts.
+pub const ADDR_NO_RANDOMIZE: u64 = 0x0040000;
+
+/// Sets the personality flags of this process and returns the old ones.
+/// `0xFFFFFFFF` only queries them.
+pub fn personality(persona: u64) -> u64 {
+    unsafe { syscall(135, persona, 0, 0) }
+}
+
+pub const RLIMIT_NPROC: usize = 6;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIMIT_AS: usize = 9;
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct RLimit {
+    pub cur: u64,
+    pub max: u64,
+}
+
+pub fn getrlimit(resource: usize) -> Option<RLimit> {
+    let mut limit = RLimit { cur: 0, max: 0 };
+    let ret = unsafe { syscall(97, resource as u64, &mut limit as *mut RLimit as u64, 0) };
+    if ret == u64::MAX { None } else { Some(limit) }
+}
+
+/// The hard limit can only be lowered.
+pub fn setrlimit(resource: usize, limit: &RLimit) -> bool {
+    unsafe { syscall(160, resource as u64, limit as *const RLimit as u64, 0) != u64::MAX }
+}
+
 
 pub fn brk(addr: usize) -> usize {
     unsafe { syscall(12, addr as u64, 0, 0) as usize }
 }
 
-pub fn mmap(addr: usize, len: usize) -> usize {
-    unsafe { syscall6(9, addr as u64, len as u64, 0, 0, 0, 0) as usize }
+pub const PROT_NONE: u64 = 0;
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+
+pub const MAP_SHARED: u64 = 0x01;
+pub const MAP_PRIVATE: u64 = 0x02;
+pub const MAP_FIXED: u64 = 0x10;
+pub const MAP_ANONYMOUS: u64 = 0x20;
+pub const MAP_FAILED: usize = usize::MAX;
+
+/// Maps `len` bytes of `fd` starting at `offset` (page aligned), or zeroed memory
+/// with `MAP_ANONYMOUS`. Returns the mapped address or `MAP_FAILED`.
+pub fn mmap(addr: usize, len: usize, prot: u64, flags: u64, fd: i32, offset: u64) -> usize {
+    unsafe { syscall6(9, addr as u64, len as u64, prot, flags, fd as i64 as u64, offset) as usize }
+}
+
+pub fn munmap(addr: usize, len: usize) -> i32 {
+    unsafe { syscall(11, addr as u64, len as u64, 0) as i32 }
+}
+
+/// Writes modified pages of a `MAP_SHARED` file mapping back to the file.
+pub fn msync(addr: usize, len: usize) -> i32 {
+    unsafe { syscall(26, addr as u64, len as u64, 0) as i32 }
+}
+
+/// Opens the shared memory object `name`, creating it with `size` zeroed bytes
+/// if it does not exist yet. Returns its id, or -1.
+pub fn shm_open(name: &str, size: usize) -> isize {
+    unsafe { syscall(115, name.as_ptr() as u64, name.len() as u64, size as u64) as isize }
+}
+
+/// Maps all of shm object `id` read-write and returns its address, or `MAP_FAILED`.
+pub fn shm_map(id: usize, addr: usize) -> usize {
+    unsafe { syscall(116, id as u64, addr as u64, 0) as usize }
+}
+
+/// Drops `name`; existing mappings stay valid until they are unmapped.
+pub fn shm_unlink(name: &str) -> i32 {
+    unsafe { syscall(117, name.as_ptr() as u64, name.len() as u64, 0) as i32 }
 }
\ No newline at end of file
@@ -1,8 +1,66 @@
 use core::cell::UnsafeCell;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+use crate::os::{futex_wait, futex_wake};
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// Locked, and at least one thread may be sleeping on the futex.
+const CONTENDED: u32 = 2;
+
+/// Spins before falling back to the kernel, so short critical sections never sleep.
+const SPIN_LIMIT: u32 = 100;
+
+/// Futex-backed lock word without data. `repr(transparent)` over a `u32`, so C
+/// bindings can use it in place of `pthread_mutex_t`.
+#[repr(transparent)]
+pub struct RawMutex {
+    state: AtomicU32,
+}
+
+impl RawMutex {
+    pub const fn new() -> Self {
+        Self { state: AtomicU32::new(UNLOCKED) }
+    }
+
+    pub fn try_lock(&self) -> bool {
+        self.state.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+
+    pub fn lock(&self) {
+        if self.try_lock() {
+            return;
+        }
+        for _ in 0..SPIN_LIMIT {
+            if self.state.load(Ordering::Relaxed) == UNLOCKED && self.try_lock() {
+                return;
+            }
+            core::hint::spin_loop();
+        }
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            futex_wait(&self.state, CONTENDED, 0);
+        }
+    }
+
+    /// # Safety
+    /// The caller must hold the lock.
+    pub unsafe fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&self.state, 1);
+        }
+    }
+
+    /// Reacquires after a condition variable wait. Always marks the lock
+    /// contended, since other waiters may have been woken alongside us.
+    fn lock_contended(&self) {
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            futex_wait(&self.state, CONTENDED, 0);
+        }
+    }
+}
 
 pub struct Mutex<T> {
-    lock: AtomicBool,
+    raw: RawMutex,
     data: UnsafeCell<T>,
 }
 
@@ -10,28 +68,36 @@ unsafe impl<T: Send> Sync for Mutex<T> {}
 unsafe impl<T: Send> Send for Mutex<T> {}
 
 pub struct MutexGuard<'a, T> {
-    lock: &'a AtomicBool,
+    raw: &'a RawMutex,
     data: &'a mut T,
 }
 
 impl<T> Mutex<T> {
     pub const fn new(data: T) -> Self {
         Self {
-            lock: AtomicBool::new(false),
+            raw: RawMutex::new(),
             data: UnsafeCell::new(data),
         }
     }
 
     pub fn lock(&self) -> MutexGuard<'_, T> {
-        while self.lock.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
-            core::hint::spin_loop();
-        }
+        self.raw.lock();
         MutexGuard {
-            lock: &self.lock,
+            raw: &self.raw,
             data: unsafe { &mut *self.data.get() },
         }
     }
 
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if !self.raw.try_lock() {
+            return None;
+        }
+        Some(MutexGuard {
+            raw: &self.raw,
+            data: unsafe { &mut *self.data.get() },
+        })
+    }
+
     pub fn int_lock(&self) -> IntMutexGuard<'_, T> {
         let rflags: u64;
         #[cfg(not(feature = "userland"))]
@@ -44,15 +110,21 @@ impl<T> Mutex<T> {
             rflags = 0;
         }
 
-        while self.lock.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
-            core::hint::spin_loop();
-        }
+        self.raw.lock();
         IntMutexGuard {
-            lock: &self.lock,
+            raw: &self.raw,
             data: unsafe { &mut *self.data.get() },
             rflags,
         }
     }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
 }
 
 impl<'a, T> core::ops::Deref for MutexGuard<'a, T> {
@@ -70,12 +142,12 @@ impl<'a, T> core::ops::DerefMut for MutexGuard<'a, T> {
 
 impl<'a, T> Drop for MutexGuard<'a, T> {
     fn drop(&mut self) {
-        self.lock.store(false, Ordering::Release);
+        unsafe { self.raw.unlock(); }
     }
 }
 
 pub struct IntMutexGuard<'a, T> {
-    lock: &'a AtomicBool,
+    raw: &'a RawMutex,
     data: &'a mut T,
     rflags: u64,
 }
@@ -95,7 +167,7 @@ impl<'a, T> core::ops::DerefMut for IntMutexGuard<'a, T> {
 
 impl<'a, T> Drop for IntMutexGuard<'a, T> {
     fn drop(&mut self) {
-        self.lock.store(false, Ordering::Release);
+        unsafe { self.raw.unlock(); }
         #[cfg(not(feature = "userland"))]
         unsafe {
             if (self.rflags & 0x200) != 0 {
@@ -104,3 +176,222 @@ impl<'a, T> Drop for IntMutexGuard<'a, T> {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+/// Condition variable over a sequence counter: waiters sleep until it changes.
+#[repr(transparent)]
+pub struct Condvar {
+    seq: AtomicU32,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self { seq: AtomicU32::new(0) }
+    }
+
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        unsafe { self.wait_raw(guard.raw, 0); }
+        guard
+    }
+
+    pub fn wait_while<'a, T, F>(&self, mut guard: MutexGuard<'a, T>, mut condition: F) -> MutexGuard<'a, T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn wait_timeout<'a, T>(&self, guard: MutexGuard<'a, T>, dur: Duration) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+        let ms = core::cmp::max(dur.as_millis() as u64, 1);
+        let woken = unsafe { self.wait_raw(guard.raw, ms) };
+        (guard, WaitTimeoutResult(!woken))
+    }
+
+    /// Unlocks `raw`, sleeps until notified or `timeout_ms` passes (0 waits
+    /// forever) and locks `raw` again. Returns false on timeout. May wake spuriously.
+    ///
+    /// # Safety
+    /// The caller must hold `raw`.
+    pub unsafe fn wait_raw(&self, raw: &RawMutex, timeout_ms: u64) -> bool {
+        let seq = self.seq.load(Ordering::Relaxed);
+        unsafe { raw.unlock(); }
+        let woken = futex_wait(&self.seq, seq, timeout_ms) || self.seq.load(Ordering::Relaxed) != seq;
+        raw.lock_contended();
+        woken
+    }
+
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        futex_wake(&self.seq, 1);
+    }
+
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        futex_wake(&self.seq, usize::MAX);
+    }
+}
+
+const WRITE_LOCKED: u32 = u32::MAX;
+
+/// Readers-writer lock. `state` counts readers, or is `WRITE_LOCKED`.
+pub struct RwLock<T> {
+    state: AtomicU32,
+    waiters: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            waiters: AtomicU32::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            let s = self.state.load(Ordering::Relaxed);
+            if s < WRITE_LOCKED - 1 {
+                if self.state.compare_exchange_weak(s, s + 1, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                    return RwLockReadGuard { lock: self };
+                }
+                continue;
+            }
+            self.sleep(s);
+        }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            match self.state.compare_exchange(0, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return RwLockWriteGuard { lock: self },
+                Err(s) => self.sleep(s),
+            }
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let s = self.state.load(Ordering::Relaxed);
+        if s < WRITE_LOCKED - 1 && self.state.compare_exchange(s, s + 1, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+            Some(RwLockReadGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.state.compare_exchange(0, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed).ok().map(|_| RwLockWriteGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    fn sleep(&self, seen: u32) {
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        futex_wait(&self.state, seen, 0);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn wake_all(&self) {
+        if self.waiters.load(Ordering::SeqCst) != 0 {
+            futex_wake(&self.state, usize::MAX);
+        }
+    }
+}
+
+impl<'a, T> core::ops::Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        if self.lock.state.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.lock.wake_all();
+        }
+    }
+}
+
+impl<'a, T> core::ops::Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> core::ops::DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::SeqCst);
+        self.lock.wake_all();
+    }
+}
+
+const ONCE_INCOMPLETE: u32 = 0;
+const ONCE_RUNNING: u32 = 1;
+const ONCE_COMPLETE: u32 = 2;
+
+pub struct Once {
+    state: AtomicU32,
+}
+
+impl Once {
+    pub const fn new() -> Self {
+        Self { state: AtomicU32::new(ONCE_INCOMPLETE) }
+    }
+
+    /// Runs `f` exactly once; concurrent callers sleep until it has finished.
+    pub fn call_once<F: FnOnce()>(&self, f: F) {
+        if self.is_completed() {
+            return;
+        }
+        if self.state.compare_exchange(ONCE_INCOMPLETE, ONCE_RUNNING, Ordering::Acquire, Ordering::Acquire).is_ok() {
+            f();
+            self.state.store(ONCE_COMPLETE, Ordering::Release);
+            futex_wake(&self.state, usize::MAX);
+            return;
+        }
+        while self.state.load(Ordering::Acquire) == ONCE_RUNNING {
+            futex_wait(&self.state, ONCE_RUNNING, 0);
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == ONCE_COMPLETE
+    }
+}
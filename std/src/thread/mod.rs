@@ -86,13 +86,7 @@ impl<T> JoinHandle<T> {
 
         unsafe {
             // Wait for thread to exit
-            loop {
-                let res = crate::os::syscall(61, id as u64, 0, 0); // SYS_WAIT4
-                if res != u64::MAX {
-                    break;
-                }
-                crate::os::yield_task();
-            }
+            crate::os::waitpid(id as usize);
 
             // Read result
             let packet = Box::from_raw(packet_ptr);
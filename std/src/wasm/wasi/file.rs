@@ -31,11 +31,12 @@ pub fn register(interpreter: &mut Interpreter, mod_name: &str) {
     interpreter.add_host_function(mod_name, "fd_filestat_get", |interp, args| {
         let fd = match args[0] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
         let stat_ptr = match args[1] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
-        let size = unsafe { crate::os::syscall(5, fd as u64, 0, 0) };
+        let mut meta = crate::fs::Metadata::default();
+        let res = unsafe { crate::os::syscall(5, fd as u64, &mut meta as *mut crate::fs::Metadata as u64, 0) };
+        if res == u64::MAX && fd > 2 { return Some(Value::I32(8)); }
         if stat_ptr + 64 <= interp.memory.len() {
-            for j in 0..64 { interp.memory[stat_ptr + j] = 0; }
-            interp.memory[stat_ptr+32..stat_ptr+40].copy_from_slice(&size.to_le_bytes());
-            interp.memory[stat_ptr+16] = if fd <= 2 { 2 } else { 4 };
+            write_filestat(&mut interp.memory[stat_ptr..stat_ptr+64], &meta);
+            if res == u64::MAX { interp.memory[stat_ptr+16] = 2; }
         }
         Some(Value::I32(0))
     });
@@ -205,8 +206,7 @@ pub fn register(interpreter: &mut Interpreter, mod_name: &str) {
         let path_raw = &interp.memory[path_ptr..path_ptr+path_len];
         let path = core::str::from_utf8(path_raw).unwrap_or("");
         let mut krake_path = String::from(path);
-        if krake_path.starts_with('/') { krake_path = String::from("@0xE0") + &krake_path; }
-        else if !krake_path.starts_with('@') { krake_path = String::from("@0xE0/") + &krake_path; }
+        if !krake_path.starts_with('/') && !krake_path.starts_with('@') { krake_path = String::from("/") + &krake_path; }
         let res = unsafe { crate::os::syscall(83, krake_path.as_ptr() as u64, krake_path.len() as u64, 0) };
         Some(Value::I32(if res == 0 { 0 } else { 1 }))
     });
@@ -218,22 +218,45 @@ pub fn register(interpreter: &mut Interpreter, mod_name: &str) {
         let path_raw = &interp.memory[path_ptr..path_ptr+path_len];
         let path_str = core::str::from_utf8(path_raw).unwrap_or("");
         let mut krake_path = String::from(path_str);
-        if krake_path.starts_with('/') { krake_path = String::from("@0xE0") + &krake_path; }
-        else if !krake_path.starts_with('@') { krake_path = String::from("@0xE0/") + &krake_path; }
-        let fd = unsafe { crate::os::syscall(2, krake_path.as_ptr() as u64, krake_path.len() as u64, 0) };
-        if fd == u64::MAX { return Some(Value::I32(44)); }
-        let size = unsafe { crate::os::syscall(5, fd, 0, 0) };
-        unsafe { crate::os::syscall(3, fd, 0, 0); }
+        if !krake_path.starts_with('/') && !krake_path.starts_with('@') { krake_path = String::from("/") + &krake_path; }
+        let follow = matches!(args[1], Value::I32(flags) if (flags & 1) != 0);
+        let meta = if follow { crate::fs::metadata(&krake_path) } else { crate::fs::symlink_metadata(&krake_path) };
+        let meta = match meta { Ok(m) => m, Err(_) => return Some(Value::I32(44)) };
         if stat_ptr + 64 <= interp.memory.len() {
-            for j in 0..64 { interp.memory[stat_ptr + j] = 0; }
-            interp.memory[stat_ptr+32..stat_ptr+40].copy_from_slice(&size.to_le_bytes());
-            interp.memory[stat_ptr+16] = 4;
+            write_filestat(&mut interp.memory[stat_ptr..stat_ptr+64], &meta);
         }
         Some(Value::I32(0))
     });
 
-    interpreter.add_host_function(mod_name, "path_filestat_set_times", |_interp, _args| { crate::os::yield_task(); Some(Value::I32(0)) });
-    interpreter.add_host_function(mod_name, "path_link", |_interp, _args| { crate::os::yield_task(); Some(Value::I32(58)) });
+    interpreter.add_host_function(mod_name, "path_filestat_set_times", |interp, args| {
+        let path_ptr = match args[2] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let path_len = match args[3] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let atim = match args[4] { Value::I64(v) => v as u64, _ => return Some(Value::I32(28)) };
+        let mtim = match args[5] { Value::I64(v) => v as u64, _ => return Some(Value::I32(28)) };
+        let fst_flags = match args[6] { Value::I32(v) => v as u32, _ => return Some(Value::I32(28)) };
+        let mut krake_path = core::str::from_utf8(&interp.memory[path_ptr..path_ptr+path_len]).unwrap_or("").to_string();
+        if !krake_path.starts_with('/') && !krake_path.starts_with('@') { krake_path = String::from("/") + &krake_path; }
+        let meta = match crate::fs::metadata(&krake_path) { Ok(m) => m, Err(_) => return Some(Value::I32(44)) };
+        let now = crate::time::SystemTime::now().duration_since(crate::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        // fst_flags: ATIM, ATIM_NOW, MTIM, MTIM_NOW; times are in ns.
+        let pick = |set: u32, set_now: u32, value: u64, current: u64| {
+            if (fst_flags & set_now) != 0 { now } else if (fst_flags & set) != 0 { value / 1_000_000_000 } else { current }
+        };
+        let atime = pick(1, 2, atim, meta.atime);
+        let mtime = pick(4, 8, mtim, meta.mtime);
+        Some(Value::I32(if crate::fs::set_times(&krake_path, atime, mtime).is_ok() { 0 } else { 44 }))
+    });
+    interpreter.add_host_function(mod_name, "path_link", |interp, args| {
+        let old_ptr = match args[2] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let old_len = match args[3] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let new_ptr = match args[5] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let new_len = match args[6] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let mut old_path = core::str::from_utf8(&interp.memory[old_ptr..old_ptr+old_len]).unwrap_or("").to_string();
+        let mut new_path = core::str::from_utf8(&interp.memory[new_ptr..new_ptr+new_len]).unwrap_or("").to_string();
+        if !old_path.starts_with('/') && !old_path.starts_with('@') { old_path = String::from("/") + &old_path; }
+        if !new_path.starts_with('/') && !new_path.starts_with('@') { new_path = String::from("/") + &new_path; }
+        Some(Value::I32(if crate::fs::hard_link(&old_path, &new_path).is_ok() { 0 } else { 44 }))
+    });
     
     interpreter.add_host_function(mod_name, "path_open", |interp, args| {
         let path_ptr = match args[2] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
@@ -244,9 +267,8 @@ pub fn register(interpreter: &mut Interpreter, mod_name: &str) {
         let path_raw = &interp.memory[path_ptr..path_ptr+path_len];
         let path_str = core::str::from_utf8(path_raw).unwrap_or("");
         let mut krake_path = String::from(path_str);
-        if krake_path == "." || krake_path == "" { krake_path = String::from("@0xE0/"); }
-        else if krake_path.starts_with('/') { krake_path = String::from("@0xE0") + &krake_path; }
-        else if !krake_path.starts_with('@') { krake_path = String::from("@0xE0/") + &krake_path; }
+        if krake_path == "." || krake_path == "" { krake_path = String::from("/"); }
+        else if !krake_path.starts_with('/') && !krake_path.starts_with('@') { krake_path = String::from("/") + &krake_path; }
                     let mut krake_flags = 0;
                     if (rights_base & 64) != 0 { krake_flags = 2; }
                     if (oflags & 1) != 0 { unsafe { crate::os::syscall(85, krake_path.as_ptr() as u64, krake_path.len() as u64, 0); } }
@@ -263,7 +285,21 @@ pub fn register(interpreter: &mut Interpreter, mod_name: &str) {
         Some(Value::I32(0))
     });
 
-    interpreter.add_host_function(mod_name, "path_readlink", |_interp, _args| { crate::os::yield_task(); Some(Value::I32(58)) });
+    interpreter.add_host_function(mod_name, "path_readlink", |interp, args| {
+        let path_ptr = match args[1] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let path_len = match args[2] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let buf_ptr = match args[3] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let buf_len = match args[4] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let used_ptr = match args[5] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let mut krake_path = core::str::from_utf8(&interp.memory[path_ptr..path_ptr+path_len]).unwrap_or("").to_string();
+        if !krake_path.starts_with('/') && !krake_path.starts_with('@') { krake_path = String::from("/") + &krake_path; }
+        let target = match crate::fs::read_link(&krake_path) { Ok(t) => t, Err(_) => return Some(Value::I32(28)) };
+        let len = target.len().min(buf_len);
+        if buf_ptr + len > interp.memory.len() || used_ptr + 4 > interp.memory.len() { return Some(Value::I32(21)); }
+        interp.memory[buf_ptr..buf_ptr+len].copy_from_slice(&target.as_bytes()[..len]);
+        interp.memory[used_ptr..used_ptr+4].copy_from_slice(&(len as u32).to_le_bytes());
+        Some(Value::I32(0))
+    });
 
     interpreter.add_host_function(mod_name, "path_remove_directory", |interp, args| {
         let path_ptr = match args[1] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
@@ -271,8 +307,7 @@ pub fn register(interpreter: &mut Interpreter, mod_name: &str) {
         let path_raw = &interp.memory[path_ptr..path_ptr+path_len];
         let path = core::str::from_utf8(path_raw).unwrap_or("");
         let mut krake_path = String::from(path);
-        if krake_path.starts_with('/') { krake_path = String::from("@0xE0") + &krake_path; }
-        else if !krake_path.starts_with('@') { krake_path = String::from("@0xE0/") + &krake_path; }
+        if !krake_path.starts_with('/') && !krake_path.starts_with('@') { krake_path = String::from("/") + &krake_path; }
         let res = unsafe { crate::os::syscall(87, krake_path.as_ptr() as u64, krake_path.len() as u64, 0) };
         Some(Value::I32(if res == 0 { 0 } else { 1 }))
     });
@@ -282,15 +317,22 @@ pub fn register(interpreter: &mut Interpreter, mod_name: &str) {
         let old_len = match args[2] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
         let new_ptr = match args[3] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
         let new_len = match args[4] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
-        let mut old_path = core::str::from_utf8(&interp.memory[old_ptr..old_ptr+old_len]).unwrap_or("").to_string();
-        let mut new_path = core::str::from_utf8(&interp.memory[new_ptr..new_ptr+new_len]).unwrap_or("").to_string();
-        if old_path.starts_with('/') { old_path = String::from("@0xE0") + &old_path; }
-        if new_path.starts_with('/') { new_path = String::from("@0xE0") + &new_path; }
+        let old_path = core::str::from_utf8(&interp.memory[old_ptr..old_ptr+old_len]).unwrap_or("").to_string();
+        let new_path = core::str::from_utf8(&interp.memory[new_ptr..new_ptr+new_len]).unwrap_or("").to_string();
         let res = unsafe { crate::os::syscall4(82, old_path.as_ptr() as u64, old_path.len() as u64, new_path.as_ptr() as u64, new_path.len() as u64) };
         Some(Value::I32(if res == 0 { 0 } else { 1 }))
     });
 
-    interpreter.add_host_function(mod_name, "path_symlink", |_interp, _args| { crate::os::yield_task(); Some(Value::I32(58)) });
+    interpreter.add_host_function(mod_name, "path_symlink", |interp, args| {
+        let old_ptr = match args[0] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let old_len = match args[1] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let new_ptr = match args[3] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let new_len = match args[4] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
+        let target = core::str::from_utf8(&interp.memory[old_ptr..old_ptr+old_len]).unwrap_or("").to_string();
+        let mut new_path = core::str::from_utf8(&interp.memory[new_ptr..new_ptr+new_len]).unwrap_or("").to_string();
+        if !new_path.starts_with('/') && !new_path.starts_with('@') { new_path = String::from("/") + &new_path; }
+        Some(Value::I32(if crate::fs::symlink(&target, &new_path).is_ok() { 0 } else { 44 }))
+    });
 
     interpreter.add_host_function(mod_name, "path_unlink_file", |interp, args| {
         let path_ptr = match args[1] { Value::I32(v) => v as usize, _ => return Some(Value::I32(28)) };
@@ -298,8 +340,7 @@ pub fn register(interpreter: &mut Interpreter, mod_name: &str) {
         let path_raw = &interp.memory[path_ptr..path_ptr+path_len];
         let path = core::str::from_utf8(path_raw).unwrap_or("");
         let mut krake_path = String::from(path);
-        if krake_path.starts_with('/') { krake_path = String::from("@0xE0") + &krake_path; }
-        else if !krake_path.starts_with('@') { krake_path = String::from("@0xE0/") + &krake_path; }
+        if !krake_path.starts_with('/') && !krake_path.starts_with('@') { krake_path = String::from("/") + &krake_path; }
         let res = unsafe { crate::os::syscall(87, krake_path.as_ptr() as u64, krake_path.len() as u64, 0) };
         Some(Value::I32(if res == 0 { 0 } else { 1 }))
     });
@@ -322,4 +363,24 @@ pub fn register(interpreter: &mut Interpreter, mod_name: &str) {
         if fd == 3 && path_ptr < interp.memory.len() { interp.memory[path_ptr] = b'/'; return Some(Value::I32(0)); }
         Some(Value::I32(8))
     });
-}
\ No newline at end of file
+}
+
+/// Fills a WASI `filestat` (64 bytes) from `meta`. WASI times are in ns.
+fn write_filestat(out: &mut [u8], meta: &crate::fs::Metadata) {
+    let filetype: u8 = match meta.file_type() {
+        crate::fs::FileType::Directory => 3,
+        crate::fs::FileType::File => 4,
+        crate::fs::FileType::Device => 2,
+        crate::fs::FileType::Symlink => 7,
+        crate::fs::FileType::Unknown => if (meta.mode & 0o170000) == 0o010000 { 6 } else { 0 },
+    };
+    out.fill(0);
+    out[0..8].copy_from_slice(&meta.dev.to_le_bytes());
+    out[8..16].copy_from_slice(&meta.ino.to_le_bytes());
+    out[16] = filetype;
+    out[24..32].copy_from_slice(&meta.nlink.to_le_bytes());
+    out[32..40].copy_from_slice(&meta.size.to_le_bytes());
+    out[40..48].copy_from_slice(&(meta.atime * 1_000_000_000).to_le_bytes());
+    out[48..56].copy_from_slice(&(meta.mtime * 1_000_000_000).to_le_bytes());
+    out[56..64].copy_from_slice(&(meta.ctime * 1_000_000_000).to_le_bytes());
+}
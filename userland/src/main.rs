@@ -28,7 +28,7 @@ pub extern "C" fn main() -> i32 {
         .background_color(Color::rgb(255, 0, 0)); 
 
 
-    if let Ok(mut file) = File::open("@0xE0/sys/img/wallpaper2.png") {
+    if let Ok(mut file) = File::open("/sys/img/wallpaper2.png") {
         let size = file.size();
         if size > 0 {
             let buffer_addr = std::memory::malloc(size);
@@ -51,9 +51,9 @@ pub extern "C" fn main() -> i32 {
 
     println!("Desktop Environment Initialized.");
 
-    std::os::exec("@0xE0/sys/bin/taskbar.elf");
+    std::os::exec("/sys/bin/taskbar.elf");
 
-    std::os::exec("@0xE0/sys/bin/term.elf");
+    std::os::exec("/sys/bin/term.elf");
 
     test_wasm();
 
@@ -73,7 +73,7 @@ fn test_wasm() {
 
     debugln!("WASM: Starting WASI Test App...");
     
-    if let Ok(mut file) = File::open("@0xE0/wasm_test.wasm") {
+    if let Ok(mut file) = File::open("/wasm_test.wasm") {
         let size = file.size();
         let mut buffer = vec![0u8; size];
         if file.read(&mut buffer).is_ok() {
@@ -96,6 +96,6 @@ fn test_wasm() {
             }
         }
     } else {
-        debugln!("WASM: wasm_test.wasm not found at @0xE0/wasm_test.wasm");
+        debugln!("WASM: wasm_test.wasm not found at /wasm_test.wasm");
     }
 }
\ No newline at end of file